@@ -1,5 +1,13 @@
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::{generate, Shell};
+use openconnect_core::{
+    config::{CompressionMode, TunnelOptions},
+    csd::{CsdConfig, CsdMode},
+    dns::DnsMode,
+    proxy::ProxyAuthMethod,
+    storage::AddressOverride,
+};
+use std::net::IpAddr;
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -30,12 +38,18 @@ pub enum Commands {
     #[command(about = "Close the current connection and exit the daemon process", visible_aliases = ["kill", "disconnect"])]
     Stop,
 
+    #[command(about = "Tear down the tunnel of the current connection but keep the session, so that it can be resumed later", visible_aliases = ["suspend"])]
+    Pause,
+
+    #[command(about = "Reconnect the paused connection with the kept session")]
+    Resume,
+
     #[command(
         subcommand,
         about = "Add new VPN server configuration to local config file",
         visible_aliases = ["new", "create", "insert"]
     )]
-    Add(SeverConfigArgs),
+    Add(Box<SeverConfigArgs>),
 
     #[command(about = "Import VPN server configurations from a base64 encoded string")]
     Import {
@@ -55,12 +69,44 @@ pub enum Commands {
         name: String,
     },
 
+    #[command(about = "Clear cached sessions so that the next start authenticates again", visible_aliases = ["signout"])]
+    Logout {
+        /// The server name to clear the cached session for, clear all cached sessions if not given
+        name: Option<String>,
+
+        /// The path to the local config file
+        #[arg(short, long, value_hint = clap::ValueHint::FilePath)]
+        config_file: Option<String>,
+    },
+
     #[command(about = "List all VPN server configurations in local config file", visible_aliases = ["ls", "l"])]
     List,
 
     #[command(about = "Show logs of the daemon process", visible_aliases = ["log"])]
     Logs,
 
+    #[command(about = "Show recent connection attempts and where each one failed", visible_aliases = ["hist"])]
+    History {
+        /// The number of recent attempts to show
+        #[arg(short = 'n', long, default_value_t = 10)]
+        limit: usize,
+
+        /// Show every status transition of the attempts
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    #[command(about = "Check a VPN server before connecting: certificate, login form, single sign-on and latency", visible_aliases = ["check"])]
+    Probe {
+        /// The VPN server URL
+        #[arg(value_hint = clap::ValueHint::Url)]
+        server: String,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
     #[command(about = "Generate shell completion script")]
     GenComplete {
         generator: Shell,
@@ -98,6 +144,31 @@ pub enum SeverConfigArgs {
         /// Allow insecure peer certificate verification
         #[arg(short, long, default_value = "false")]
         allow_insecure: Option<bool>,
+
+        /// Maximum dead peer detection interval in seconds
+        #[arg(long)]
+        dpd: Option<u32>,
+
+        /// Send a keepalive packet through the tunnel every given seconds, useful when the gateway drops idle sessions
+        #[arg(long)]
+        keepalive: Option<u32>,
+
+        /// How to apply DNS settings pushed by the server, the vpnc-script handles them when not set
+        #[arg(long, value_enum)]
+        dns: Option<DnsArg>,
+
+        /// Obtain a fresh cookie with the stored credentials before the session expires and reconnect in place
+        #[arg(long)]
+        auto_reauth: bool,
+
+        #[command(flatten)]
+        tunnel: TunnelArgs,
+
+        #[command(flatten)]
+        proxy: ProxyArgs,
+
+        #[command(flatten)]
+        address: AddressArgs,
     },
 
     #[command(
@@ -119,7 +190,265 @@ pub enum SeverConfigArgs {
         /// Allow insecure peer certificate verification
         #[arg(short, long, default_value = "false")]
         allow_insecure: Option<bool>,
+
+        /// Maximum dead peer detection interval in seconds
+        #[arg(long)]
+        dpd: Option<u32>,
+
+        /// Send a keepalive packet through the tunnel every given seconds, useful when the gateway drops idle sessions
+        #[arg(long)]
+        keepalive: Option<u32>,
+
+        /// How to apply DNS settings pushed by the server, the vpnc-script handles them when not set
+        #[arg(long, value_enum)]
+        dns: Option<DnsArg>,
+
+        /// Obtain a fresh cookie with the stored credentials before the session expires and reconnect in place
+        #[arg(long)]
+        auto_reauth: bool,
+
+        #[command(flatten)]
+        tunnel: TunnelArgs,
+
+        #[command(flatten)]
+        csd: CsdArgs,
+
+        #[command(flatten)]
+        proxy: ProxyArgs,
+
+        #[command(flatten)]
+        address: AddressArgs,
     },
+
+    #[command(
+        long_about = "Add a single sign-on (SAML) VPN server. The login page will be opened in the default browser when connecting"
+    )]
+    Saml {
+        /// The unique name of the VPN server configuration
+        #[arg(short, long)]
+        name: String,
+
+        /// The VPN server URL
+        #[arg(short, long, value_hint = clap::ValueHint::Url)]
+        server: String,
+
+        /// Allow insecure peer certificate verification
+        #[arg(short, long, default_value = "false")]
+        allow_insecure: Option<bool>,
+
+        /// Maximum dead peer detection interval in seconds
+        #[arg(long)]
+        dpd: Option<u32>,
+
+        /// Send a keepalive packet through the tunnel every given seconds, useful when the gateway drops idle sessions
+        #[arg(long)]
+        keepalive: Option<u32>,
+
+        /// How to apply DNS settings pushed by the server, the vpnc-script handles them when not set
+        #[arg(long, value_enum)]
+        dns: Option<DnsArg>,
+
+        #[command(flatten)]
+        tunnel: TunnelArgs,
+
+        #[command(flatten)]
+        proxy: ProxyArgs,
+
+        #[command(flatten)]
+        address: AddressArgs,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum DnsArg {
+    /// Leave DNS configuration to the vpnc-script
+    Script,
+    /// Configure per-link DNS through systemd-resolved (Linux only)
+    SystemdResolved,
+    /// Rewrite resolv.conf and restore it on disconnect
+    ResolvConf,
+}
+
+impl From<DnsArg> for DnsMode {
+    fn from(arg: DnsArg) -> Self {
+        match arg {
+            DnsArg::Script => DnsMode::Script,
+            DnsArg::SystemdResolved => DnsMode::SystemdResolved,
+            DnsArg::ResolvConf => DnsMode::ResolvConf,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum CompressionArg {
+    None,
+    Stateless,
+    All,
+}
+
+impl From<CompressionArg> for CompressionMode {
+    fn from(arg: CompressionArg) -> Self {
+        match arg {
+            CompressionArg::None => CompressionMode::None,
+            CompressionArg::Stateless => CompressionMode::Stateless,
+            CompressionArg::All => CompressionMode::All,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct TunnelArgs {
+    /// The MTU to request from the server
+    #[arg(long)]
+    pub mtu: Option<i32>,
+
+    /// Do not request IPv6 connectivity from the server
+    #[arg(long)]
+    pub disable_ipv6: bool,
+
+    /// The compression mode, default to stateless
+    #[arg(long, value_enum)]
+    pub compression: Option<CompressionArg>,
+
+    /// Require perfect forward secrecy
+    #[arg(long)]
+    pub pfs: bool,
+
+    /// Allow insecure ciphers and protocol versions, needed by some legacy gateways
+    #[arg(long)]
+    pub allow_insecure_crypto: bool,
+
+    /// Copy the TOS/TCLASS field of tunneled packets to the outer packets
+    #[arg(long)]
+    pub pass_tos: bool,
+}
+
+impl From<TunnelArgs> for Option<TunnelOptions> {
+    fn from(args: TunnelArgs) -> Self {
+        let options = TunnelOptions {
+            mtu: args.mtu,
+            disable_ipv6: args.disable_ipv6,
+            compression: args.compression.map(CompressionMode::from),
+            pfs: args.pfs,
+            allow_insecure_crypto: args.allow_insecure_crypto,
+            pass_tos: args.pass_tos,
+        };
+
+        // keep the config file clean when nothing is set
+        if options == TunnelOptions::default() {
+            None
+        } else {
+            Some(options)
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct CsdArgs {
+    /// Run this wrapper script when the gateway asks for a CSD hostscan, e.g. csd-post.sh shipped with openconnect
+    #[arg(long, value_hint = clap::ValueHint::FilePath, conflicts_with = "csd_responder")]
+    pub csd_wrapper: Option<String>,
+
+    /// Answer the CSD hostscan with the built-in responder
+    #[arg(long)]
+    pub csd_responder: bool,
+
+    /// A file with the hostscan result posted by the built-in responder, a clean endpoint is reported by default
+    #[arg(long, value_hint = clap::ValueHint::FilePath, requires = "csd_responder")]
+    pub csd_hostscan: Option<String>,
+
+    /// Run the CSD hostscan again every given seconds
+    #[arg(long)]
+    pub trojan_interval: Option<u32>,
+}
+
+impl From<CsdArgs> for Option<CsdConfig> {
+    fn from(args: CsdArgs) -> Self {
+        let mode = match (args.csd_wrapper, args.csd_responder) {
+            (Some(path), _) => CsdMode::Wrapper { path },
+            (None, true) => CsdMode::Responder {
+                hostscan: args.csd_hostscan.map(|file| {
+                    std::fs::read_to_string(file).expect("Failed to read hostscan file")
+                }),
+            },
+            (None, false) => return None,
+        };
+
+        Some(CsdConfig {
+            mode,
+            trojan_interval: args.trojan_interval,
+        })
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ProxyAuthArg {
+    Basic,
+    Digest,
+    Ntlm,
+    Gssapi,
+}
+
+impl From<ProxyAuthArg> for ProxyAuthMethod {
+    fn from(arg: ProxyAuthArg) -> Self {
+        match arg {
+            ProxyAuthArg::Basic => ProxyAuthMethod::Basic,
+            ProxyAuthArg::Digest => ProxyAuthMethod::Digest,
+            ProxyAuthArg::Ntlm => ProxyAuthMethod::Ntlm,
+            ProxyAuthArg::Gssapi => ProxyAuthMethod::Gssapi,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ProxyArgs {
+    /// Reach the server through this proxy, e.g. http://proxy.example.com:3128 or socks5://127.0.0.1:1080, or "auto" to read https_proxy and no_proxy from the environment when connecting
+    #[arg(long, value_hint = clap::ValueHint::Url)]
+    pub proxy: Option<String>,
+
+    /// Find the proxy with this proxy auto-config (PAC) file when connecting, a http(s):// or file:// URL or a local path
+    #[arg(long, conflicts_with = "proxy", value_hint = clap::ValueHint::Url)]
+    pub proxy_pac: Option<String>,
+
+    /// The username for the proxy, the proxy password will be prompted in terminal later
+    #[arg(long)]
+    pub proxy_user: Option<String>,
+
+    /// Only offer these authentication methods to the proxy, comma separated
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub proxy_auth: Vec<ProxyAuthArg>,
+}
+
+#[derive(Args, Debug)]
+pub struct AddressArgs {
+    /// Present this name in the TLS handshake instead of the server host, e.g. when the server is given by address
+    #[arg(long)]
+    pub sni: Option<String>,
+
+    /// Connect to the address instead of resolving the host, e.g. vpn.example.com:192.0.2.10, can be repeated
+    #[arg(long, value_name = "HOST:ADDRESS", value_parser = parse_resolve)]
+    pub resolve: Vec<(String, IpAddr)>,
+}
+
+fn parse_resolve(value: &str) -> Result<(String, IpAddr), String> {
+    let (host, ip) = value
+        .split_once(':')
+        .ok_or("expected HOST:ADDRESS".to_string())?;
+    let ip = ip
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .map_err(|e| format!("invalid address {}: {}", ip, e))?;
+    Ok((host.to_string(), ip))
+}
+
+impl From<AddressArgs> for AddressOverride {
+    fn from(args: AddressArgs) -> Self {
+        AddressOverride {
+            sni: args.sni,
+            resolve: args.resolve.into_iter().collect(),
+        }
+    }
 }
 
 pub fn print_completions(generator: Shell, binary_name: Option<String>) {
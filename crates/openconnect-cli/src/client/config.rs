@@ -1,8 +1,14 @@
-use crate::cli::SeverConfigArgs;
+use crate::cli::{ProxyArgs, SeverConfigArgs};
 use base64::Engine;
 use colored::Colorize;
 use comfy_table::Table;
-use openconnect_core::storage::{OidcServer, PasswordServer, StoredConfigs, StoredServer};
+use openconnect_core::{
+    config::TunnelOptions,
+    dns::DnsMode,
+    proxy::{AutoProxy, ProxyAuthMethod, ProxyConfig},
+    secret::Secret,
+    storage::{OidcServer, PasswordServer, SamlServer, StoredConfigs, StoredServer},
+};
 use std::path::PathBuf;
 
 pub async fn read_server_config_from_fs(
@@ -24,6 +30,10 @@ pub async fn read_server_config_from_fs(
                     println!("Connecting to password server: {}", server_name);
                     println!("Server host: {}", server);
                 }
+                StoredServer::Saml(SamlServer { server, .. }) => {
+                    println!("Connecting to SAML server: {}", server_name);
+                    println!("Server host: {}", server);
+                }
             }
             Ok((server.clone(), stored_configs))
         }
@@ -53,6 +63,43 @@ fn add_server_internal(stored_server: StoredServer) {
     });
 }
 
+/// Build the proxy settings from the arguments, the proxy password is prompted if a proxy user is given
+fn prompt_proxy(args: ProxyArgs) -> Option<ProxyConfig> {
+    let proxy = match (args.proxy, args.proxy_pac) {
+        (Some(url), _) if url == "auto" => ProxyConfig::auto(AutoProxy::Env),
+        (Some(url), _) => {
+            let proxy = ProxyConfig::new(&url);
+            proxy.parse_url().expect("Invalid proxy");
+            proxy
+        }
+        (None, Some(url)) => ProxyConfig::auto(AutoProxy::Pac { url }),
+        (None, None) => {
+            if args.proxy_user.is_some() || !args.proxy_auth.is_empty() {
+                panic!("--proxy-user and --proxy-auth require --proxy or --proxy-pac");
+            }
+            return None;
+        }
+    };
+
+    let mut proxy = proxy.with_auth_methods(
+        args.proxy_auth
+            .into_iter()
+            .map(ProxyAuthMethod::from)
+            .collect(),
+    );
+
+    if let Some(username) = args.proxy_user {
+        let password = dialoguer::Password::new()
+            .with_prompt("Enter proxy password")
+            .allow_empty_password(true)
+            .interact()
+            .expect("Failed to get proxy password");
+        proxy = proxy.with_credentials(&username, &password);
+    }
+
+    Some(proxy)
+}
+
 pub fn request_add_server(server_config: SeverConfigArgs) {
     let new_server = match server_config {
         SeverConfigArgs::Oidc {
@@ -62,14 +109,28 @@ pub fn request_add_server(server_config: SeverConfigArgs) {
             client_id,
             client_secret,
             allow_insecure,
+            dpd,
+            keepalive,
+            dns,
+            auto_reauth,
+            tunnel,
+            proxy,
+            address,
         } => {
             let oidc_server = OidcServer {
                 name,
                 server,
                 issuer,
                 client_id,
-                client_secret,
+                client_secret: client_secret.map(Secret::new),
                 allow_insecure,
+                dpd,
+                keepalive,
+                tunnel: tunnel.into(),
+                dns: dns.map(DnsMode::from),
+                auto_reauth: auto_reauth.then_some(true),
+                proxy: prompt_proxy(proxy),
+                address: address.into(),
                 updated_at: None,
             };
 
@@ -80,6 +141,14 @@ pub fn request_add_server(server_config: SeverConfigArgs) {
             server,
             username,
             allow_insecure,
+            dpd,
+            keepalive,
+            dns,
+            auto_reauth,
+            tunnel,
+            csd,
+            proxy,
+            address,
         } => {
             let password = dialoguer::Password::new()
                 .with_prompt("Enter password")
@@ -90,13 +159,44 @@ pub fn request_add_server(server_config: SeverConfigArgs) {
                 name,
                 server,
                 username,
-                password: Some(password),
+                password: Some(password.into()),
                 allow_insecure,
+                dpd,
+                keepalive,
+                tunnel: tunnel.into(),
+                dns: dns.map(DnsMode::from),
+                auto_reauth: auto_reauth.then_some(true),
+                csd: csd.into(),
+                proxy: prompt_proxy(proxy),
+                address: address.into(),
                 updated_at: None,
             };
 
             StoredServer::Password(password_server)
         }
+        SeverConfigArgs::Saml {
+            name,
+            server,
+            allow_insecure,
+            dpd,
+            keepalive,
+            dns,
+            tunnel,
+            proxy,
+            address,
+        } => StoredServer::Saml(SamlServer {
+            name,
+            server,
+            allow_insecure,
+            dpd,
+            keepalive,
+            tunnel: tunnel.into(),
+            dns: dns.map(DnsMode::from),
+            webview: None,
+            proxy: prompt_proxy(proxy),
+            address: address.into(),
+            updated_at: None,
+        }),
     };
 
     add_server_internal(new_server);
@@ -118,6 +218,40 @@ pub fn request_delete_server(name: &str) {
             .remove_server(name)
             .await
             .expect("Failed to delete server");
+
+        let mut session_cache = stored_configs.session_cache();
+        if let Ok(session_cache) = session_cache.read_from_file().await {
+            let _ = session_cache.remove(name).await;
+        }
+    });
+}
+
+pub fn request_logout(name: Option<String>, config_file: PathBuf) {
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
+    runtime.block_on(async {
+        let stored_configs = StoredConfigs::new(None, config_file);
+        let mut session_cache = stored_configs.session_cache();
+        let session_cache = session_cache
+            .read_from_file()
+            .await
+            .expect("Failed to read session cache");
+
+        match name {
+            Some(name) => {
+                session_cache
+                    .remove(&name)
+                    .await
+                    .expect("Failed to clear cached session");
+                println!("Cleared cached session of server: {}", name);
+            }
+            None => {
+                session_cache
+                    .clear()
+                    .await
+                    .expect("Failed to clear cached sessions");
+                println!("Cleared all cached sessions");
+            }
+        }
     });
 }
 
@@ -168,6 +302,20 @@ pub fn request_list_servers() {
                         updated_at.as_ref().unwrap_or(&"".to_string()).to_owned(),
                     ]);
                 }
+                StoredServer::Saml(SamlServer {
+                    server,
+                    allow_insecure,
+                    updated_at,
+                    ..
+                }) => {
+                    table.add_row(vec![
+                        name.clone(),
+                        "SAML Server".to_string(),
+                        server.clone(),
+                        allow_insecure.unwrap_or(false).to_string(),
+                        updated_at.as_ref().unwrap_or(&"".to_string()).to_owned(),
+                    ]);
+                }
             }
         }
 
@@ -184,12 +332,23 @@ pub enum SharableServer {
         allow_insecure: Option<bool>,
         issuer: String,
         client_id: String,
-        client_secret: Option<String>,
+        client_secret: Option<Secret<String>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tunnel: Option<TunnelOptions>,
     },
     #[serde(rename_all = "camelCase")]
     Password {
         server: String,
         allow_insecure: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tunnel: Option<TunnelOptions>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Saml {
+        server: String,
+        allow_insecure: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tunnel: Option<TunnelOptions>,
     },
 }
 
@@ -218,6 +377,7 @@ pub fn request_export_server(server_name: &str) {
                             issuer: oidc_server.issuer,
                             client_id: oidc_server.client_id,
                             client_secret: oidc_server.client_secret,
+                            tunnel: oidc_server.tunnel,
                         };
                         let json =
                             serde_json::to_string(&partial_server).expect("Failed to serialize");
@@ -228,6 +388,18 @@ pub fn request_export_server(server_name: &str) {
                         let partial_server = SharableServer::Password {
                             server: password_server.server,
                             allow_insecure: password_server.allow_insecure,
+                            tunnel: password_server.tunnel,
+                        };
+                        let json =
+                            serde_json::to_string(&partial_server).expect("Failed to serialize");
+                        base64::prelude::BASE64_STANDARD.encode(json.as_bytes())
+                    }
+                    StoredServer::Saml(saml_server) => {
+                        let saml_server = saml_server.clone();
+                        let partial_server = SharableServer::Saml {
+                            server: saml_server.server,
+                            allow_insecure: saml_server.allow_insecure,
+                            tunnel: saml_server.tunnel,
                         };
                         let json =
                             serde_json::to_string(&partial_server).expect("Failed to serialize");
@@ -261,6 +433,7 @@ pub fn request_import_server(base64: &str) {
         SharableServer::Password {
             server,
             allow_insecure,
+            tunnel,
         } => {
             println!("We still need some extra information to complete the import");
             println!("==============================================\n");
@@ -286,8 +459,16 @@ pub fn request_import_server(base64: &str) {
                 name,
                 server,
                 username,
-                password: Some(password),
+                password: Some(password.into()),
                 allow_insecure,
+                dpd: None,
+                keepalive: None,
+                tunnel,
+                dns: None,
+                auto_reauth: None,
+                csd: None,
+                proxy: None,
+                address: Default::default(),
                 updated_at: None,
             })
         }
@@ -297,6 +478,7 @@ pub fn request_import_server(base64: &str) {
             issuer,
             client_id,
             client_secret,
+            tunnel,
         } => {
             println!("We still need some information to complete the import");
             println!("==============================================\n");
@@ -314,6 +496,41 @@ pub fn request_import_server(base64: &str) {
                 client_id,
                 client_secret,
                 allow_insecure,
+                dpd: None,
+                keepalive: None,
+                tunnel,
+                dns: None,
+                auto_reauth: None,
+                proxy: None,
+                address: Default::default(),
+                updated_at: None,
+            })
+        }
+        SharableServer::Saml {
+            server,
+            allow_insecure,
+            tunnel,
+        } => {
+            println!("We still need some information to complete the import");
+            println!("==============================================\n");
+
+            println!("Enter an unique server name, this will be used as an identifier for the local config file");
+            let name = dialoguer::Input::<String>::new()
+                .with_prompt("Server name")
+                .interact()
+                .expect("Failed to get server name");
+
+            StoredServer::Saml(SamlServer {
+                name,
+                server,
+                allow_insecure,
+                dpd: None,
+                keepalive: None,
+                tunnel,
+                dns: None,
+                webview: None,
+                proxy: None,
+                address: Default::default(),
                 updated_at: None,
             })
         }
@@ -329,7 +546,8 @@ fn test_import_server() {
         allow_insecure: Some(true),
         issuer: "https://example.com".to_string(),
         client_id: "12345".to_string(),
-        client_secret: Some("123456".to_string()),
+        client_secret: Some("123456".into()),
+        tunnel: None,
     };
 
     let json = serde_json::to_string(&partial_import_server).expect("Failed to serialize");
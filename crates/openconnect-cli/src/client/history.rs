@@ -0,0 +1,112 @@
+use colored::Colorize;
+use comfy_table::Table;
+use openconnect_core::{
+    journal::{Journal, SessionSummary},
+    log::JOURNAL_FILE_NAME,
+    storage::StoredConfigs,
+};
+
+/// How the attempt ended, with the step it failed at
+fn outcome(session: &SessionSummary) -> (String, String, String) {
+    if let Some((step, error)) = session.failure() {
+        let step = step
+            .map(|step| step.message.clone().unwrap_or(step.status.clone()))
+            .unwrap_or_default();
+        return (
+            "FAILED".red().to_string(),
+            step,
+            error.message.clone().unwrap_or_default(),
+        );
+    }
+
+    let last = session
+        .last_status()
+        .map(|entry| entry.status.clone())
+        .unwrap_or_default();
+    let outcome = match last.as_str() {
+        "CONNECTED" | "AUTHENTICATED" => last.green().to_string(),
+        "DISCONNECTED" if session.was_connected() => last,
+        // the process stopped before the attempt finished
+        _ => format!("{} (interrupted)", last).yellow().to_string(),
+    };
+    (outcome, String::new(), String::new())
+}
+
+fn format_duration(ms: u64) -> String {
+    match ms {
+        ms if ms < 1000 => format!("{}ms", ms),
+        ms if ms < 60_000 => format!("{:.1}s", ms as f64 / 1000.0),
+        ms => format!("{}m{}s", ms / 60_000, ms % 60_000 / 1000),
+    }
+}
+
+pub fn request_history(limit: usize, verbose: bool) {
+    let log_config = StoredConfigs::getorinit_config_file()
+        .map(|config_file| StoredConfigs::read_log_config(&config_file))
+        .unwrap_or_default();
+
+    // the daemon runs as root and may write to another directory than the current user
+    let mut entries = vec![];
+    for log_dir in log_config.candidate_dirs() {
+        match Journal::new(&log_dir.join(JOURNAL_FILE_NAME)).read() {
+            Ok(found) => entries.extend(found),
+            Err(e) => eprintln!("Failed to read journal in {}: {}", log_dir.display(), e),
+        }
+    }
+
+    let sessions = Journal::sessions(entries);
+    if sessions.is_empty() {
+        println!("No connection history found");
+        return;
+    }
+
+    let mut table = Table::new();
+    table.set_header(vec![
+        "Started At",
+        "Server",
+        "Duration",
+        "Outcome",
+        "Failed At",
+        "Error",
+    ]);
+
+    for session in sessions.iter().take(limit) {
+        let (outcome, step, error) = outcome(session);
+        table.add_row(vec![
+            session.started_at().to_string(),
+            session.server.clone().unwrap_or_default(),
+            format_duration(session.duration_ms()),
+            outcome,
+            step,
+            error,
+        ]);
+    }
+
+    println!("{table}");
+
+    if !verbose {
+        return;
+    }
+
+    for session in sessions.iter().take(limit) {
+        println!(
+            "\n{} {} ({})",
+            "Attempt".bold(),
+            session.attempt,
+            session.server.as_deref().unwrap_or_default()
+        );
+
+        let mut table = Table::new();
+        table.set_header(vec!["At", "Elapsed", "Step", "Status", "Message"]);
+        for entry in &session.entries {
+            table.add_row(vec![
+                entry.at.clone(),
+                format_duration(entry.elapsed_ms),
+                format!("+{}", format_duration(entry.step_ms)),
+                entry.status.clone(),
+                entry.message.clone().unwrap_or_default(),
+            ]);
+        }
+        println!("{table}");
+    }
+}
@@ -1,2 +1,4 @@
 pub(crate) mod config;
+pub(crate) mod history;
+pub(crate) mod probe;
 pub(crate) mod state;
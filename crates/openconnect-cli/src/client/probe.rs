@@ -0,0 +1,135 @@
+use colored::Colorize;
+use comfy_table::Table;
+use openconnect_core::probe::{self, FieldKind, ProbeReport, SsoKind};
+
+fn field_kind(kind: FieldKind) -> &'static str {
+    match kind {
+        FieldKind::Text => "text",
+        FieldKind::Password => "password",
+        FieldKind::Select => "select",
+        FieldKind::Hidden => "hidden",
+        FieldKind::Token => "token",
+        FieldKind::SsoToken => "sso token",
+        FieldKind::SsoUser => "sso user",
+        FieldKind::Unknown => "unknown",
+    }
+}
+
+fn print_report(report: &ProbeReport) {
+    let mut table = Table::new();
+    table.add_row(vec![
+        "Server".to_string(),
+        format!(
+            "{}:{}",
+            report.hostname.as_deref().unwrap_or_default(),
+            report.port
+        ),
+    ]);
+    table.add_row(vec![
+        "Connect".to_string(),
+        format!("{}ms", report.latency.connect_ms),
+    ]);
+    if let Some(auth_form_ms) = report.latency.auth_form_ms {
+        table.add_row(vec![
+            "Login page".to_string(),
+            format!("{}ms", auth_form_ms),
+        ]);
+    }
+    println!("{table}");
+
+    if let Some(ref certificate) = report.certificate {
+        let trust = if certificate.trusted {
+            "trusted by the system".green()
+        } else {
+            "not trusted, the fingerprint must be accepted to connect".yellow()
+        };
+        println!(
+            "\n{} {} ({})",
+            "Certificate".bold(),
+            certificate.fingerprint,
+            trust
+        );
+
+        let mut table = Table::new();
+        table.set_header(vec![
+            "Subject",
+            "Issuer",
+            "Valid From",
+            "Valid Until",
+            "SHA-256",
+        ]);
+        for cert in &certificate.chain {
+            table.add_row(vec![
+                cert.subject.clone().unwrap_or_default(),
+                cert.issuer.clone().unwrap_or_default(),
+                cert.not_before.clone().unwrap_or_default(),
+                cert.not_after.clone().unwrap_or_default(),
+                cert.sha256.clone(),
+            ]);
+        }
+        println!("{table}");
+    }
+
+    if let Some(ref form) = report.auth_form {
+        println!("\n{} {}", "Login form".bold(), form.auth_id);
+        for text in [&form.banner, &form.message, &form.error]
+            .into_iter()
+            .flatten()
+        {
+            println!("{}", text);
+        }
+        if !form.groups.is_empty() {
+            let groups = form
+                .groups
+                .iter()
+                .map(|group| match form.selected_group {
+                    Some(ref selected) if selected == group => format!("{} (default)", group),
+                    _ => group.clone(),
+                })
+                .collect::<Vec<_>>();
+            println!("Groups: {}", groups.join(", "));
+        }
+
+        let mut table = Table::new();
+        table.set_header(vec!["Field", "Label", "Type", "Choices"]);
+        for field in &form.fields {
+            table.add_row(vec![
+                field.name.clone(),
+                field.label.clone().unwrap_or_default(),
+                field_kind(field.kind).to_string(),
+                field.choices.join(", "),
+            ]);
+        }
+        println!("{table}");
+    }
+
+    if let Some(ref sso) = report.sso {
+        let kind = match sso.kind {
+            SsoKind::Saml => "SAML",
+            SsoKind::Oidc => "OIDC",
+            SsoKind::Unknown => "unknown protocol",
+        };
+        println!("\n{} {}", "Single sign-on".bold(), kind);
+        println!("Login page: {}", sso.login_url);
+        if let Some(ref redirect) = sso.redirect {
+            println!("Identity provider: {}", redirect);
+        }
+    }
+}
+
+pub fn request_probe(server: &str, json: bool) {
+    match probe::probe(server) {
+        Ok(report) if json => match serde_json::to_string_pretty(&report) {
+            Ok(report) => println!("{}", report),
+            Err(e) => {
+                eprintln!("{}", format!("Failed to serialize report: {}", e).red());
+                std::process::exit(1);
+            }
+        },
+        Ok(report) => print_report(&report),
+        Err(e) => {
+            eprintln!("{}", format!("Failed to probe server: {}", e).red());
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,20 +1,26 @@
-use crate::{sock, JsonRequest, JsonResponse};
+use crate::{sock, JsonRequest, JsonResponse, ReauthRequest};
 use colored::Colorize;
 use comfy_table::Table;
 use futures::TryStreamExt;
 use openconnect_core::{
-    config::{ConfigBuilder, EntrypointBuilder, LogLevel},
+    browser::{BrowserHandler, SystemBrowser},
+    config::{Config, ConfigBuilder, Entrypoint, EntrypointBuilder, LogLevel},
     events::EventHandlers,
     log::Logger,
     result::OpenconnectError,
-    storage::{OidcServer, PasswordServer, StoredConfigs, StoredServer},
+    secret::Secret,
+    session::Session,
+    storage::{
+        OidcServer, PasswordServer, SamlServer, StoredConfigError, StoredConfigs, StoredServer,
+    },
     Connectable, VpnClient,
 };
 use openconnect_oidc::{
-    obtain_cookie_by_oidc_token,
+    obtain_cookie_by_oidc_token_with_options,
     oidc_device::{OpenIDDeviceAuth, OpenIDDeviceAuthConfig, OpenIDDeviceAuthError},
+    CookieRequestOptions,
 };
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[allow(clippy::enum_variant_names)]
 #[derive(thiserror::Error, Debug)]
@@ -30,6 +36,9 @@ pub enum StateError {
 
     #[error("OpenID device auth error: {0}")]
     OpenIDAuthError(#[from] OpenIDDeviceAuthError),
+
+    #[error("Config error: {0}")]
+    ConfigError(#[from] StoredConfigError),
 }
 
 pub fn get_vpnc_script() -> Result<String, StateError> {
@@ -54,36 +63,183 @@ pub async fn obtain_cookie_from_password_server(
 
     let vpncscript = get_vpnc_script()?;
 
-    let config = ConfigBuilder::default()
+    let mut config = ConfigBuilder::default();
+    config
         .vpncscript(&vpncscript)
         .loglevel(LogLevel::Info)
-        .build()?;
+        .journal(&stored_configs.journal_file());
+
+    if let Some(proxy) = password_server.proxy.clone() {
+        config.proxy(proxy);
+    }
+
+    let config = config.build()?;
 
-    let entrypoint = EntrypointBuilder::new()
+    let mut entrypoint = EntrypointBuilder::new();
+    entrypoint
         .name(&password_server.name)
         .server(&password_server.server)
         .username(&password_server.username)
-        .password(&password_server.password.clone().unwrap_or("".to_string()))
+        .password(
+            password_server
+                .password
+                .as_ref()
+                .map(|p| p.expose().as_str())
+                .unwrap_or_default(),
+        )
         .accept_insecure_cert(password_server.allow_insecure.unwrap_or(false))
         .enable_udp(true)
-        .build()?;
+        .tunnel_options(password_server.tunnel.clone().unwrap_or_default());
+
+    if let Some(csd) = password_server.csd.clone() {
+        entrypoint.csd(csd);
+    }
+
+    password_server.address.apply(&mut entrypoint);
+
+    let entrypoint = entrypoint.build()?;
+
+    connect_for_cookie(config, entrypoint, EventHandlers::default(), stored_configs).await
+}
+
+/// Obtain a cookie with single sign-on, the login page is opened in the default browser of the system
+pub async fn obtain_cookie_from_saml_server(
+    saml_server: &SamlServer,
+    stored_configs: &StoredConfigs,
+) -> Result<Option<String>, StateError> {
+    let saml_server = saml_server.decrypted_by(&stored_configs.cipher);
+    let vpncscript = get_vpnc_script()?;
 
-    let event_handler = EventHandlers::default();
+    let mut config = ConfigBuilder::default();
+    config
+        .vpncscript(&vpncscript)
+        .loglevel(LogLevel::Info)
+        .journal(&stored_configs.journal_file());
+
+    if let Some(proxy) = saml_server.proxy.clone() {
+        config.proxy(proxy);
+    }
+
+    let config = config.build()?;
+
+    let mut entrypoint = EntrypointBuilder::new();
+    entrypoint
+        .name(&saml_server.name)
+        .server(&saml_server.server)
+        .accept_insecure_cert(saml_server.allow_insecure.unwrap_or(false))
+        .enable_udp(true)
+        .tunnel_options(saml_server.tunnel.clone().unwrap_or_default());
+    saml_server.address.apply(&mut entrypoint);
+    let entrypoint = entrypoint.build()?;
+
+    let event_handler = EventHandlers::default().with_browser_handler(|uri: &str| {
+        println!(
+            "Please complete the single sign-on in your browser: {}",
+            uri
+        );
+
+        // the page can still be opened manually, e.g. when running without a desktop session
+        if let Err(e) = open_browser(uri) {
+            tracing::warn!("Failed to open browser: {}", e);
+        }
+        Ok(())
+    });
+
+    connect_for_cookie(config, entrypoint, event_handler, stored_configs).await
+}
+
+/// Open the login page as the user who ran `sudo`, never as root
+fn open_browser(uri: &str) -> Result<(), String> {
+    #[cfg(unix)]
+    if unsafe { libc::geteuid() } == 0 {
+        let id = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u32>().ok());
+        return match (id("SUDO_UID"), id("SUDO_GID")) {
+            (Some(uid), Some(gid)) => SystemBrowser.open_as(uri, uid, gid),
+            _ => Err("refusing to start a browser as root".to_string()),
+        };
+    }
+
+    SystemBrowser.open(uri)
+}
+
+/// Authenticate with a temporary client and cache the obtained session
+async fn connect_for_cookie(
+    config: Config,
+    entrypoint: Entrypoint,
+    event_handler: EventHandlers,
+    stored_configs: &StoredConfigs,
+) -> Result<Option<String>, StateError> {
+    let name = entrypoint.name.clone().unwrap_or_default();
+    let server = entrypoint.server.clone();
 
     let client = VpnClient::new(config, event_handler)?;
     let client_clone = client.clone();
 
-    Ok(tokio::task::spawn_blocking(move || client_clone.connect_for_cookie(entrypoint)).await??)
+    let session = tokio::task::spawn_blocking(move || {
+        client_clone
+            .connect_for_cookie(entrypoint)
+            .map(|_| client_clone.get_session())
+    })
+    .await??;
+
+    if let Some(ref session) = session {
+        cache_session(&name, &server, session, stored_configs).await;
+    }
+
+    Ok(session.map(|session| session.cookie))
 }
 
+/// Cache the session for the next start, failing to cache does not fail the connection
+async fn cache_session(
+    name: &str,
+    server: &str,
+    session: &Session,
+    stored_configs: &StoredConfigs,
+) {
+    let mut session_cache = stored_configs.session_cache();
+    let result = match session_cache.read_from_file().await {
+        Ok(session_cache) => session_cache
+            .insert(name, server, session)
+            .await
+            .map(|_| ()),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to cache session: {}", e);
+    }
+}
+
+/// How the OIDC cookie request reaches the server, through its decrypted proxy and pinned addresses
+fn cookie_request_options(
+    oidc_server: &OidcServer,
+    stored_configs: &StoredConfigs,
+) -> Result<CookieRequestOptions, StateError> {
+    let proxy = oidc_server.decrypted_by(&stored_configs.cipher).proxy;
+    let proxy = proxy
+        .map(|proxy| proxy.resolve(&oidc_server.server))
+        .transpose()
+        .map_err(OpenconnectError::from)?
+        .flatten()
+        .map(|proxy| proxy.to_reqwest())
+        .transpose()
+        .map_err(OpenconnectError::from)?;
+
+    Ok(CookieRequestOptions {
+        proxy,
+        resolve: oidc_server.address.resolve.clone().into_iter().collect(),
+    })
+}
+
+/// Obtain a cookie with the device flow, returns the cookie and the refresh token issued by the identity provider
 pub async fn obtain_cookie_from_oidc_server(
     oidc_server: &OidcServer,
-    _stored_configs: &StoredConfigs,
-) -> Result<Option<String>, StateError> {
+    stored_configs: &StoredConfigs,
+) -> Result<(Option<String>, Option<String>), StateError> {
     let openid_config = OpenIDDeviceAuthConfig {
         issuer_url: oidc_server.issuer.clone(),
         client_id: oidc_server.client_id.clone(),
-        client_secret: oidc_server.client_secret.clone(),
+        client_secret: oidc_server.client_secret.clone().map(Secret::into_inner),
     };
 
     let mut openid = OpenIDDeviceAuth::new(openid_config).await?;
@@ -100,7 +256,85 @@ pub async fn obtain_cookie_from_oidc_server(
         .exchange_token(&device_auth_response, tokio::time::sleep, None)
         .await?;
 
-    Ok(obtain_cookie_by_oidc_token(&oidc_server.server, &token).await)
+    let options = cookie_request_options(oidc_server, stored_configs)?;
+    let cookie =
+        obtain_cookie_by_oidc_token_with_options(&oidc_server.server, &token, options).await;
+    let refresh_token = openid.refresh_token();
+
+    if let Some(ref cookie) = cookie {
+        let session = Session::new(cookie)
+            .with_token_expiration(&token)
+            .with_refresh_token(refresh_token.clone());
+        cache_session(
+            &oidc_server.name,
+            &oidc_server.server,
+            &session,
+            stored_configs,
+        )
+        .await;
+    }
+
+    Ok((cookie, refresh_token))
+}
+
+/// Obtain a fresh cookie for a running connection without user interaction
+///
+/// Password servers log in again with the stored credentials, OIDC servers use the refresh token. Returns the cookie and the refresh token to use next time
+pub async fn reobtain_cookie(
+    name: &str,
+    reauth: &ReauthRequest,
+) -> Result<(Option<String>, Option<String>), StateError> {
+    let mut stored_configs = StoredConfigs::new(None, reauth.config_file.clone());
+    stored_configs.read_from_file().await?;
+
+    match stored_configs.servers.get(name) {
+        Some(StoredServer::Password(password_server)) => Ok((
+            obtain_cookie_from_password_server(password_server, &stored_configs).await?,
+            None,
+        )),
+        Some(StoredServer::Oidc(oidc_server)) => {
+            let refresh_token =
+                reauth
+                    .refresh_token
+                    .as_ref()
+                    .ok_or(OpenIDDeviceAuthError::TokenExchangeError(
+                        "No refresh token".to_string(),
+                    ))?;
+
+            let openid_config = OpenIDDeviceAuthConfig {
+                issuer_url: oidc_server.issuer.clone(),
+                client_id: oidc_server.client_id.clone(),
+                client_secret: oidc_server.client_secret.clone().map(Secret::into_inner),
+            };
+
+            let mut openid = OpenIDDeviceAuth::new(openid_config).await?;
+            openid.set_refresh_token(refresh_token);
+            let token = openid.refresh().await?;
+
+            let options = cookie_request_options(oidc_server, &stored_configs)?;
+            let cookie =
+                obtain_cookie_by_oidc_token_with_options(&oidc_server.server, &token, options)
+                    .await;
+            let refresh_token = openid.refresh_token();
+
+            if let Some(ref cookie) = cookie {
+                let session = Session::new(cookie)
+                    .with_token_expiration(&token)
+                    .with_refresh_token(refresh_token.clone());
+                cache_session(name, &oidc_server.server, &session, &stored_configs).await;
+            }
+
+            Ok((cookie, refresh_token))
+        }
+        Some(StoredServer::Saml(_)) => Err(StoredConfigError::BadInput(format!(
+            "Server {} uses single sign-on, which can not re-authenticate without user interaction",
+            name
+        )))?,
+        None => Err(StoredConfigError::BadInput(format!(
+            "Server {} not found",
+            name
+        )))?,
+    }
 }
 
 pub fn request_get_status() {
@@ -187,59 +421,146 @@ pub fn request_get_status() {
     });
 }
 
+/// Obtain a cookie with a full authentication, returns the cookie and the OIDC refresh token
+async fn obtain_cookie(
+    stored_server: &StoredServer,
+    stored_configs: &StoredConfigs,
+) -> Result<(Option<String>, Option<String>), StateError> {
+    match stored_server {
+        StoredServer::Password(password_server) => Ok((
+            obtain_cookie_from_password_server(password_server, stored_configs).await?,
+            None,
+        )),
+        StoredServer::Oidc(oidc_server) => {
+            obtain_cookie_from_oidc_server(oidc_server, stored_configs).await
+        }
+        StoredServer::Saml(saml_server) => Ok((
+            obtain_cookie_from_saml_server(saml_server, stored_configs).await?,
+            None,
+        )),
+    }
+}
+
+/// Build the start request for the daemon from the decrypted stored server and the cookie
+fn start_request(
+    stored_server: StoredServer,
+    cookie: String,
+    refresh_token: Option<String>,
+    config_file: &Path,
+) -> JsonRequest {
+    match stored_server {
+        StoredServer::Password(password_server) => JsonRequest::Start {
+            reauth: password_server
+                .auto_reauth
+                .unwrap_or(false)
+                .then(|| ReauthRequest {
+                    config_file: config_file.to_path_buf(),
+                    refresh_token: None,
+                }),
+            name: password_server.name,
+            server: password_server.server,
+            allow_insecure: password_server.allow_insecure.unwrap_or(false),
+            cookie,
+            dpd: password_server.dpd,
+            keepalive: password_server.keepalive,
+            tunnel: password_server.tunnel,
+            dns: password_server.dns,
+            proxy: password_server.proxy,
+            address: password_server.address,
+            config_file: config_file.to_path_buf(),
+        },
+        StoredServer::Oidc(oidc_server) => {
+            let reauth = match (oidc_server.auto_reauth, refresh_token) {
+                (Some(true), Some(refresh_token)) => Some(ReauthRequest {
+                    config_file: config_file.to_path_buf(),
+                    refresh_token: Some(refresh_token),
+                }),
+                (Some(true), None) => {
+                    println!("The identity provider did not issue a refresh token, auto re-authentication is disabled");
+                    None
+                }
+                _ => None,
+            };
+
+            JsonRequest::Start {
+                name: oidc_server.name,
+                server: oidc_server.server,
+                allow_insecure: oidc_server.allow_insecure.unwrap_or(false),
+                cookie,
+                dpd: oidc_server.dpd,
+                keepalive: oidc_server.keepalive,
+                tunnel: oidc_server.tunnel,
+                dns: oidc_server.dns,
+                proxy: oidc_server.proxy,
+                address: oidc_server.address,
+                config_file: config_file.to_path_buf(),
+                reauth,
+            }
+        }
+        StoredServer::Saml(saml_server) => JsonRequest::Start {
+            name: saml_server.name,
+            server: saml_server.server,
+            allow_insecure: saml_server.allow_insecure.unwrap_or(false),
+            cookie,
+            dpd: saml_server.dpd,
+            keepalive: saml_server.keepalive,
+            tunnel: saml_server.tunnel,
+            dns: saml_server.dns,
+            proxy: saml_server.proxy,
+            address: saml_server.address,
+            config_file: config_file.to_path_buf(),
+            reauth: None,
+        },
+    }
+}
+
+/// Send the start request to the daemon, returns the error message if the connection failed
+async fn send_start_request(
+    unix_client: &mut sock::UnixDomainClient,
+    request: JsonRequest,
+) -> Result<(), String> {
+    unix_client
+        .send(request)
+        .await
+        .expect("Failed to send start command");
+
+    match unix_client.framed_reader.try_next().await {
+        Ok(Some(JsonResponse::StartResult {
+            name,
+            success,
+            err_message,
+        })) => {
+            if success {
+                println!("\nStarted connection to server: {}", name);
+                Ok(())
+            } else {
+                Err(err_message.unwrap_or("Unknown error".to_string()))
+            }
+        }
+        _ => Err("Received unexpected response".to_string()),
+    }
+}
+
 pub fn request_start_server(name: String, config_file: PathBuf) {
     let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
     runtime.block_on(async {
-        Logger::init().expect("Failed to initialize logger");
+        Logger::init_with_config(&StoredConfigs::read_log_config(&config_file))
+            .expect("Failed to initialize logger");
 
-        match crate::client::config::read_server_config_from_fs(&name, config_file).await {
+        match crate::client::config::read_server_config_from_fs(&name, config_file.clone()).await {
             Ok((stored_server, stored_configs)) => {
-                let (cookie, name, server, allow_insecure) = match stored_server {
-                    StoredServer::Password(password_server) => {
-                        let cookie = crate::client::state::obtain_cookie_from_password_server(
-                            &password_server,
-                            &stored_configs,
-                        )
-                        .await;
-
-                        let cookie = match cookie {
-                            Ok(cookie) => cookie,
-                            Err(e) => {
-                                tracing::error!("Failed to obtain cookie: {}", e);
-                                None
-                            }
-                        };
-
-                        (
-                            cookie,
-                            password_server.name,
-                            password_server.server,
-                            password_server.allow_insecure,
-                        )
-                    }
-                    StoredServer::Oidc(oidc_server) => {
-                        let cookie_res = crate::client::state::obtain_cookie_from_oidc_server(
-                            &oidc_server,
-                            &stored_configs,
-                        )
-                        .await;
-
-                        let cookie = match cookie_res {
-                            Ok(cookie) => cookie,
-                            Err(e) => {
-                                tracing::error!("Failed to obtain cookie: {}", e);
-                                None
-                            }
-                        };
-
-                        (
-                            cookie,
-                            oidc_server.name,
-                            oidc_server.server,
-                            oidc_server.allow_insecure,
-                        )
+                let server = match &stored_server {
+                    StoredServer::Password(password_server) => password_server.server.clone(),
+                    StoredServer::Oidc(oidc_server) => oidc_server.server.clone(),
+                    StoredServer::Saml(saml_server) => saml_server.server.clone(),
+                };
 
-                        // TODO: optimize error message handling
+                let mut session_cache = stored_configs.session_cache();
+                let cached_session = match session_cache.read_from_file().await {
+                    Ok(session_cache) => session_cache.get(&name, &server),
+                    Err(e) => {
+                        tracing::warn!("Failed to read session cache: {}", e);
+                        None
                     }
                 };
 
@@ -247,44 +568,49 @@ pub fn request_start_server(name: String, config_file: PathBuf) {
                     .await
                     .expect("Failed to connect to daemon");
 
+                if let Some(session) = cached_session {
+                    println!("Reusing cached session");
+
+                    let request = start_request(
+                        stored_server.decrypted_by(&stored_configs.cipher),
+                        session.cookie,
+                        session.refresh_token,
+                        &config_file,
+                    );
+
+                    match send_start_request(&mut unix_client, request).await {
+                        Ok(()) => return,
+                        Err(e) => {
+                            println!("Cached session rejected, authenticating again: {}", e);
+                            if let Err(e) = session_cache.remove(&name).await {
+                                tracing::warn!("Failed to remove cached session: {}", e);
+                            }
+                        }
+                    }
+                }
+
+                // TODO: optimize error message handling
+                let (cookie, refresh_token) =
+                    match obtain_cookie(&stored_server, &stored_configs).await {
+                        Ok(cookie) => cookie,
+                        Err(e) => {
+                            tracing::error!("Failed to obtain cookie: {}", e);
+                            (None, None)
+                        }
+                    };
+
                 if let Some(cookie) = cookie {
                     println!("Obtained cookie from server");
 
-                    unix_client
-                        .send(JsonRequest::Start {
-                            name,
-                            server,
-                            allow_insecure: allow_insecure.unwrap_or(false),
-                            cookie,
-                        })
-                        .await
-                        .expect("Failed to send start command");
-
-                    if let Ok(Some(response)) = unix_client.framed_reader.try_next().await {
-                        match response {
-                            JsonResponse::StartResult {
-                                name,
-                                success,
-                                err_message,
-                            } => {
-                                if success {
-                                    println!("\nStarted connection to server: {}", name);
-                                } else {
-                                    eprintln!(
-                                        "{}",
-                                        format!(
-                                            "\nFailed to start connection: {}",
-                                            err_message.unwrap_or("Unknown error".to_string())
-                                        )
-                                        .red()
-                                    );
-                                    std::process::exit(1);
-                                }
-                            }
-                            _ => {
-                                eprintln!("{}", "\nReceived unexpected response".red());
-                            }
-                        }
+                    let request = start_request(
+                        stored_server.decrypted_by(&stored_configs.cipher),
+                        cookie,
+                        refresh_token,
+                        &config_file,
+                    );
+                    if let Err(e) = send_start_request(&mut unix_client, request).await {
+                        eprintln!("{}", format!("\nFailed to start connection: {}", e).red());
+                        std::process::exit(1);
                     }
                 } else {
                     unix_client
@@ -338,3 +664,101 @@ pub fn request_stop_server() {
         };
     });
 }
+
+pub fn request_pause_server() {
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
+
+    runtime.block_on(async {
+        let client = sock::UnixDomainClient::connect().await;
+
+        match client {
+            Ok(mut client) => {
+                client
+                    .send(JsonRequest::Pause)
+                    .await
+                    .expect("Failed to send pause command");
+
+                if let Ok(Some(response)) = client.framed_reader.try_next().await {
+                    match response {
+                        JsonResponse::PauseResult {
+                            name,
+                            success,
+                            err_message,
+                        } => {
+                            if success {
+                                println!("\nPaused connection to server: {}", name)
+                            } else {
+                                eprintln!(
+                                    "{}",
+                                    format!(
+                                        "\nFailed to pause connection to server {}: {}",
+                                        name,
+                                        err_message.unwrap_or("".to_string())
+                                    )
+                                    .red()
+                                );
+                                std::process::exit(1);
+                            }
+                        }
+                        _ => {
+                            println!("Received unexpected response");
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", format!("\nFailed to connect to server: {}", e).red());
+                std::process::exit(1);
+            }
+        };
+    });
+}
+
+pub fn request_resume_server() {
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
+
+    runtime.block_on(async {
+        let client = sock::UnixDomainClient::connect().await;
+
+        match client {
+            Ok(mut client) => {
+                client
+                    .send(JsonRequest::Resume)
+                    .await
+                    .expect("Failed to send resume command");
+
+                if let Ok(Some(response)) = client.framed_reader.try_next().await {
+                    match response {
+                        JsonResponse::ResumeResult {
+                            name,
+                            success,
+                            err_message,
+                        } => {
+                            if success {
+                                println!("\nResuming connection to server: {}", name)
+                            } else {
+                                eprintln!(
+                                    "{}",
+                                    format!(
+                                        "\nFailed to resume connection to server {}: {}",
+                                        name,
+                                        err_message.unwrap_or("".to_string())
+                                    )
+                                    .red()
+                                );
+                                std::process::exit(1);
+                            }
+                        }
+                        _ => {
+                            println!("Received unexpected response");
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", format!("\nFailed to connect to server: {}", e).red());
+                std::process::exit(1);
+            }
+        };
+    });
+}
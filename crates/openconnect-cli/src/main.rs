@@ -6,9 +6,18 @@ mod sock;
 
 use clap::Parser;
 use cli::{Cli, Commands};
-use openconnect_core::{ip_info::IpInfo, log::Logger, storage::StoredConfigs};
+use openconnect_core::{
+    config::TunnelOptions,
+    dns::DnsMode,
+    ip_info::IpInfo,
+    log::Logger,
+    proxy::ProxyConfig,
+    storage::{AddressOverride, StoredConfigs},
+};
 use std::{io::BufRead, path::PathBuf};
 
+// sent once per connection, boxing the start request is not worth it
+#[allow(clippy::large_enum_variant)]
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub enum JsonRequest {
     Start {
@@ -16,11 +25,30 @@ pub enum JsonRequest {
         server: String,
         allow_insecure: bool,
         cookie: String,
+        dpd: Option<u32>,
+        keepalive: Option<u32>,
+        tunnel: Option<TunnelOptions>,
+        dns: Option<DnsMode>,
+        /// Decrypted proxy settings, the daemon does not read the config file for them
+        proxy: Option<ProxyConfig>,
+        address: AddressOverride,
+        reauth: Option<ReauthRequest>,
+        /// Watched by the daemon for settings changed while connected
+        config_file: PathBuf,
     },
     Stop,
+    Pause,
+    Resume,
     Info,
 }
 
+/// Where the daemon finds the credentials to obtain a fresh cookie before the session expires
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ReauthRequest {
+    pub config_file: PathBuf,
+    pub refresh_token: Option<String>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub enum JsonResponse {
     StartResult {
@@ -31,6 +59,16 @@ pub enum JsonResponse {
     StopResult {
         name: String,
     },
+    PauseResult {
+        name: String,
+        success: bool,
+        err_message: Option<String>,
+    },
+    ResumeResult {
+        name: String,
+        success: bool,
+        err_message: Option<String>,
+    },
     InfoResult {
         server_name: String,
         server_url: String,
@@ -41,6 +79,9 @@ pub enum JsonResponse {
 }
 
 fn main() {
+    // this process may be started by libopenconnect to answer a CSD hostscan
+    openconnect_core::csd::run_responder_if_requested();
+
     let cli = Cli::parse();
 
     match cli.command {
@@ -51,7 +92,7 @@ fn main() {
             crate::cli::print_completions(generator, binary_name);
         }
         Commands::Add(server_config) => {
-            crate::client::config::request_add_server(server_config);
+            crate::client::config::request_add_server(*server_config);
         }
 
         Commands::Import { base64 } => {
@@ -66,6 +107,21 @@ fn main() {
             crate::client::config::request_delete_server(&name);
         }
 
+        Commands::Logout { name, config_file } => {
+            // the session cache is written by the daemon with root permission
+            #[cfg(target_os = "macos")]
+            sudo::escalate_if_needed().expect("Failed to escalate permissions");
+
+            #[cfg(target_os = "linux")]
+            sudo::with_env(&["HOME"]).expect("Failed to escalate permissions");
+
+            let config_file = config_file.map(PathBuf::from).unwrap_or(
+                StoredConfigs::getorinit_config_file().expect("Failed to get config file"),
+            );
+
+            crate::client::config::request_logout(name, config_file);
+        }
+
         Commands::List => {
             crate::client::config::request_list_servers();
         }
@@ -75,11 +131,22 @@ fn main() {
         }
 
         Commands::Logs => {
-            let log_path = Logger::get_log_path();
-            let files = std::fs::read_dir(log_path)
-                .expect("Failed to read log directory")
-                .flatten()
+            let log_config = StoredConfigs::getorinit_config_file()
+                .map(|config_file| StoredConfigs::read_log_config(&config_file))
+                .unwrap_or_default();
+
+            // the daemon may have fallen back to the user log directory
+            let files = log_config
+                .candidate_dirs()
+                .into_iter()
+                .filter_map(|log_path| std::fs::read_dir(log_path).ok())
+                .flat_map(|entries| entries.flatten())
                 .filter(|f| f.metadata().unwrap().is_file())
+                .filter(|f| {
+                    f.file_name()
+                        .to_string_lossy()
+                        .starts_with("openconnect-rs.log")
+                })
                 .max_by_key(|f| f.metadata().unwrap().modified().unwrap());
 
             if let Some(file) = files {
@@ -93,10 +160,26 @@ fn main() {
             }
         }
 
+        Commands::History { limit, verbose } => {
+            crate::client::history::request_history(limit, verbose);
+        }
+
+        Commands::Probe { server, json } => {
+            crate::client::probe::request_probe(&server, json);
+        }
+
         Commands::Stop => {
             crate::client::state::request_stop_server();
         }
 
+        Commands::Pause => {
+            crate::client::state::request_pause_server();
+        }
+
+        Commands::Resume => {
+            crate::client::state::request_resume_server();
+        }
+
         Commands::Start { name, config_file } => {
             sock::exit_when_socket_exists();
 
@@ -104,7 +187,16 @@ fn main() {
             sudo::escalate_if_needed().expect("Failed to escalate permissions");
 
             #[cfg(target_os = "linux")]
-            sudo::with_env(&["HOME"]).expect("Failed to escalate permissions"); // keep HOME env so that we can find the config file and vpnc script
+            // keep HOME env so that we can find the config file and vpnc script, and the desktop session env for the SSO browser
+            sudo::with_env(&[
+                "HOME",
+                "DISPLAY",
+                "WAYLAND_DISPLAY",
+                "XAUTHORITY",
+                "XDG_RUNTIME_DIR",
+                "DBUS_SESSION_BUS_ADDRESS",
+            ])
+            .expect("Failed to escalate permissions");
 
             let config_file = config_file.map(PathBuf::from).unwrap_or(
                 StoredConfigs::getorinit_config_file().expect("Failed to get config file"),
@@ -132,8 +224,9 @@ fn main() {
             let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
 
             runtime.block_on(async {
-                Logger::init().expect("Failed to initialize logger");
-                let start_result = crate::server::start_daemon().await;
+                let log_config = StoredConfigs::read_log_config(&config_file);
+                Logger::init_with_config(&log_config).expect("Failed to initialize logger");
+                let start_result = crate::server::start_daemon(log_config.journal_file()).await;
                 if let Err(e) = start_result {
                     tracing::error!("Failed to start daemon: {}", e);
                 }
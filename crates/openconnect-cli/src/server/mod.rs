@@ -1,31 +1,57 @@
 use crate::{
-    client::state::{get_vpnc_script, StateError},
+    client::state::{get_vpnc_script, reobtain_cookie, StateError},
     sock::{self, UnixDomainServer},
-    JsonRequest, JsonResponse,
+    JsonRequest, JsonResponse, ReauthRequest,
 };
 use futures::{SinkExt, TryStreamExt};
 use openconnect_core::{
-    config::{ConfigBuilder, EntrypointBuilder, LogLevel},
+    command::{SignalMap, SignalPolicy},
+    config::{ConfigBuilder, EntrypointBuilder, LogLevel, ReconnectSettings, TunnelOptions},
+    dns::DnsMode,
     events::EventHandlers,
+    proxy::ProxyConfig,
+    storage::{
+        AddressOverride, OidcServer, PasswordServer, SamlServer, StoredConfigs, StoredServer,
+    },
     Connectable, Status, VpnClient,
 };
-use std::sync::Arc;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::{
     select,
     signal::unix::{signal, SignalKind},
-    sync::RwLock,
+    sync::{Notify, RwLock},
 };
 
+/// Attempts to start a connection when the gateway can not be reached, other errors abort at once
+const START_ATTEMPTS: u32 = 3;
+const START_RETRY_DELAY: Duration = Duration::from_secs(5);
+
 struct State {
     client: RwLock<Option<Arc<VpnClient>>>,
     server: UnixDomainServer,
+    journal: PathBuf,
+    /// Hand the tunnel to this script instead of a tun device set up by the vpnc script
+    tun_script: Option<String>,
+    /// Stop the daemon once the connection is stopped or failed to start
+    shutdown: Notify,
 }
 
 impl State {
-    pub fn new(server: UnixDomainServer) -> Arc<Self> {
+    pub fn new(
+        server: UnixDomainServer,
+        journal: PathBuf,
+        tun_script: Option<String>,
+    ) -> Arc<Self> {
         Arc::new(State {
             client: RwLock::new(None),
             server,
+            journal,
+            tun_script,
+            shutdown: Notify::new(),
         })
     }
 }
@@ -34,31 +60,171 @@ trait Acceptable {
     async fn try_accept(self);
 }
 
+/// Build a reauth handler which obtains a fresh cookie on the daemon runtime
+fn reauth_handler(name: &str, reauth: ReauthRequest) -> impl Fn() -> Option<String> + Send {
+    let runtime = tokio::runtime::Handle::current();
+    let name = name.to_string();
+    let reauth = Mutex::new(reauth);
+
+    move || {
+        let mut reauth = reauth.lock().ok()?;
+        match runtime.block_on(reobtain_cookie(&name, &reauth)) {
+            Ok((cookie, refresh_token)) => {
+                if refresh_token.is_some() {
+                    reauth.refresh_token = refresh_token;
+                }
+                cookie
+            }
+            Err(e) => {
+                tracing::error!("Failed to re-authenticate: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Pass the settings of the connected server changed in the config file to the client, they are applied when it reconnects
+fn watch_settings(
+    name: &str,
+    config_file: &Path,
+    mut settings: ReconnectSettings,
+    client: Arc<VpnClient>,
+) {
+    let name = name.to_string();
+    let mut watcher = StoredConfigs::new(None, config_file.to_path_buf()).watch();
+
+    tokio::spawn(async move {
+        while let Some(configs) = watcher.changed().await {
+            let Some(server) = configs.servers.get(&name) else {
+                tracing::warn!("Server {} was removed from the config file", name);
+                continue;
+            };
+
+            let changed = match server {
+                StoredServer::Oidc(OidcServer { dpd, tunnel, .. })
+                | StoredServer::Password(PasswordServer { dpd, tunnel, .. })
+                | StoredServer::Saml(SamlServer { dpd, tunnel, .. }) => ReconnectSettings {
+                    dpd: *dpd,
+                    tunnel: tunnel.clone().unwrap_or_default(),
+                },
+            };
+
+            if changed != settings {
+                // other settings need a new connection, credentials are read again on re-authentication
+                tracing::info!(
+                    "Settings of {} changed, applying them on the next reconnect",
+                    name
+                );
+                client.update_settings(changed.clone());
+                settings = changed;
+            }
+        }
+    });
+}
+
+fn event_handlers(name: &str, reauth: Option<ReauthRequest>) -> EventHandlers {
+    let event_handler = EventHandlers::default()
+        .with_handle_disconnect(|reason| {
+            tracing::info!("Connection closed: {:?}", reason);
+        })
+        .with_handle_auth_expiring(|expiration| {
+            tracing::warn!("Session is about to expire at {:?}", expiration);
+        });
+
+    match reauth {
+        Some(reauth) => event_handler.with_handle_reauth(reauth_handler(name, reauth)),
+        None => event_handler,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn connect_to_vpn_server(
     name: &str,
     server: &str,
     allow_insecure: bool,
     cookie: &str,
+    dpd: Option<u32>,
+    keepalive: Option<u32>,
+    tunnel: Option<TunnelOptions>,
+    dns: Option<DnsMode>,
+    proxy: Option<ProxyConfig>,
+    address: AddressOverride,
+    reauth: Option<ReauthRequest>,
+    config_file: &Path,
+    journal: &Path,
+    tun_script: Option<&str>,
 ) -> Result<Arc<VpnClient>, StateError> {
-    let vpncscript = get_vpnc_script()?;
+    let settings = ReconnectSettings {
+        dpd,
+        tunnel: tunnel.clone().unwrap_or_default(),
+    };
 
-    let config = ConfigBuilder::default()
-        .vpncscript(&vpncscript)
+    let mut config = ConfigBuilder::default();
+    let config = config
         .loglevel(LogLevel::Info)
-        .build()?;
+        .journal(journal)
+        // the daemon owns the process, signals sent to it control the connection
+        .signal_policy(SignalPolicy::Handle(SignalMap::process_signals()));
+
+    match tun_script {
+        Some(tun_script) => config.tun_script(tun_script),
+        None => config.vpncscript(&get_vpnc_script()?),
+    };
+
+    if let Some(dpd) = dpd {
+        config.dpd(dpd);
+    }
+
+    if let Some(keepalive) = keepalive {
+        config.keepalive(keepalive);
+    }
+
+    if let Some(proxy) = proxy {
+        config.proxy(proxy);
+    }
+
+    if let Some(dns) = dns {
+        config.dns_mode(dns);
+    }
+
+    config.auto_reauth(reauth.is_some());
 
-    let entrypoint = EntrypointBuilder::new()
+    let config = config.build()?;
+
+    let mut entrypoint = EntrypointBuilder::new();
+    entrypoint
         .name(name)
         .server(server)
         .accept_insecure_cert(allow_insecure)
         .cookie(cookie)
         .enable_udp(true)
-        .build()?;
+        .tunnel_options(tunnel.unwrap_or_default());
+    address.apply(&mut entrypoint);
+    let entrypoint = entrypoint.build()?;
 
-    let event_handler = EventHandlers::default();
+    // a fresh client for each attempt, a failed one may have half set up its libopenconnect instance
+    let mut attempt = 1;
+    let client = loop {
+        let result = VpnClient::new(config.clone(), event_handlers(name, reauth.clone()))
+            .and_then(|client| client.init_connection(entrypoint.clone()).map(|_| client));
 
-    let client = VpnClient::new(config, event_handler)?;
-    client.init_connection(entrypoint)?;
+        match result {
+            Ok(client) => break client,
+            Err(e) if e.is_retryable() && attempt < START_ATTEMPTS => {
+                tracing::warn!(
+                    "Failed to connect to {} (attempt {}/{}), retrying: {}",
+                    name,
+                    attempt,
+                    START_ATTEMPTS,
+                    e
+                );
+                tokio::time::sleep(START_RETRY_DELAY * attempt).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    watch_settings(name, config_file, settings, client.clone());
 
     let client_cloned = client.clone();
     tokio::task::spawn_blocking(move || {
@@ -83,11 +249,33 @@ impl Acceptable for Arc<State> {
                             server,
                             allow_insecure,
                             cookie,
+                            dpd,
+                            keepalive,
+                            tunnel,
+                            dns,
+                            proxy,
+                            address,
+                            reauth,
+                            config_file,
                         } => {
                             tracing::debug!("Received start command, name: {}", name);
-                            let connection_result =
-                                connect_to_vpn_server(&name, &server, allow_insecure, &cookie)
-                                    .await;
+                            let connection_result = connect_to_vpn_server(
+                                &name,
+                                &server,
+                                allow_insecure,
+                                &cookie,
+                                dpd,
+                                keepalive,
+                                tunnel,
+                                dns,
+                                proxy,
+                                address,
+                                reauth,
+                                &config_file,
+                                &self.journal,
+                                self.tun_script.as_deref(),
+                            )
+                            .await;
 
                             match connection_result {
                                 Ok(client) => {
@@ -112,9 +300,7 @@ impl Acceptable for Arc<State> {
                                         })
                                         .await;
 
-                                    unsafe {
-                                        libc::raise(libc::SIGTERM);
-                                    }
+                                    self.shutdown.notify_one();
                                 }
                             }
                         }
@@ -137,8 +323,42 @@ impl Acceptable for Arc<State> {
                             {
                                 self.client.write().await.take();
                             }
-                            unsafe {
-                                libc::raise(libc::SIGTERM);
+                            self.shutdown.notify_one();
+                        }
+
+                        JsonRequest::Pause => {
+                            tracing::debug!("Received pause command");
+                            let client = self.client.read().await;
+                            if let Some(ref client) = *client {
+                                let name = client.get_server_name().unwrap_or("".to_string());
+                                let result = client.pause();
+
+                                // ignore send error
+                                let _ = framed_writer
+                                    .send(JsonResponse::PauseResult {
+                                        name,
+                                        success: result.is_ok(),
+                                        err_message: result.err().map(|e| e.to_string()),
+                                    })
+                                    .await;
+                            }
+                        }
+
+                        JsonRequest::Resume => {
+                            tracing::debug!("Received resume command");
+                            let client = self.client.read().await;
+                            if let Some(ref client) = *client {
+                                let name = client.get_server_name().unwrap_or("".to_string());
+                                let result = client.resume();
+
+                                // ignore send error
+                                let _ = framed_writer
+                                    .send(JsonResponse::ResumeResult {
+                                        name,
+                                        success: result.is_ok(),
+                                        err_message: result.err().map(|e| e.to_string()),
+                                    })
+                                    .await;
                             }
                         }
 
@@ -155,14 +375,14 @@ impl Acceptable for Arc<State> {
                                     let status = client.get_status();
                                     let info = client.get_info().ok().flatten().map(Box::new);
                                     let status = match status {
-                                        Status::Connected => "Connected",
-                                        Status::Connecting(_) => "Connecting",
-                                        Status::Disconnected => "Disconnected",
-                                        Status::Disconnecting => "Disconnecting",
-                                        Status::Error(_) => "Error",
-                                        Status::Initialized => "Initialized",
-                                    }
-                                    .to_string();
+                                        Status::Connected => "Connected".to_string(),
+                                        Status::Connecting(_) => "Connecting".to_string(),
+                                        Status::Disconnected => "Disconnected".to_string(),
+                                        Status::Disconnecting => "Disconnecting".to_string(),
+                                        Status::Paused => "Paused".to_string(),
+                                        Status::Error(e) => format!("Error: {}", e),
+                                        Status::Initialized => "Initialized".to_string(),
+                                    };
 
                                     // ignore send error
                                     let _ = framed_writer
@@ -184,16 +404,23 @@ impl Acceptable for Arc<State> {
     }
 }
 
-pub async fn start_daemon() -> anyhow::Result<()> {
+pub async fn start_daemon(journal: PathBuf) -> anyhow::Result<()> {
     let server = UnixDomainServer::bind()?;
+    serve(State::new(server, journal, None)).await
+}
+
+/// Accept commands until the connection is stopped or the daemon is terminated by a signal
+async fn serve(state: Arc<State>) -> anyhow::Result<()> {
     let mut sigterm = signal(SignalKind::terminate())?;
     let mut sigint = signal(SignalKind::interrupt())?;
     let mut sigquit = signal(SignalKind::quit())?;
-    let state = State::new(server);
 
     loop {
-        let state = state.clone();
+        let accepting = state.clone();
         select! {
+            _ = state.shutdown.notified() => {
+                break;
+            }
             _ = sigquit.recv() => {
                 break;
             }
@@ -203,7 +430,7 @@ pub async fn start_daemon() -> anyhow::Result<()> {
             _ = sigterm.recv() => {
                 break;
             }
-            _ = state.try_accept() => {
+            _ = accepting.try_accept() => {
                 // noop
             }
         };
@@ -211,3 +438,111 @@ pub async fn start_daemon() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Start a connection through the socket of the daemon and stop it again, against a local gateway
+#[test]
+fn test_daemon() {
+    use crate::sock::UnixDomainClient;
+    use openconnect_core::config::EntrypointBuilder;
+    use openconnect_mock::{GatewayEvent, MockGatewayBuilder};
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    let gateway = MockGatewayBuilder::new()
+        .user("alice", "hunter2")
+        .start()
+        .unwrap();
+    let dir = std::env::temp_dir().join(format!("openconnect-rs-daemon-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let sock = dir.join("daemon.sock");
+
+    // the CLI obtains the cookie before it hands it to the daemon
+    let pin = gateway.pin_sha256().to_string();
+    let cookie = VpnClient::new(
+        ConfigBuilder::default().build().unwrap(),
+        EventHandlers::default()
+            .with_handle_peer_cert_invalid(move |fingerprint| fingerprint == pin),
+    )
+    .unwrap()
+    .connect_for_cookie(
+        EntrypointBuilder::new()
+            .server(&gateway.url())
+            .username("alice")
+            .password("hunter2")
+            .enable_udp(false)
+            .build()
+            .unwrap(),
+    )
+    .unwrap()
+    .unwrap();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let server = UnixDomainServer::bind_at(&sock).unwrap();
+        let daemon = tokio::spawn(serve(State::new(
+            server,
+            dir.join("timeline.jsonl"),
+            Some(r#"exec cat <&"$VPNFD" >/dev/null"#.to_string()),
+        )));
+
+        let mut client = UnixDomainClient::connect_at(&sock).await.unwrap();
+        client
+            .send(JsonRequest::Start {
+                name: "mock".to_string(),
+                server: gateway.url(),
+                allow_insecure: true,
+                cookie: cookie.clone(),
+                dpd: None,
+                keepalive: None,
+                tunnel: None,
+                dns: None,
+                proxy: None,
+                address: AddressOverride::default(),
+                reauth: None,
+                config_file: dir.join("config.json"),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            client.framed_reader.try_next().await.unwrap(),
+            Some(JsonResponse::StartResult { success: true, .. })
+        ));
+
+        client.send(JsonRequest::Info).await.unwrap();
+        match client.framed_reader.try_next().await.unwrap() {
+            Some(JsonResponse::InfoResult {
+                server_name,
+                status,
+                ..
+            }) => {
+                assert_eq!(server_name, "mock");
+                assert_eq!(status, "Connected");
+            }
+            _ => panic!("expected the info of the connection"),
+        }
+
+        client.send(JsonRequest::Stop).await.unwrap();
+        assert!(matches!(
+            client.framed_reader.try_next().await.unwrap(),
+            Some(JsonResponse::StopResult { name }) if name == "mock"
+        ));
+
+        // the daemon exits once the connection is stopped
+        tokio::time::timeout(TIMEOUT, daemon)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    });
+    // the connection task keeps the socket until the runtime is shut down
+    drop(runtime);
+
+    assert!(gateway
+        .events()
+        .contains(&GatewayEvent::TunnelConnected { cookie }));
+    assert!(gateway
+        .wait_for_event(TIMEOUT, |event| *event == GatewayEvent::ClientDisconnected)
+        .is_some());
+    assert!(!sock.exists());
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -58,22 +58,30 @@ pub fn get_framed_reader<T: Sized>(read_half: OwnedReadHalf) -> FramedReader<T>
 
 pub struct UnixDomainServer {
     pub listener: UnixListener,
+    path: PathBuf,
 }
 
 impl UnixDomainServer {
     pub fn bind() -> Result<Self, SockError> {
-        let listener = UnixListener::bind(get_sock())?;
+        Self::bind_at(&get_sock())
+    }
+
+    pub fn bind_at(path: &Path) -> Result<Self, SockError> {
+        let listener = UnixListener::bind(path)?;
         let listener = listener.into_std()?;
         listener.set_nonblocking(true)?;
         let listener = UnixListener::from_std(listener)?;
-        Ok(UnixDomainServer { listener })
+        Ok(UnixDomainServer {
+            listener,
+            path: path.to_path_buf(),
+        })
     }
 }
 
 impl Drop for UnixDomainServer {
     fn drop(&mut self) {
         // There's no way to return a useful error here
-        std::fs::remove_file(get_sock()).expect("Failed to remove socket file");
+        std::fs::remove_file(&self.path).expect("Failed to remove socket file");
     }
 }
 
@@ -84,7 +92,10 @@ pub struct UnixDomainClient {
 
 impl UnixDomainClient {
     pub async fn connect() -> Result<Self, SockError> {
-        let sock = get_sock();
+        Self::connect_at(&get_sock()).await
+    }
+
+    pub async fn connect_at(sock: &Path) -> Result<Self, SockError> {
         if !sock.exists() {
             return Err(SockError::NoValidConnection);
         }
@@ -10,7 +10,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenvy::from_path(".env.local").unwrap();
     env::set_var("OPENSSL_CONF", "/dev/null");
 
-    let protocol = get_anyconnect_protocol();
+    let protocol = get_anyconnect_protocol()?;
 
     let config = ConfigBuilder::default().loglevel(LogLevel::Info).build()?;
 
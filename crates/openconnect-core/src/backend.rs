@@ -0,0 +1,427 @@
+use crate::{
+    browser, cert::PeerCerts, config::CompressionMode, form::FormManager, ip_info::IpInfo,
+    log::Logger, result::OpenconnectError, result::OpenconnectResult, stats, webview, VpnClient,
+};
+use openconnect_sys::*;
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_void},
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+#[cfg(all(test, unix))]
+pub(crate) mod fake;
+
+/// The libopenconnect operations used by [VpnClient]
+///
+/// Return codes follow libopenconnect, 0 on success and a negative errno otherwise. Callbacks of libopenconnect are
+/// invoked with the privdata passed to [Backend::attach], which points to the owning [VpnClient]
+pub(crate) trait Backend: Send + Sync {
+    /// Create the underlying instance, must be called once before any other operation
+    fn attach(&self, privdata: *mut c_void) -> OpenconnectResult<()>;
+
+    /// Offer single sign-on in an external browser to the server
+    fn set_external_browser_handler(&self);
+
+    /// Offer single sign-on in an embedded login page to the server
+    fn set_webview_handler(&self);
+
+    fn set_loglevel(&self, level: i32);
+    fn set_protocol(&self, protocol: &CStr) -> i32;
+    fn set_dpd(&self, min_seconds: i32);
+    fn get_auth_expiration(&self) -> time_t;
+    fn get_idle_timeout(&self) -> i32;
+    fn set_stats_handler(&self);
+    fn set_setup_tun_handler(&self);
+    fn setup_tun_device(&self, vpnc_script: &CStr, ifname: Option<&CStr>) -> i32;
+    fn setup_tun_script(&self, tun_script: &CStr) -> i32;
+    fn set_reported_os(&self, os: &CStr) -> i32;
+
+    /// Run the authentication, form and certificate callbacks are invoked until a cookie is obtained
+    fn obtain_cookie(&self) -> i32;
+    fn set_cookie(&self, cookie: &CStr) -> i32;
+    fn clear_cookie(&self);
+    fn get_cookie(&self) -> Option<String>;
+
+    /// Create the command pipe, returns the file descriptor commands are written to
+    fn setup_cmd_pipe(&self) -> i32;
+    fn reset_ssl(&self);
+    fn make_cstp_connection(&self) -> i32;
+
+    /// Run the tunnel until it is cancelled, paused or lost
+    fn mainloop(&self, reconnect_timeout: i32, reconnect_interval: i32) -> i32;
+    fn get_dtls_cipher(&self) -> Option<String>;
+    fn get_peer_cert_hash(&self) -> String;
+    fn check_peer_cert_hash(&self, fingerprint: &CStr) -> i32;
+
+    /// Human readable details of the peer certificate, `None` before the TLS handshake
+    fn get_peer_cert_details(&self) -> Option<String>;
+
+    /// The DER encoded certificates presented by the server, the peer certificate first
+    fn get_peer_cert_chain(&self) -> Vec<Vec<u8>>;
+
+    fn disable_dtls(&self) -> i32;
+    fn set_reqmtu(&self, mtu: i32);
+    fn disable_ipv6(&self) -> i32;
+    fn set_compression_mode(&self, mode: CompressionMode) -> i32;
+    fn set_pfs(&self, enable: bool);
+    fn set_allow_insecure_crypto(&self, allow: bool) -> i32;
+    fn set_pass_tos(&self, enable: bool);
+    fn set_csd_environ(&self, name: &CStr, value: &CStr) -> i32;
+    fn setup_csd(&self, uid: u32, silent: bool, wrapper: &CStr) -> i32;
+    fn set_trojan_interval(&self, seconds: i32);
+    fn set_http_proxy(&self, proxy: &CStr) -> i32;
+    fn set_proxy_auth(&self, methods: &CStr) -> i32;
+    fn parse_url(&self, url: &CStr) -> i32;
+    fn set_hostname(&self, hostname: &CStr) -> i32;
+    fn set_sni(&self, sni: &CStr) -> i32;
+
+    /// Resolve host names through [crate::resolve::Resolver] of the entrypoint
+    fn set_resolver_handler(&self);
+    fn get_hostname(&self) -> Option<String>;
+    fn get_port(&self) -> i32;
+    fn get_ifname(&self) -> Option<String>;
+    fn set_client_cert(&self, cert: &CStr, sslkey: &CStr) -> i32;
+    fn set_mca_cert(&self, cert: &CStr, key: &CStr) -> i32;
+    fn get_ip_info(&self) -> Result<Option<IpInfo>, i32>;
+
+    /// Fill in an option of the form passed to the form callback
+    ///
+    /// SAFETY: `opt` must point to an option of the form which is being processed
+    unsafe fn set_option_value(&self, opt: *mut oc_form_opt, value: &CStr) -> i32;
+}
+
+/// [Backend] on top of libopenconnect
+pub(crate) struct FfiBackend {
+    vpninfo: AtomicPtr<openconnect_info>,
+    useragent: CString,
+}
+
+impl FfiBackend {
+    pub(crate) fn new() -> OpenconnectResult<Self> {
+        let useragent = CString::new("AnyConnect-compatible OpenConnect VPN Agent")
+            .map_err(|_| OpenconnectError::OtherError("useragent is not valid".to_string()))?;
+
+        let ret = unsafe { openconnect_init_ssl() };
+        if ret != 0 {
+            return Err(OpenconnectError::InitSslError(ret));
+        }
+
+        Ok(Self {
+            vpninfo: AtomicPtr::new(std::ptr::null_mut()),
+            useragent,
+        })
+    }
+
+    fn vpninfo(&self) -> *mut openconnect_info {
+        self.vpninfo.load(Ordering::SeqCst)
+    }
+}
+
+/// Copy a string owned by libopenconnect, `None` for a null pointer
+unsafe fn to_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    CStr::from_ptr(ptr).to_str().map(|s| s.to_string()).ok()
+}
+
+unsafe impl Send for FfiBackend {}
+unsafe impl Sync for FfiBackend {}
+
+impl Backend for FfiBackend {
+    fn attach(&self, privdata: *mut c_void) -> OpenconnectResult<()> {
+        // format args on C side, logs are routed to each client by privdata
+        Logger::set_progress_handler();
+
+        let vpninfo = unsafe {
+            openconnect_vpninfo_new(
+                self.useragent.as_ptr(),
+                Some(PeerCerts::validate_peer_cert),
+                None,
+                Some(FormManager::process_auth_form_cb),
+                Some(helper_format_vargs), // format args on C side
+                privdata,
+            )
+        };
+
+        if vpninfo.is_null() {
+            return Err(OpenconnectError::CreateVpnInfoError);
+        }
+
+        self.vpninfo.store(vpninfo, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn set_external_browser_handler(&self) {
+        unsafe {
+            openconnect_set_external_browser_callback(
+                self.vpninfo(),
+                Some(browser::open_external_browser_cb),
+            );
+        }
+    }
+
+    fn set_webview_handler(&self) {
+        unsafe {
+            openconnect_set_webview_callback(self.vpninfo(), Some(webview::open_webview_cb));
+        }
+    }
+
+    fn set_loglevel(&self, level: i32) {
+        unsafe { openconnect_set_loglevel(self.vpninfo(), level) }
+    }
+
+    fn set_protocol(&self, protocol: &CStr) -> i32 {
+        unsafe { openconnect_set_protocol(self.vpninfo(), protocol.as_ptr()) }
+    }
+
+    fn set_dpd(&self, min_seconds: i32) {
+        unsafe { openconnect_set_dpd(self.vpninfo(), min_seconds) }
+    }
+
+    fn get_auth_expiration(&self) -> time_t {
+        unsafe { openconnect_get_auth_expiration(self.vpninfo()) }
+    }
+
+    fn get_idle_timeout(&self) -> i32 {
+        unsafe { openconnect_get_idle_timeout(self.vpninfo()) }
+    }
+
+    fn set_stats_handler(&self) {
+        unsafe { openconnect_set_stats_handler(self.vpninfo(), Some(stats::stats_fn)) }
+    }
+
+    fn set_setup_tun_handler(&self) {
+        unsafe {
+            openconnect_set_setup_tun_handler(
+                self.vpninfo(),
+                Some(VpnClient::default_setup_tun_vfn),
+            )
+        }
+    }
+
+    fn setup_tun_device(&self, vpnc_script: &CStr, ifname: Option<&CStr>) -> i32 {
+        unsafe {
+            openconnect_setup_tun_device(
+                self.vpninfo(),
+                vpnc_script.as_ptr(),
+                ifname.map_or_else(std::ptr::null, |s| s.as_ptr()),
+            )
+        }
+    }
+
+    fn setup_tun_script(&self, tun_script: &CStr) -> i32 {
+        unsafe { openconnect_setup_tun_script(self.vpninfo(), tun_script.as_ptr()) }
+    }
+
+    fn set_reported_os(&self, os: &CStr) -> i32 {
+        unsafe { openconnect_set_reported_os(self.vpninfo(), os.as_ptr()) }
+    }
+
+    fn obtain_cookie(&self) -> i32 {
+        unsafe { openconnect_obtain_cookie(self.vpninfo()) }
+    }
+
+    fn set_cookie(&self, cookie: &CStr) -> i32 {
+        unsafe { openconnect_set_cookie(self.vpninfo(), cookie.as_ptr()) }
+    }
+
+    fn clear_cookie(&self) {
+        unsafe { openconnect_clear_cookie(self.vpninfo()) }
+    }
+
+    fn get_cookie(&self) -> Option<String> {
+        unsafe { to_string(openconnect_get_cookie(self.vpninfo())) }
+    }
+
+    fn setup_cmd_pipe(&self) -> i32 {
+        unsafe { openconnect_setup_cmd_pipe(self.vpninfo()) }
+    }
+
+    fn reset_ssl(&self) {
+        unsafe { openconnect_reset_ssl(self.vpninfo()) }
+    }
+
+    fn make_cstp_connection(&self) -> i32 {
+        unsafe { openconnect_make_cstp_connection(self.vpninfo()) }
+    }
+
+    fn mainloop(&self, reconnect_timeout: i32, reconnect_interval: i32) -> i32 {
+        unsafe { openconnect_mainloop(self.vpninfo(), reconnect_timeout, reconnect_interval) }
+    }
+
+    fn get_dtls_cipher(&self) -> Option<String> {
+        unsafe {
+            let cipher = openconnect_get_dtls_cipher(self.vpninfo());
+            (!cipher.is_null()).then(|| CStr::from_ptr(cipher).to_string_lossy().to_string())
+        }
+    }
+
+    fn get_peer_cert_hash(&self) -> String {
+        // SAFETY: we should not use CString::from_raw(peer_fingerprint)
+        // because peer_fingerprint will be deallocated in rust and cause a double free
+        unsafe { CStr::from_ptr(openconnect_get_peer_cert_hash(self.vpninfo())) }
+            .to_string_lossy()
+            .to_string()
+    }
+
+    fn check_peer_cert_hash(&self, fingerprint: &CStr) -> i32 {
+        unsafe { openconnect_check_peer_cert_hash(self.vpninfo(), fingerprint.as_ptr()) }
+    }
+
+    fn get_peer_cert_details(&self) -> Option<String> {
+        unsafe {
+            let details = openconnect_get_peer_cert_details(self.vpninfo());
+            let result = to_string(details);
+            if !details.is_null() {
+                openconnect_free_cert_info(self.vpninfo(), details.cast());
+            }
+            result
+        }
+    }
+
+    fn get_peer_cert_chain(&self) -> Vec<Vec<u8>> {
+        unsafe {
+            let mut chain = std::ptr::null_mut::<oc_cert>();
+            let count = openconnect_get_peer_cert_chain(self.vpninfo(), &mut chain);
+            if count <= 0 || chain.is_null() {
+                return vec![];
+            }
+
+            let certs = std::slice::from_raw_parts(chain, count as usize)
+                .iter()
+                .filter(|cert| !cert.der_data.is_null() && cert.der_len > 0)
+                .map(|cert| {
+                    std::slice::from_raw_parts(cert.der_data, cert.der_len as usize).to_vec()
+                })
+                .collect();
+            openconnect_free_peer_cert_chain(self.vpninfo(), chain);
+            certs
+        }
+    }
+
+    fn disable_dtls(&self) -> i32 {
+        unsafe { openconnect_disable_dtls(self.vpninfo()) }
+    }
+
+    fn set_reqmtu(&self, mtu: i32) {
+        unsafe { openconnect_set_reqmtu(self.vpninfo(), mtu) }
+    }
+
+    fn disable_ipv6(&self) -> i32 {
+        unsafe { openconnect_disable_ipv6(self.vpninfo()) }
+    }
+
+    fn set_compression_mode(&self, mode: CompressionMode) -> i32 {
+        unsafe { openconnect_set_compression_mode(self.vpninfo(), mode.into()) }
+    }
+
+    fn set_pfs(&self, enable: bool) {
+        unsafe { openconnect_set_pfs(self.vpninfo(), enable as u32) }
+    }
+
+    fn set_allow_insecure_crypto(&self, allow: bool) -> i32 {
+        unsafe { openconnect_set_allow_insecure_crypto(self.vpninfo(), allow as u32) }
+    }
+
+    fn set_pass_tos(&self, enable: bool) {
+        unsafe { openconnect_set_pass_tos(self.vpninfo(), enable as i32) }
+    }
+
+    fn set_csd_environ(&self, name: &CStr, value: &CStr) -> i32 {
+        unsafe { openconnect_set_csd_environ(self.vpninfo(), name.as_ptr(), value.as_ptr()) }
+    }
+
+    fn setup_csd(&self, uid: u32, silent: bool, wrapper: &CStr) -> i32 {
+        unsafe { openconnect_setup_csd(self.vpninfo(), uid, silent as i32, wrapper.as_ptr()) }
+    }
+
+    fn set_trojan_interval(&self, seconds: i32) {
+        unsafe { openconnect_set_trojan_interval(self.vpninfo(), seconds) }
+    }
+
+    fn set_http_proxy(&self, proxy: &CStr) -> i32 {
+        unsafe { openconnect_set_http_proxy(self.vpninfo(), proxy.as_ptr()) }
+    }
+
+    fn set_proxy_auth(&self, methods: &CStr) -> i32 {
+        unsafe { openconnect_set_proxy_auth(self.vpninfo(), methods.as_ptr()) }
+    }
+
+    fn parse_url(&self, url: &CStr) -> i32 {
+        unsafe { openconnect_parse_url(self.vpninfo(), url.as_ptr()) }
+    }
+
+    fn set_hostname(&self, hostname: &CStr) -> i32 {
+        unsafe { openconnect_set_hostname(self.vpninfo(), hostname.as_ptr()) }
+    }
+
+    fn set_sni(&self, sni: &CStr) -> i32 {
+        unsafe { openconnect_set_sni(self.vpninfo(), sni.as_ptr()) }
+    }
+
+    fn set_resolver_handler(&self) {
+        #[cfg(unix)]
+        unsafe {
+            openconnect_override_getaddrinfo(self.vpninfo(), Some(crate::resolve::getaddrinfo_cb));
+        }
+
+        #[cfg(not(unix))]
+        tracing::warn!(
+            "Pinned addresses are not supported on this platform, the system resolver is used"
+        );
+    }
+
+    fn get_hostname(&self) -> Option<String> {
+        unsafe { to_string(openconnect_get_hostname(self.vpninfo())) }
+    }
+
+    fn get_port(&self) -> i32 {
+        unsafe { openconnect_get_port(self.vpninfo()) }
+    }
+
+    fn get_ifname(&self) -> Option<String> {
+        unsafe { to_string(openconnect_get_ifname(self.vpninfo())) }
+    }
+
+    fn set_client_cert(&self, cert: &CStr, sslkey: &CStr) -> i32 {
+        unsafe { openconnect_set_client_cert(self.vpninfo(), cert.as_ptr(), sslkey.as_ptr()) }
+    }
+
+    fn set_mca_cert(&self, cert: &CStr, key: &CStr) -> i32 {
+        unsafe { openconnect_set_mca_cert(self.vpninfo(), cert.as_ptr(), key.as_ptr()) }
+    }
+
+    fn get_ip_info(&self) -> Result<Option<IpInfo>, i32> {
+        unsafe {
+            let mut info: *const oc_ip_info = std::ptr::null();
+            let ret = openconnect_get_ip_info(
+                self.vpninfo(),
+                &mut info,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            );
+
+            match ret {
+                0 => Ok(info.as_ref().map(IpInfo::from)),
+                _ => Err(ret),
+            }
+        }
+    }
+
+    unsafe fn set_option_value(&self, opt: *mut oc_form_opt, value: &CStr) -> i32 {
+        openconnect_set_option_value(opt, value.as_ptr())
+    }
+}
+
+impl Drop for FfiBackend {
+    fn drop(&mut self) {
+        let vpninfo = self.vpninfo();
+        if !vpninfo.is_null() {
+            unsafe {
+                openconnect_vpninfo_free(vpninfo);
+            }
+        }
+    }
+}
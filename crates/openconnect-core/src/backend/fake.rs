@@ -0,0 +1,1437 @@
+//! An in-memory [Backend] playing a scripted server, so that [VpnClient] is tested without libopenconnect
+
+use super::Backend;
+use crate::{
+    browser::open_external_browser_cb,
+    cert::PeerCerts,
+    config::{
+        CompressionMode, ConfigBuilder, Entrypoint, EntrypointBuilder, LogLevel, ReconnectSettings,
+        TunnelOptions,
+    },
+    events::{DisconnectReason, EventHandlers},
+    form::FormManager,
+    ip_info::IpInfo,
+    log::Logger,
+    probe::{FieldKind, SsoKind},
+    protocols::Protocol,
+    result::{OpenconnectError, OpenconnectResult},
+    Connectable, Status, VpnClient,
+};
+use openconnect_sys::{
+    oc_auth_form, oc_choice, oc_form_opt, oc_form_opt_select, time_t, OC_CMD_CANCEL, OC_CMD_DETACH,
+    OC_CMD_PAUSE, OC_FORM_OPT_PASSWORD, OC_FORM_OPT_SELECT, OC_FORM_OPT_TEXT,
+    OC_FORM_RESULT_NEWGROUP, OC_FORM_RESULT_OK,
+};
+use std::{
+    collections::VecDeque,
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_void},
+    sync::{
+        atomic::{AtomicPtr, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+/// What happens while a cookie is obtained, in order
+#[derive(Debug, Clone)]
+pub(crate) enum AuthStep {
+    /// Present a certificate which is not trusted, the certificate callback decides
+    PeerCert(String),
+
+    /// Ask for the form through the form callback
+    Form(FakeForm),
+
+    /// Write a progress line through the progress callback
+    Log(LogLevel, String),
+
+    /// Fail with this code
+    Fail(i32),
+
+    /// Send the user to this single sign-on page through the browser callback
+    Browser(String),
+}
+
+/// What the main loop does, in order, it waits for a command once the steps are used up
+#[derive(Debug, Clone)]
+pub(crate) enum MainLoopStep {
+    /// Return this code at once
+    Return(i32),
+
+    /// Write a progress line through the progress callback, then go on with the next step
+    Log(LogLevel, String),
+
+    /// Block until a command is read from the command pipe, cancel returns `-EINTR` and pause returns 0
+    UntilCommand,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct FakeForm {
+    auth_id: String,
+    error: Option<String>,
+    /// The type, the name and the choices of a select field
+    opts: Vec<(u32, String, Vec<String>)>,
+    groups: Vec<String>,
+}
+
+impl FakeForm {
+    pub(crate) fn new(auth_id: &str) -> Self {
+        Self {
+            auth_id: auth_id.to_string(),
+            error: None,
+            opts: vec![],
+            groups: vec![],
+        }
+    }
+
+    /// The login form of most gateways
+    pub(crate) fn login() -> Self {
+        Self::new("main").text("username").password("password")
+    }
+
+    pub(crate) fn text(mut self, name: &str) -> Self {
+        self.opts.push((OC_FORM_OPT_TEXT, name.to_string(), vec![]));
+        self
+    }
+
+    pub(crate) fn password(mut self, name: &str) -> Self {
+        self.opts
+            .push((OC_FORM_OPT_PASSWORD, name.to_string(), vec![]));
+        self
+    }
+
+    pub(crate) fn select(mut self, name: &str, choices: &[&str]) -> Self {
+        let choices = choices.iter().map(|choice| choice.to_string()).collect();
+        self.opts
+            .push((OC_FORM_OPT_SELECT, name.to_string(), choices));
+        self
+    }
+
+    /// Offer these auth groups in a select field before the other fields, the first one is selected
+    pub(crate) fn groups(mut self, groups: &[&str]) -> Self {
+        self.groups = groups.iter().map(|group| group.to_string()).collect();
+        self
+    }
+
+    pub(crate) fn error(mut self, error: &str) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+/// A value filled in by the form callback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Submitted {
+    pub(crate) auth_id: String,
+    pub(crate) name: String,
+    pub(crate) value: String,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    auth: VecDeque<AuthStep>,
+    main_loop: VecDeque<MainLoopStep>,
+    issued_cookie: Option<String>,
+    rejected_cookies: Vec<String>,
+    cookie: Option<String>,
+    peer_cert: String,
+    peer_cert_chain: Vec<Vec<u8>>,
+    hostname: Option<String>,
+    port: i32,
+    idle_timeout: i32,
+    submitted: Vec<Submitted>,
+    calls: Vec<String>,
+    /// Values set through [Backend::set_option_value], kept alive while the form is read
+    option_values: Vec<CString>,
+    pipe: Option<(i32, i32)>,
+}
+
+/// Clones share the script and the record, so the test keeps one while the client owns the other
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FakeBackend {
+    privdata: Arc<AtomicPtr<c_void>>,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeBackend {
+    pub(crate) fn new() -> Self {
+        let backend = Self::default();
+        backend.issue_cookie("fake-cookie");
+        backend
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record(&self, call: impl Into<String>) {
+        self.state().calls.push(call.into());
+    }
+
+    pub(crate) fn auth_step(&self, step: AuthStep) -> &Self {
+        self.state().auth.push_back(step);
+        self
+    }
+
+    pub(crate) fn main_loop_step(&self, step: MainLoopStep) -> &Self {
+        self.state().main_loop.push_back(step);
+        self
+    }
+
+    /// The cookie set once the auth steps passed
+    pub(crate) fn issue_cookie(&self, cookie: &str) -> &Self {
+        self.state().issued_cookie = Some(cookie.to_string());
+        self
+    }
+
+    /// Fail the CSTP connection with this cookie, like a server which does not know it
+    pub(crate) fn reject_cookie(&self, cookie: &str) -> &Self {
+        self.state().rejected_cookies.push(cookie.to_string());
+        self
+    }
+
+    /// The DER encoded certificates presented with the peer certificate
+    pub(crate) fn peer_cert_chain(&self, chain: Vec<Vec<u8>>) -> &Self {
+        self.state().peer_cert_chain = chain;
+        self
+    }
+
+    pub(crate) fn idle_timeout(&self, idle_timeout: i32) -> &Self {
+        self.state().idle_timeout = idle_timeout;
+        self
+    }
+
+    /// The operations called so far, with their arguments
+    pub(crate) fn calls(&self) -> Vec<String> {
+        self.state().calls.clone()
+    }
+
+    pub(crate) fn submitted(&self) -> Vec<Submitted> {
+        self.state().submitted.clone()
+    }
+
+    fn privdata(&self) -> *mut c_void {
+        self.privdata.load(Ordering::SeqCst)
+    }
+
+    fn progress(&self, level: LogLevel, message: &str) {
+        let Ok(message) = CString::new(message) else {
+            return;
+        };
+        unsafe { Logger::raw_handle_process_log(self.privdata(), level as i32, message.as_ptr()) };
+    }
+
+    fn validate_peer_cert(&self, fingerprint: &str) -> i32 {
+        self.state().peer_cert = fingerprint.to_string();
+        let reason = CString::new("certificate is not trusted").unwrap_or_default();
+        let ret = PeerCerts::validate_peer_cert(self.privdata(), reason.as_ptr());
+        if ret != 0 {
+            self.progress(LogLevel::Err, "Server certificate verify failed");
+            return -libc::EINVAL;
+        }
+        0
+    }
+
+    /// Lay out the form like libopenconnect, pass it to the form callback and read back the values
+    fn process_form(&self, form: &FakeForm) -> i32 {
+        let to_c = |s: &str| CString::new(s).unwrap_or_default();
+        let auth_id = to_c(&form.auth_id);
+        let error = form.error.as_deref().map(to_c);
+
+        // the auth group select comes first, like in the forms of libopenconnect
+        let mut fields = form.opts.clone();
+        if !form.groups.is_empty() {
+            fields.insert(
+                0,
+                (
+                    OC_FORM_OPT_SELECT,
+                    "group_list".to_string(),
+                    form.groups.clone(),
+                ),
+            );
+        }
+
+        let names = fields
+            .iter()
+            .map(|(_, name, _)| to_c(name))
+            .collect::<Vec<_>>();
+        let choice_names = fields
+            .iter()
+            .map(|(_, _, choices)| choices.iter().map(|c| to_c(c)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let mut choices = choice_names
+            .iter()
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| oc_choice {
+                        name: name.as_ptr() as *mut c_char,
+                        label: name.as_ptr() as *mut c_char,
+                        auth_type: std::ptr::null_mut(),
+                        override_name: std::ptr::null_mut(),
+                        override_label: std::ptr::null_mut(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let mut choice_ptrs = choices
+            .iter_mut()
+            .map(|choices| {
+                choices
+                    .iter_mut()
+                    .map(|choice| choice as *mut oc_choice)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // every field is laid out as a select, which starts with the plain field
+        let mut opts = fields
+            .iter()
+            .zip(&names)
+            .zip(choice_ptrs.iter_mut())
+            .map(|(((type_, _, _), name), choices)| oc_form_opt_select {
+                form: oc_form_opt {
+                    next: std::ptr::null_mut(),
+                    type_: *type_ as i32,
+                    name: name.as_ptr() as *mut c_char,
+                    label: name.as_ptr() as *mut c_char,
+                    _value: std::ptr::null_mut(),
+                    flags: 0,
+                    reserved: std::ptr::null_mut(),
+                },
+                nr_choices: choices.len() as i32,
+                choices: choices.as_mut_ptr(),
+            })
+            .collect::<Vec<_>>();
+        for i in 1..opts.len() {
+            let next = &mut opts[i] as *mut oc_form_opt_select as *mut oc_form_opt;
+            opts[i - 1].form.next = next;
+        }
+
+        let mut auth_form = oc_auth_form {
+            banner: std::ptr::null_mut(),
+            message: std::ptr::null_mut(),
+            error: error
+                .as_ref()
+                .map_or(std::ptr::null_mut(), |error| error.as_ptr() as *mut c_char),
+            auth_id: auth_id.as_ptr() as *mut c_char,
+            method: std::ptr::null_mut(),
+            action: std::ptr::null_mut(),
+            opts: opts.first_mut().map_or(std::ptr::null_mut(), |opt| {
+                opt as *mut oc_form_opt_select as *mut oc_form_opt
+            }),
+            authgroup_opt: match form.groups.is_empty() {
+                true => std::ptr::null_mut(),
+                false => &mut opts[0] as *mut oc_form_opt_select,
+            },
+            authgroup_selection: 0,
+        };
+
+        let ret = FormManager::process_auth_form_cb(self.privdata(), &mut auth_form);
+
+        let mut state = self.state();
+        for (opt, (_, name, _)) in opts.iter().zip(&fields) {
+            if opt.form._value.is_null() {
+                continue;
+            }
+            let value = unsafe { CStr::from_ptr(opt.form._value) }
+                .to_string_lossy()
+                .to_string();
+            state.submitted.push(Submitted {
+                auth_id: form.auth_id.clone(),
+                name: name.clone(),
+                value,
+            });
+        }
+        state.option_values.clear();
+        ret
+    }
+
+    fn wait_for_command(&self) -> i32 {
+        let Some((read_fd, _)) = self.state().pipe else {
+            return -libc::EINVAL;
+        };
+
+        let mut paused = false;
+        loop {
+            // libopenconnect only flags a pause, so commands written before it returns are merged into the pause
+            if paused && !Self::readable(read_fd) {
+                return 0;
+            }
+
+            let mut cmd = 0u8;
+            let ret = unsafe { libc::read(read_fd, &mut cmd as *mut u8 as *mut c_void, 1) };
+            if ret <= 0 {
+                return -libc::EPIPE;
+            }
+            match cmd {
+                OC_CMD_CANCEL => return -libc::EINTR,
+                OC_CMD_PAUSE => paused = true,
+                OC_CMD_DETACH => return -libc::ECONNABORTED,
+                _ => {}
+            }
+        }
+    }
+
+    fn readable(fd: i32) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pollfd, 1, 0) > 0 }
+    }
+}
+
+impl Backend for FakeBackend {
+    fn attach(&self, privdata: *mut c_void) -> OpenconnectResult<()> {
+        self.privdata.store(privdata, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn set_external_browser_handler(&self) {
+        self.record("set_external_browser_handler");
+    }
+
+    fn set_webview_handler(&self) {
+        self.record("set_webview_handler");
+    }
+
+    fn set_loglevel(&self, _level: i32) {}
+
+    fn set_protocol(&self, protocol: &CStr) -> i32 {
+        self.record(format!("set_protocol {}", protocol.to_string_lossy()));
+        0
+    }
+
+    fn set_dpd(&self, min_seconds: i32) {
+        self.record(format!("set_dpd {}", min_seconds));
+    }
+
+    fn get_auth_expiration(&self) -> time_t {
+        0
+    }
+
+    fn get_idle_timeout(&self) -> i32 {
+        self.state().idle_timeout
+    }
+
+    fn set_stats_handler(&self) {}
+
+    fn set_setup_tun_handler(&self) {}
+
+    fn setup_tun_device(&self, vpnc_script: &CStr, _ifname: Option<&CStr>) -> i32 {
+        self.record(format!(
+            "setup_tun_device {}",
+            vpnc_script.to_string_lossy()
+        ));
+        0
+    }
+
+    fn setup_tun_script(&self, tun_script: &CStr) -> i32 {
+        self.record(format!("setup_tun_script {}", tun_script.to_string_lossy()));
+        0
+    }
+
+    fn set_reported_os(&self, _os: &CStr) -> i32 {
+        0
+    }
+
+    fn obtain_cookie(&self) -> i32 {
+        self.record("obtain_cookie");
+        loop {
+            let Some(step) = self.state().auth.pop_front() else {
+                break;
+            };
+
+            let ret = match step {
+                AuthStep::PeerCert(fingerprint) => self.validate_peer_cert(&fingerprint),
+                // libopenconnect asks for the form of the new group, which is the next step here
+                AuthStep::Form(ref form) => match self.process_form(form) {
+                    ret if ret == OC_FORM_RESULT_OK as i32 => 0,
+                    ret if ret == OC_FORM_RESULT_NEWGROUP as i32 => {
+                        self.record("new_group");
+                        0
+                    }
+                    ret => ret,
+                },
+                AuthStep::Log(level, message) => {
+                    self.progress(level, &message);
+                    0
+                }
+                AuthStep::Fail(ret) => ret,
+                AuthStep::Browser(uri) => {
+                    let uri = CString::new(uri).unwrap_or_default();
+                    open_external_browser_cb(std::ptr::null_mut(), uri.as_ptr(), self.privdata())
+                }
+            };
+            if ret != 0 {
+                return ret;
+            }
+        }
+
+        let mut state = self.state();
+        state.cookie = state.issued_cookie.clone();
+        0
+    }
+
+    fn set_cookie(&self, cookie: &CStr) -> i32 {
+        let cookie = cookie.to_string_lossy().to_string();
+        self.record(format!("set_cookie {}", cookie));
+        self.state().cookie = Some(cookie);
+        0
+    }
+
+    fn clear_cookie(&self) {
+        self.record("clear_cookie");
+        self.state().cookie = None;
+    }
+
+    fn get_cookie(&self) -> Option<String> {
+        self.state().cookie.clone()
+    }
+
+    fn setup_cmd_pipe(&self) -> i32 {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return -libc::EIO;
+        }
+        let mut state = self.state();
+        state.close_pipe();
+        state.pipe = Some((fds[0], fds[1]));
+        fds[1]
+    }
+
+    fn reset_ssl(&self) {}
+
+    fn make_cstp_connection(&self) -> i32 {
+        self.record("make_cstp_connection");
+        let (cookie, rejected) = {
+            let state = self.state();
+            let rejected = state
+                .cookie
+                .as_ref()
+                .is_some_and(|cookie| state.rejected_cookies.contains(cookie));
+            (state.cookie.clone(), rejected)
+        };
+
+        if cookie.is_none() || rejected {
+            self.progress(
+                LogLevel::Err,
+                "Got inappropriate HTTP CONNECT response: HTTP/1.1 401 Unauthorized",
+            );
+            return -libc::EPERM;
+        }
+        0
+    }
+
+    fn mainloop(&self, _reconnect_timeout: i32, _reconnect_interval: i32) -> i32 {
+        self.record("mainloop");
+        loop {
+            let step = self
+                .state()
+                .main_loop
+                .pop_front()
+                .unwrap_or(MainLoopStep::UntilCommand);
+
+            match step {
+                MainLoopStep::Return(ret) => return ret,
+                MainLoopStep::Log(level, message) => self.progress(level, &message),
+                MainLoopStep::UntilCommand => return self.wait_for_command(),
+            }
+        }
+    }
+
+    fn get_dtls_cipher(&self) -> Option<String> {
+        None
+    }
+
+    fn get_peer_cert_hash(&self) -> String {
+        self.state().peer_cert.clone()
+    }
+
+    fn check_peer_cert_hash(&self, fingerprint: &CStr) -> i32 {
+        if self.state().peer_cert == fingerprint.to_string_lossy() {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn get_peer_cert_details(&self) -> Option<String> {
+        let state = self.state();
+        (!state.peer_cert.is_empty()).then(|| format!("Fake certificate {}", state.peer_cert))
+    }
+
+    fn get_peer_cert_chain(&self) -> Vec<Vec<u8>> {
+        self.state().peer_cert_chain.clone()
+    }
+
+    fn disable_dtls(&self) -> i32 {
+        self.record("disable_dtls");
+        0
+    }
+
+    fn set_reqmtu(&self, mtu: i32) {
+        self.record(format!("set_reqmtu {}", mtu));
+    }
+
+    fn disable_ipv6(&self) -> i32 {
+        self.record("disable_ipv6");
+        0
+    }
+
+    fn set_compression_mode(&self, mode: CompressionMode) -> i32 {
+        self.record(format!("set_compression_mode {:?}", mode));
+        0
+    }
+
+    fn set_pfs(&self, _enable: bool) {}
+
+    fn set_allow_insecure_crypto(&self, allow: bool) -> i32 {
+        self.record(format!("set_allow_insecure_crypto {}", allow));
+        0
+    }
+
+    fn set_pass_tos(&self, _enable: bool) {}
+
+    fn set_csd_environ(&self, name: &CStr, value: &CStr) -> i32 {
+        self.record(format!(
+            "set_csd_environ {}={}",
+            name.to_string_lossy(),
+            value.to_string_lossy()
+        ));
+        0
+    }
+
+    fn setup_csd(&self, _uid: u32, _silent: bool, wrapper: &CStr) -> i32 {
+        self.record(format!("setup_csd {}", wrapper.to_string_lossy()));
+        0
+    }
+
+    fn set_trojan_interval(&self, seconds: i32) {
+        self.record(format!("set_trojan_interval {}", seconds));
+    }
+
+    fn set_http_proxy(&self, proxy: &CStr) -> i32 {
+        self.record(format!("set_http_proxy {}", proxy.to_string_lossy()));
+        0
+    }
+
+    fn set_proxy_auth(&self, methods: &CStr) -> i32 {
+        self.record(format!("set_proxy_auth {}", methods.to_string_lossy()));
+        0
+    }
+
+    fn parse_url(&self, url: &CStr) -> i32 {
+        let url = url.to_string_lossy().to_string();
+        self.record(format!("parse_url {}", url));
+
+        let authority = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+        let authority = authority.split('/').next().unwrap_or_default();
+        let (hostname, port) = match authority.rsplit_once(':') {
+            Some((hostname, port)) => (hostname, port.parse().unwrap_or(443)),
+            None => (authority, 443),
+        };
+        if hostname.is_empty() {
+            return -libc::EINVAL;
+        }
+
+        let mut state = self.state();
+        state.hostname = Some(hostname.to_string());
+        state.port = port;
+        0
+    }
+
+    fn set_hostname(&self, hostname: &CStr) -> i32 {
+        let hostname = hostname.to_string_lossy().to_string();
+        self.record(format!("set_hostname {}", hostname));
+        self.state().hostname = Some(hostname);
+        0
+    }
+
+    fn set_sni(&self, sni: &CStr) -> i32 {
+        self.record(format!("set_sni {}", sni.to_string_lossy()));
+        0
+    }
+
+    fn set_resolver_handler(&self) {
+        self.record("set_resolver_handler");
+    }
+
+    fn get_hostname(&self) -> Option<String> {
+        self.state().hostname.clone()
+    }
+
+    fn get_port(&self) -> i32 {
+        self.state().port
+    }
+
+    fn get_ifname(&self) -> Option<String> {
+        None
+    }
+
+    fn set_client_cert(&self, _cert: &CStr, _sslkey: &CStr) -> i32 {
+        0
+    }
+
+    fn set_mca_cert(&self, _cert: &CStr, _key: &CStr) -> i32 {
+        0
+    }
+
+    fn get_ip_info(&self) -> Result<Option<IpInfo>, i32> {
+        Ok(None)
+    }
+
+    unsafe fn set_option_value(&self, opt: *mut oc_form_opt, value: &CStr) -> i32 {
+        let value = value.to_owned();
+        (*opt)._value = value.as_ptr() as *mut c_char;
+        self.state().option_values.push(value);
+        0
+    }
+}
+
+impl State {
+    fn close_pipe(&mut self) {
+        if let Some((read_fd, write_fd)) = self.pipe.take() {
+            unsafe {
+                libc::close(read_fd);
+                libc::close(write_fd);
+            }
+        }
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        self.close_pipe();
+    }
+}
+
+const FINGERPRINT: &str = "pin-sha256:q7ZhCgJR1t6CnxNBCu+5P43Bhm/lRM1pv4S6MZ1AD8s=";
+
+type Statuses = Arc<Mutex<Vec<Status>>>;
+type Reason = Arc<Mutex<Option<DisconnectReason>>>;
+
+/// A client on top of the fake, with the handlers recording the statuses and the disconnect reason
+fn new_client(
+    backend: &FakeBackend,
+    handlers: EventHandlers,
+) -> (Arc<VpnClient>, Statuses, Reason) {
+    let statuses = Arc::new(Mutex::new(vec![]));
+    let reason = Arc::new(Mutex::new(None));
+    let handlers = handlers
+        .with_handle_connection_state_change({
+            let statuses = statuses.clone();
+            move |status| statuses.lock().unwrap().push(status)
+        })
+        .with_handle_disconnect({
+            let reason = reason.clone();
+            move |disconnect_reason| *reason.lock().unwrap() = Some(disconnect_reason)
+        });
+
+    let config = ConfigBuilder::default().build().unwrap();
+    let client = VpnClient::with_backend(config, handlers, Box::new(backend.clone())).unwrap();
+    (client, statuses, reason)
+}
+
+fn entrypoint() -> Entrypoint {
+    // the supported protocols are listed by libopenconnect
+    let protocol = Protocol {
+        name: "anyconnect".to_string(),
+        pretty_name: "Cisco AnyConnect or OpenConnect".to_string(),
+        description: "Compatible with Cisco AnyConnect SSL VPN, as well as ocserv".to_string(),
+        flags: 0,
+    };
+
+    EntrypointBuilder::new()
+        .name("fake")
+        .protocol(protocol)
+        .server("https://vpn.example.com/staff")
+        .username("alice")
+        .password("hunter2")
+        .build()
+        .unwrap()
+}
+
+fn wait_for_status(client: &VpnClient, status: Status) {
+    for _ in 0..200 {
+        if client.get_status() == status {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("expected {:?}, got {:?}", status, client.get_status());
+}
+
+fn names(statuses: &Mutex<Vec<Status>>) -> Vec<&'static str> {
+    let mut names = statuses
+        .lock()
+        .unwrap()
+        .iter()
+        .map(Status::as_str)
+        .collect::<Vec<_>>();
+    names.dedup();
+    names
+}
+
+#[test]
+fn test_connect_and_disconnect() {
+    let backend = FakeBackend::new();
+    backend.auth_step(AuthStep::Form(FakeForm::login()));
+    let (client, statuses, reason) = new_client(&backend, EventHandlers::default());
+
+    client.init_connection(entrypoint()).unwrap();
+    assert_eq!(client.get_status(), Status::Connected);
+    assert_eq!(client.get_cookie().as_deref(), Some("fake-cookie"));
+    assert_eq!(
+        backend
+            .submitted()
+            .iter()
+            .map(|submitted| (submitted.name.as_str(), submitted.value.as_str()))
+            .collect::<Vec<_>>(),
+        vec![("username", "alice"), ("password", "hunter2")]
+    );
+
+    let main_loop = {
+        let client = client.clone();
+        std::thread::spawn(move || client.run_loop())
+    };
+    client.disconnect();
+
+    assert!(main_loop.join().unwrap().is_ok());
+    assert_eq!(
+        names(&statuses),
+        vec![
+            "INITIALIZED",
+            "CONNECTING",
+            "CONNECTED",
+            "DISCONNECTING",
+            "DISCONNECTED"
+        ]
+    );
+    assert_eq!(*reason.lock().unwrap(), Some(DisconnectReason::Cancelled));
+    assert_eq!(
+        backend.calls()[..3],
+        [
+            "set_protocol anyconnect",
+            "parse_url https://vpn.example.com/staff",
+            "obtain_cookie"
+        ]
+    );
+}
+
+#[test]
+fn test_peer_cert() {
+    let backend = FakeBackend::new();
+    backend.auth_step(AuthStep::PeerCert(FINGERPRINT.to_string()));
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+
+    let result = client.connect_for_cookie(entrypoint());
+
+    assert_eq!(
+        result,
+        Err(OpenconnectError::PeerCertRejectedError(
+            FINGERPRINT.to_string()
+        ))
+    );
+    assert!(matches!(client.get_status(), Status::Error(_)));
+
+    // an accepted certificate is remembered for the next connection of the client
+    let prompts = Arc::new(AtomicUsize::new(0));
+    let handlers = EventHandlers::default().with_handle_peer_cert_invalid({
+        let prompts = prompts.clone();
+        move |fingerprint| {
+            prompts.fetch_add(1, Ordering::SeqCst);
+            fingerprint == FINGERPRINT
+        }
+    });
+    let (client, _, _) = new_client(&backend, handlers);
+
+    for _ in 0..2 {
+        backend.auth_step(AuthStep::PeerCert(FINGERPRINT.to_string()));
+        let cookie = client.connect_for_cookie(entrypoint()).unwrap();
+        assert_eq!(cookie.as_deref(), Some("fake-cookie"));
+    }
+    assert_eq!(prompts.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_repeated_login_form() {
+    let backend = FakeBackend::new();
+    backend.auth_step(AuthStep::Form(FakeForm::login()));
+    for _ in 0..4 {
+        backend.auth_step(AuthStep::Form(FakeForm::login().error("Login failed.")));
+    }
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+
+    let result = client.connect_for_cookie(entrypoint());
+
+    // the rejected password is not sent again, the form is cancelled instead
+    assert!(matches!(
+        result,
+        Err(OpenconnectError::ObtainCookieError(_))
+    ));
+    assert!(matches!(client.get_status(), Status::Error(_)));
+    assert_eq!(client.get_cookie(), None);
+    let passwords = backend
+        .submitted()
+        .into_iter()
+        .filter(|submitted| submitted.name == "password")
+        .count();
+    assert_eq!(passwords, 1);
+}
+
+#[test]
+fn test_empty_forms() {
+    let backend = FakeBackend::new();
+    for _ in 0..5 {
+        backend.auth_step(AuthStep::Form(FakeForm::new("main")));
+    }
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+
+    let result = client.connect_for_cookie(entrypoint());
+
+    // the loop of forms without anything to fill in is aborted
+    assert!(matches!(
+        result,
+        Err(OpenconnectError::ObtainCookieError(_))
+    ));
+    assert_eq!(backend.state().auth.len(), 1);
+}
+
+#[test]
+fn test_filled_forms() {
+    let backend = FakeBackend::new();
+    backend
+        .auth_step(AuthStep::Form(FakeForm::login()))
+        .auth_step(AuthStep::Form(FakeForm::new("token").password("otp")))
+        .auth_step(AuthStep::Form(
+            FakeForm::new("challenge").password("answer"),
+        ))
+        .auth_step(AuthStep::Form(FakeForm::new("pin").password("pin")));
+    let handlers = EventHandlers::default().with_handle_challenge(|_| Some("123456".to_string()));
+    let (client, _, _) = new_client(&backend, handlers);
+
+    // filled forms in a row are not taken for a loop
+    client.connect_for_cookie(entrypoint()).unwrap();
+    assert_eq!(backend.submitted().len(), 5);
+}
+
+#[test]
+fn test_unsupported_form_field() {
+    let backend = FakeBackend::new();
+    backend.auth_step(AuthStep::Form(FakeForm::new("main").text("answer")));
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+
+    let result = client.connect_for_cookie(entrypoint());
+
+    assert_eq!(
+        result,
+        Err(OpenconnectError::UnsupportedFormFieldError(
+            "answer".to_string()
+        ))
+    );
+    assert!(backend.submitted().is_empty());
+}
+
+#[test]
+fn test_auth_group() {
+    let submitted = |backend: &FakeBackend| {
+        backend
+            .submitted()
+            .into_iter()
+            .map(|submitted| (submitted.name, submitted.value))
+            .collect::<Vec<_>>()
+    };
+    let pairs = |pairs: &[(&str, &str)]| {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+    };
+
+    // the group selected by the gateway is kept
+    let backend = FakeBackend::new();
+    backend.auth_step(AuthStep::Form(
+        FakeForm::login().groups(&["staff", "contractors"]),
+    ));
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+    client.connect_for_cookie(entrypoint()).unwrap();
+    assert_eq!(
+        submitted(&backend),
+        pairs(&[("username", "alice"), ("password", "hunter2")])
+    );
+
+    // another group is submitted first, then the form of that group is filled in
+    let backend = FakeBackend::new();
+    backend
+        .auth_step(AuthStep::Form(
+            FakeForm::login().groups(&["staff", "contractors"]),
+        ))
+        .auth_step(AuthStep::Form(
+            FakeForm::login().groups(&["contractors", "staff"]),
+        ));
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+    let mut with_group = entrypoint();
+    with_group.group = Some("Contractors".to_string());
+    client.connect_for_cookie(with_group).unwrap();
+    assert!(backend.calls().contains(&"new_group".to_string()));
+    assert_eq!(
+        submitted(&backend),
+        pairs(&[
+            ("group_list", "contractors"),
+            ("group_list", "contractors"),
+            ("username", "alice"),
+            ("password", "hunter2")
+        ])
+    );
+
+    let backend = FakeBackend::new();
+    backend.auth_step(AuthStep::Form(
+        FakeForm::login().groups(&["staff", "contractors"]),
+    ));
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+    let mut unknown_group = entrypoint();
+    unknown_group.group = Some("finance".to_string());
+    assert!(matches!(
+        client.connect_for_cookie(unknown_group),
+        Err(OpenconnectError::ProcessAuthFormError(_))
+    ));
+}
+
+#[test]
+fn test_challenge() {
+    let backend = FakeBackend::new();
+    backend
+        .auth_step(AuthStep::Form(FakeForm::login()))
+        .auth_step(AuthStep::Form(
+            FakeForm::new("challenge").password("secondary_password"),
+        ));
+    let prompts = Arc::new(Mutex::new(vec![]));
+    let handlers = EventHandlers::default().with_handle_challenge({
+        let prompts = prompts.clone();
+        move |prompt| {
+            prompts.lock().unwrap().push(prompt.to_string());
+            Some("123456".to_string())
+        }
+    });
+    let (client, _, _) = new_client(&backend, handlers);
+
+    client.connect_for_cookie(entrypoint()).unwrap();
+
+    // the login password is not passed to the challenge handler
+    assert_eq!(*prompts.lock().unwrap(), vec!["secondary_password"]);
+    assert_eq!(
+        backend.submitted().last(),
+        Some(&Submitted {
+            auth_id: "challenge".to_string(),
+            name: "secondary_password".to_string(),
+            value: "123456".to_string(),
+        })
+    );
+}
+
+#[test]
+fn test_server_error() {
+    let backend = FakeBackend::new();
+    backend
+        .auth_step(AuthStep::Log(
+            LogLevel::Err,
+            "Unexpected 500 result from server".to_string(),
+        ))
+        .auth_step(AuthStep::Fail(-libc::EINVAL));
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+
+    let err = client.connect_for_cookie(entrypoint()).unwrap_err();
+
+    // the last error line of libopenconnect is attached to the failed call
+    assert_eq!(
+        *err.inner(),
+        OpenconnectError::ObtainCookieError(-libc::EINVAL)
+    );
+    assert!(err
+        .to_string()
+        .contains("Unexpected 500 result from server"));
+    assert_eq!(client.get_status(), Status::Error(err));
+}
+
+#[test]
+fn test_stale_cookie() {
+    let backend = FakeBackend::new();
+    backend
+        .reject_cookie("stale")
+        .auth_step(AuthStep::Form(FakeForm::login()));
+    let (client, statuses, _) = new_client(&backend, EventHandlers::default());
+
+    let mut entrypoint = entrypoint();
+    entrypoint.cookie = Some("stale".into());
+    client.init_connection(entrypoint).unwrap();
+
+    assert_eq!(client.get_status(), Status::Connected);
+    assert_eq!(client.get_cookie().as_deref(), Some("fake-cookie"));
+    assert!(statuses
+        .lock()
+        .unwrap()
+        .contains(&Status::Connecting("Obtaining a new cookie".to_string())));
+    assert_eq!(
+        backend.calls()[2..],
+        [
+            "set_cookie stale",
+            "make_cstp_connection",
+            "clear_cookie",
+            "obtain_cookie",
+            "make_cstp_connection"
+        ]
+    );
+}
+
+#[test]
+fn test_idle_timeout() {
+    let backend = FakeBackend::new();
+    backend.idle_timeout(1800).main_loop_step(MainLoopStep::Log(
+        LogLevel::Info,
+        "Received server disconnect: b0 (Idle timeout)".to_string(),
+    ));
+    backend.main_loop_step(MainLoopStep::Return(-libc::EPIPE));
+    let (client, _, reason) = new_client(&backend, EventHandlers::default());
+    client.init_connection(entrypoint()).unwrap();
+
+    let result = client.run_loop();
+
+    assert_eq!(result, Err(OpenconnectError::IdleTimeoutError(1800)));
+    assert_eq!(
+        client.get_status(),
+        Status::Error(OpenconnectError::IdleTimeoutError(1800))
+    );
+    assert_eq!(
+        *reason.lock().unwrap(),
+        Some(DisconnectReason::IdleTimeout(1800))
+    );
+}
+
+#[test]
+fn test_disconnect_reason() {
+    let backend = FakeBackend::new();
+    backend.idle_timeout(600);
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+    let error = OpenconnectError::MainLoopError(-libc::EPIPE);
+
+    assert_eq!(
+        client.disconnect_reason(error.clone()),
+        DisconnectReason::Error(error.clone())
+    );
+
+    client.handle_progress_log(
+        LogLevel::Info,
+        "CSTP Dead Peer Detection detected dead peer!",
+    );
+    assert_eq!(
+        client.disconnect_reason(error.clone()),
+        DisconnectReason::DeadPeer
+    );
+
+    // a disconnect for another reason is not taken for an idle timeout
+    client.handle_progress_log(
+        LogLevel::Info,
+        "Received server disconnect: 00 (Administrative reset)",
+    );
+    assert_eq!(
+        client.disconnect_reason(error.clone()),
+        DisconnectReason::DeadPeer
+    );
+
+    client.handle_progress_log(
+        LogLevel::Info,
+        "Session terminated by server; reason: Idle Timeout",
+    );
+    assert_eq!(
+        client.disconnect_reason(error.clone()),
+        DisconnectReason::IdleTimeout(600)
+    );
+
+    // cancelling wins over anything seen before
+    assert_eq!(
+        client.disconnect_reason(OpenconnectError::MainLoopError(-libc::EINTR)),
+        DisconnectReason::Cancelled
+    );
+}
+
+#[test]
+fn test_pause_and_resume() {
+    let backend = FakeBackend::new();
+    let (client, statuses, reason) = new_client(&backend, EventHandlers::default());
+    assert!(client.resume().is_err());
+    client.init_connection(entrypoint()).unwrap();
+
+    let main_loop = {
+        let client = client.clone();
+        std::thread::spawn(move || client.run_loop())
+    };
+    client.pause().unwrap();
+    wait_for_status(&client, Status::Paused);
+    assert!(client.pause().is_err());
+
+    client.resume().unwrap();
+    wait_for_status(&client, Status::Connected);
+    client.disconnect();
+
+    assert!(main_loop.join().unwrap().is_ok());
+    assert_eq!(
+        names(&statuses)[2..],
+        [
+            "CONNECTED",
+            "PAUSED",
+            "CONNECTING",
+            "CONNECTED",
+            "DISCONNECTING",
+            "DISCONNECTED"
+        ]
+    );
+    assert_eq!(*reason.lock().unwrap(), Some(DisconnectReason::Cancelled));
+    assert_eq!(
+        backend
+            .calls()
+            .iter()
+            .filter(|call| *call == "make_cstp_connection")
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn test_pause_during_reauth() {
+    for pause_first in [true, false] {
+        let backend = FakeBackend::new();
+        let handlers =
+            EventHandlers::default().with_handle_reauth(|| Some("webvpn=fresh".to_string()));
+        let (client, statuses, _) = new_client(&backend, handlers);
+        client.init_connection(entrypoint()).unwrap();
+
+        // both are requested before the main loop returns
+        if pause_first {
+            client.pause().unwrap();
+            client.reauth();
+        } else {
+            client.reauth();
+            client.pause().unwrap();
+        }
+        let main_loop = {
+            let client = client.clone();
+            std::thread::spawn(move || client.run_loop())
+        };
+
+        // the fresh cookie is set and the pause of the user is kept
+        wait_for_status(&client, Status::Paused);
+        assert!(backend
+            .calls()
+            .contains(&"set_cookie webvpn=fresh".to_string()));
+
+        client.resume().unwrap();
+        wait_for_status(&client, Status::Connected);
+        client.disconnect();
+
+        assert!(main_loop.join().unwrap().is_ok());
+        assert_eq!(
+            names(&statuses)[2..],
+            [
+                "CONNECTED",
+                "PAUSED",
+                "CONNECTING",
+                "CONNECTED",
+                "DISCONNECTING",
+                "DISCONNECTED"
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_resume_rejected() {
+    let backend = FakeBackend::new();
+    let (client, statuses, reason) = new_client(&backend, EventHandlers::default());
+    client.init_connection(entrypoint()).unwrap();
+
+    let main_loop = {
+        let client = client.clone();
+        std::thread::spawn(move || client.run_loop())
+    };
+    client.pause().unwrap();
+    wait_for_status(&client, Status::Paused);
+
+    // the gateway dropped the session while paused
+    backend.reject_cookie("fake-cookie");
+    client.resume().unwrap();
+
+    assert!(main_loop.join().unwrap().is_ok());
+    assert_eq!(
+        names(&statuses)[2..],
+        ["CONNECTED", "PAUSED", "CONNECTING", "DISCONNECTED"]
+    );
+    assert!(matches!(
+        *reason.lock().unwrap(),
+        Some(DisconnectReason::Error(_))
+    ));
+    // the main loop is not entered again
+    assert_eq!(
+        backend
+            .calls()
+            .iter()
+            .filter(|call| *call == "mainloop")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn test_disconnect_while_paused() {
+    let backend = FakeBackend::new();
+    let (client, statuses, reason) = new_client(&backend, EventHandlers::default());
+    client.init_connection(entrypoint()).unwrap();
+
+    let main_loop = {
+        let client = client.clone();
+        std::thread::spawn(move || client.run_loop())
+    };
+    client.pause().unwrap();
+    wait_for_status(&client, Status::Paused);
+    client.disconnect();
+
+    assert!(main_loop.join().unwrap().is_ok());
+    assert_eq!(
+        names(&statuses)[2..],
+        ["CONNECTED", "PAUSED", "DISCONNECTING", "DISCONNECTED"]
+    );
+    assert_eq!(*reason.lock().unwrap(), Some(DisconnectReason::Cancelled));
+    assert!(client.resume().is_err());
+    assert_eq!(
+        backend
+            .calls()
+            .iter()
+            .filter(|call| *call == "make_cstp_connection")
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn test_reconnect_settings() {
+    let backend = FakeBackend::new();
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+    client.init_connection(entrypoint()).unwrap();
+
+    let main_loop = {
+        let client = client.clone();
+        std::thread::spawn(move || client.run_loop())
+    };
+    client.pause().unwrap();
+    wait_for_status(&client, Status::Paused);
+
+    // changed while paused, applied before the connection is made again
+    client.update_settings(ReconnectSettings {
+        dpd: Some(45),
+        tunnel: TunnelOptions {
+            mtu: Some(1300),
+            ..Default::default()
+        },
+    });
+    client.resume().unwrap();
+    wait_for_status(&client, Status::Connected);
+    client.disconnect();
+    assert!(main_loop.join().unwrap().is_ok());
+
+    let calls = backend.calls();
+    let position = |name: &str| calls.iter().position(|call| call == name).unwrap();
+    let connected = position("make_cstp_connection");
+    let resumed = calls
+        .iter()
+        .rposition(|call| call == "make_cstp_connection")
+        .unwrap();
+    for applied in [position("set_dpd 45"), position("set_reqmtu 1300")] {
+        assert!(connected < applied && applied < resumed);
+    }
+}
+
+#[test]
+fn test_probe_auth_form() {
+    // a listener for the connect latency and a real certificate for the chain
+    let gateway = openconnect_mock::MockGatewayBuilder::new().start().unwrap();
+    let backend = FakeBackend::new();
+    backend
+        .peer_cert_chain(vec![gateway.certificate_der().to_vec()])
+        .auth_step(AuthStep::PeerCert(FINGERPRINT.to_string()))
+        .auth_step(AuthStep::Form(
+            FakeForm::login()
+                .groups(&["staff", "contractors"])
+                .select("method", &["push", "sms"]),
+        ));
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+
+    let report = client.probe(&gateway.url()).unwrap();
+
+    let certificate = report.certificate.unwrap();
+    assert_eq!(certificate.fingerprint, FINGERPRINT);
+    assert!(!certificate.trusted);
+    assert_eq!(certificate.chain.len(), 1);
+    assert_eq!(
+        certificate.chain[0].subject.as_deref(),
+        Some("CN=rcgen self signed cert")
+    );
+    assert_eq!(certificate.chain[0].subject, certificate.chain[0].issuer);
+    assert!(certificate.chain[0].not_after.is_some());
+
+    let form = report.auth_form.unwrap();
+    assert_eq!(form.groups, vec!["staff", "contractors"]);
+    assert_eq!(form.selected_group.as_deref(), Some("staff"));
+    assert_eq!(
+        form.fields
+            .iter()
+            .map(|field| (field.name.as_str(), field.kind))
+            .collect::<Vec<_>>(),
+        vec![
+            ("username", FieldKind::Text),
+            ("password", FieldKind::Password),
+            ("method", FieldKind::Select)
+        ]
+    );
+    assert_eq!(form.fields[2].choices, vec!["push", "sms"]);
+    assert!(report.latency.auth_form_ms.is_some());
+    assert_eq!(report.sso, None);
+
+    // nothing is filled in and the client answers normally afterwards
+    assert!(backend.submitted().is_empty());
+    assert!(!client.is_probing());
+}
+
+#[test]
+fn test_probe_sso() {
+    let gateway = openconnect_mock::MockGatewayBuilder::new().start().unwrap();
+    let backend = FakeBackend::new();
+    // nothing listens on port 1, so the login page is classified by its own URL
+    backend.auth_step(AuthStep::Browser(
+        "https://127.0.0.1:1/+CSCOE+/saml/sp/login?tgname=staff".to_string(),
+    ));
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+
+    let report = client.probe(&gateway.url()).unwrap();
+
+    assert!(report.certificate.unwrap().trusted);
+    assert_eq!(report.auth_form, None);
+    let sso = report.sso.unwrap();
+    assert_eq!(sso.kind, SsoKind::Saml);
+    assert_eq!(sso.redirect, None);
+}
+
+#[test]
+fn test_probe_unreachable() {
+    let backend = FakeBackend::new();
+    let (client, _, _) = new_client(&backend, EventHandlers::default());
+
+    let result = client.probe("https://127.0.0.1:1/");
+
+    assert!(matches!(
+        result,
+        Err(OpenconnectError::ServerUnreachableError(_))
+    ));
+    assert!(!backend.calls().contains(&"obtain_cookie".to_string()));
+}
@@ -0,0 +1,187 @@
+use crate::{result, VpnClient};
+use openconnect_sys::openconnect_info;
+use std::ffi::CStr;
+
+/// Open the single sign-on (SAML) login page of the gateway during authentication
+///
+/// libopenconnect listens on `localhost:29786` and catches the final redirect of the login page by itself,
+/// so the handler only needs to show the page and return
+pub trait BrowserHandler: Send + Sync {
+    fn open(&self, uri: &str) -> Result<(), String>;
+}
+
+impl<F> BrowserHandler for F
+where
+    F: Fn(&str) -> Result<(), String>,
+    F: Send + Sync,
+{
+    fn open(&self, uri: &str) -> Result<(), String> {
+        self(uri)
+    }
+}
+
+/// Open the login page with the default browser of the system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemBrowser;
+
+impl SystemBrowser {
+    fn command(uri: &str) -> std::process::Command {
+        #[cfg(target_os = "linux")]
+        let mut command = std::process::Command::new("xdg-open");
+
+        #[cfg(target_os = "macos")]
+        let mut command = std::process::Command::new("open");
+
+        #[cfg(target_os = "windows")]
+        let mut command = {
+            let mut command = std::process::Command::new("cmd");
+            // the empty title keeps `start` from treating the uri as window title
+            command.args(["/C", "start", ""]);
+            command
+        };
+
+        command.arg(uri);
+        command
+    }
+
+    fn run(mut command: std::process::Command) -> Result<(), String> {
+        let status = command
+            .status()
+            .map_err(|e| format!("Failed to start browser: {}", e))?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("Browser exited with {}", status))
+        }
+    }
+
+    /// Open the login page as another user, e.g. the one who ran `sudo`, a browser must not run as root
+    ///
+    /// Supplementary groups are dropped, the session bus of the user is located when it is not in the environment
+    #[cfg(unix)]
+    pub fn open_as(&self, uri: &str, uid: u32, gid: u32) -> Result<(), String> {
+        use std::os::unix::process::CommandExt;
+
+        let mut command = Self::command(uri);
+        command.uid(uid).gid(gid);
+
+        let runtime_dir = std::path::PathBuf::from(format!("/run/user/{}", uid));
+        if std::env::var_os("XDG_RUNTIME_DIR").is_none() && runtime_dir.exists() {
+            command.env("XDG_RUNTIME_DIR", &runtime_dir);
+        }
+        let bus = runtime_dir.join("bus");
+        if std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() && bus.exists() {
+            command.env(
+                "DBUS_SESSION_BUS_ADDRESS",
+                format!("unix:path={}", bus.display()),
+            );
+        }
+        if let Ok(user) = std::env::var("SUDO_USER") {
+            command.env("USER", &user).env("LOGNAME", &user);
+        }
+
+        Self::run(command)
+    }
+}
+
+impl BrowserHandler for SystemBrowser {
+    fn open(&self, uri: &str) -> Result<(), String> {
+        Self::run(Self::command(uri))
+    }
+}
+
+pub(crate) extern "C" fn open_external_browser_cb(
+    _vpninfo: *mut openconnect_info,
+    uri: *const ::std::os::raw::c_char,
+    privdata: *mut ::std::os::raw::c_void,
+) -> ::std::os::raw::c_int {
+    let client = unsafe { VpnClient::ref_from_raw(privdata) };
+
+    result::guard_callback(Some(client), "open_external_browser", -1, || {
+        if uri.is_null() {
+            return -1;
+        }
+
+        let uri = unsafe { CStr::from_ptr(uri) }.to_string_lossy();
+        if client.is_probing() {
+            client.record_probe_sso(&uri);
+            return -1;
+        }
+
+        match client.handle_open_browser(&uri) {
+            Ok(()) => 0,
+            Err(e) => {
+                tracing::error!("Failed to open login page: {}", e);
+                client.set_callback_error(e);
+                -1
+            }
+        }
+    })
+}
+
+#[cfg(all(test, unix))]
+#[test]
+fn test_open_external_browser_cb() {
+    use crate::{
+        backend::fake::FakeBackend, config::ConfigBuilder, events::EventHandlers,
+        result::OpenconnectError,
+    };
+    use std::{
+        ffi::CString,
+        sync::{Arc, Mutex},
+    };
+
+    let new_client = |handlers: EventHandlers| {
+        let config = ConfigBuilder::default().build().unwrap();
+        VpnClient::with_backend(config, handlers, Box::new(FakeBackend::new())).unwrap()
+    };
+    let open = |client: &Arc<VpnClient>, uri: Option<&str>| {
+        let uri = uri.map(|uri| CString::new(uri).unwrap());
+        let uri = uri.as_ref().map_or(std::ptr::null(), |uri| uri.as_ptr());
+        open_external_browser_cb(
+            std::ptr::null_mut(),
+            uri,
+            Arc::as_ptr(client) as *mut std::os::raw::c_void,
+        )
+    };
+    let callback_error = |client: &VpnClient| client.callback_error.write().unwrap().take();
+
+    let opened = Arc::new(Mutex::new(vec![]));
+    let client = new_client(EventHandlers::default().with_browser_handler({
+        let opened = opened.clone();
+        move |uri: &str| {
+            opened.lock().unwrap().push(uri.to_string());
+            Ok(())
+        }
+    }));
+
+    assert_eq!(
+        open(&client, Some("https://vpn.example.com/+CSCOE+/saml")),
+        0
+    );
+    assert_eq!(
+        *opened.lock().unwrap(),
+        ["https://vpn.example.com/+CSCOE+/saml"]
+    );
+    assert_eq!(open(&client, None), -1);
+    assert_eq!(opened.lock().unwrap().len(), 1);
+    assert_eq!(callback_error(&client), None);
+
+    // the failure of the handler is kept for the failing call
+    let failing = new_client(
+        EventHandlers::default().with_browser_handler(|_: &str| Err("no display".to_string())),
+    );
+    assert_eq!(open(&failing, Some("https://vpn.example.com")), -1);
+    assert_eq!(
+        callback_error(&failing),
+        Some(OpenconnectError::OpenBrowserError("no display".to_string()))
+    );
+
+    let without_handler = new_client(EventHandlers::default());
+    assert_eq!(open(&without_handler, Some("https://vpn.example.com")), -1);
+    assert!(matches!(
+        callback_error(&without_handler),
+        Some(OpenconnectError::OpenBrowserError(_))
+    ));
+}
@@ -1,5 +1,7 @@
-use crate::VpnClient;
-use openconnect_sys::*;
+use crate::{
+    result::{self, OpenconnectError},
+    VpnClient,
+};
 use std::{ffi::CString, sync::Mutex};
 
 #[derive(Debug, PartialEq, Eq)]
@@ -20,7 +22,20 @@ impl PeerCerts {
         _reason: *const ::std::os::raw::c_char,
     ) -> ::std::os::raw::c_int {
         let client = unsafe { VpnClient::ref_from_raw(privdata) };
-        let vpninfo = client.vpninfo;
+
+        // reject the certificate if anything goes wrong
+        result::guard_callback(Some(client), "validate_peer_cert", 1, || {
+            Self::validate_peer_cert_for(client)
+        })
+    }
+
+    fn validate_peer_cert_for(client: &VpnClient) -> ::std::os::raw::c_int {
+        // a probe reports the certificate instead of deciding on it
+        if client.is_probing() {
+            client.record_probe_cert(false);
+            return 0;
+        }
+
         let host = client.get_hostname();
         let port = client.get_port();
 
@@ -28,11 +43,11 @@ impl PeerCerts {
         if let Ok(openssl_cert) = openssl_cert_guard {
             for cert in openssl_cert.iter().rev() {
                 if (host.is_none() || cert.host == host) && (port == 0 || cert.port == port) {
-                    let fingerprint_in_cstr =
-                        CString::new(cert.fingerprint.as_str()).expect("Invalid fingerprint");
-                    let err = unsafe {
-                        openconnect_check_peer_cert_hash(vpninfo, fingerprint_in_cstr.as_ptr())
+                    let Ok(fingerprint_in_cstr) = CString::new(cert.fingerprint.as_str()) else {
+                        tracing::error!("Invalid fingerprint: {}", cert.fingerprint);
+                        continue;
                     };
+                    let err = client.backend.check_peer_cert_hash(&fingerprint_in_cstr);
                     if err == 0 {
                         return 0;
                     }
@@ -60,6 +75,7 @@ impl PeerCerts {
             0
         } else {
             tracing::debug!("User rejected insecure certificate");
+            client.set_callback_error(OpenconnectError::PeerCertRejectedError(fingerprint));
             1
         }
     }
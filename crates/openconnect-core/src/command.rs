@@ -1,9 +1,11 @@
-use crate::VpnClient;
-use lazy_static::lazy_static;
+use crate::{registry, VpnClient};
 use openconnect_sys::{OC_CMD_CANCEL, OC_CMD_DETACH, OC_CMD_PAUSE, OC_CMD_STATS};
-use std::sync::{atomic::Ordering, Mutex, Weak};
+use std::{collections::HashMap, sync::atomic::Ordering};
 
-#[derive(Debug, Clone, Copy)]
+#[cfg(not(target_os = "windows"))]
+use {lazy_static::lazy_static, std::sync::Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Command {
     Cancel,
     Detach,
@@ -105,121 +107,203 @@ impl CmdPipe for VpnClient {
     }
 }
 
-pub struct SignalHandle {
-    client: Mutex<Weak<VpnClient>>,
-}
+/// A trigger chosen by the application, e.g. a process signal number or a console control event on Windows
+pub type Trigger = i32;
 
-lazy_static! {
-    pub static ref SIGNAL_HANDLE: SignalHandle = {
-        let sig_handle = SignalHandle {
-            client: Mutex::new(Weak::new()),
-        };
-        sig_handle.set_sig_handler();
-        sig_handle
-    };
+/// Map triggers to the commands sent to the client
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignalMap {
+    commands: HashMap<Trigger, Command>,
 }
 
-impl SignalHandle {
-    /// Set the current client singleton to the given client.
-    /// This is used when signal handler is called to send command to the client.
-    pub fn update_client_singleton(&self, client: Weak<VpnClient>) {
-        let saved_client = self.client.lock();
-        if let Ok(mut saved_client) = saved_client {
-            *saved_client = client;
-        }
+impl SignalMap {
+    /// An empty map, no trigger is routed to the client
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    /// Set the signal handler for the current process.
-    fn set_sig_handler(&self) {
+    /// The mapping of process signals used by the openconnect CLI
+    ///
+    /// - SIGINT, SIGTERM: [Command::Cancel]
+    /// - SIGHUP: [Command::Detach]
+    /// - SIGUSR2: [Command::Pause]
+    /// - SIGUSR1: [Command::Stats]
+    ///
+    /// On Windows, ctrl-c, close, logoff and shutdown events cancel the connection, ctrl-break detaches it
+    pub fn process_signals() -> Self {
         #[cfg(not(target_os = "windows"))]
         {
-            use signal_hook::{
-                consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2},
-                iterator::Signals,
-            };
-
-            let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP, SIGUSR1, SIGUSR2])
-                .expect("Failed to register signal handler");
-
-            std::thread::spawn(move || {
-                for sig in signals.forever() {
-                    let cmd = match sig {
-                        SIGINT | SIGTERM => {
-                            println!("Received SIGINT or SIGTERM");
-                            Command::Cancel
-                        }
-                        SIGHUP => {
-                            println!("Received SIGHUP");
-                            Command::Detach
-                        }
-                        SIGUSR2 => {
-                            println!("Received SIGUSR2");
-                            Command::Pause
-                        }
-                        SIGUSR1 => {
-                            println!("Received SIGUSR1");
-                            Command::Stats
-                        }
-                        _ => {
-                            println!("Received unknown signal");
-                            unreachable!()
-                        }
-                    };
-
-                    {
-                        let this = SIGNAL_HANDLE
-                            .client
-                            .lock()
-                            .ok()
-                            .and_then(|this| this.upgrade());
-
-                        if let Some(this) = this {
-                            this.send_command(cmd);
-                        }
-                    }
+            use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
 
-                    if sig == SIGINT || sig == SIGTERM {
-                        // Exit the signal handler thread since the process is going to exit
-                        break;
-                    }
-                }
-            });
+            Self::new()
+                .map(SIGINT, Command::Cancel)
+                .map(SIGTERM, Command::Cancel)
+                .map(SIGHUP, Command::Detach)
+                .map(SIGUSR2, Command::Pause)
+                .map(SIGUSR1, Command::Stats)
         }
 
         #[cfg(target_os = "windows")]
         {
             use windows_sys::Win32::System::Console::{
-                SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT,
-                CTRL_LOGOFF_EVENT, CTRL_SHUTDOWN_EVENT,
+                CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT, CTRL_LOGOFF_EVENT,
+                CTRL_SHUTDOWN_EVENT,
             };
 
-            unsafe extern "system" fn console_control_handle(dw_ctrl_type: u32) -> i32 {
-                let cmd = match dw_ctrl_type {
-                    CTRL_C_EVENT | CTRL_CLOSE_EVENT | CTRL_LOGOFF_EVENT | CTRL_SHUTDOWN_EVENT => {
-                        Command::Cancel
-                    }
-                    CTRL_BREAK_EVENT => Command::Detach,
-                    _ => unreachable!(),
-                };
+            Self::new()
+                .map(CTRL_C_EVENT as Trigger, Command::Cancel)
+                .map(CTRL_CLOSE_EVENT as Trigger, Command::Cancel)
+                .map(CTRL_LOGOFF_EVENT as Trigger, Command::Cancel)
+                .map(CTRL_SHUTDOWN_EVENT as Trigger, Command::Cancel)
+                .map(CTRL_BREAK_EVENT as Trigger, Command::Detach)
+        }
+    }
+
+    /// Send the command to the client when the trigger is dispatched
+    pub fn map(mut self, trigger: Trigger, command: Command) -> Self {
+        self.commands.insert(trigger, command);
+        self
+    }
 
-                {
-                    let this = SIGNAL_HANDLE
-                        .client
-                        .lock()
-                        .ok()
-                        .and_then(|this| this.upgrade());
+    pub fn get(&self, trigger: Trigger) -> Option<Command> {
+        self.commands.get(&trigger).copied()
+    }
 
-                    if let Some(this) = this {
-                        this.send_command(cmd);
-                    }
-                }
+    pub fn triggers(&self) -> impl Iterator<Item = Trigger> + '_ {
+        self.commands.keys().copied()
+    }
+}
+
+/// Decide how a client reacts to process signals and application triggers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SignalPolicy {
+    /// The client ignores all triggers, signals are left to the host application
+    #[default]
+    Disabled,
+
+    /// No signal handler is installed, the host application forwards triggers with [dispatch]
+    Manual(SignalMap),
+
+    /// Install process signal handlers for the mapped signals, [dispatch] works as well
+    Handle(SignalMap),
+}
+
+impl SignalPolicy {
+    fn command_for(&self, trigger: Trigger, from_signal: bool) -> Option<Command> {
+        match self {
+            SignalPolicy::Disabled => None,
+            SignalPolicy::Manual(_) if from_signal => None,
+            SignalPolicy::Manual(map) | SignalPolicy::Handle(map) => map.get(trigger),
+        }
+    }
+}
 
-                1
+/// Send the command mapped to the trigger to every client whose [SignalPolicy] maps it
+///
+/// Returns the number of clients which received a command
+pub fn dispatch(trigger: Trigger) -> usize {
+    route(trigger, false)
+}
+
+fn route(trigger: Trigger, from_signal: bool) -> usize {
+    let mut routed = 0;
+    for client in registry::clients() {
+        if let Some(cmd) = client
+            .config
+            .signal_policy
+            .command_for(trigger, from_signal)
+        {
+            tracing::debug!(
+                "Route trigger {} to client {} as {:?}",
+                trigger,
+                client.id(),
+                cmd
+            );
+            client.send_command(cmd);
+            routed += 1;
+        }
+    }
+    routed
+}
+
+#[cfg(not(target_os = "windows"))]
+lazy_static! {
+    static ref SIGNAL_HANDLE: Mutex<Option<signal_hook::iterator::Handle>> = Mutex::new(None);
+}
+
+/// Install process signal handlers for the triggers of the map, signals already handled are skipped
+///
+/// The handlers live until the process exits, since other clients may rely on them
+pub(crate) fn install_signal_handlers(map: &SignalMap) -> std::io::Result<()> {
+    if map.triggers().next().is_none() {
+        return Ok(());
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        use signal_hook::iterator::Signals;
+
+        let mut handle = SIGNAL_HANDLE
+            .lock()
+            .map_err(|_| std::io::Error::other("signal handle is poisoned"))?;
+
+        if let Some(ref handle) = *handle {
+            for trigger in map.triggers() {
+                handle.add_signal(trigger)?;
             }
+            return Ok(());
+        }
 
-            unsafe {
-                SetConsoleCtrlHandler(Some(console_control_handle), 1);
+        let mut signals = Signals::new(map.triggers())?;
+        *handle = Some(signals.handle());
+
+        std::thread::spawn(move || {
+            for sig in signals.forever() {
+                tracing::info!("Received signal {}", sig);
+                route(sig, true);
+            }
+        });
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::sync::Once;
+        use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+
+        unsafe extern "system" fn console_control_handle(dw_ctrl_type: u32) -> i32 {
+            tracing::info!("Received console control event {}", dw_ctrl_type);
+            match route(dw_ctrl_type as Trigger, true) {
+                0 => 0, // not handled, pass to the next handler
+                _ => 1,
             }
         }
+
+        static INIT: Once = Once::new();
+        INIT.call_once(|| unsafe {
+            SetConsoleCtrlHandler(Some(console_control_handle), 1);
+        });
+
+        Ok(())
     }
 }
+
+#[test]
+fn test_signal_policy() {
+    let map = SignalMap::new()
+        .map(1, Command::Pause)
+        .map(2, Command::Cancel);
+
+    assert_eq!(SignalPolicy::Disabled.command_for(1, false), None);
+    assert_eq!(SignalPolicy::Manual(map.clone()).command_for(1, true), None);
+    assert_eq!(
+        SignalPolicy::Manual(map.clone()).command_for(1, false),
+        Some(Command::Pause)
+    );
+    assert_eq!(
+        SignalPolicy::Handle(map.clone()).command_for(2, true),
+        Some(Command::Cancel)
+    );
+    assert_eq!(SignalPolicy::Handle(map).command_for(3, false), None);
+}
@@ -1,8 +1,24 @@
 use crate::{
+    command::SignalPolicy,
+    csd::CsdConfig,
+    dns::DnsMode,
     protocols::{get_anyconnect_protocol, Protocol},
+    proxy::ProxyConfig,
+    resolve::Resolver,
     result::{OpenconnectError, OpenconnectResult},
+    secret::Secret,
+};
+use openconnect_sys::{
+    oc_compression_mode_t, oc_compression_mode_t_OC_COMPRESSION_MODE_ALL,
+    oc_compression_mode_t_OC_COMPRESSION_MODE_NONE,
+    oc_compression_mode_t_OC_COMPRESSION_MODE_STATELESS, PRG_DEBUG, PRG_ERR, PRG_INFO, PRG_TRACE,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
 };
-use openconnect_sys::{PRG_DEBUG, PRG_ERR, PRG_INFO, PRG_TRACE};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum LogLevel {
@@ -15,22 +31,49 @@ pub enum LogLevel {
 #[derive(Debug, Clone)]
 pub struct Config {
     pub vpncscript: Option<String>,
-    pub http_proxy: Option<String>,
+    pub proxy: Option<ProxyConfig>,
     pub loglevel: LogLevel,
+    pub dns_mode: DnsMode,
+    pub dpd: Option<u32>,
+    pub keepalive: Option<u32>,
+    pub expiry_warning: u32,
+    pub auto_reauth: bool,
+    pub signal_policy: SignalPolicy,
+    pub journal: Option<PathBuf>,
+    pub tun_script: Option<String>,
 }
 
+/// Default seconds before the session expires to emit the expiring event
+pub const DEFAULT_EXPIRY_WARNING: u32 = 300;
+
 pub struct ConfigBuilder {
     vpncscript: Option<String>,
-    http_proxy: Option<String>,
+    proxy: Option<ProxyConfig>,
     loglevel: Option<LogLevel>,
+    dns_mode: DnsMode,
+    dpd: Option<u32>,
+    keepalive: Option<u32>,
+    expiry_warning: u32,
+    auto_reauth: bool,
+    signal_policy: SignalPolicy,
+    journal: Option<PathBuf>,
+    tun_script: Option<String>,
 }
 
 impl ConfigBuilder {
     pub fn new() -> Self {
         Self {
             vpncscript: None,
-            http_proxy: None,
+            proxy: None,
             loglevel: None,
+            dns_mode: DnsMode::default(),
+            dpd: None,
+            keepalive: None,
+            expiry_warning: DEFAULT_EXPIRY_WARNING,
+            auto_reauth: false,
+            signal_policy: SignalPolicy::default(),
+            journal: None,
+            tun_script: None,
         }
     }
 
@@ -45,15 +88,81 @@ impl ConfigBuilder {
     }
 
     pub fn http_proxy(&mut self, http_proxy: &str) -> &mut Self {
-        self.http_proxy = Some(http_proxy.to_string());
+        self.proxy = Some(ProxyConfig::new(http_proxy));
+        self
+    }
+
+    /// Reach the server through a HTTP(S) or SOCKS proxy, with optional credentials and authentication methods
+    ///
+    /// Use [ProxyConfig::auto] to find the proxy from the environment or a PAC file when connecting
+    pub fn proxy(&mut self, proxy: ProxyConfig) -> &mut Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Set how DNS settings pushed by the server are applied, default to [DnsMode::Script]
+    pub fn dns_mode(&mut self, dns_mode: DnsMode) -> &mut Self {
+        self.dns_mode = dns_mode;
+        self
+    }
+
+    /// Set the maximum dead peer detection interval in seconds, a shorter interval requested by the server still wins
+    pub fn dpd(&mut self, dpd: u32) -> &mut Self {
+        self.dpd = Some(dpd);
+        self
+    }
+
+    /// Send a small packet through the tunnel every given seconds, so that the gateway does not consider the session idle
+    pub fn keepalive(&mut self, keepalive: u32) -> &mut Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Set how many seconds before the session expires the expiring event is emitted, default to [DEFAULT_EXPIRY_WARNING]
+    pub fn expiry_warning(&mut self, seconds: u32) -> &mut Self {
+        self.expiry_warning = seconds;
+        self
+    }
+
+    /// Obtain a fresh cookie through [crate::events::EventHandlers::with_handle_reauth] before the session expires and reconnect in place
+    pub fn auto_reauth(&mut self, auto_reauth: bool) -> &mut Self {
+        self.auto_reauth = auto_reauth;
+        self
+    }
+
+    /// Set how the client reacts to process signals, default to [SignalPolicy::Disabled] so that signals are left to the host application
+    pub fn signal_policy(&mut self, signal_policy: SignalPolicy) -> &mut Self {
+        self.signal_policy = signal_policy;
+        self
+    }
+
+    /// Append the status transitions of each connection attempt to this file, see [crate::journal::Journal]
+    pub fn journal(&mut self, journal: &Path) -> &mut Self {
+        self.journal = Some(journal.to_path_buf());
+        self
+    }
+
+    /// Pass the tunnel traffic to this script through its stdin and stdout instead of a tun device
+    ///
+    /// No privileges are required, the vpncscript is not run, see `--script-tun` of openconnect
+    pub fn tun_script(&mut self, tun_script: &str) -> &mut Self {
+        self.tun_script = Some(tun_script.to_string());
         self
     }
 
     pub fn build(&self) -> OpenconnectResult<Config> {
         Ok(Config {
-            http_proxy: self.http_proxy.clone(),
+            proxy: self.proxy.clone(),
             vpncscript: self.vpncscript.clone(),
             loglevel: self.loglevel.unwrap_or(LogLevel::Info),
+            dns_mode: self.dns_mode,
+            dpd: self.dpd,
+            keepalive: self.keepalive,
+            expiry_warning: self.expiry_warning,
+            auto_reauth: self.auto_reauth,
+            signal_policy: self.signal_policy.clone(),
+            journal: self.journal.clone(),
+            tun_script: self.tun_script.clone(),
         })
     }
 }
@@ -70,27 +179,98 @@ impl Default for ConfigBuilder {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CompressionMode {
+    /// Disable compression
+    None,
+
+    /// Only use stateless compression algorithms, this is the default of libopenconnect
+    Stateless,
+
+    /// Allow all compression algorithms including stateful ones
+    All,
+}
+
+impl From<CompressionMode> for oc_compression_mode_t {
+    fn from(mode: CompressionMode) -> oc_compression_mode_t {
+        match mode {
+            CompressionMode::None => oc_compression_mode_t_OC_COMPRESSION_MODE_NONE,
+            CompressionMode::Stateless => oc_compression_mode_t_OC_COMPRESSION_MODE_STATELESS,
+            CompressionMode::All => oc_compression_mode_t_OC_COMPRESSION_MODE_ALL,
+        }
+    }
+}
+
+/// Tunnel parameters negotiated with the server, the defaults leave everything to libopenconnect
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelOptions {
+    /// MTU requested from the server, the server may still pick a smaller one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<i32>,
+
+    /// Do not request IPv6 addresses from the server
+    #[serde(default)]
+    pub disable_ipv6: bool,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<CompressionMode>,
+
+    /// Require perfect forward secrecy for the TLS connections
+    #[serde(default)]
+    pub pfs: bool,
+
+    /// Allow insecure ciphers and protocol versions, needed by some legacy gateways
+    #[serde(default)]
+    pub allow_insecure_crypto: bool,
+
+    /// Copy the TOS/TCLASS field of tunneled packets to the outer packets
+    #[serde(default)]
+    pub pass_tos: bool,
+}
+
+/// Settings of a running connection which can change before it reconnects, see [crate::VpnClient::update_settings]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconnectSettings {
+    pub dpd: Option<u32>,
+    pub tunnel: TunnelOptions,
+}
+
 #[derive(Debug, Clone)]
 pub struct Entrypoint {
     pub name: Option<String>,
     pub server: String,
     pub username: Option<String>,
-    pub password: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub group: Option<String>,
     pub protocol: Protocol,
-    pub cookie: Option<String>,
+    pub cookie: Option<Secret<String>>,
     pub enable_udp: bool,
     pub accept_insecure_cert: bool,
+    pub tunnel: TunnelOptions,
+    pub csd: Option<CsdConfig>,
+    pub hostname: Option<String>,
+    pub sni: Option<String>,
+    pub resolver: Option<Resolver>,
 }
 
 pub struct EntrypointBuilder {
     name: Option<String>,
     server: Option<String>,
     username: Option<String>,
-    password: Option<String>,
+    password: Option<Secret<String>>,
+    group: Option<String>,
     protocol: Option<Protocol>,
-    cookie: Option<String>,
+    cookie: Option<Secret<String>>,
     enable_udp: bool,
     accept_insecure_cert: Option<bool>,
+    tunnel: TunnelOptions,
+    csd: Option<CsdConfig>,
+    hostname: Option<String>,
+    sni: Option<String>,
+    pinned: HashMap<String, IpAddr>,
+    resolver: Option<Resolver>,
 }
 
 impl EntrypointBuilder {
@@ -100,10 +280,17 @@ impl EntrypointBuilder {
             server: None,
             username: None,
             password: None,
+            group: None,
             protocol: None,
             cookie: None,
             enable_udp: true,
             accept_insecure_cert: None,
+            tunnel: TunnelOptions::default(),
+            csd: None,
+            hostname: None,
+            sni: None,
+            pinned: HashMap::new(),
+            resolver: None,
         }
     }
 
@@ -123,7 +310,13 @@ impl EntrypointBuilder {
     }
 
     pub fn password(&mut self, password: &str) -> &mut Self {
-        self.password = Some(password.to_string());
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Select this auth group in the login form instead of the one the gateway selects, matched by its label
+    pub fn group(&mut self, group: &str) -> &mut Self {
+        self.group = Some(group.to_string());
         self
     }
 
@@ -133,7 +326,7 @@ impl EntrypointBuilder {
     }
 
     pub fn cookie(&mut self, cookie: &str) -> &mut Self {
-        self.cookie = Some(cookie.to_string());
+        self.cookie = Some(cookie.into());
         self
     }
 
@@ -147,6 +340,41 @@ impl EntrypointBuilder {
         self
     }
 
+    pub fn tunnel_options(&mut self, tunnel: TunnelOptions) -> &mut Self {
+        self.tunnel = tunnel;
+        self
+    }
+
+    /// Answer the CSD (hostscan) posture check of the gateway during authentication
+    pub fn csd(&mut self, csd: CsdConfig) -> &mut Self {
+        self.csd = Some(csd);
+        self
+    }
+
+    /// Connect to this host instead of the host of the server URL, it is also verified against the certificate
+    pub fn hostname(&mut self, hostname: &str) -> &mut Self {
+        self.hostname = Some(hostname.to_string());
+        self
+    }
+
+    /// Present this name in the TLS handshake instead of the hostname, e.g. when the server is given by address
+    pub fn sni(&mut self, sni: &str) -> &mut Self {
+        self.sni = Some(sni.to_string());
+        self
+    }
+
+    /// Connect to the address instead of resolving the host, for the gateway and the proxy alike
+    pub fn resolve(&mut self, host: &str, ip: IpAddr) -> &mut Self {
+        self.pinned.insert(host.to_string(), ip);
+        self
+    }
+
+    /// Resolve host names with a custom resolver, addresses pinned with [EntrypointBuilder::resolve] take precedence
+    pub fn resolver(&mut self, resolver: Resolver) -> &mut Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
     pub fn build(&self) -> OpenconnectResult<Entrypoint> {
         let server = self
             .server
@@ -155,20 +383,32 @@ impl EntrypointBuilder {
                 "Server is required".to_string(),
             ))?;
 
-        let protocol = self
-            .protocol
-            .clone()
-            .unwrap_or_else(get_anyconnect_protocol);
+        let pinned = (!self.pinned.is_empty()).then(|| Resolver::from_map(self.pinned.clone()));
+        let resolver = match (pinned, self.resolver.clone()) {
+            (Some(pinned), Some(resolver)) => Some(pinned.or(resolver)),
+            (pinned, resolver) => pinned.or(resolver),
+        };
+
+        let protocol = match self.protocol.clone() {
+            Some(protocol) => protocol,
+            None => get_anyconnect_protocol()?,
+        };
 
         Ok(Entrypoint {
             name: self.name.clone(),
             server,
             username: self.username.clone(),
             password: self.password.clone(),
+            group: self.group.clone(),
             protocol,
             cookie: self.cookie.clone(),
             enable_udp: self.enable_udp,
             accept_insecure_cert: self.accept_insecure_cert.unwrap_or(false),
+            tunnel: self.tunnel.clone(),
+            csd: self.csd.clone(),
+            hostname: self.hostname.clone(),
+            sni: self.sni.clone(),
+            resolver,
         })
     }
 }
@@ -178,3 +418,58 @@ impl Default for EntrypointBuilder {
         Self::new()
     }
 }
+
+#[test]
+fn test_tunnel_options_defaults() {
+    let options: TunnelOptions = serde_json::from_str("{}").unwrap();
+    assert_eq!(options, TunnelOptions::default());
+    assert_eq!(
+        serde_json::to_value(&options).unwrap(),
+        serde_json::json!({
+            "disableIpv6": false,
+            "pfs": false,
+            "allowInsecureCrypto": false,
+            "passTos": false,
+        })
+    );
+
+    let options: TunnelOptions =
+        serde_json::from_str(r#"{ "mtu": 1300, "compression": "none", "passTos": true }"#).unwrap();
+    assert_eq!(
+        options,
+        TunnelOptions {
+            mtu: Some(1300),
+            compression: Some(CompressionMode::None),
+            pass_tos: true,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn test_compression_mode() {
+    for (mode, name, oc_mode) in [
+        (
+            CompressionMode::None,
+            "none",
+            oc_compression_mode_t_OC_COMPRESSION_MODE_NONE,
+        ),
+        (
+            CompressionMode::Stateless,
+            "stateless",
+            oc_compression_mode_t_OC_COMPRESSION_MODE_STATELESS,
+        ),
+        (
+            CompressionMode::All,
+            "all",
+            oc_compression_mode_t_OC_COMPRESSION_MODE_ALL,
+        ),
+    ] {
+        assert_eq!(oc_compression_mode_t::from(mode), oc_mode);
+        assert_eq!(serde_json::to_value(mode).unwrap(), name);
+        assert_eq!(
+            serde_json::from_value::<CompressionMode>(name.into()).unwrap(),
+            mode
+        );
+    }
+}
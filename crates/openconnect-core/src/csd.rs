@@ -0,0 +1,324 @@
+use base64::Engine;
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    CertificateError, DigitallySignedStruct, SignatureScheme,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Set in the environment of the wrapper process, so that the current executable runs the built-in responder
+pub(crate) const RESPONDER_ENV: &str = "OPENCONNECT_RS_CSD_RESPONDER";
+
+/// The hostscan result posted by the built-in responder
+pub(crate) const HOSTSCAN_ENV: &str = "OPENCONNECT_RS_CSD_HOSTSCAN";
+
+/// How the Cisco Secure Desktop (hostscan) posture check is answered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "mode")]
+pub enum CsdMode {
+    /// Run an external wrapper script, e.g. `csd-post.sh` shipped with openconnect
+    #[serde(rename_all = "camelCase")]
+    Wrapper { path: String },
+
+    /// Post a hostscan result with the built-in responder, [default_hostscan] is posted when not set
+    ///
+    /// The responder runs in a child process of the current executable, see [run_responder_if_requested]
+    #[serde(rename_all = "camelCase")]
+    Responder {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hostscan: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CsdConfig {
+    #[serde(flatten)]
+    pub mode: CsdMode,
+
+    /// Run the posture check again every given seconds, for gateways which require periodic checks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trojan_interval: Option<u32>,
+}
+
+#[derive(Error, Debug)]
+pub enum CsdError {
+    #[error("Missing environment variable: {0}")]
+    MissingEnv(&'static str),
+
+    #[error("Request error: {0}")]
+    RequestError(#[from] reqwest::Error),
+
+    #[error("TLS error: {0}")]
+    TlsError(#[from] rustls::Error),
+
+    #[error("Hostscan rejected by server with status {0}")]
+    Rejected(u16),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// A hostscan result reporting a clean endpoint of the current OS, accepted by gateways with the default policy
+pub fn default_hostscan() -> String {
+    #[cfg(target_os = "windows")]
+    const OS_VERSION: &str = "Windows 10";
+
+    #[cfg(target_os = "macos")]
+    const OS_VERSION: &str = "Mac OS X";
+
+    #[cfg(target_os = "linux")]
+    const OS_VERSION: &str = "Linux";
+
+    [
+        format!("endpoint.os.version=\"{}\";", OS_VERSION),
+        "endpoint.os.servicepack=\"\";".to_string(),
+        format!("endpoint.os.architecture=\"{}\";", std::env::consts::ARCH),
+        "endpoint.policy.location=\"Default\";".to_string(),
+        "endpoint.device.protection=\"none\";".to_string(),
+        "endpoint.device.protection_version=\"3.1.03103\";".to_string(),
+        "endpoint.device.protection_extension=\"4.3.05050\";".to_string(),
+    ]
+    .join("\n")
+}
+
+/// Post a hostscan result to the gateway, as `csd-post.sh` does
+pub struct HostscanResponder {
+    base_url: String,
+    token: String,
+    hostscan: String,
+    pinned_sha256: Option<String>,
+}
+
+impl HostscanResponder {
+    pub fn new(base_url: &str, token: &str, hostscan: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            hostscan: hostscan.to_string(),
+            pinned_sha256: None,
+        }
+    }
+
+    /// Only talk to a gateway whose certificate has this public key, given as the base64 SHA-256 hash of its
+    /// SubjectPublicKeyInfo, the format of `CSD_SHA256`
+    ///
+    /// Neither the name nor the chain of the certificate is verified then, the pin identifies the exact key
+    pub fn pin_sha256(mut self, sha256: &str) -> Self {
+        self.pinned_sha256 = Some(sha256.trim().to_string());
+        self
+    }
+
+    /// Build the responder from the environment set by libopenconnect for CSD wrappers
+    ///
+    /// The gateway certificate was accepted by libopenconnect before the wrapper is started, the responder pins its
+    /// public key to `CSD_SHA256` like `csd-post.sh` does with `curl --pinnedpubkey sha256//$CSD_SHA256`
+    pub fn from_env() -> Result<Self, CsdError> {
+        let hostname =
+            std::env::var("CSD_HOSTNAME").map_err(|_| CsdError::MissingEnv("CSD_HOSTNAME"))?;
+        let token = std::env::var("CSD_TOKEN").map_err(|_| CsdError::MissingEnv("CSD_TOKEN"))?;
+        let sha256 = std::env::var("CSD_SHA256").map_err(|_| CsdError::MissingEnv("CSD_SHA256"))?;
+        let hostscan = std::env::var(HOSTSCAN_ENV).unwrap_or_else(|_| default_hostscan());
+
+        Ok(Self::new(&format!("https://{}", hostname), &token, &hostscan).pin_sha256(&sha256))
+    }
+
+    pub fn scan_url(&self) -> String {
+        format!("{}/+CSCOE+/sdesktop/scan.xml?reusebrowser=1", self.base_url)
+    }
+
+    pub async fn respond(&self) -> Result<(), CsdError> {
+        let mut client = reqwest::Client::builder();
+        if let Some(ref sha256) = self.pinned_sha256 {
+            client = client.use_preconfigured_tls(PinnedPublicKey::client_config(sha256)?);
+        }
+        let client = client.build()?;
+
+        let response = client
+            .post(self.scan_url())
+            .header("X-Transcend-Version", "1")
+            .header("X-Aggregate-Auth", "1")
+            .header("Cookie", format!("sdesktop={}", self.token))
+            .header("Content-Type", "text/xml")
+            .body(self.hostscan.clone())
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(CsdError::Rejected(response.status().as_u16()));
+        }
+
+        tracing::debug!("Hostscan result posted to {}", self.base_url);
+        Ok(())
+    }
+}
+
+/// Accept only the certificate whose public key hashes to the pin, handshake signatures are still verified
+#[derive(Debug)]
+struct PinnedPublicKey {
+    sha256: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl PinnedPublicKey {
+    fn client_config(sha256: &str) -> Result<rustls::ClientConfig, CsdError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier = Arc::new(Self {
+            sha256: sha256.to_string(),
+            provider: provider.clone(),
+        });
+
+        Ok(rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(verifier)
+            .with_no_client_auth())
+    }
+}
+
+impl ServerCertVerifier for PinnedPublicKey {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let spki = crate::probe::subject_public_key_info(end_entity).ok_or(
+            rustls::Error::InvalidCertificate(CertificateError::BadEncoding),
+        )?;
+
+        let sha256 = base64::engine::general_purpose::STANDARD.encode(Sha256::digest(spki));
+        if sha256 != self.sha256 {
+            tracing::error!("Gateway public key {} does not match CSD_SHA256", sha256);
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Run the built-in responder and exit, if the current process was started by libopenconnect as CSD wrapper
+///
+/// Applications using [CsdMode::Responder] must call this at the very beginning of `main`
+pub fn run_responder_if_requested() {
+    if std::env::var_os(RESPONDER_ENV).is_none() {
+        return;
+    }
+
+    let result = HostscanResponder::from_env().and_then(|responder| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(responder.respond())
+    });
+
+    match result {
+        Ok(()) => std::process::exit(0),
+        Err(e) => {
+            // the wrapper process has no other way to report, libopenconnect forwards its stderr
+            eprintln!("CSD responder failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_hostscan_responder() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !String::from_utf8_lossy(&request).contains("protection=\"none\";") {
+            let n = stream.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        String::from_utf8(request).unwrap()
+    });
+
+    let responder = HostscanResponder::new(
+        &format!("http://{}", addr),
+        "token123",
+        "endpoint.device.protection=\"none\";",
+    );
+    responder.respond().await.unwrap();
+
+    let request = server.await.unwrap().to_lowercase();
+    assert!(request.starts_with("post /+cscoe+/sdesktop/scan.xml?reusebrowser=1 "));
+    assert!(request.contains("cookie: sdesktop=token123"));
+    assert!(request.ends_with("endpoint.device.protection=\"none\";"));
+}
+
+#[tokio::test]
+async fn test_hostscan_responder_pinned() {
+    let gateway = openconnect_mock::MockGatewayBuilder::new().start().unwrap();
+    let pin = gateway.pin_sha256().trim_start_matches("pin-sha256:");
+    let base_url = format!("https://{}", gateway.addr());
+
+    // another key is refused before the token is sent
+    let other = base64::engine::general_purpose::STANDARD.encode(Sha256::digest(b"other"));
+    let responder = HostscanResponder::new(&base_url, "token123", "").pin_sha256(&other);
+    assert!(matches!(
+        responder.respond().await,
+        Err(CsdError::RequestError(_))
+    ));
+    assert!(gateway.events().is_empty());
+
+    // the certificate is self-signed, only its key is checked
+    let responder = HostscanResponder::new(&base_url, "token123", "").pin_sha256(pin);
+    responder.respond().await.unwrap();
+    assert!(!gateway.events().is_empty());
+}
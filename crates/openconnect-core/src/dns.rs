@@ -0,0 +1,603 @@
+use crate::ip_info::IpInfo;
+use std::net::IpAddr;
+
+#[cfg(not(target_os = "windows"))]
+use std::path::{Path, PathBuf};
+
+/// How DNS settings pushed by the VPN server are applied to the system
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DnsMode {
+    /// Leave DNS configuration to the vpnc-script
+    #[default]
+    Script,
+
+    /// Configure per-link DNS through systemd-resolved over D-Bus (Linux only)
+    SystemdResolved,
+
+    /// Rewrite resolv.conf, the original file is restored on disconnect
+    ResolvConf,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DnsError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("D-Bus error: {0}")]
+    BusError(String),
+
+    #[error("Network interface not found: {0}")]
+    InterfaceNotFound(String),
+
+    #[error("DNS backend is not supported on this platform: {0}")]
+    Unsupported(String),
+}
+
+/// DNS settings extracted from the IP info pushed by the VPN server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DnsSettings {
+    /// Resolvers provided by the VPN server
+    pub servers: Vec<IpAddr>,
+
+    /// Search domains provided by the VPN server
+    pub search_domains: Vec<String>,
+
+    /// Split DNS domains, when not empty only these domains are resolved by the VPN resolvers
+    pub routing_domains: Vec<String>,
+}
+
+impl From<&IpInfo> for DnsSettings {
+    fn from(info: &IpInfo) -> Self {
+        let servers = info
+            .dns
+            .iter()
+            .flatten()
+            .filter_map(|dns| dns.trim().parse().ok())
+            .collect();
+
+        // the server may push several search domains separated by spaces or commas
+        let search_domains = info
+            .domain
+            .iter()
+            .flat_map(|domain| domain.split([' ', ',']))
+            .filter(|domain| !domain.is_empty())
+            .map(|domain| domain.to_string())
+            .collect();
+
+        let routing_domains = info
+            .split_dns
+            .iter()
+            .map(|domain| domain.trim().trim_start_matches('~').to_string())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+
+        Self {
+            servers,
+            search_domains,
+            routing_domains,
+        }
+    }
+}
+
+impl DnsSettings {
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+
+    /// Whether all queries should go to the VPN resolvers rather than only split DNS domains
+    pub fn is_default_route(&self) -> bool {
+        self.routing_domains.is_empty()
+    }
+}
+
+/// A backend which points name resolution of the system to the VPN resolvers
+///
+/// [DnsBackend::restore] must be idempotent, it is called on disconnect and again when the client is dropped
+pub trait DnsBackend: Send + Sync {
+    fn apply(&self, ifname: &str, settings: &DnsSettings) -> Result<(), DnsError>;
+    fn restore(&self) -> Result<(), DnsError>;
+
+    /// Undo the settings left behind by a process which crashed while connected, called when a client is created
+    fn recover(&self) -> Result<(), DnsError> {
+        Ok(())
+    }
+}
+
+/// Create the DNS backend for the given mode, [DnsMode::Script] has no backend
+pub fn backend_for(mode: DnsMode) -> Result<Option<Box<dyn DnsBackend>>, DnsError> {
+    match mode {
+        DnsMode::Script => Ok(None),
+
+        #[cfg(target_os = "linux")]
+        DnsMode::SystemdResolved => Ok(Some(Box::new(SystemdResolved::new()?))),
+
+        #[cfg(not(target_os = "linux"))]
+        DnsMode::SystemdResolved => Err(DnsError::Unsupported("systemd-resolved".to_string())),
+
+        #[cfg(not(target_os = "windows"))]
+        DnsMode::ResolvConf => Ok(Some(Box::new(ResolvConf::new()))),
+
+        #[cfg(target_os = "windows")]
+        DnsMode::ResolvConf => Err(DnsError::Unsupported("resolv.conf".to_string())),
+    }
+}
+
+/// Per-link DNS through the systemd-resolved D-Bus API
+///
+/// Split DNS domains are registered as routing-only domains, so only queries for them reach the VPN resolvers.
+/// Link settings vanish together with the tun device, so nothing is left behind if the process crashes.
+#[cfg(target_os = "linux")]
+pub struct SystemdResolved {
+    connection: zbus::blocking::Connection,
+    ifindex: std::sync::Mutex<Option<i32>>,
+}
+
+#[cfg(target_os = "linux")]
+impl SystemdResolved {
+    const DESTINATION: &'static str = "org.freedesktop.resolve1";
+    const PATH: &'static str = "/org/freedesktop/resolve1";
+    const INTERFACE: &'static str = "org.freedesktop.resolve1.Manager";
+
+    /// Connect to systemd-resolved on the system bus
+    pub fn new() -> Result<Self, DnsError> {
+        let connection =
+            zbus::blocking::Connection::system().map_err(|e| DnsError::BusError(e.to_string()))?;
+        Ok(Self::with_connection(connection))
+    }
+
+    /// Connect to a bus at the given address, e.g. a private bus hosting a mock resolve1 service
+    pub fn with_address(address: &str) -> Result<Self, DnsError> {
+        let connection = zbus::blocking::ConnectionBuilder::address(address)
+            .and_then(|builder| builder.build())
+            .map_err(|e| DnsError::BusError(e.to_string()))?;
+        Ok(Self::with_connection(connection))
+    }
+
+    pub fn with_connection(connection: zbus::blocking::Connection) -> Self {
+        Self {
+            connection,
+            ifindex: std::sync::Mutex::new(None),
+        }
+    }
+
+    fn call<B>(&self, method: &str, body: &B) -> Result<(), DnsError>
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        self.connection
+            .call_method(
+                Some(Self::DESTINATION),
+                Self::PATH,
+                Some(Self::INTERFACE),
+                method,
+                body,
+            )
+            .map(|_| ())
+            .map_err(|e| DnsError::BusError(format!("{} failed: {}", method, e)))
+    }
+
+    fn ifindex(ifname: &str) -> Result<i32, DnsError> {
+        let name = std::ffi::CString::new(ifname)
+            .map_err(|_| DnsError::InterfaceNotFound(ifname.to_string()))?;
+        match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+            0 => Err(DnsError::InterfaceNotFound(ifname.to_string())),
+            index => Ok(index as i32),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl DnsBackend for SystemdResolved {
+    fn apply(&self, ifname: &str, settings: &DnsSettings) -> Result<(), DnsError> {
+        let ifindex = Self::ifindex(ifname)?;
+
+        let servers = settings
+            .servers
+            .iter()
+            .map(|server| match server {
+                IpAddr::V4(v4) => (libc::AF_INET, v4.octets().to_vec()),
+                IpAddr::V6(v6) => (libc::AF_INET6, v6.octets().to_vec()),
+            })
+            .collect::<Vec<_>>();
+
+        // (domain, routing_only)
+        let domains = settings
+            .search_domains
+            .iter()
+            .map(|domain| (domain.as_str(), false))
+            .chain(
+                settings
+                    .routing_domains
+                    .iter()
+                    .map(|domain| (domain.as_str(), true)),
+            )
+            .collect::<Vec<_>>();
+
+        self.call("SetLinkDNS", &(ifindex, servers))?;
+        self.call("SetLinkDomains", &(ifindex, domains))?;
+        self.call(
+            "SetLinkDefaultRoute",
+            &(ifindex, settings.is_default_route()),
+        )?;
+
+        if let Ok(mut saved) = self.ifindex.lock() {
+            *saved = Some(ifindex);
+        }
+
+        tracing::debug!(
+            "Applied DNS settings to link {} via systemd-resolved",
+            ifname
+        );
+        Ok(())
+    }
+
+    fn restore(&self) -> Result<(), DnsError> {
+        let ifindex = self.ifindex.lock().ok().and_then(|mut saved| saved.take());
+        if let Some(ifindex) = ifindex {
+            self.call("RevertLink", &(ifindex,))?;
+            tracing::debug!("Reverted DNS settings of link {}", ifindex);
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites resolv.conf so that the VPN resolvers are queried first
+///
+/// resolv.conf has no notion of split DNS, so the split domains are only added to the search list.
+/// The original file is renamed to a backup next to it and renamed back on restore, a file which did not exist is
+/// recorded by a marker next to it and removed again on restore.
+/// A backup or marker left behind by a crashed process is restored when a client is created and before the file is
+/// rewritten again.
+#[cfg(not(target_os = "windows"))]
+pub struct ResolvConf {
+    path: PathBuf,
+    backup: PathBuf,
+    absent_marker: PathBuf,
+}
+
+#[cfg(not(target_os = "windows"))]
+impl ResolvConf {
+    pub const DEFAULT_PATH: &'static str = "/etc/resolv.conf";
+
+    // glibc only reads the first 3 nameservers
+    const MAX_NAMESERVERS: usize = 3;
+
+    pub fn new() -> Self {
+        Self::with_path(Self::DEFAULT_PATH)
+    }
+
+    pub fn with_path<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let next_to_path = |suffix: &str| {
+            let mut file = path.clone().into_os_string();
+            file.push(suffix);
+            PathBuf::from(file)
+        };
+        Self {
+            backup: next_to_path(".openconnect-rs.bak"),
+            absent_marker: next_to_path(".openconnect-rs.absent"),
+            path,
+        }
+    }
+
+    pub fn backup_path(&self) -> &Path {
+        &self.backup
+    }
+
+    /// Restore a backup left behind by a previous session, returns whether a backup or marker was found
+    pub fn restore_stale(&self) -> Result<bool, DnsError> {
+        if self.backup.exists() {
+            std::fs::rename(&self.backup, &self.path)?;
+            tracing::warn!(
+                "Restored stale resolv.conf backup: {}",
+                self.backup.display()
+            );
+            return Ok(true);
+        }
+
+        if self.absent_marker.exists() {
+            match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            std::fs::remove_file(&self.absent_marker)?;
+            tracing::warn!(
+                "Removed {} which did not exist before the VPN connected",
+                self.path.display()
+            );
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    pub(crate) fn render(original: &str, settings: &DnsSettings) -> String {
+        let mut nameservers = settings
+            .servers
+            .iter()
+            .map(|server| server.to_string())
+            .collect::<Vec<_>>();
+        let mut search = settings.search_domains.clone();
+        search.extend(settings.routing_domains.iter().cloned());
+        let mut others = vec![];
+
+        for line in original.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    if let Some(server) = words.next() {
+                        if !nameservers.iter().any(|s| s == server) {
+                            nameservers.push(server.to_string());
+                        }
+                    }
+                }
+                Some("search") | Some("domain") => {
+                    for domain in words {
+                        if !search.iter().any(|d| d == domain) {
+                            search.push(domain.to_string());
+                        }
+                    }
+                }
+                Some(word) if word.starts_with('#') || word.starts_with(';') => {}
+                Some(_) => others.push(line.to_string()),
+                None => {}
+            }
+        }
+
+        let mut content = String::from("# Generated by openconnect-rs, do not edit\n");
+        for server in nameservers.iter().take(Self::MAX_NAMESERVERS) {
+            content.push_str(&format!("nameserver {}\n", server));
+        }
+        if !search.is_empty() {
+            content.push_str(&format!("search {}\n", search.join(" ")));
+        }
+        for line in others {
+            content.push_str(&line);
+            content.push('\n');
+        }
+        content
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+impl Default for ResolvConf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+impl DnsBackend for ResolvConf {
+    fn apply(&self, _ifname: &str, settings: &DnsSettings) -> Result<(), DnsError> {
+        self.restore_stale()?;
+
+        // follows symlinks, e.g. when resolv.conf points to the resolved stub
+        let original = std::fs::read_to_string(&self.path).unwrap_or_default();
+        let content = Self::render(&original, settings);
+
+        // rename keeps a symlink intact, so restoring puts back exactly what was there
+        if self.path.symlink_metadata().is_ok() {
+            std::fs::rename(&self.path, &self.backup)?;
+        } else {
+            std::fs::write(&self.absent_marker, "")?;
+        }
+
+        if let Err(e) = std::fs::write(&self.path, content) {
+            let _ = self.restore();
+            return Err(e.into());
+        }
+
+        tracing::debug!("Rewrote {}", self.path.display());
+        Ok(())
+    }
+
+    fn restore(&self) -> Result<(), DnsError> {
+        if self.restore_stale()? {
+            tracing::debug!("Restored {}", self.path.display());
+        }
+        Ok(())
+    }
+
+    fn recover(&self) -> Result<(), DnsError> {
+        self.restore_stale().map(|_| ())
+    }
+}
+
+#[test]
+fn test_dns_settings_from_ip_info() {
+    let info = IpInfo {
+        addr: Some("10.0.0.2".to_string()),
+        netmask: None,
+        addr6: None,
+        netmask6: None,
+        dns: [
+            Some("10.0.0.53".to_string()),
+            Some("fd00::53".to_string()),
+            None,
+        ],
+        nbns: [None, None, None],
+        domain: Some("corp.example.com example.com".to_string()),
+        proxy_pac: None,
+        mtu: 1400,
+        split_dns: vec!["~internal.example.com".to_string()],
+        split_includes: vec![],
+        split_excludes: vec![],
+        gateway_addr: None,
+    };
+
+    let settings = DnsSettings::from(&info);
+    assert_eq!(
+        settings.servers,
+        vec![
+            "10.0.0.53".parse::<IpAddr>().unwrap(),
+            "fd00::53".parse::<IpAddr>().unwrap()
+        ]
+    );
+    assert_eq!(
+        settings.search_domains,
+        vec!["corp.example.com", "example.com"]
+    );
+    assert_eq!(settings.routing_domains, vec!["internal.example.com"]);
+    assert!(!settings.is_default_route());
+}
+
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn test_resolv_conf_apply_and_restore() {
+    let dir = std::env::temp_dir().join(format!("openconnect-rs-dns-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("resolv.conf");
+    let original = "# original\nnameserver 192.168.1.1\nsearch home.lan\noptions edns0\n";
+    std::fs::write(&path, original).unwrap();
+
+    let settings = DnsSettings {
+        servers: vec!["10.0.0.53".parse().unwrap()],
+        search_domains: vec!["corp.example.com".to_string()],
+        routing_domains: vec![],
+    };
+
+    let backend = ResolvConf::with_path(&path);
+    backend.apply("tun0", &settings).unwrap();
+    let rewritten = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        rewritten,
+        "# Generated by openconnect-rs, do not edit\nnameserver 10.0.0.53\nnameserver 192.168.1.1\nsearch corp.example.com home.lan\noptions edns0\n"
+    );
+    assert!(backend.backup_path().exists());
+
+    // applying twice must not overwrite the backup of the original file
+    backend.apply("tun0", &settings).unwrap();
+
+    backend.restore().unwrap();
+    backend.restore().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), original);
+    assert!(!backend.backup_path().exists());
+
+    // a file which did not exist is removed again
+    std::fs::remove_file(&path).unwrap();
+    backend.apply("tun0", &settings).unwrap();
+    assert!(std::fs::read_to_string(&path)
+        .unwrap()
+        .contains("nameserver 10.0.0.53"));
+    backend.restore().unwrap();
+    assert!(!path.exists());
+
+    // the leftovers of a crashed session are cleaned up by the next client
+    backend.apply("tun0", &settings).unwrap();
+    ResolvConf::with_path(&path).recover().unwrap();
+    assert!(!path.exists());
+    std::fs::write(&path, original).unwrap();
+    backend.apply("tun0", &settings).unwrap();
+    ResolvConf::with_path(&path).recover().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), original);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_systemd_resolved_calls() {
+    use std::sync::{Arc, Mutex};
+
+    /// Stand-in for org.freedesktop.resolve1.Manager recording every call
+    struct Resolve1 {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.resolve1.Manager")]
+    impl Resolve1 {
+        #[zbus(name = "SetLinkDNS")]
+        fn set_link_dns(&self, ifindex: i32, servers: Vec<(i32, Vec<u8>)>) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("SetLinkDNS {} {:?}", ifindex, servers));
+        }
+
+        #[zbus(name = "SetLinkDomains")]
+        fn set_link_domains(&self, ifindex: i32, domains: Vec<(String, bool)>) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("SetLinkDomains {} {:?}", ifindex, domains));
+        }
+
+        #[zbus(name = "SetLinkDefaultRoute")]
+        fn set_link_default_route(&self, ifindex: i32, enable: bool) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("SetLinkDefaultRoute {} {}", ifindex, enable));
+        }
+
+        #[zbus(name = "RevertLink")]
+        fn revert_link(&self, ifindex: i32) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("RevertLink {}", ifindex));
+        }
+    }
+
+    // a peer-to-peer connection over a socket pair stands in for the system bus
+    let (server_stream, client_stream) = tokio::net::UnixStream::pair().unwrap();
+    let calls = Arc::new(Mutex::new(vec![]));
+    let server = zbus::connection::Builder::unix_stream(server_stream)
+        .server(zbus::Guid::generate())
+        .unwrap()
+        .p2p()
+        .serve_at(
+            SystemdResolved::PATH,
+            Resolve1 {
+                calls: calls.clone(),
+            },
+        )
+        .unwrap()
+        .build();
+    let client = zbus::connection::Builder::unix_stream(client_stream)
+        .p2p()
+        .build();
+    let (server, client) = tokio::join!(server, client);
+    let (_server, client) = (server.unwrap(), client.unwrap());
+
+    let settings = DnsSettings {
+        servers: vec!["10.0.0.53".parse().unwrap(), "fd00::53".parse().unwrap()],
+        search_domains: vec!["corp.example.com".to_string()],
+        routing_domains: vec!["internal.example.com".to_string()],
+    };
+
+    let ifindex = SystemdResolved::ifindex("lo").unwrap();
+    tokio::task::spawn_blocking(move || {
+        let backend = SystemdResolved::with_connection(client.into());
+        backend.apply("lo", &settings).unwrap();
+        backend.restore().unwrap();
+        // the link is only reverted once
+        backend.restore().unwrap();
+    })
+    .await
+    .unwrap();
+
+    let mut fd00 = vec![0xfd, 0];
+    fd00.extend([0; 13]);
+    fd00.push(0x53);
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            format!(
+                "SetLinkDNS {} [({}, [10, 0, 0, 53]), ({}, {:?})]",
+                ifindex,
+                libc::AF_INET,
+                libc::AF_INET6,
+                fd00
+            ),
+            format!(
+                "SetLinkDomains {} [(\"corp.example.com\", false), (\"internal.example.com\", true)]",
+                ifindex
+            ),
+            format!("SetLinkDefaultRoute {} false", ifindex),
+            format!("RevertLink {}", ifindex),
+        ]
+    );
+}
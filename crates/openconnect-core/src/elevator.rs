@@ -52,7 +52,7 @@ pub mod windows {
     pub fn elevate(cmd: &Command, with_cmd: bool) -> std::io::Result<Output> {
         let args = cmd
             .get_args()
-            .map(|c| c.to_str().expect("Invalid args").to_string())
+            .map(|c| c.to_string_lossy().to_string())
             .collect::<Vec<_>>();
 
         let parameters = if args.is_empty() {
@@ -115,7 +115,7 @@ pub mod macos {
     pub fn elevate(cmd: &Command) -> Result<()> {
         let args = cmd
             .get_args()
-            .map(|c| c.to_str().expect("Invalid args").to_string())
+            .map(|c| c.to_string_lossy().to_string())
             .collect::<Vec<_>>();
 
         let flags = Flags::default();
@@ -1,5 +1,55 @@
-use crate::{result::OpenconnectError, Status};
-use std::sync::Arc;
+use crate::{
+    browser::BrowserHandler, config::LogLevel, result::OpenconnectError, webview::WebviewHandler,
+    Status,
+};
+use std::{sync::Arc, time::SystemTime};
+
+/// Describe why the main loop of the client stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The connection was cancelled by [crate::Connectable::disconnect] or a signal
+    Cancelled,
+
+    /// Dead peer detection found the gateway unreachable and reconnecting failed
+    DeadPeer,
+
+    /// The gateway closed the session because it was idle, with the idle timeout in seconds announced by the server
+    IdleTimeout(i32),
+
+    /// The main loop stopped because of another error
+    Error(OpenconnectError),
+}
+
+/// Events of libopenconnect which are only reported through its progress log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LogEvent {
+    /// Dead peer detection gave up on the gateway
+    DeadPeer,
+
+    /// The gateway closed the session, the rest of the message tells why
+    ServerDisconnect,
+}
+
+/// Prefixes of the progress messages recognized as [LogEvent]
+///
+/// libopenconnect translates its messages, so these only match when it runs with an English or untranslated locale.
+const LOG_PATTERNS: &[(&str, LogEvent)] = &[
+    (
+        "CSTP Dead Peer Detection detected dead peer",
+        LogEvent::DeadPeer,
+    ),
+    ("Received server disconnect", LogEvent::ServerDisconnect),
+    ("Session terminated by server", LogEvent::ServerDisconnect),
+];
+
+impl LogEvent {
+    pub(crate) fn from_message(message: &str) -> Option<Self> {
+        LOG_PATTERNS
+            .iter()
+            .find(|(prefix, _)| message.starts_with(prefix))
+            .map(|(_, event)| *event)
+    }
+}
 
 /// Event handlers for the VPN client.
 #[allow(clippy::type_complexity)]
@@ -7,6 +57,13 @@ use std::sync::Arc;
 pub struct EventHandlers {
     pub(crate) handle_connection_state_change: Option<Arc<dyn Fn(Status)>>,
     pub(crate) handle_peer_cert_invalid: Option<Arc<dyn Fn(&str) -> bool>>,
+    pub(crate) handle_disconnect: Option<Arc<dyn Fn(DisconnectReason)>>,
+    pub(crate) handle_auth_expiring: Option<Arc<dyn Fn(SystemTime)>>,
+    pub(crate) handle_reauth: Option<Arc<dyn Fn() -> Option<String>>>,
+    pub(crate) handle_challenge: Option<Arc<dyn Fn(&str) -> Option<String>>>,
+    pub(crate) handle_progress: Option<Arc<dyn Fn(LogLevel, &str)>>,
+    pub(crate) browser_handler: Option<Arc<dyn BrowserHandler>>,
+    pub(crate) webview_handler: Option<Arc<dyn WebviewHandler>>,
 }
 
 impl EventHandlers {
@@ -14,6 +71,13 @@ impl EventHandlers {
         Self {
             handle_connection_state_change: None,
             handle_peer_cert_invalid: None,
+            handle_disconnect: None,
+            handle_auth_expiring: None,
+            handle_reauth: None,
+            handle_challenge: None,
+            handle_progress: None,
+            browser_handler: None,
+            webview_handler: None,
         }
     }
 
@@ -34,6 +98,86 @@ impl EventHandlers {
         self.handle_peer_cert_invalid = Some(Arc::new(handler));
         self
     }
+
+    pub fn with_handle_disconnect<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(DisconnectReason),
+        F: Send + 'static,
+    {
+        self.handle_disconnect = Some(Arc::new(handler));
+        self
+    }
+
+    /// Called once the session is about to expire, with the expiration time reported by the server
+    ///
+    /// The warning time is configured by [crate::config::ConfigBuilder::expiry_warning]
+    pub fn with_handle_auth_expiring<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(SystemTime),
+        F: Send + 'static,
+    {
+        self.handle_auth_expiring = Some(Arc::new(handler));
+        self
+    }
+
+    /// Obtain a fresh cookie with the stored credentials when [crate::config::ConfigBuilder::auto_reauth] is enabled
+    ///
+    /// The handler runs on a background thread and may block, return `None` if the cookie can not be obtained
+    pub fn with_handle_reauth<F>(mut self, handler: F) -> Self
+    where
+        F: Fn() -> Option<String>,
+        F: Send + 'static,
+    {
+        self.handle_reauth = Some(Arc::new(handler));
+        self
+    }
+
+    /// Answer a second factor, e.g. the code of an authenticator asked once the password was accepted
+    ///
+    /// The handler gets the message of the form, or the label of the field, and returns `None` to give up the login.
+    /// Without a challenge handler, the password of the entrypoint is sent again
+    pub fn with_handle_challenge<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+        F: Send + 'static,
+    {
+        self.handle_challenge = Some(Arc::new(handler));
+        self
+    }
+
+    /// Receive the progress lines of libopenconnect, e.g. to show them in a log view
+    ///
+    /// Lines above the level of [crate::config::ConfigBuilder::loglevel] are not reported by libopenconnect
+    pub fn with_handle_progress<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(LogLevel, &str),
+        F: Send + 'static,
+    {
+        self.handle_progress = Some(Arc::new(handler));
+        self
+    }
+
+    /// Open the single sign-on page when the gateway asks for SAML authentication, e.g. [crate::browser::SystemBrowser]
+    ///
+    /// Without a browser handler the client does not offer single sign-on to the gateway
+    pub fn with_browser_handler<H>(mut self, handler: H) -> Self
+    where
+        H: BrowserHandler + 'static,
+    {
+        self.browser_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Show the single sign-on page in an embedded webview, which reports the cookies of each page load back to the client
+    ///
+    /// Used instead of the browser handler for gateways which only accept tokens obtained inside the client
+    pub fn with_webview_handler<H>(mut self, handler: H) -> Self
+    where
+        H: WebviewHandler + 'static,
+    {
+        self.webview_handler = Some(Arc::new(handler));
+        self
+    }
 }
 
 impl Default for EventHandlers {
@@ -46,3 +190,25 @@ pub(crate) trait Events {
     fn emit_state_change(&self, status: Status);
     fn emit_error(&self, error: &OpenconnectError);
 }
+
+#[test]
+fn test_log_event_from_message() {
+    assert_eq!(
+        LogEvent::from_message("CSTP Dead Peer Detection detected dead peer!"),
+        Some(LogEvent::DeadPeer)
+    );
+    assert_eq!(
+        LogEvent::from_message("Received server disconnect: b0 (Idle timeout)"),
+        Some(LogEvent::ServerDisconnect)
+    );
+    assert_eq!(
+        LogEvent::from_message("Session terminated by server; reason: Idle Timeout"),
+        Some(LogEvent::ServerDisconnect)
+    );
+    assert_eq!(LogEvent::from_message("Connected as 10.0.0.2"), None);
+    // only prefixes are matched
+    assert_eq!(
+        LogEvent::from_message("Warning: Received server disconnect"),
+        None
+    );
+}
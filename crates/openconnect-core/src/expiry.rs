@@ -0,0 +1,87 @@
+use crate::{Connectable, Status, VpnClient};
+use std::{
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::{Duration, SystemTime},
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Whether the expiration is within the warning period and was not handled yet
+fn is_due(
+    now: SystemTime,
+    expiration: SystemTime,
+    warning: Duration,
+    handled: Option<SystemTime>,
+) -> bool {
+    handled != Some(expiration) && now + warning >= expiration
+}
+
+/// Watch the session expiration until `stop` is closed
+///
+/// The expiring event and re-authentication are triggered once for every expiration reported by the server
+pub(crate) fn run(client: &VpnClient, stop: Receiver<()>) {
+    let warning = Duration::from_secs(client.config.expiry_warning as u64);
+    let mut handled: Option<SystemTime> = None;
+
+    while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(CHECK_INTERVAL) {
+        if client.get_status() != Status::Connected {
+            continue;
+        }
+
+        let Some(expiration) = client.get_auth_expiration() else {
+            continue;
+        };
+
+        if !is_due(SystemTime::now(), expiration, warning, handled) {
+            continue;
+        }
+
+        handled = Some(expiration);
+        tracing::info!("Session expires at {:?}", expiration);
+
+        if let Some(ref handler) = client.callbacks.handle_auth_expiring {
+            handler(expiration);
+        }
+
+        if client.config.auto_reauth {
+            client.reauth();
+        }
+    }
+
+    tracing::debug!("Expiration watcher stopped");
+}
+
+#[test]
+fn test_is_due() {
+    let now = SystemTime::now();
+    let warning = Duration::from_secs(300);
+
+    // outside the warning period
+    assert!(!is_due(now, now + Duration::from_secs(301), warning, None));
+
+    // on and within the warning threshold
+    assert!(is_due(now, now + warning, warning, None));
+    assert!(is_due(now, now + Duration::from_secs(10), warning, None));
+
+    // already expired sessions are still reported
+    assert!(is_due(now, now - Duration::from_secs(10), warning, None));
+
+    // each expiration is handled once, a renewed session is watched again
+    let expiration = now + Duration::from_secs(10);
+    assert!(!is_due(now, expiration, warning, Some(expiration)));
+    assert!(is_due(
+        now + Duration::from_secs(3600),
+        expiration + Duration::from_secs(3600),
+        warning,
+        Some(expiration)
+    ));
+
+    // without a warning period the session is only due once it expires
+    assert!(!is_due(
+        now,
+        now + Duration::from_secs(1),
+        Duration::ZERO,
+        None
+    ));
+    assert!(is_due(now, now, Duration::ZERO, None));
+}
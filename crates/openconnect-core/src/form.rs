@@ -1,8 +1,11 @@
-use crate::VpnClient;
+use crate::{
+    result::{self, OpenconnectError, OpenconnectResult},
+    VpnClient,
+};
 use openconnect_sys::{
-    oc_form_opt_select, openconnect_set_option_value, OC_FORM_OPT_HIDDEN, OC_FORM_OPT_IGNORE,
-    OC_FORM_OPT_PASSWORD, OC_FORM_OPT_SELECT, OC_FORM_OPT_TEXT, OC_FORM_OPT_TOKEN,
-    OC_FORM_RESULT_CANCELLED, OC_FORM_RESULT_OK,
+    oc_form_opt_select, OC_FORM_OPT_HIDDEN, OC_FORM_OPT_IGNORE, OC_FORM_OPT_PASSWORD,
+    OC_FORM_OPT_SELECT, OC_FORM_OPT_TEXT, OC_FORM_OPT_TOKEN, OC_FORM_RESULT_CANCELLED,
+    OC_FORM_RESULT_ERR, OC_FORM_RESULT_NEWGROUP, OC_FORM_RESULT_OK,
 };
 use std::{
     ffi::{CStr, CString},
@@ -17,6 +20,10 @@ pub struct FormField {
 
 pub struct FormManager {
     last_form_empty: i32,
+    /// Set once the auth group of the entrypoint was submitted, the gateway answers with the form of that group
+    authgroup_set: bool,
+    /// Set once the stored password was filled in, a form asking for it again with an error means it was rejected
+    password_submitted: bool,
     saved_form_fields: Vec<FormField>, // TODO: currently not in use
 }
 
@@ -25,12 +32,16 @@ impl FormManager {
     pub fn new() -> Self {
         Self {
             last_form_empty: -1,
+            authgroup_set: false,
+            password_submitted: false,
             saved_form_fields: Vec::new(),
         }
     }
 
     pub fn reset(&mut self) {
         self.last_form_empty = -1;
+        self.authgroup_set = false;
+        self.password_submitted = false;
         self.saved_form_fields.clear();
     }
 
@@ -60,13 +71,16 @@ impl FormManager {
 
         for i in 0..(*select_opt).nr_choices {
             let choice = *(*select_opt).choices.offset(i as isize);
-            let choice_label = std::ffi::CStr::from_ptr((*choice).label).to_str().unwrap();
-            if label[..input_len].eq_ignore_ascii_case(&choice_label[..input_len]) {
+            let choice_label = std::ffi::CStr::from_ptr((*choice).label).to_string_lossy();
+            if choice_label
+                .get(..input_len)
+                .is_some_and(|prefix| label.eq_ignore_ascii_case(prefix))
+            {
                 if choice_label.len() == input_len {
                     (*select_opt).form._value = (*choice).name;
                     return 0;
                 }
-                match_ = (*choice).label;
+                match_ = (*choice).name;
                 partial_matches += 1;
             }
         }
@@ -76,10 +90,10 @@ impl FormManager {
             return 0;
         }
         if partial_matches > 1 {
-            println!("Auth choice '{}' is ambiguous", label);
+            tracing::warn!("Auth choice '{}' is ambiguous", label);
             -libc::EINVAL
         } else {
-            println!("Auth choice '{}' not found", label);
+            tracing::warn!("Auth choice '{}' not found", label);
             -libc::EINVAL
         }
     }
@@ -93,40 +107,84 @@ impl FormManager {
         tracing::debug!("Calling process_auth_form_cb");
 
         let client = unsafe { VpnClient::ref_from_raw(privdata) };
-        unsafe {
+        result::guard_callback(
+            Some(client),
+            "process_auth_form",
+            OC_FORM_RESULT_ERR,
+            || {
+                // a probe stops at the first form, before any credential is filled in
+                if client.is_probing() {
+                    unsafe { client.record_probe_form(form) };
+                    return OC_FORM_RESULT_CANCELLED as i32;
+                }
+
+                match unsafe { Self::process_auth_form(client, form) } {
+                    Ok(result) => result,
+                    Err(e) => {
+                        tracing::error!("{}", e);
+                        client.set_callback_error(e);
+                        OC_FORM_RESULT_ERR
+                    }
+                }
+            },
+        )
+    }
+
+    unsafe fn process_auth_form(
+        client: &VpnClient,
+        form: *mut openconnect_sys::oc_auth_form,
+    ) -> OpenconnectResult<::std::os::raw::c_int> {
+        {
             // TODO: review this
-            let mut this = client
-                .form_manager
-                .try_write()
-                .expect("try_write form_context failed");
+            let mut this = client.form_manager.try_write().map_err(|_| {
+                OpenconnectError::ProcessAuthFormError("form manager is busy".to_string())
+            })?;
 
-            let mut opt = (*form).opts;
+            let mut next_opt = (*form).opts;
             let mut empty = 1;
 
             if (*form).auth_id.is_null() {
-                return -libc::EINVAL;
+                return Ok(-libc::EINVAL);
             }
 
             if !(*form).error.is_null() {
                 let error: String = std::ffi::CStr::from_ptr((*form).error)
                     .to_string_lossy()
                     .into();
-                println!("Authentication failed: {}", error);
+                tracing::warn!("Authentication failed: {}", error);
             }
 
-            if !(*form).authgroup_opt.is_null() {
-                // TODO: implement authgroup
-                println!("authgroup_opt");
+            if let Some(group) = client.auth_group() {
+                let select_opt = (*form).authgroup_opt;
+                if select_opt.is_null() {
+                    tracing::warn!("The gateway does not offer auth groups, ignoring {}", group);
+                } else {
+                    if this.match_choice_label(select_opt, &group) != 0 {
+                        return Err(OpenconnectError::ProcessAuthFormError(format!(
+                            "auth group {} is not offered by the gateway",
+                            group
+                        )));
+                    }
+
+                    // the fields depend on the group, so the gateway is asked for the form of the chosen one first
+                    let changed = !this.authgroup_set && !is_selected_group(form);
+                    this.authgroup_set = true;
+                    if changed {
+                        return Ok(OC_FORM_RESULT_NEWGROUP as i32);
+                    }
+                }
             }
 
-            'loop_opt: while !opt.is_null() {
+            'loop_opt: while !next_opt.is_null() {
+                let opt = next_opt;
+                next_opt = (*opt).next;
+
                 if ((*opt).flags & OC_FORM_OPT_IGNORE) != 0 {
                     continue 'loop_opt;
                 }
 
                 match (*opt).type_ as u32 {
                     OC_FORM_OPT_SELECT => {
-                        println!("OC_FORM_OPT_SELECT");
                         let select_opt = opt.cast::<oc_form_opt_select>();
 
                         if select_opt == (*form).authgroup_opt {
@@ -147,11 +205,11 @@ impl FormManager {
                         empty = 0;
                     }
                     OC_FORM_OPT_TEXT => {
-                        let opt_name = std::ffi::CStr::from_ptr((*opt).name).to_str().unwrap();
-                        let value = client.handle_text_input(opt_name);
+                        let opt_name = std::ffi::CStr::from_ptr((*opt).name).to_string_lossy();
+                        let value = client.handle_text_input(&opt_name)?;
                         if let Some(value) = value {
-                            let value = CString::new(value).unwrap();
-                            openconnect_set_option_value(opt, value.as_ptr());
+                            let value = to_option_value(&opt_name, value)?;
+                            client.backend.set_option_value(opt, &value);
 
                             // if client.form_attempt == 0
                             //     && (opt_name == "user" || opt_name == "uname" || opt_name == "username")
@@ -171,33 +229,45 @@ impl FormManager {
                             // }
 
                             if (*opt)._value.is_null() {
-                                println!("No value for {}", opt_name);
+                                tracing::warn!("No value for {}", opt_name);
                                 // goto error;
                             }
                             empty = 0;
                         }
                     }
                     OC_FORM_OPT_PASSWORD => {
-                        let value = client.handle_password_input();
+                        let opt_name = CStr::from_ptr((*opt).name).to_string_lossy();
+                        let prompt = [(*form).message, (*opt).label]
+                            .into_iter()
+                            .find(|text| !text.is_null())
+                            .map(|text| CStr::from_ptr(text).to_string_lossy())
+                            .unwrap_or_default();
+                        let stored = is_stored_password(&opt_name);
+                        if stored && this.password_submitted && !(*form).error.is_null() {
+                            // the same password would be rejected again
+                            tracing::error!("The password was rejected by the gateway");
+                            return Ok(OC_FORM_RESULT_CANCELLED as i32);
+                        }
+
+                        let value = client.handle_password_input(&opt_name, &prompt);
                         if let Some(value) = value {
-                            let value = CString::new(value).unwrap();
-                            openconnect_set_option_value(opt, value.as_ptr());
+                            let value = to_option_value("password", value)?;
+                            client.backend.set_option_value(opt, &value);
+                            this.password_submitted |= stored;
                             empty = 0;
                         }
                     }
                     OC_FORM_OPT_TOKEN => {
-                        println!("OC_FORM_OPT_TOKEN");
                         // Nothing to do here
                         empty = 0;
                     }
                     OC_FORM_OPT_HIDDEN => {
-                        println!("OC_FORM_OPT_HIDDEN");
                         let auth_id = CStr::from_ptr((*form).auth_id).to_str().ok();
                         let opt_id = CStr::from_ptr((*opt).name).to_str().ok();
                         let value = this.saved_form_field(auth_id, opt_id);
-                        if value.is_some() {
-                            let value = CString::new(value.unwrap()).unwrap();
-                            openconnect_set_option_value(opt, value.as_ptr());
+                        if let Some(value) = value {
+                            let value = to_option_value(opt_id.unwrap_or_default(), value)?;
+                            client.backend.set_option_value(opt, &value);
                         } else {
                             // TODO: implement prompt;
                         }
@@ -206,30 +276,50 @@ impl FormManager {
                         continue 'loop_opt;
                     }
                 }
-
-                opt = (*opt).next;
             }
 
-            // TODO: optimize this stupid empty check
-            if empty != 0 {
+            if empty == 0 {
                 this.last_form_empty = 0;
-            } else if {
+            } else {
                 this.last_form_empty += 1;
-                this.last_form_empty
-            } >= 3
-            {
-                tracing::debug!(
-                    "{} consecutive empty forms, aborting loop",
-                    this.last_form_empty
-                );
-                println!();
-                return OC_FORM_RESULT_CANCELLED as i32;
+                if this.last_form_empty >= 3 {
+                    tracing::error!(
+                        "{} consecutive empty forms, aborting loop",
+                        this.last_form_empty
+                    );
+                    return Ok(OC_FORM_RESULT_CANCELLED as i32);
+                }
             }
         }
 
         tracing::debug!("Successfully processed auth form");
-        OC_FORM_RESULT_OK as i32
+        Ok(OC_FORM_RESULT_OK as i32)
+    }
+}
+
+/// Whether a password field asks for the password of the entrypoint rather than a challenge
+pub(crate) fn is_stored_password(field_name: &str) -> bool {
+    matches!(field_name, "password" | "passwd" | "pass")
+}
+
+/// Whether the choice of the auth group select is the group the gateway already selected in the form
+unsafe fn is_selected_group(form: *mut openconnect_sys::oc_auth_form) -> bool {
+    let select_opt = (*form).authgroup_opt;
+    let Ok(selection) = usize::try_from((*form).authgroup_selection) else {
+        return false;
+    };
+    if selection >= usize::try_from((*select_opt).nr_choices).unwrap_or_default() {
+        return false;
     }
+
+    let choice = *(*select_opt).choices.add(selection);
+    (*choice).name == (*select_opt).form._value
+}
+
+fn to_option_value(opt_name: &str, value: String) -> OpenconnectResult<CString> {
+    CString::new(value).map_err(|_| {
+        OpenconnectError::ProcessAuthFormError(format!("value of {} contains a nul byte", opt_name))
+    })
 }
 
 impl Default for FormManager {
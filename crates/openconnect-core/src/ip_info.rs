@@ -1,13 +1,7 @@
-use openconnect_sys::oc_ip_info;
+use openconnect_sys::{oc_ip_info, oc_split_include};
 use std::ffi::CStr;
 
-// TODO: Implement SplitInclude
-// pub struct SplitInclude {
-//     pub route: String,
-//     pub next: Arc<SplitInclude>,
-// }
-
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct IpInfo {
     pub addr: Option<String>,
     pub netmask: Option<String>,
@@ -18,9 +12,13 @@ pub struct IpInfo {
     pub domain: Option<String>,
     pub proxy_pac: Option<String>,
     pub mtu: i32,
-    // pub split_dns: SplitInclude,
-    // pub split_includes: SplitInclude,
-    // pub split_excludes: SplitInclude,
+    /// Domains which should be resolved by the VPN DNS servers only
+    #[serde(default)]
+    pub split_dns: Vec<String>,
+    #[serde(default)]
+    pub split_includes: Vec<String>,
+    #[serde(default)]
+    pub split_excludes: Vec<String>,
     pub gateway_addr: Option<String>,
 }
 
@@ -32,6 +30,17 @@ unsafe fn raw_to_string(raw: *const i8) -> Option<String> {
     }
 }
 
+unsafe fn split_include_to_vec(mut raw: *const oc_split_include) -> Vec<String> {
+    let mut routes = vec![];
+    while !raw.is_null() {
+        if let Some(route) = raw_to_string((*raw).route) {
+            routes.push(route);
+        }
+        raw = (*raw).next;
+    }
+    routes
+}
+
 impl From<&oc_ip_info> for IpInfo {
     fn from(value: &oc_ip_info) -> Self {
         unsafe {
@@ -54,6 +63,9 @@ impl From<&oc_ip_info> for IpInfo {
                 domain: raw_to_string(value.domain),
                 proxy_pac: raw_to_string(value.proxy_pac),
                 mtu: value.mtu,
+                split_dns: split_include_to_vec(value.split_dns),
+                split_includes: split_include_to_vec(value.split_includes),
+                split_excludes: split_include_to_vec(value.split_excludes),
                 gateway_addr: raw_to_string(value.gateway_addr),
             }
         }
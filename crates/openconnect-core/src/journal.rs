@@ -0,0 +1,248 @@
+use crate::{ClientId, Status};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Identify a connection attempt across processes, the start time in microseconds since the epoch
+pub type AttemptId = u64;
+
+/// The journal is rotated once it grows over this size, only the previous file is kept
+const MAX_JOURNAL_SIZE: u64 = 1024 * 1024;
+
+/// One status transition of a connection attempt, stored as a JSON line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineEntry {
+    pub attempt: AttemptId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    /// RFC 3339 time of the transition
+    pub at: String,
+    /// One of the names of [Status::as_str], or `AUTHENTICATED` once a cookie is obtained without connecting
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Milliseconds since the attempt started
+    pub elapsed_ms: u64,
+    /// Milliseconds spent in the previous status
+    pub step_ms: u64,
+}
+
+/// The timeline of one connection attempt, see [Journal::sessions]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSummary {
+    pub attempt: AttemptId,
+    pub server: Option<String>,
+    pub entries: Vec<TimelineEntry>,
+}
+
+impl SessionSummary {
+    pub fn started_at(&self) -> &str {
+        self.entries.first().map_or("", |entry| entry.at.as_str())
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        self.entries.last().map_or(0, |entry| entry.elapsed_ms)
+    }
+
+    pub fn last_status(&self) -> Option<&TimelineEntry> {
+        self.entries.last()
+    }
+
+    pub fn was_connected(&self) -> bool {
+        self.entries.iter().any(|entry| entry.status == "CONNECTED")
+    }
+
+    /// The step the attempt was in when it failed and the error, `None` if it did not fail
+    pub fn failure(&self) -> Option<(Option<&TimelineEntry>, &TimelineEntry)> {
+        let index = self
+            .entries
+            .iter()
+            .rposition(|entry| entry.status == "ERROR")?;
+        let step = self.entries[..index]
+            .iter()
+            .rev()
+            .find(|entry| entry.status != "ERROR");
+        Some((step, &self.entries[index]))
+    }
+}
+
+/// Status transitions of connection attempts, kept next to the logs so that failed connections can be reviewed later
+#[derive(Debug, Clone)]
+pub struct Journal {
+    file: PathBuf,
+}
+
+impl Journal {
+    pub fn new(file: &Path) -> Self {
+        Self {
+            file: file.to_path_buf(),
+        }
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    fn rotated_file(&self) -> PathBuf {
+        let mut name = self.file.file_name().unwrap_or_default().to_os_string();
+        name.push(".1");
+        self.file.with_file_name(name)
+    }
+
+    pub fn append(&self, entry: &TimelineEntry) -> std::io::Result<()> {
+        if let Some(dir) = self.file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        if std::fs::metadata(&self.file).is_ok_and(|metadata| metadata.len() > MAX_JOURNAL_SIZE) {
+            std::fs::rename(&self.file, self.rotated_file())?;
+        }
+
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file)?
+            .write_all(&line)
+    }
+
+    /// All entries of the rotated and the current file, lines which can not be parsed are skipped
+    pub fn read(&self) -> std::io::Result<Vec<TimelineEntry>> {
+        let mut entries = vec![];
+        for file in [self.rotated_file(), self.file.clone()] {
+            let file = match std::fs::File::open(file) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            entries.extend(
+                std::io::BufReader::new(file)
+                    .lines()
+                    .map_while(Result::ok)
+                    .filter_map(|line| serde_json::from_str(&line).ok()),
+            );
+        }
+        Ok(entries)
+    }
+
+    /// Group the entries by attempt, the most recent attempt first
+    pub fn sessions(entries: Vec<TimelineEntry>) -> Vec<SessionSummary> {
+        let mut sessions: Vec<SessionSummary> = vec![];
+        for entry in entries {
+            match sessions
+                .iter_mut()
+                .find(|session| session.attempt == entry.attempt)
+            {
+                Some(session) => session.entries.push(entry),
+                None => sessions.push(SessionSummary {
+                    attempt: entry.attempt,
+                    server: entry.server.clone(),
+                    entries: vec![entry],
+                }),
+            }
+        }
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.attempt));
+        sessions
+    }
+}
+
+/// The connection attempt of a client, from connecting until the main loop stops
+pub(crate) struct Attempt {
+    pub(crate) id: AttemptId,
+    pub(crate) span: tracing::Span,
+    server: Option<String>,
+    started: Instant,
+    last: Instant,
+}
+
+impl Attempt {
+    pub(crate) fn new(client: ClientId, server: Option<String>, protocol: &str) -> Self {
+        let id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_micros() as u64);
+        let span = tracing::info_span!(
+            "connection",
+            attempt = id,
+            client,
+            server = server.as_deref().unwrap_or_default(),
+            protocol
+        );
+        let now = Instant::now();
+        Self {
+            id,
+            span,
+            server,
+            started: now,
+            last: now,
+        }
+    }
+
+    /// The journal entry of a transition to the status
+    pub(crate) fn entry(&mut self, status: &str, message: Option<String>) -> TimelineEntry {
+        let now = Instant::now();
+        let entry = TimelineEntry {
+            attempt: self.id,
+            server: self.server.clone(),
+            at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            status: status.to_string(),
+            message,
+            elapsed_ms: now.duration_since(self.started).as_millis() as u64,
+            step_ms: now.duration_since(self.last).as_millis() as u64,
+        };
+        self.last = now;
+        entry
+    }
+
+    pub(crate) fn status_entry(&mut self, status: &Status) -> TimelineEntry {
+        let message = match status {
+            Status::Connecting(message) => Some(message.clone()),
+            Status::Error(err) => Some(err.to_string()),
+            _ => None,
+        };
+        self.entry(status.as_str(), message)
+    }
+}
+
+#[test]
+fn test_journal() {
+    let file = std::env::temp_dir()
+        .join(format!("openconnect-rs-journal-{}", std::process::id()))
+        .join("timeline.jsonl");
+    let journal = Journal::new(&file);
+
+    let mut first = Attempt::new(1, Some("office".to_string()), "anyconnect");
+    let mut second = Attempt::new(2, Some("lab".to_string()), "anyconnect");
+    second.id = first.id + 1;
+
+    for entry in [
+        first.status_entry(&Status::Connecting("Initializing connection".to_string())),
+        first.status_entry(&Status::Connected),
+        second.status_entry(&Status::Connecting("Obtaining cookie".to_string())),
+        second.status_entry(&Status::Error(
+            crate::result::OpenconnectError::ObtainCookieError(-libc::EPERM),
+        )),
+        first.status_entry(&Status::Disconnected),
+    ] {
+        journal.append(&entry).unwrap();
+    }
+
+    let sessions = Journal::sessions(journal.read().unwrap());
+    let _ = std::fs::remove_dir_all(file.parent().unwrap());
+
+    assert_eq!(sessions.len(), 2);
+    let (lab, office) = (&sessions[0], &sessions[1]);
+    assert_eq!(lab.server.as_deref(), Some("lab"));
+    let (step, error) = lab.failure().unwrap();
+    assert_eq!(step.unwrap().message.as_deref(), Some("Obtaining cookie"));
+    assert!(error.message.as_ref().unwrap().contains("cookie"));
+    assert!(!lab.was_connected());
+
+    assert_eq!(office.entries.len(), 3);
+    assert!(office.was_connected() && office.failure().is_none());
+    assert_eq!(office.last_status().unwrap().status, "DISCONNECTED");
+}
@@ -0,0 +1,67 @@
+use crate::{Connectable, Status, VpnClient};
+use std::{
+    net::{IpAddr, UdpSocket},
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+// A DNS query for the NS records of the root zone, small and harmless for any resolver
+const PROBE: [u8; 17] = [
+    0x6f, 0x63, // id
+    0x01, 0x00, // flags: recursion desired
+    0x00, 0x01, // qdcount
+    0x00, 0x00, // ancount
+    0x00, 0x00, // nscount
+    0x00, 0x00, // arcount
+    0x00, // qname: root
+    0x00, 0x02, // qtype: NS
+    0x00, 0x01, // qclass: IN
+];
+
+/// Pick the address to send keepalive probes to, the first resolver pushed by the gateway is always routed through the tunnel
+///
+/// Returns `None` and warns when the gateway pushed no resolver, there is no other address known to be routed through the tunnel
+pub(crate) fn probe_target(client: &VpnClient) -> Option<IpAddr> {
+    let info = client.get_info().ok().flatten()?;
+    let target = info
+        .dns
+        .iter()
+        .flatten()
+        .find_map(|dns| dns.trim().parse().ok());
+
+    if target.is_none() {
+        tracing::warn!(
+            "Keepalive is disabled, the gateway did not push a DNS server to send probes to"
+        );
+    }
+
+    target
+}
+
+/// Send a probe through the tunnel every `interval` seconds until `stop` is closed
+pub(crate) fn run(client: &VpnClient, target: IpAddr, interval: u32, stop: Receiver<()>) {
+    let interval = Duration::from_secs(interval.max(1) as u64);
+
+    while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+        if client.get_status() != Status::Connected {
+            continue;
+        }
+
+        if let Err(e) = send_probe(target) {
+            tracing::debug!("Failed to send keepalive probe to {}: {}", target, e);
+        }
+    }
+
+    tracing::debug!("Keepalive stopped");
+}
+
+fn send_probe(target: IpAddr) -> std::io::Result<()> {
+    let bind_addr = match target {
+        IpAddr::V4(_) => "0.0.0.0:0",
+        IpAddr::V6(_) => "[::]:0",
+    };
+    let socket = UdpSocket::bind(bind_addr)?;
+    socket.send_to(&PROBE, (target, 53))?;
+    tracing::trace!("Sent keepalive probe to {}", target);
+    Ok(())
+}
@@ -8,35 +8,62 @@
 #![doc = include_str!("../examples/password_server.rs")]
 //! ```
 
+mod backend;
+pub mod browser;
 mod cert;
 pub mod command;
 pub mod config;
+pub mod csd;
+pub mod dns;
 pub mod elevator;
 pub mod events;
+mod expiry;
 mod form;
 pub mod ip_info;
+pub mod journal;
+mod keepalive;
 pub mod log;
+#[cfg(feature = "pac")]
+pub mod pac;
+pub mod probe;
 pub mod protocols;
+pub mod proxy;
+mod registry;
+pub mod resolve;
 pub mod result;
+pub mod secret;
+pub mod session;
 pub mod stats;
 pub mod storage;
+pub mod webview;
 
+use crate::backend::{Backend, FfiBackend};
 use crate::cert::PeerCerts;
-use crate::command::{CmdPipe, SIGNAL_HANDLE};
-use crate::config::{Config, Entrypoint, LogLevel};
-use crate::events::{EventHandlers, Events};
+use crate::command::{CmdPipe, SignalPolicy};
+use crate::config::{
+    CompressionMode, Config, Entrypoint, LogLevel, ReconnectSettings, TunnelOptions,
+};
+use crate::csd::{CsdConfig, CsdMode};
+use crate::dns::{DnsBackend, DnsSettings};
+use crate::events::{DisconnectReason, EventHandlers, Events, LogEvent};
 use crate::form::FormManager;
 use crate::ip_info::IpInfo;
-use crate::log::Logger;
+use crate::journal::{Attempt, Journal, TimelineEntry};
+use crate::probe::ProbeState;
+use crate::proxy::ProxyConfig;
+pub use crate::registry::ClientId;
 use crate::result::{EmitError, OpenconnectError, OpenconnectResult};
+use crate::secret::Secret;
+use crate::session::Session;
 use crate::stats::Stats;
+use crate::webview::WebviewSession;
 
-use openconnect_sys::*;
+use openconnect_sys::RECONNECT_INTERVAL_MIN;
 use std::{
     ffi::CString,
     sync::{
-        atomic::{AtomicI32, Ordering},
-        Arc, RwLock, Weak,
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc, Condvar, Mutex, RwLock, Weak,
     },
 };
 
@@ -58,16 +85,35 @@ pub enum Status {
     /// The client is connected to the VPN server and the main loop is running
     Connected,
 
+    /// The tunnel is torn down by [VpnClient::pause] but the session cookie is kept, waiting for [VpnClient::resume]
+    Paused,
+
     /// The client is in an error state
     Error(OpenconnectError),
 }
 
+impl Status {
+    /// The name of the status without its details, e.g. `CONNECTING`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Initialized => "INITIALIZED",
+            Status::Disconnecting => "DISCONNECTING",
+            Status::Disconnected => "DISCONNECTED",
+            Status::Connecting(_) => "CONNECTING",
+            Status::Connected => "CONNECTED",
+            Status::Paused => "PAUSED",
+            Status::Error(_) => "ERROR",
+        }
+    }
+}
+
 /// VpnClient struct
 ///
 /// This struct is the main entrypoint for interacting with the Openconnect C library (on top of [openconnect-sys](https://crates.io/crates/openconnect-sys))
 #[repr(C)]
 pub struct VpnClient {
-    vpninfo: *mut openconnect_info,
+    backend: Box<dyn Backend>,
+    id: ClientId,
     config: Config,
     cmd_fd: AtomicI32,
     status: RwLock<Status>,
@@ -75,6 +121,29 @@ pub struct VpnClient {
     entrypoint: RwLock<Option<Entrypoint>>,
     form_manager: RwLock<FormManager>,
     peer_certs: PeerCerts,
+    dns_backend: Option<Box<dyn DnsBackend>>,
+    dead_peer_detected: AtomicBool,
+    idle_disconnected: AtomicBool,
+    pending_cookie: RwLock<Option<String>>,
+    /// Set by [VpnClient::pause], told apart from the pause of a re-authentication once the main loop returned
+    pause_requested: AtomicBool,
+    /// Set while a pause command is on its way to the main loop, so that a pause and a re-authentication send only one
+    pause_sent: AtomicBool,
+    pending_settings: RwLock<Option<ReconnectSettings>>,
+    callback_error: RwLock<Option<OpenconnectError>>,
+    last_error_log: RwLock<Option<String>>,
+    pause_action: Mutex<Option<PauseAction>>,
+    pause_cond: Condvar,
+    attempt: RwLock<Option<Attempt>>,
+    journal: Option<Journal>,
+    probe: Mutex<Option<ProbeState>>,
+}
+
+/// What a paused main loop should do next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseAction {
+    Resume,
+    Cancel,
 }
 
 unsafe impl Send for VpnClient {}
@@ -83,29 +152,85 @@ unsafe impl Sync for VpnClient {}
 impl VpnClient {
     pub(crate) extern "C" fn default_setup_tun_vfn(privdata: *mut ::std::os::raw::c_void) {
         let client = unsafe { VpnClient::ref_from_raw(privdata) };
+        result::guard_callback(Some(client), "setup_tun", (), || {
+            if let Some(ref tun_script) = client.config.tun_script {
+                if let Err(e) = client.setup_tun_script(tun_script) {
+                    tracing::error!("Failed to set up tunnel script: {}", e);
+                }
+                return;
+            }
 
-        #[cfg(target_os = "windows")]
-        {
-            // currently use wintun on windows
-            // https://gitlab.com/openconnect/openconnect-gui/-/blob/main/src/vpninfo.cpp?ref_type=heads#L407
-            // TODO: investigate tap ip address allocation, since it works well in Openconnect-GUI
-            let ifname = client
-                .get_hostname()
-                .map(|hostname| format!("tun_{}", hostname));
+            #[cfg(target_os = "windows")]
+            {
+                // currently use wintun on windows
+                // https://gitlab.com/openconnect/openconnect-gui/-/blob/main/src/vpninfo.cpp?ref_type=heads#L407
+                // TODO: investigate tap ip address allocation, since it works well in Openconnect-GUI
+                let ifname = client
+                    .get_hostname()
+                    .map(|hostname| format!("tun_{}", hostname));
 
-            println!("ifname: {:?}", ifname);
+                println!("ifname: {:?}", ifname);
 
-            // TODO: handle result
-            let _result = client.setup_tun_device(None, ifname);
+                // TODO: handle result
+                let _result = client.setup_tun_device(None, ifname);
+            }
+
+            #[cfg(not(target_os = "windows"))]
+            {
+                // TODO: handle result
+                if client.setup_tun_device(None, None).is_ok() {
+                    client.apply_dns();
+                }
+            }
+        });
+    }
+
+    /// Apply DNS settings pushed by the server through the configured [dns::DnsBackend]
+    ///
+    /// This runs after the vpnc-script configured the tun device, so the backend takes precedence over the script
+    pub(crate) fn apply_dns(&self) {
+        let Some(backend) = self.dns_backend.as_ref() else {
+            return;
+        };
+
+        let Some(ifname) = self.get_ifname() else {
+            tracing::error!("Failed to apply DNS settings: tun device name not found");
+            return;
+        };
+
+        let settings = match self.get_info() {
+            Ok(Some(info)) => DnsSettings::from(&info),
+            Ok(None) => DnsSettings::default(),
+            Err(e) => {
+                tracing::error!("Failed to apply DNS settings: {}", e);
+                return;
+            }
+        };
+
+        if settings.is_empty() {
+            tracing::debug!("No DNS servers pushed by server, skip DNS setup");
+            return;
         }
 
-        #[cfg(not(target_os = "windows"))]
-        {
-            // TODO: handle result
-            let _result = client.setup_tun_device(None, None);
+        if let Err(e) = backend.apply(&ifname, &settings) {
+            tracing::error!("Failed to apply DNS settings: {}", e);
         }
     }
 
+    /// Restore DNS settings changed by [VpnClient::apply_dns], safe to call multiple times
+    pub(crate) fn restore_dns(&self) {
+        if let Some(backend) = self.dns_backend.as_ref() {
+            if let Err(e) = backend.restore() {
+                tracing::error!("Failed to restore DNS settings: {}", e);
+            }
+        }
+    }
+
+    /// The unique id of this client within the current process
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
     /// Reclaim a reference from c_void
     ///
     /// SAFETY: You must ensure that the pointer is valid and points to a valid instance of `Self`
@@ -114,18 +239,286 @@ impl VpnClient {
         &*ptr
     }
 
-    pub(crate) fn handle_text_input(&self, field_name: &str) -> Option<String> {
+    /// The auth group to select in the login form, if any
+    pub(crate) fn auth_group(&self) -> Option<String> {
         let entrypoint = self.entrypoint.read().ok()?;
-        let entrypoint = (*entrypoint).as_ref()?;
+        (*entrypoint).as_ref()?.group.clone()
+    }
+
+    pub(crate) fn handle_text_input(&self, field_name: &str) -> OpenconnectResult<Option<String>> {
+        let Some(entrypoint) = self.entrypoint.read().ok() else {
+            return Ok(None);
+        };
+        let Some(entrypoint) = (*entrypoint).as_ref() else {
+            return Ok(None);
+        };
         match field_name {
-            "username" | "user" | "uname" => entrypoint.username.clone(),
-            _ => todo!("handle_text_input: {}", field_name),
+            "username" | "user" | "uname" => Ok(entrypoint.username.clone()),
+            _ => Err(OpenconnectError::UnsupportedFormFieldError(
+                field_name.to_string(),
+            )),
+        }
+    }
+
+    /// Keep an error raised inside a callback, libopenconnect only reports a generic failure code to the caller
+    pub(crate) fn set_callback_error(&self, err: OpenconnectError) {
+        if let Ok(mut callback_error) = self.callback_error.write() {
+            callback_error.get_or_insert(err);
         }
     }
 
-    pub(crate) fn handle_password_input(&self) -> Option<String> {
+    /// Keep the last error line logged by libopenconnect, it is attached to the error of the failing call
+    pub(crate) fn set_last_error_log(&self, message: &str) {
+        if let Ok(mut last_error_log) = self.last_error_log.write() {
+            *last_error_log = Some(message.to_string());
+        }
+    }
+
+    /// Forget errors of previous calls into libopenconnect
+    fn reset_library_errors(&self) {
+        if let Ok(mut callback_error) = self.callback_error.write() {
+            callback_error.take();
+        }
+        if let Ok(mut last_error_log) = self.last_error_log.write() {
+            last_error_log.take();
+        }
+    }
+
+    /// Take the error raised inside a callback, or fall back to the error of the failed call with the last error log attached
+    fn library_error(&self, err: OpenconnectError) -> OpenconnectError {
+        if let Some(callback_error) = self
+            .callback_error
+            .write()
+            .ok()
+            .and_then(|mut callback_error| callback_error.take())
+        {
+            return callback_error;
+        }
+
+        let log = self
+            .last_error_log
+            .write()
+            .ok()
+            .and_then(|mut last_error_log| last_error_log.take());
+        err.with_library_log(log)
+    }
+
+    pub(crate) fn handle_open_browser(&self, uri: &str) -> OpenconnectResult<()> {
+        let handler =
+            self.callbacks
+                .browser_handler
+                .as_ref()
+                .ok_or(OpenconnectError::OpenBrowserError(
+                    "No browser handler".to_string(),
+                ))?;
+
+        self.emit_state_change(Status::Connecting(
+            "Waiting for single sign-on in browser".to_string(),
+        ));
+        handler
+            .open(uri)
+            .map_err(OpenconnectError::OpenBrowserError)
+    }
+
+    pub(crate) fn handle_open_webview(
+        &self,
+        uri: &str,
+        session: Arc<WebviewSession>,
+    ) -> OpenconnectResult<()> {
+        let handler =
+            self.callbacks
+                .webview_handler
+                .as_ref()
+                .ok_or(OpenconnectError::WebviewError(
+                    "No webview handler".to_string(),
+                ))?;
+
+        self.emit_state_change(Status::Connecting(
+            "Waiting for single sign-on in login page".to_string(),
+        ));
+        handler
+            .open(uri, session.clone())
+            .map_err(OpenconnectError::WebviewError)?;
+        session.wait().map_err(OpenconnectError::WebviewError)
+    }
+
+    /// The answer to a password field, the entrypoint password for the login and the challenge handler for other fields
+    pub(crate) fn handle_password_input(&self, field_name: &str, prompt: &str) -> Option<String> {
+        if !form::is_stored_password(field_name) {
+            if let Some(ref handler) = self.callbacks.handle_challenge {
+                return handler(prompt);
+            }
+        }
+
         let entrypoint = self.entrypoint.read().ok()?;
-        (*entrypoint).as_ref()?.password.clone()
+        (*entrypoint)
+            .as_ref()?
+            .password
+            .clone()
+            .map(Secret::into_inner)
+    }
+
+    /// Inspect progress messages of libopenconnect for events which are not reported by return codes
+    pub(crate) fn handle_progress_log(&self, level: LogLevel, message: &str) {
+        if let Some(ref handler) = self.callbacks.handle_progress {
+            handler(level, message);
+        }
+
+        match LogEvent::from_message(message) {
+            Some(LogEvent::DeadPeer) => self.dead_peer_detected.store(true, Ordering::SeqCst),
+            Some(LogEvent::ServerDisconnect) if message.to_lowercase().contains("idle") => {
+                self.idle_disconnected.store(true, Ordering::SeqCst)
+            }
+            _ => {}
+        }
+    }
+
+    /// Tell why the main loop stopped from its error and the events seen while it was running
+    pub(crate) fn disconnect_reason(&self, err: OpenconnectError) -> DisconnectReason {
+        match err.inner() {
+            OpenconnectError::MainLoopError(ret) if *ret == -libc::EINTR => {
+                DisconnectReason::Cancelled
+            }
+            _ if self.idle_disconnected.load(Ordering::SeqCst) => {
+                DisconnectReason::IdleTimeout(self.get_idle_timeout())
+            }
+            _ if self.dead_peer_detected.load(Ordering::SeqCst) => DisconnectReason::DeadPeer,
+            _ => DisconnectReason::Error(err),
+        }
+    }
+
+    /// Obtain a fresh cookie from the reauth handler and ask the main loop to reconnect with it
+    ///
+    /// The cookie can not be replaced while the main loop is running, so the main loop is paused and the cookie is set before it resumes
+    pub(crate) fn reauth(&self) {
+        let Some(ref handler) = self.callbacks.handle_reauth else {
+            tracing::warn!("Auto re-authentication is enabled but no reauth handler is set");
+            return;
+        };
+
+        tracing::info!("Re-authenticating before the session expires");
+        let Some(cookie) = handler() else {
+            tracing::error!("Failed to obtain a fresh cookie, the session will expire");
+            return;
+        };
+
+        if let Ok(mut pending_cookie) = self.pending_cookie.write() {
+            *pending_cookie = Some(cookie);
+            self.send_pause();
+        }
+    }
+
+    /// Ask the main loop to return, unless a pause command is already on its way
+    fn send_pause(&self) {
+        if !self.pause_sent.swap(true, Ordering::SeqCst) {
+            self.send_command(command::Command::Pause);
+        }
+    }
+
+    /// Set the cookie obtained by [VpnClient::reauth] after the main loop is paused, returns false if there was none
+    pub(crate) fn apply_pending_cookie(&self) -> bool {
+        let cookie = self
+            .pending_cookie
+            .write()
+            .ok()
+            .and_then(|mut pending_cookie| pending_cookie.take());
+
+        let Some(cookie) = cookie else {
+            return false;
+        };
+
+        match self.set_cookie(&cookie) {
+            Ok(()) => tracing::info!("Reconnecting with a fresh cookie"),
+            Err(e) => tracing::error!("Failed to set the fresh cookie: {}", e),
+        }
+
+        true
+    }
+
+    /// Keep settings changed while connected, they are applied before the connection is made again after a pause or a re-authentication
+    pub fn update_settings(&self, settings: ReconnectSettings) {
+        if let Ok(mut pending_settings) = self.pending_settings.write() {
+            *pending_settings = Some(settings);
+        }
+    }
+
+    /// Apply the settings kept by [VpnClient::update_settings], the main loop must not be running
+    pub(crate) fn apply_pending_settings(&self) {
+        let settings = self
+            .pending_settings
+            .write()
+            .ok()
+            .and_then(|mut pending_settings| pending_settings.take());
+
+        let Some(settings) = settings else {
+            return;
+        };
+
+        if let Some(dpd) = settings.dpd {
+            self.set_dpd(dpd as i32);
+        }
+
+        match self.set_tunnel_options(&settings.tunnel) {
+            Ok(()) => tracing::info!("Applied changed settings before reconnecting"),
+            Err(e) => tracing::error!("Failed to apply changed tunnel options: {}", e),
+        }
+    }
+
+    /// Block the main loop thread until the paused connection is resumed or cancelled
+    fn wait_while_paused(&self) -> PauseAction {
+        if let Ok(mut action) = self.pause_action.lock() {
+            *action = None;
+        }
+
+        // emit before waiting, so that handlers can call resume without a deadlock
+        self.emit_state_change(Status::Paused);
+
+        let Ok(mut action) = self.pause_action.lock() else {
+            return PauseAction::Cancel;
+        };
+
+        loop {
+            if let Some(next) = action.take() {
+                return next;
+            }
+
+            action = match self.pause_cond.wait(action) {
+                Ok(action) => action,
+                Err(_) => return PauseAction::Cancel,
+            };
+        }
+    }
+
+    fn notify_paused(&self, next: PauseAction) {
+        if let Ok(mut action) = self.pause_action.lock() {
+            *action = Some(next);
+            self.pause_cond.notify_all();
+        }
+    }
+
+    /// Tear down the tunnel but keep the session cookie, the main loop waits until [VpnClient::resume] or [Connectable::disconnect]
+    pub fn pause(&self) -> OpenconnectResult<()> {
+        if self.get_status() != Status::Connected {
+            return Err(OpenconnectError::InvalidStateError(
+                "only a connected client can be paused".to_string(),
+            ));
+        }
+
+        self.pause_requested.store(true, Ordering::SeqCst);
+        self.send_pause();
+        Ok(())
+    }
+
+    /// Reconnect a paused connection with the kept session cookie
+    pub fn resume(&self) -> OpenconnectResult<()> {
+        if self.get_status() != Status::Paused {
+            return Err(OpenconnectError::InvalidStateError(
+                "only a paused client can be resumed".to_string(),
+            ));
+        }
+
+        self.notify_paused(PauseAction::Resume);
+        Ok(())
     }
 
     pub(crate) fn handle_stats(&self, (dlts, stats): (Option<String>, Option<Stats>)) {
@@ -157,25 +550,47 @@ impl VpnClient {
     }
 
     pub fn set_loglevel(&self, level: LogLevel) {
-        unsafe {
-            openconnect_set_loglevel(self.vpninfo, level as i32);
-        }
+        self.backend.set_loglevel(level as i32);
     }
 
     pub fn set_protocol(&self, protocol: &str) -> OpenconnectResult<()> {
         let protocol =
             CString::new(protocol).map_err(|_| OpenconnectError::SetProtocolError(libc::EIO))?;
-        let ret = unsafe { openconnect_set_protocol(self.vpninfo, protocol.as_ptr()) };
+        let ret = self.backend.set_protocol(&protocol);
         match ret {
             0 => Ok(()),
             _ => Err(OpenconnectError::SetProtocolError(ret)),
         }
     }
 
-    pub fn set_stats_handler(&self) {
-        unsafe {
-            openconnect_set_stats_handler(self.vpninfo, Some(stats::stats_fn));
+    /// Set the maximum dead peer detection interval in seconds
+    pub fn set_dpd(&self, min_seconds: i32) {
+        self.backend.set_dpd(min_seconds);
+    }
+
+    /// Get the time when the authenticated session expires, `None` if the server did not report it
+    pub fn get_auth_expiration(&self) -> Option<std::time::SystemTime> {
+        let expiration = self.backend.get_auth_expiration();
+        if expiration <= 0 {
+            return None;
         }
+
+        Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(expiration as u64))
+    }
+
+    /// Get the current cookie with its expiration, which can be cached by [session::SessionCache] for later connections
+    pub fn get_session(&self) -> Option<Session> {
+        let cookie = self.get_cookie().filter(|cookie| !cookie.is_empty())?;
+        Some(Session::new(&cookie).with_expires_at(self.get_auth_expiration()))
+    }
+
+    /// Get the idle timeout in seconds announced by the server, 0 if the server did not announce one
+    pub fn get_idle_timeout(&self) -> i32 {
+        self.backend.get_idle_timeout()
+    }
+
+    pub fn set_stats_handler(&self) {
+        self.backend.set_stats_handler();
     }
 
     pub fn setup_tun_device(
@@ -203,16 +618,21 @@ impl VpnClient {
 
         let ifname = ifname.and_then(|s| CString::new(s).ok());
 
-        let ret = unsafe {
-            openconnect_setup_tun_device(
-                self.vpninfo,
-                vpnc_script.as_ptr(),
-                ifname.as_ref().map_or_else(std::ptr::null, |s| s.as_ptr()),
-            )
-        };
+        let ret = self
+            .backend
+            .setup_tun_device(&vpnc_script, ifname.as_deref());
 
-        let _manually_dropped = ifname; // SAFETY: dont remove this line, ifname's lifetime should be extended
+        match ret {
+            0 => Ok(()),
+            _ => Err(OpenconnectError::SetupTunDeviceEror(ret)),
+        }
+    }
 
+    /// Hand the tunnel to a script instead of a tun device, see [config::ConfigBuilder::tun_script]
+    pub fn setup_tun_script(&self, tun_script: &str) -> OpenconnectResult<()> {
+        let tun_script = CString::new(tun_script)
+            .map_err(|_| OpenconnectError::SetupTunDeviceEror(libc::EIO))?;
+        let ret = self.backend.setup_tun_script(&tun_script);
         match ret {
             0 => Ok(()),
             _ => Err(OpenconnectError::SetupTunDeviceEror(ret)),
@@ -220,14 +640,12 @@ impl VpnClient {
     }
 
     pub fn set_setup_tun_handler(&self) {
-        unsafe {
-            openconnect_set_setup_tun_handler(self.vpninfo, Some(VpnClient::default_setup_tun_vfn));
-        }
+        self.backend.set_setup_tun_handler();
     }
 
     pub fn set_report_os(&self, os: &str) -> OpenconnectResult<()> {
         let os = CString::new(os).map_err(|_| OpenconnectError::SetReportOSError(libc::EIO))?;
-        let ret = unsafe { openconnect_set_reported_os(self.vpninfo, os.as_ptr()) };
+        let ret = self.backend.set_reported_os(&os);
         match ret {
             0 => Ok(()),
             _ => Err(OpenconnectError::SetReportOSError(ret)),
@@ -235,17 +653,18 @@ impl VpnClient {
     }
 
     pub fn obtain_cookie(&self) -> OpenconnectResult<()> {
-        let ret = unsafe { openconnect_obtain_cookie(self.vpninfo) };
+        self.reset_library_errors();
+        let ret = self.backend.obtain_cookie();
         match ret {
             0 => Ok(()),
-            _ => Err(result::OpenconnectError::ObtainCookieError(ret)),
+            _ => Err(self.library_error(OpenconnectError::ObtainCookieError(ret))),
         }
     }
 
     pub fn set_cookie(&self, cookie: &str) -> OpenconnectResult<()> {
         let cookie =
             CString::new(cookie).map_err(|_| OpenconnectError::SetCookieError(libc::EIO))?;
-        let ret = unsafe { openconnect_set_cookie(self.vpninfo, cookie.as_ptr()) };
+        let ret = self.backend.set_cookie(&cookie);
         match ret {
             0 => Ok(()),
             _ => Err(OpenconnectError::SetCookieError(ret)),
@@ -253,92 +672,230 @@ impl VpnClient {
     }
 
     pub fn clear_cookie(&self) {
-        unsafe {
-            openconnect_clear_cookie(self.vpninfo);
-        }
+        self.backend.clear_cookie();
     }
 
     pub fn get_cookie(&self) -> Option<String> {
-        unsafe {
-            let cookie = openconnect_get_cookie(self.vpninfo);
-            std::ffi::CStr::from_ptr(cookie)
-                .to_str()
-                .map(|s| s.to_string())
-                .ok()
-        }
+        self.backend.get_cookie()
     }
 
     pub fn setup_cmd_pipe(&self) -> OpenconnectResult<()> {
-        let cmd_fd = unsafe {
-            let cmd_fd = openconnect_setup_cmd_pipe(self.vpninfo);
-            self.cmd_fd.store(cmd_fd, Ordering::Relaxed);
-            if cmd_fd < 0 {
-                return Err(result::OpenconnectError::CmdPipeError(cmd_fd));
-            }
-            cmd_fd
-        };
+        let cmd_fd = self.backend.setup_cmd_pipe();
+        self.cmd_fd.store(cmd_fd, Ordering::Relaxed);
+        if cmd_fd < 0 {
+            return Err(result::OpenconnectError::CmdPipeError(cmd_fd));
+        }
         self.set_sock_block(cmd_fd);
         Ok(())
     }
 
     pub fn reset_ssl(&self) {
-        unsafe {
-            openconnect_reset_ssl(self.vpninfo);
-        }
+        self.backend.reset_ssl();
     }
 
     pub fn make_cstp_connection(&self) -> OpenconnectResult<()> {
-        let ret = unsafe { openconnect_make_cstp_connection(self.vpninfo) };
+        self.reset_library_errors();
+        let ret = self.backend.make_cstp_connection();
         match ret {
             0 => Ok(()),
-            _ => Err(OpenconnectError::MakeCstpError(ret)),
+            _ => Err(self.library_error(OpenconnectError::MakeCstpError(ret))),
         }
     }
 
     pub fn get_dlts_cipher(&self) -> Option<String> {
-        unsafe {
-            let cipher = openconnect_get_dtls_cipher(self.vpninfo);
-            if !cipher.is_null() {
-                Some(
-                    std::ffi::CStr::from_ptr(cipher)
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
-                )
-            } else {
-                None
-            }
-        }
+        self.backend.get_dtls_cipher()
     }
 
     pub fn get_peer_cert_hash(&self) -> String {
-        // SAFETY: we should not use CString::from_raw(peer_fingerprint)
-        // because peer_fingerprint will be deallocated in rust and cause a double free
-        unsafe { std::ffi::CStr::from_ptr(openconnect_get_peer_cert_hash(self.vpninfo)) }
-            .to_string_lossy()
-            .to_string()
+        self.backend.get_peer_cert_hash()
     }
 
     pub fn disable_dtls(&self) -> OpenconnectResult<()> {
-        let ret = unsafe { openconnect_disable_dtls(self.vpninfo) };
+        let ret = self.backend.disable_dtls();
         match ret {
             0 => Ok(()),
             _ => Err(OpenconnectError::DisableDTLSError(ret)),
         }
     }
 
+    pub fn set_reqmtu(&self, mtu: i32) {
+        self.backend.set_reqmtu(mtu);
+    }
+
+    pub fn disable_ipv6(&self) -> OpenconnectResult<()> {
+        let ret = self.backend.disable_ipv6();
+        match ret {
+            0 => Ok(()),
+            _ => Err(OpenconnectError::DisableIPv6Error(ret)),
+        }
+    }
+
+    pub fn set_compression_mode(&self, mode: CompressionMode) -> OpenconnectResult<()> {
+        let ret = self.backend.set_compression_mode(mode);
+        match ret {
+            0 => Ok(()),
+            _ => Err(OpenconnectError::SetCompressionModeError(ret)),
+        }
+    }
+
+    pub fn set_pfs(&self, enable: bool) {
+        self.backend.set_pfs(enable);
+    }
+
+    pub fn set_allow_insecure_crypto(&self, allow: bool) -> OpenconnectResult<()> {
+        let ret = self.backend.set_allow_insecure_crypto(allow);
+        match ret {
+            0 => Ok(()),
+            _ => Err(OpenconnectError::SetInsecureCryptoError(ret)),
+        }
+    }
+
+    pub fn set_pass_tos(&self, enable: bool) {
+        self.backend.set_pass_tos(enable);
+    }
+
+    /// Apply tunnel options, this must happen before the TLS connection to the server is made
+    pub fn set_tunnel_options(&self, options: &TunnelOptions) -> OpenconnectResult<()> {
+        if let Some(mtu) = options.mtu {
+            self.set_reqmtu(mtu);
+        }
+
+        if options.disable_ipv6 {
+            self.disable_ipv6()?;
+        }
+
+        if let Some(mode) = options.compression {
+            self.set_compression_mode(mode)?;
+        }
+
+        self.set_pfs(options.pfs);
+        self.set_pass_tos(options.pass_tos);
+
+        if options.allow_insecure_crypto {
+            self.set_allow_insecure_crypto(true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Set an environment variable for the CSD wrapper process
+    pub fn set_csd_environ(&self, name: &str, value: &str) -> OpenconnectResult<()> {
+        let name =
+            CString::new(name).map_err(|_| OpenconnectError::SetCsdEnvironError(libc::EIO))?;
+        let value =
+            CString::new(value).map_err(|_| OpenconnectError::SetCsdEnvironError(libc::EIO))?;
+        let ret = self.backend.set_csd_environ(&name, &value);
+        match ret {
+            0 => Ok(()),
+            _ => Err(OpenconnectError::SetCsdEnvironError(ret)),
+        }
+    }
+
+    /// Set how the CSD (hostscan) posture check requested by the gateway is answered
+    ///
+    /// The wrapper runs as the user who invoked sudo if there is one, so that a script is never run as root by accident
+    pub fn set_csd(&self, csd: &CsdConfig) -> OpenconnectResult<()> {
+        let wrapper = match csd.mode {
+            CsdMode::Wrapper { ref path } => path.clone(),
+            CsdMode::Responder { ref hostscan } => {
+                self.set_csd_environ(csd::RESPONDER_ENV, "1")?;
+                if let Some(hostscan) = hostscan {
+                    self.set_csd_environ(csd::HOSTSCAN_ENV, hostscan)?;
+                }
+                std::env::current_exe()
+                    .map_err(|_| OpenconnectError::SetupCsdError(libc::ENOENT))?
+                    .to_string_lossy()
+                    .to_string()
+            }
+        };
+
+        let wrapper =
+            CString::new(wrapper).map_err(|_| OpenconnectError::SetupCsdError(libc::EIO))?;
+
+        #[cfg(not(target_os = "windows"))]
+        let uid = std::env::var("SUDO_UID")
+            .ok()
+            .and_then(|uid| uid.parse().ok())
+            .unwrap_or_else(|| unsafe { libc::getuid() });
+
+        #[cfg(target_os = "windows")]
+        let uid = 0;
+
+        let ret = self.backend.setup_csd(uid, true, &wrapper);
+        if ret != 0 {
+            return Err(OpenconnectError::SetupCsdError(ret));
+        }
+
+        if let Some(interval) = csd.trojan_interval {
+            self.backend.set_trojan_interval(interval as i32);
+        }
+
+        Ok(())
+    }
+
     pub fn set_http_proxy(&self, proxy: &str) -> OpenconnectResult<()> {
         let proxy = CString::new(proxy).map_err(|_| OpenconnectError::SetProxyError(libc::EIO))?;
-        let ret = unsafe { openconnect_set_http_proxy(self.vpninfo, proxy.as_ptr()) };
+        let ret = self.backend.set_http_proxy(&proxy);
         match ret {
             0 => Ok(()),
             _ => Err(OpenconnectError::SetProxyError(ret)),
         }
     }
 
+    /// Set the comma separated authentication methods offered to the proxy, e.g. `basic,ntlm`
+    pub fn set_proxy_auth(&self, methods: &str) -> OpenconnectResult<()> {
+        let methods =
+            CString::new(methods).map_err(|_| OpenconnectError::SetProxyAuthError(libc::EIO))?;
+        let ret = self.backend.set_proxy_auth(&methods);
+        match ret {
+            0 => Ok(()),
+            _ => Err(OpenconnectError::SetProxyAuthError(ret)),
+        }
+    }
+
+    pub fn set_proxy(&self, proxy: &ProxyConfig) -> OpenconnectResult<()> {
+        if let Some(methods) = proxy.auth_methods_str() {
+            self.set_proxy_auth(&methods)?;
+        }
+
+        self.set_http_proxy(&proxy.to_libopenconnect_url()?)
+    }
+
+    /// Connect to this host instead of the host of the parsed URL, must be called after [VpnClient::parse_url]
+    pub fn set_hostname(&self, hostname: &str) -> OpenconnectResult<()> {
+        let hostname =
+            CString::new(hostname).map_err(|_| OpenconnectError::SetHostnameError(libc::EIO))?;
+        let ret = self.backend.set_hostname(&hostname);
+        match ret {
+            0 => Ok(()),
+            _ => Err(OpenconnectError::SetHostnameError(ret)),
+        }
+    }
+
+    /// Present this name in the TLS handshake instead of the hostname
+    pub fn set_sni(&self, sni: &str) -> OpenconnectResult<()> {
+        let sni = CString::new(sni).map_err(|_| OpenconnectError::SetSniError(libc::EIO))?;
+        let ret = self.backend.set_sni(&sni);
+        match ret {
+            0 => Ok(()),
+            _ => Err(OpenconnectError::SetSniError(ret)),
+        }
+    }
+
+    /// The pinned address of the host from the resolver of the entrypoint
+    pub(crate) fn resolve_host(&self, host: &str) -> Option<std::net::IpAddr> {
+        let entrypoint = self.entrypoint.read().ok()?;
+        (*entrypoint).as_ref()?.resolver.as_ref()?.lookup(host)
+    }
+
+    /// Resolve host names with the resolver of the entrypoint, hosts without a pinned address fall back to the system
+    pub fn set_resolver_handler(&self) {
+        self.backend.set_resolver_handler();
+    }
+
     pub fn parse_url(&self, url: &str) -> OpenconnectResult<()> {
         let url = CString::new(url).map_err(|_| OpenconnectError::ParseUrlError(libc::EIO))?;
-        let ret = unsafe { openconnect_parse_url(self.vpninfo, url.as_ptr()) };
+        let ret = self.backend.parse_url(&url);
         match ret {
             0 => Ok(()),
             _ => Err(OpenconnectError::ParseUrlError(ret)),
@@ -359,18 +916,16 @@ impl VpnClient {
         }
     }
 
+    pub fn get_ifname(&self) -> Option<String> {
+        self.backend.get_ifname()
+    }
+
     pub fn get_port(&self) -> i32 {
-        unsafe { openconnect_get_port(self.vpninfo) }
+        self.backend.get_port()
     }
 
     pub fn get_hostname(&self) -> Option<String> {
-        unsafe {
-            let hostname = openconnect_get_hostname(self.vpninfo);
-            std::ffi::CStr::from_ptr(hostname)
-                .to_str()
-                .map(|s| s.to_string())
-                .ok()
-        }
+        self.backend.get_hostname()
     }
 
     pub fn set_client_cert(&self, cert: &str, sslkey: &str) -> OpenconnectResult<()> {
@@ -378,8 +933,7 @@ impl VpnClient {
             CString::new(cert).map_err(|_| OpenconnectError::SetClientCertError(libc::EIO))?;
         let sslkey =
             CString::new(sslkey).map_err(|_| OpenconnectError::SetClientCertError(libc::EIO))?;
-        let ret =
-            unsafe { openconnect_set_client_cert(self.vpninfo, cert.as_ptr(), sslkey.as_ptr()) };
+        let ret = self.backend.set_client_cert(&cert, &sslkey);
         match ret {
             0 => Ok(()),
             _ => Err(OpenconnectError::SetClientCertError(ret)),
@@ -389,7 +943,7 @@ impl VpnClient {
     pub fn set_mca_cert(&self, cert: &str, key: &str) -> OpenconnectResult<()> {
         let cert = CString::new(cert).map_err(|_| OpenconnectError::SetMCACertError(libc::EIO))?;
         let key = CString::new(key).map_err(|_| OpenconnectError::SetMCACertError(libc::EIO))?;
-        let ret = unsafe { openconnect_set_mca_cert(self.vpninfo, cert.as_ptr(), key.as_ptr()) };
+        let ret = self.backend.set_mca_cert(&cert, &key);
         match ret {
             0 => Ok(()),
             _ => Err(OpenconnectError::SetMCACertError(ret)),
@@ -397,23 +951,9 @@ impl VpnClient {
     }
 
     pub fn get_info(&self) -> OpenconnectResult<Option<IpInfo>> {
-        unsafe {
-            let info = std::ptr::null_mut();
-            let ret = openconnect_get_ip_info(
-                self.vpninfo,
-                info,
-                std::ptr::null_mut(),
-                std::ptr::null_mut(),
-            );
-
-            match ret {
-                0 => Ok(info
-                    .as_ref()
-                    .and_then(|info| info.as_ref())
-                    .map(IpInfo::from)),
-                _ => Err(OpenconnectError::GetIpInfoError(ret)),
-            }
-        }
+        self.backend
+            .get_ip_info()
+            .map_err(OpenconnectError::GetIpInfoError)
     }
 
     pub(crate) fn main_loop(
@@ -421,114 +961,18 @@ impl VpnClient {
         reconnect_timeout: i32,
         reconnect_interval: u32,
     ) -> OpenconnectResult<()> {
-        let ret = unsafe {
-            openconnect_mainloop(self.vpninfo, reconnect_timeout, reconnect_interval as i32)
-        };
+        self.reset_library_errors();
+        let ret = self
+            .backend
+            .mainloop(reconnect_timeout, reconnect_interval as i32);
         match ret {
             0 => Ok(()),
-            _ => Err(OpenconnectError::MainLoopError(ret)),
+            _ => Err(self.library_error(OpenconnectError::MainLoopError(ret))),
         }
     }
 
-    pub(crate) fn free(&self) {
-        unsafe {
-            openconnect_vpninfo_free(self.vpninfo);
-        }
-        tracing::debug!("Client instance is dropped");
-    }
-}
-
-impl Drop for VpnClient {
-    fn drop(&mut self) {
-        self.disconnect();
-        self.free();
-    }
-}
-
-/// Trait for creating a new instance of VpnClient and connecting to the VPN server
-///
-/// This trait is implemented for the lifecycle of the VpnClient
-pub trait Connectable {
-    fn new(config: Config, callbacks: EventHandlers) -> OpenconnectResult<Arc<Self>>;
-    fn connect_for_cookie(&self, entrypoint: Entrypoint) -> OpenconnectResult<Option<String>>;
-    fn init_connection(&self, entrypoint: Entrypoint) -> OpenconnectResult<()>;
-    fn run_loop(&self) -> OpenconnectResult<()>;
-    fn disconnect(&self);
-    fn get_status(&self) -> Status;
-    fn get_server_name(&self) -> Option<String>;
-}
-
-impl Connectable for VpnClient {
-    /// Create a new instance of VpnClient
-    ///
-    /// config can be created using [config::ConfigBuilder]
-    ///
-    /// callbacks can be created using [events::EventHandlers]
-    fn new(config: Config, callbacks: EventHandlers) -> OpenconnectResult<Arc<Self>> {
-        let useragent = std::ffi::CString::new("AnyConnect-compatible OpenConnect VPN Agent")
-            .map_err(|_| OpenconnectError::OtherError("useragent is not valid".to_string()))?;
-
-        let instance = Arc::new(Self {
-            vpninfo: std::ptr::null_mut(),
-            config,
-            cmd_fd: (-1).into(),
-            status: RwLock::new(Status::Initialized),
-            callbacks,
-            entrypoint: RwLock::new(None),
-            form_manager: RwLock::new(FormManager::default()),
-            peer_certs: PeerCerts::default(),
-        });
-
-        unsafe {
-            let weak_instance = Arc::downgrade(&instance);
-            let raw_instance = Weak::into_raw(weak_instance) as *mut VpnClient; // dangerous, leak for assign to vpninfo
-            let ret = openconnect_init_ssl();
-            if ret != 0 {
-                panic!("openconnect_init_ssl failed");
-            }
-
-            // format args on C side
-            helper_set_global_progress_vfn(Some(Logger::raw_handle_process_log));
-
-            let vpninfo = openconnect_vpninfo_new(
-                useragent.as_ptr(),
-                Some(PeerCerts::validate_peer_cert),
-                None,
-                Some(FormManager::process_auth_form_cb),
-                Some(helper_format_vargs), // format args on C side
-                raw_instance as *mut ::std::os::raw::c_void,
-            );
-
-            if vpninfo.is_null() {
-                panic!("openconnect_vpninfo_new failed");
-            }
-
-            (*raw_instance).vpninfo = vpninfo;
-        };
-
-        SIGNAL_HANDLE.update_client_singleton(Arc::downgrade(&instance));
-        instance.set_loglevel(instance.config.loglevel);
-        instance.set_setup_tun_handler();
-
-        if let Some(proxy) = &instance.config.http_proxy {
-            instance
-                .set_http_proxy(proxy.as_str())
-                .emit_error(&instance)?;
-        }
-
-        instance.emit_state_change(Status::Initialized);
-
-        Ok(instance)
-    }
-
-    /// Connect to the VPN server and obtain a cookie
-    ///
-    /// This function will not keep the connection, it will only connect and obtain a cookie. This function will not block the thread
-    ///
-    /// The cookie can be used to connect to the VPN server later by passing it to another [config::EntrypointBuilder]
-    ///
-    /// entrypoint can be created using [config::EntrypointBuilder]
-    fn connect_for_cookie(&self, entrypoint: Entrypoint) -> OpenconnectResult<Option<String>> {
+    /// Set up the connection and obtain the cookie, shared by [Connectable::connect_for_cookie] and [Connectable::init_connection]
+    fn authenticate(&self, entrypoint: Entrypoint) -> OpenconnectResult<Option<String>> {
         self.emit_state_change(Status::Connecting("Initializing connection".to_string()));
         {
             if let Ok(mut form_context) = self.form_manager.try_write() {
@@ -571,16 +1015,47 @@ impl Connectable for VpnClient {
             self.disable_dtls().emit_error(self)?;
         }
 
+        self.set_tunnel_options(&entrypoint.tunnel)
+            .emit_error(self)?;
+
+        if let Some(ref csd) = entrypoint.csd {
+            self.set_csd(csd).emit_error(self)?;
+        }
+
+        // an automatic proxy depends on the server, so it is applied once the server is known
+        if let Some(ref proxy) = self.config.proxy {
+            self.emit_state_change(Status::Connecting("Resolving proxy".to_string()));
+            let proxy = proxy
+                .resolve(&entrypoint.server)
+                .map_err(OpenconnectError::from)
+                .emit_error(self)?;
+            if let Some(ref proxy) = proxy {
+                self.set_proxy(proxy).emit_error(self)?;
+            }
+        }
+
         self.emit_state_change(Status::Connecting("Parsing URL".to_string()));
         self.parse_url(&entrypoint.server).emit_error(self)?;
+
+        if let Some(ref hostname) = entrypoint.hostname {
+            self.set_hostname(hostname).emit_error(self)?;
+        }
+
+        if let Some(ref sni) = entrypoint.sni {
+            self.set_sni(sni).emit_error(self)?;
+        }
+
+        if entrypoint.resolver.is_some() {
+            self.set_resolver_handler();
+        }
         let hostname = self.get_hostname();
 
         self.emit_state_change(Status::Connecting(format!(
             "Obtaining cookie from: {}",
             hostname.unwrap_or("".to_string())
         )));
-        if let Some(cookie) = entrypoint.cookie.clone() {
-            self.set_cookie(&cookie).emit_error(self)?;
+        if let Some(ref cookie) = entrypoint.cookie {
+            self.set_cookie(cookie.expose()).emit_error(self)?;
         } else {
             self.obtain_cookie().emit_error(self)?;
         }
@@ -588,39 +1063,309 @@ impl Connectable for VpnClient {
         Ok(self.get_cookie())
     }
 
+    /// Start a new connection attempt with its tracing span, the status transitions are recorded under it
+    fn begin_attempt(&self, entrypoint: &Entrypoint) -> tracing::Span {
+        let attempt = Attempt::new(self.id, entrypoint.name.clone(), &entrypoint.protocol.name);
+        let span = attempt.span.clone();
+        if let Ok(mut current) = self.attempt.write() {
+            *current = Some(attempt);
+        }
+        span
+    }
+
+    /// The span of the current connection attempt, disabled before the first attempt
+    fn attempt_span(&self) -> tracing::Span {
+        self.attempt
+            .read()
+            .ok()
+            .and_then(|attempt| attempt.as_ref().map(|attempt| attempt.span.clone()))
+            .unwrap_or_else(tracing::Span::none)
+    }
+
+    /// Append a transition of the current attempt to the journal
+    fn record_timeline(&self, status: &str, message: Option<String>) {
+        self.record_entry(|attempt| attempt.entry(status, message));
+    }
+
+    fn record_entry(&self, entry: impl FnOnce(&mut Attempt) -> TimelineEntry) {
+        let Some(entry) = self
+            .attempt
+            .write()
+            .ok()
+            .and_then(|mut attempt| attempt.as_mut().map(entry))
+        else {
+            return;
+        };
+
+        tracing::debug!(parent: &self.attempt_span(), status = %entry.status, step_ms = entry.step_ms, "{}", entry.message.as_deref().unwrap_or(&entry.status));
+
+        if let Some(ref journal) = self.journal {
+            if let Err(e) = journal.append(&entry) {
+                tracing::warn!("Failed to write the connection journal: {}", e);
+            }
+        }
+    }
+
+    /// Create a client on top of a [Backend], [Connectable::new] uses libopenconnect
+    pub(crate) fn with_backend(
+        config: Config,
+        callbacks: EventHandlers,
+        backend: Box<dyn Backend>,
+    ) -> OpenconnectResult<Arc<Self>> {
+        let dns_backend = dns::backend_for(config.dns_mode)
+            .map_err(|e| OpenconnectError::SetupDnsError(e.to_string()))?;
+        if let Some(Err(e)) = dns_backend.as_ref().map(|backend| backend.recover()) {
+            tracing::warn!(
+                "Failed to recover DNS settings of a previous session: {}",
+                e
+            );
+        }
+
+        let journal = config.journal.as_deref().map(Journal::new);
+
+        let instance = Arc::new(Self {
+            backend,
+            id: registry::next_id(),
+            config,
+            cmd_fd: (-1).into(),
+            status: RwLock::new(Status::Initialized),
+            callbacks,
+            entrypoint: RwLock::new(None),
+            form_manager: RwLock::new(FormManager::default()),
+            peer_certs: PeerCerts::default(),
+            dns_backend,
+            dead_peer_detected: AtomicBool::new(false),
+            idle_disconnected: AtomicBool::new(false),
+            pending_cookie: RwLock::new(None),
+            pause_requested: AtomicBool::new(false),
+            pause_sent: AtomicBool::new(false),
+            pending_settings: RwLock::new(None),
+            callback_error: RwLock::new(None),
+            last_error_log: RwLock::new(None),
+            pause_action: Mutex::new(None),
+            pause_cond: Condvar::new(),
+            attempt: RwLock::new(None),
+            journal,
+            probe: Mutex::new(None),
+        });
+
+        // leaked on purpose, libopenconnect holds it as privdata of the callbacks
+        let privdata = Weak::into_raw(Arc::downgrade(&instance)) as *mut std::os::raw::c_void;
+        instance.backend.attach(privdata)?;
+
+        // offering single sign-on without a way to show the login page would fail the authentication
+        if instance.callbacks.browser_handler.is_some() {
+            instance.backend.set_external_browser_handler();
+        }
+
+        if instance.callbacks.webview_handler.is_some() {
+            instance.backend.set_webview_handler();
+        }
+
+        if let SignalPolicy::Handle(ref map) = instance.config.signal_policy {
+            command::install_signal_handlers(map)
+                .map_err(|e| OpenconnectError::InstallSignalHandlerError(e.to_string()))
+                .emit_error(&instance)?;
+        }
+        registry::register(instance.id, Arc::downgrade(&instance));
+        instance.set_loglevel(instance.config.loglevel);
+        instance.set_setup_tun_handler();
+
+        if let Some(dpd) = instance.config.dpd {
+            instance.set_dpd(dpd as i32);
+        }
+
+        instance.emit_state_change(Status::Initialized);
+
+        Ok(instance)
+    }
+}
+
+impl Drop for VpnClient {
+    fn drop(&mut self) {
+        registry::unregister(self.id);
+        self.disconnect();
+        self.restore_dns();
+        tracing::debug!("Client instance is dropped");
+    }
+}
+
+/// Trait for creating a new instance of VpnClient and connecting to the VPN server
+///
+/// This trait is implemented for the lifecycle of the VpnClient
+pub trait Connectable {
+    fn new(config: Config, callbacks: EventHandlers) -> OpenconnectResult<Arc<Self>>;
+    fn connect_for_cookie(&self, entrypoint: Entrypoint) -> OpenconnectResult<Option<String>>;
+    fn init_connection(&self, entrypoint: Entrypoint) -> OpenconnectResult<()>;
+    fn run_loop(&self) -> OpenconnectResult<()>;
+    fn disconnect(&self);
+    fn get_status(&self) -> Status;
+    fn get_server_name(&self) -> Option<String>;
+}
+
+impl Connectable for VpnClient {
+    /// Create a new instance of VpnClient
+    ///
+    /// config can be created using [config::ConfigBuilder]
+    ///
+    /// callbacks can be created using [events::EventHandlers]
+    fn new(config: Config, callbacks: EventHandlers) -> OpenconnectResult<Arc<Self>> {
+        Self::with_backend(config, callbacks, Box::new(FfiBackend::new()?))
+    }
+
+    /// Connect to the VPN server and obtain a cookie
+    ///
+    /// This function will not keep the connection, it will only connect and obtain a cookie. This function will not block the thread
+    ///
+    /// The cookie can be used to connect to the VPN server later by passing it to another [config::EntrypointBuilder]
+    ///
+    /// entrypoint can be created using [config::EntrypointBuilder]
+    fn connect_for_cookie(&self, entrypoint: Entrypoint) -> OpenconnectResult<Option<String>> {
+        let span = self.begin_attempt(&entrypoint);
+        let _entered = span.enter();
+
+        let cookie = self.authenticate(entrypoint)?;
+        self.record_timeline("AUTHENTICATED", None);
+        Ok(cookie)
+    }
+
     /// Initialize the connection to the VPN server, this function will not block the thread and only make a CSTP connection
     ///
     /// entrypoint can be created using [config::EntrypointBuilder]
     fn init_connection(&self, entrypoint: Entrypoint) -> OpenconnectResult<()> {
+        let span = self.begin_attempt(&entrypoint);
+        let _entered = span.enter();
+
         self.emit_state_change(Status::Connecting("Make CSTP connection".to_string()));
-        self.connect_for_cookie(entrypoint)?;
-        self.make_cstp_connection().emit_error(self)?;
+
+        // a cached cookie may be rejected, authenticate again if the entrypoint has credentials or single sign-on is possible
+        let can_reauth = entrypoint.cookie.is_some()
+            && (entrypoint.username.is_some()
+                || self.callbacks.browser_handler.is_some()
+                || self.callbacks.webview_handler.is_some());
+
+        self.authenticate(entrypoint)?;
+
+        match self.make_cstp_connection() {
+            Err(e) if can_reauth => {
+                tracing::info!("Cookie rejected by server, authenticating again: {}", e);
+                self.clear_cookie();
+                self.emit_state_change(Status::Connecting("Obtaining a new cookie".to_string()));
+                self.obtain_cookie().emit_error(self)?;
+                self.make_cstp_connection().emit_error(self)?;
+            }
+            result => result.emit_error(self)?,
+        }
+
         self.emit_state_change(Status::Connected);
 
         Ok(())
     }
 
     /// Run main loop and block until the connection is closed
+    ///
+    /// Returns an error when the connection is lost because of dead peer detection or idle timeout
     fn run_loop(&self) -> OpenconnectResult<()> {
-        loop {
-            if let Err(err) = self.main_loop(300, RECONNECT_INTERVAL_MIN) {
-                tracing::event!(tracing::Level::ERROR, "Main loop failed: {}", err);
-                break;
+        let span = self.attempt_span();
+        let _entered = span.enter();
+
+        self.dead_peer_detected.store(false, Ordering::SeqCst);
+        self.idle_disconnected.store(false, Ordering::SeqCst);
+
+        let keepalive = self
+            .config
+            .keepalive
+            .and_then(|interval| Some((keepalive::probe_target(self)?, interval)));
+
+        let watch_expiry = self.callbacks.handle_auth_expiring.is_some() || self.config.auto_reauth;
+
+        let reason = std::thread::scope(|scope| {
+            let (stop_keepalive, keepalive_stopped) = std::sync::mpsc::channel::<()>();
+            if let Some((target, interval)) = keepalive {
+                scope.spawn(move || keepalive::run(self, target, interval, keepalive_stopped));
             }
-        }
+
+            let (stop_expiry, expiry_stopped) = std::sync::mpsc::channel::<()>();
+            if watch_expiry {
+                scope.spawn(move || expiry::run(self, expiry_stopped));
+            }
+
+            let reason = loop {
+                let result = self.main_loop(300, RECONNECT_INTERVAL_MIN);
+                self.pause_sent.store(false, Ordering::SeqCst);
+
+                // a re-authentication and a pause of the user may both be pending, the fresh cookie is set either way,
+                // and a pause without either comes from a signal
+                let reauthenticated = result.is_ok() && self.apply_pending_cookie();
+                let paused = self.pause_requested.swap(false, Ordering::SeqCst) || !reauthenticated;
+
+                match result {
+                    // paused for re-authentication only, the main loop reconnects with the fresh cookie
+                    Ok(()) if !paused => self.apply_pending_settings(),
+                    Ok(()) => match self.wait_while_paused() {
+                        PauseAction::Resume => {
+                            self.emit_state_change(Status::Connecting(
+                                "Resuming connection".to_string(),
+                            ));
+                            self.apply_pending_settings();
+                            // the main loop would reconnect by itself, but it keeps retrying for the whole reconnect
+                            // timeout when the session was dropped while paused, and it is only known to be connected
+                            // once the tunnel is up, so connect before entering it again
+                            if let Err(err) = self.make_cstp_connection() {
+                                tracing::error!("Failed to resume connection: {}", err);
+                                break DisconnectReason::Error(err);
+                            }
+                            self.emit_state_change(Status::Connected);
+                        }
+                        PauseAction::Cancel => break DisconnectReason::Cancelled,
+                    },
+                    Err(err) => {
+                        tracing::event!(tracing::Level::ERROR, "Main loop failed: {}", err);
+                        break self.disconnect_reason(err);
+                    }
+                }
+            };
+
+            drop(stop_keepalive);
+            drop(stop_expiry);
+            reason
+        });
+
+        // a pause which did not reach the main loop ends with the connection
+        self.pause_requested.store(false, Ordering::SeqCst);
+        self.pause_sent.store(false, Ordering::SeqCst);
 
         // TODO: check if the following should be invoke?
         // self.reset_ssl();
         // self.clear_cookie();
-        self.emit_state_change(Status::Disconnected);
+        self.restore_dns();
 
-        Ok(())
+        if let Some(ref handler) = self.callbacks.handle_disconnect {
+            handler(reason.clone());
+        }
+
+        match reason {
+            DisconnectReason::DeadPeer => Err(OpenconnectError::DeadPeerError).emit_error(self),
+            DisconnectReason::IdleTimeout(timeout) => {
+                Err(OpenconnectError::IdleTimeoutError(timeout)).emit_error(self)
+            }
+            _ => {
+                self.emit_state_change(Status::Disconnected);
+                Ok(())
+            }
+        }
     }
 
     /// Gracefully stop the main loop
     ///
     /// This function will send a cancel command to the main loop and wait for the main loop to stop
     fn disconnect(&self) {
+        if self.get_status() == Status::Paused {
+            self.emit_state_change(Status::Disconnecting);
+            self.notify_paused(PauseAction::Cancel);
+            return;
+        }
+
         if self.get_status() != Status::Connected {
             return;
         }
@@ -653,6 +1398,8 @@ impl Events for VpnClient {
             handler(status.clone());
         }
 
+        self.record_entry(|attempt| attempt.status_entry(&status));
+
         {
             let status_write_guard = self.status.write();
             if let Ok(mut write) = status_write_guard {
@@ -1,10 +1,179 @@
-use openconnect_sys::{PRG_DEBUG, PRG_ERR, PRG_INFO, PRG_TRACE};
+use crate::{config::LogLevel, result, secret, ClientId, VpnClient};
+use openconnect_sys::{helper_set_global_progress_vfn, PRG_DEBUG, PRG_ERR, PRG_INFO};
+use serde::{Deserialize, Serialize};
+use std::{io::Write, path::PathBuf, sync::Once};
 use tracing::{
     event,
     subscriber::{set_global_default, SetGlobalDefaultError},
     Level,
 };
-use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_appender::rolling::{InitError, RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    filter::{ParseError, Targets},
+    fmt::MakeWriter,
+    layer::SubscriberExt,
+    Layer, Registry,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum LogError {
+    #[error("Failed to create file appender: {0}")]
+    AppenderError(#[from] InitError),
+
+    #[error("Failed to set global subscriber: {0}")]
+    SubscriberError(#[from] SetGlobalDefaultError),
+
+    #[error("Invalid log level filter: {0}")]
+    FilterError(#[from] ParseError),
+}
+
+/// File name of the connection journal in the log directory
+pub const JOURNAL_FILE_NAME: &str = "timeline.jsonl";
+
+/// How often a new log file is started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+/// Where and how the logs are written, see [Logger::init_with_config]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LogConfig {
+    /// Directory of the log files, defaults to [Logger::get_log_path] or the user state directory if it is not writable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+
+    pub rotation: LogRotation,
+
+    /// Number of rotated log files to keep
+    pub max_files: usize,
+
+    /// Level filter such as `info` or `info,libopenconnect=debug`, libopenconnect progress lines use the `libopenconnect` target
+    pub level: String,
+
+    /// Write one JSON object per line instead of compact text
+    pub json: bool,
+
+    /// Mirror the logs to stderr
+    pub stderr: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            rotation: LogRotation::Daily,
+            max_files: 5,
+            level: "trace".to_string(),
+            json: false,
+            stderr: false,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Directories which may hold the log files, the first writable one is used by [LogConfig::log_dir]
+    pub fn candidate_dirs(&self) -> Vec<PathBuf> {
+        match self.path {
+            Some(ref path) => vec![path.clone()],
+            None => std::iter::once(PathBuf::from(Logger::get_log_path()))
+                .chain(Logger::get_user_log_path())
+                .collect(),
+        }
+    }
+
+    /// The directory the log files are written to
+    pub fn log_dir(&self) -> PathBuf {
+        let candidates = self.candidate_dirs();
+        candidates
+            .iter()
+            .find(|dir| is_writable(dir))
+            .unwrap_or(&candidates[0])
+            .clone()
+    }
+
+    /// The connection journal next to the log files, see [crate::config::ConfigBuilder::journal]
+    pub fn journal_file(&self) -> PathBuf {
+        self.log_dir().join(JOURNAL_FILE_NAME)
+    }
+
+    fn layer<W>(&self, writer: W, ansi: bool) -> Box<dyn Layer<Registry> + Send + Sync>
+    where
+        W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    {
+        let layer = tracing_subscriber::fmt::layer()
+            .with_ansi(ansi)
+            .with_level(true)
+            .with_target(true)
+            .with_writer(RedactingWriter(writer));
+
+        if self.json {
+            // one JSON object per line, with the fields of the enclosing spans
+            layer
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .boxed()
+        } else {
+            layer.compact().boxed()
+        }
+    }
+}
+
+/// Redact secrets of every formatted event before it reaches the underlying writer, see [secret::redact]
+struct RedactingWriter<W>(W);
+
+impl<'a, W> MakeWriter<'a> for RedactingWriter<W>
+where
+    W: MakeWriter<'a>,
+{
+    type Writer = RedactingWriter<W::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+impl<W> Write for RedactingWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // events are formatted into one buffer before they are written, so secrets are not split across writes
+        let text = String::from_utf8_lossy(buf);
+        self.0.write_all(secret::redact(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Create the directory if needed and check that files can be created in it
+fn is_writable(dir: &PathBuf) -> bool {
+    let probe = dir.join(".openconnect-rs-probe");
+    let writable = std::fs::create_dir_all(dir).is_ok() && std::fs::write(&probe, b"").is_ok();
+    let _ = std::fs::remove_file(probe);
+    writable
+}
 
 pub struct Logger;
 
@@ -22,42 +191,110 @@ impl Logger {
         LOG_PATH
     }
 
-    pub fn init() -> Result<(), SetGlobalDefaultError> {
+    /// Log directory of the current user, used when the system log directory is not writable
+    pub fn get_user_log_path() -> Option<PathBuf> {
+        #[cfg(target_os = "linux")]
+        let state_dir = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| home::home_dir().map(|home| home.join(".local").join("state")));
+
+        #[cfg(target_os = "macos")]
+        let state_dir = home::home_dir().map(|home| home.join("Library").join("Logs"));
+
+        #[cfg(target_os = "windows")]
+        let state_dir = std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+
+        state_dir.map(|dir| dir.join("openconnect-rs"))
+    }
+
+    /// Initialize the global logger with the default [LogConfig]
+    pub fn init() -> Result<(), LogError> {
+        Self::init_with_config(&LogConfig::default())
+    }
+
+    pub fn init_with_config(config: &LogConfig) -> Result<(), LogError> {
+        let filter = config.level.parse::<Targets>()?;
+
         let file_appender = RollingFileAppender::builder()
-            .max_log_files(5)
-            .rotation(Rotation::DAILY)
+            .max_log_files(config.max_files.max(1))
+            .rotation(config.rotation.into())
             .filename_prefix("openconnect-rs.log")
-            .build(Self::get_log_path())
-            .expect("failed to create file appender");
+            .build(config.log_dir())?;
 
-        // for file based logging, waiting https://github.com/tokio-rs/tracing/pull/2497 to be merged
-        let subscriber = tracing_subscriber::fmt()
-            .compact()
-            .with_level(true)
-            .with_target(true)
-            .with_max_level(Level::TRACE)
-            .with_writer(file_appender)
-            .finish();
+        let mut layers = vec![config.layer(file_appender, false)];
+        if config.stderr {
+            layers.push(config.layer(std::io::stderr, true));
+        }
 
-        set_global_default(subscriber)
+        let subscriber = tracing_subscriber::registry().with(layers).with(filter);
+
+        set_global_default(subscriber)?;
+        Ok(())
+    }
+
+    /// Install the progress callback of libopenconnect once per process
+    pub(crate) fn set_progress_handler() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| unsafe {
+            helper_set_global_progress_vfn(Some(Logger::raw_handle_process_log));
+        });
     }
 
     pub(crate) unsafe extern "C" fn raw_handle_process_log(
-        _privdata: *mut ::std::os::raw::c_void,
+        privdata: *mut ::std::os::raw::c_void,
         level: ::std::os::raw::c_int,
         buf: *const ::std::os::raw::c_char,
     ) {
-        let buf = std::ffi::CStr::from_ptr(buf).to_str().ok();
-        let level = level as u32;
-        let level = match level {
-            PRG_ERR => Level::ERROR,
-            PRG_INFO => Level::INFO,
-            PRG_DEBUG => Level::DEBUG,
-            PRG_TRACE => Level::TRACE,
-            _ => unreachable!("unknown log level: {}", level),
-        };
-        if buf.is_some() {
-            Logger::log(level, buf.unwrap_or(""));
+        let client = (!privdata.is_null()).then(|| VpnClient::ref_from_raw(privdata));
+        result::guard_callback(client, "progress", (), || {
+            let buf = std::ffi::CStr::from_ptr(buf).to_str().ok();
+            let level = match level as u32 {
+                PRG_ERR => LogLevel::Err,
+                PRG_INFO => LogLevel::Info,
+                PRG_DEBUG => LogLevel::Debug,
+                _ => LogLevel::Trace,
+            };
+            if let Some(buf) = buf {
+                // the lines are also kept for error messages and progress events, not only for the log file
+                let buf = secret::redact(buf.trim_end());
+                let buf = buf.as_ref();
+                match client {
+                    Some(client) => {
+                        if level == LogLevel::Err {
+                            client.set_last_error_log(buf);
+                        }
+                        client.handle_progress_log(level, buf);
+                        Logger::log_library(Some(client.id()), level, buf);
+                    }
+                    None => Logger::log_library(None, level, buf),
+                }
+            }
+        })
+    }
+
+    /// Log a progress line of libopenconnect under the `libopenconnect` target, so that it can be filtered apart
+    fn log_library(client: Option<ClientId>, level: LogLevel, message: &str) {
+        match level {
+            LogLevel::Err => event!(target: "libopenconnect", Level::ERROR, client, "{}", message),
+            LogLevel::Info => event!(target: "libopenconnect", Level::INFO, client, "{}", message),
+            LogLevel::Debug => {
+                event!(target: "libopenconnect", Level::DEBUG, client, "{}", message)
+            }
+            LogLevel::Trace => {
+                event!(target: "libopenconnect", Level::TRACE, client, "{}", message)
+            }
+        }
+    }
+
+    /// Log a message of a client, tagged with the client id so that logs of concurrent clients can be told apart
+    pub fn log_client(client: ClientId, level: Level, message: &str) {
+        match level {
+            Level::ERROR => event!(Level::ERROR, client, "{}", message),
+            Level::WARN => event!(Level::WARN, client, "{}", message),
+            Level::INFO => event!(Level::INFO, client, "{}", message),
+            Level::DEBUG => event!(Level::DEBUG, client, "{}", message),
+            Level::TRACE => event!(Level::TRACE, client, "{}", message),
         }
     }
 
@@ -71,3 +308,110 @@ impl Logger {
         }
     }
 }
+
+#[test]
+fn test_log_config() {
+    let config: LogConfig =
+        serde_json::from_str(r#"{"path":"/tmp/openconnect-rs-test","level":"info","json":true}"#)
+            .unwrap();
+    assert_eq!(config.rotation, LogRotation::Daily);
+    assert_eq!(config.max_files, 5);
+    assert!(config.json && !config.stderr);
+    assert_eq!(
+        config.candidate_dirs(),
+        vec![PathBuf::from("/tmp/openconnect-rs-test")]
+    );
+    assert!(config.level.parse::<Targets>().is_ok());
+
+    let config = LogConfig::default();
+    assert_eq!(
+        config.candidate_dirs()[0],
+        PathBuf::from(Logger::get_log_path())
+    );
+    assert!("info,libopenconnect=debug".parse::<Targets>().is_ok());
+}
+
+#[test]
+fn test_log_redaction() {
+    let dir = std::env::temp_dir().join(format!("openconnect-rs-log-{}", std::process::id()));
+    let config = LogConfig {
+        path: Some(dir.clone()),
+        rotation: LogRotation::Never,
+        ..Default::default()
+    };
+    let file_appender = RollingFileAppender::builder()
+        .rotation(config.rotation.into())
+        .filename_prefix("openconnect-rs.log")
+        .build(config.log_dir())
+        .unwrap();
+    let subscriber = tracing_subscriber::registry().with(config.layer(file_appender, false));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let server: crate::storage::PasswordServer = serde_json::from_str(
+            r#"{"name":"test","server":"https://vpn.example.com","username":"alice","password":"hunter2","allowInsecure":null,"updatedAt":null}"#,
+        )
+        .unwrap();
+        tracing::info!("Connecting to {:?}", server);
+
+        let line = std::ffi::CString::new("Set-Cookie: webvpn=AB12CD; path=/; secure").unwrap();
+        unsafe {
+            Logger::raw_handle_process_log(std::ptr::null_mut(), PRG_DEBUG as i32, line.as_ptr())
+        };
+
+        let error = crate::result::OpenconnectError::ObtainCookieError(-libc::EPERM)
+            .with_library_log(Some(
+                "Failed to parse <session-token>0123ABCD</session-token>".into(),
+            ));
+        tracing::error!("{}", error);
+    });
+
+    let content = std::fs::read_to_string(dir.join("openconnect-rs.log")).unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+    for secret in ["hunter2", "AB12CD", "0123ABCD"] {
+        assert!(
+            !content.contains(secret),
+            "{} leaked in {}",
+            secret,
+            content
+        );
+    }
+    assert!(content.contains("[REDACTED]"));
+    assert!(content.contains("alice"));
+}
+
+#[test]
+fn test_log_json() {
+    let dir = std::env::temp_dir().join(format!("openconnect-rs-json-{}", std::process::id()));
+    let config = LogConfig {
+        path: Some(dir.clone()),
+        rotation: LogRotation::Never,
+        json: true,
+        ..Default::default()
+    };
+    let file_appender = RollingFileAppender::builder()
+        .rotation(config.rotation.into())
+        .filename_prefix("openconnect-rs.log")
+        .build(config.log_dir())
+        .unwrap();
+    let subscriber = tracing_subscriber::registry().with(config.layer(file_appender, false));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("connect", server = "vpn.example.com", attempt = 2);
+        let _entered = span.enter();
+        tracing::warn!(status = "paused", "Sending username=alice&password=hunter2");
+    });
+
+    let content = std::fs::read_to_string(dir.join("openconnect-rs.log")).unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+    let line: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+
+    assert_eq!(line["level"], "WARN");
+    assert_eq!(line["fields"]["status"], "paused");
+    assert_eq!(
+        line["fields"]["message"],
+        "Sending username=alice&password=[REDACTED]"
+    );
+    assert_eq!(line["span"]["name"], "connect");
+    assert_eq!(line["span"]["server"], "vpn.example.com");
+    assert_eq!(line["spans"][0]["attempt"], 2);
+}
@@ -0,0 +1,501 @@
+use crate::proxy::ProxyError;
+use boa_engine::{
+    js_string, native_function::NativeFunction, Context, JsError, JsObject, JsResult, JsString,
+    JsValue, Source,
+};
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs, UdpSocket};
+
+/// Calls nested deeper than this are rejected, so that a recursive script can not overflow the stack
+const MAX_CALL_DEPTH: usize = 64;
+
+/// Loops running longer than this are rejected, so that a script can not hang the connection
+const MAX_LOOP_ITERATIONS: u64 = 1_000_000;
+
+/// The standard PAC functions and their number of arguments, the time based ones are not provided
+const PAC_FUNCTIONS: [(&str, usize); 11] = [
+    ("isPlainHostName", 1),
+    ("dnsDomainIs", 2),
+    ("localHostOrDomainIs", 2),
+    ("isResolvable", 1),
+    ("isInNet", 3),
+    ("dnsResolve", 1),
+    ("myIpAddress", 0),
+    ("dnsDomainLevels", 1),
+    ("shExpMatch", 2),
+    ("alert", 1),
+    ("convert_addr", 1),
+];
+
+/// A proxy auto-config (PAC) file, compiled once and called for each URL
+///
+/// PAC files are JavaScript, they are evaluated by an embedded engine which provides the standard PAC functions
+/// except the time based ones
+#[derive(Debug)]
+pub struct PacResolver {
+    context: Context,
+    find_proxy: JsObject,
+}
+
+impl PacResolver {
+    /// Run the script, syntax errors and a missing `FindProxyForURL` are reported here rather than on the first call
+    pub fn parse(source: &str) -> Result<Self, ProxyError> {
+        let mut context = Context::default();
+        context
+            .runtime_limits_mut()
+            .set_recursion_limit(MAX_CALL_DEPTH);
+        context
+            .runtime_limits_mut()
+            .set_loop_iteration_limit(MAX_LOOP_ITERATIONS);
+
+        register_pac_functions(&mut context).map_err(js_error)?;
+        context.eval(Source::from_bytes(source)).map_err(js_error)?;
+
+        let find_proxy = context
+            .global_object()
+            .get(js_string!("FindProxyForURL"), &mut context)
+            .map_err(js_error)?
+            .as_callable()
+            .cloned()
+            .ok_or_else(|| pac_error("FindProxyForURL is not defined"))?;
+
+        Ok(Self {
+            context,
+            find_proxy,
+        })
+    }
+
+    /// Load the script from a `http(s)://` or `file://` URL, or a local path
+    pub fn load(location: &str) -> Result<Self, ProxyError> {
+        let source = if location.starts_with("http://") || location.starts_with("https://") {
+            // the blocking client must not live on an async runtime, which the caller may be running on
+            let location = location.to_string();
+            std::thread::spawn(move || {
+                reqwest::blocking::Client::builder()
+                    .no_proxy()
+                    .build()
+                    .and_then(|client| client.get(location).send())
+                    .and_then(|response| response.error_for_status())
+                    .and_then(|response| response.text())
+            })
+            .join()
+            .map_err(|_| pac_error("PAC download panicked"))?
+            .map_err(|e| pac_error(format!("Failed to download PAC file: {}", e)))?
+        } else {
+            let path = match url::Url::parse(location) {
+                Ok(url) if url.scheme() == "file" => url
+                    .to_file_path()
+                    .map_err(|_| pac_error(format!("Invalid PAC file URL: {}", location)))?,
+                _ => location.into(),
+            };
+            std::fs::read_to_string(&path)
+                .map_err(|e| pac_error(format!("Failed to read PAC file {:?}: {}", path, e)))?
+        };
+
+        Self::parse(&source)
+    }
+
+    /// Call `FindProxyForURL(url, host)` and return its result, e.g. `PROXY proxy.example.com:3128; DIRECT`
+    ///
+    /// Globals changed by an earlier call are kept, like in browsers
+    pub fn find_proxy(&mut self, url: &str, host: &str) -> Result<String, ProxyError> {
+        let result = self
+            .find_proxy
+            .call(
+                &JsValue::undefined(),
+                &[JsString::from(url).into(), JsString::from(host).into()],
+                &mut self.context,
+            )
+            .map_err(js_error)?;
+
+        match result {
+            JsValue::String(result) => Ok(result.to_std_string_escaped()),
+            JsValue::Undefined => Ok(String::new()),
+            value => Err(pac_error(format!(
+                "FindProxyForURL returned {} instead of a string",
+                value.display()
+            ))),
+        }
+    }
+}
+
+/// The proxy URL of the first usable entry in a `FindProxyForURL` result, `None` to connect directly
+pub fn parse_pac_result(result: &str) -> Result<Option<String>, ProxyError> {
+    let entries = result
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .collect::<Vec<_>>();
+
+    if entries.is_empty() {
+        return Ok(None);
+    }
+
+    for entry in &entries {
+        let mut parts = entry.split_whitespace();
+        let kind = parts.next().unwrap_or_default().to_uppercase();
+        let host = parts.next();
+
+        let scheme = match (kind.as_str(), host) {
+            ("DIRECT", _) => return Ok(None),
+            ("PROXY" | "HTTP", Some(_)) => "http",
+            ("HTTPS", Some(_)) => "https",
+            ("SOCKS" | "SOCKS5", Some(_)) => "socks5",
+            _ => {
+                tracing::debug!("Skipping unsupported PAC entry: {}", entry);
+                continue;
+            }
+        };
+
+        return Ok(host.map(|host| format!("{}://{}", scheme, host)));
+    }
+
+    Err(pac_error(format!(
+        "No usable proxy in PAC result: {}",
+        result
+    )))
+}
+
+fn pac_error(message: impl Into<String>) -> ProxyError {
+    ProxyError::PacError(message.into())
+}
+
+fn js_error(error: JsError) -> ProxyError {
+    pac_error(error.to_string())
+}
+
+fn resolve_ipv4(host: &str) -> Option<Ipv4Addr> {
+    if let Ok(ip) = host.parse() {
+        return Some(ip);
+    }
+
+    (host, 0)
+        .to_socket_addrs()
+        .ok()?
+        .find_map(|addr| match addr.ip() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        })
+}
+
+fn my_ip_address() -> Ipv4Addr {
+    // connecting a UDP socket sends nothing, it only picks the interface of the default route
+    UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket.connect((Ipv4Addr::new(192, 0, 2, 1), 80))?;
+            socket.local_addr()
+        })
+        .ok()
+        .and_then(|addr| match addr.ip() {
+            IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+            _ => None,
+        })
+        .unwrap_or(Ipv4Addr::LOCALHOST)
+}
+
+/// Match a shell expression with `*` and `?` wildcards, as used by `shExpMatch`
+fn shell_match(text: &str, pattern: &str) -> bool {
+    let text = text.chars().collect::<Vec<_>>();
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                t += 1;
+                p += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn pac_function(name: &str, args: &[String]) -> JsValue {
+    let arg = |i: usize| args.get(i).cloned().unwrap_or_default();
+
+    match name {
+        "isPlainHostName" => (!arg(0).contains('.')).into(),
+        "dnsDomainIs" => arg(0)
+            .to_lowercase()
+            .ends_with(&arg(1).to_lowercase())
+            .into(),
+        "localHostOrDomainIs" => {
+            let (host, domain) = (arg(0).to_lowercase(), arg(1).to_lowercase());
+            (host == domain || (!host.contains('.') && domain.starts_with(&(host + ".")))).into()
+        }
+        "isResolvable" => resolve_ipv4(&arg(0)).is_some().into(),
+        "isInNet" => {
+            let matched = (|| {
+                let ip = u32::from(resolve_ipv4(&arg(0))?);
+                let pattern = u32::from(arg(1).parse::<Ipv4Addr>().ok()?);
+                let mask = u32::from(arg(2).parse::<Ipv4Addr>().ok()?);
+                Some(ip & mask == pattern & mask)
+            })();
+            matched.unwrap_or(false).into()
+        }
+        "dnsResolve" => resolve_ipv4(&arg(0))
+            .map(|ip| JsString::from(ip.to_string()).into())
+            .unwrap_or(JsValue::null()),
+        "myIpAddress" => JsString::from(my_ip_address().to_string()).into(),
+        "dnsDomainLevels" => (arg(0).matches('.').count() as f64).into(),
+        "shExpMatch" => shell_match(&arg(0), &arg(1)).into(),
+        "convert_addr" => arg(0)
+            .parse::<Ipv4Addr>()
+            .map(|ip| f64::from(u32::from(ip)))
+            .unwrap_or(0.0)
+            .into(),
+        "alert" => {
+            tracing::debug!("PAC alert: {}", arg(0));
+            JsValue::undefined()
+        }
+        _ => JsValue::undefined(),
+    }
+}
+
+fn register_pac_functions(context: &mut Context) -> JsResult<()> {
+    for (name, length) in PAC_FUNCTIONS {
+        let function = NativeFunction::from_copy_closure(move |_, args, context| {
+            let args = args
+                .iter()
+                .map(|arg| Ok(arg.to_string(context)?.to_std_string_escaped()))
+                .collect::<JsResult<Vec<_>>>()?;
+            Ok(pac_function(name, &args))
+        });
+        context.register_global_callable(JsString::from(name), length, function)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_pac_script() {
+    let mut script = PacResolver::parse(
+        r#"
+        // proxy everything but the intranet
+        var corporate = "proxy.example.com:3128";
+
+        function isIntranet(host) {
+            return isPlainHostName(host) || dnsDomainIs(host, ".corp.example.com");
+        }
+
+        function FindProxyForURL(url, host) {
+            host = host.toLowerCase();
+            if (isIntranet(host) || isInNet(host, "10.0.0.0", "255.0.0.0"))
+                return "DIRECT";
+            /* the gateway goes through the SOCKS proxy */
+            if (shExpMatch(host, "vpn*.example.com") && url.substring(0, 6) == "https:")
+                return "SOCKS5 127.0.0.1:1080";
+            else if (url.indexOf("legacy") != -1) {
+                return "SOCKS4 legacy.example.com:1080; PROXY " + corporate;
+            }
+            return host.length > 0 ? "PROXY " + corporate + "; DIRECT" : "DIRECT";
+        }
+        "#,
+    )
+    .unwrap();
+
+    let mut find =
+        |url: &str, host: &str| parse_pac_result(&script.find_proxy(url, host).unwrap()).unwrap();
+
+    assert_eq!(find("https://intranet/", "intranet"), None);
+    assert_eq!(
+        find("https://wiki.corp.example.com/", "wiki.corp.example.com"),
+        None
+    );
+    assert_eq!(find("https://10.1.2.3/", "10.1.2.3"), None);
+    assert_eq!(
+        find("https://vpn1.example.com/", "VPN1.example.com"),
+        Some("socks5://127.0.0.1:1080".to_string())
+    );
+    assert_eq!(
+        find("https://example.com/legacy", "example.com"),
+        Some("http://proxy.example.com:3128".to_string())
+    );
+    assert_eq!(
+        find("https://example.com/", "example.com"),
+        Some("http://proxy.example.com:3128".to_string())
+    );
+
+    assert!(shell_match("vpn.example.com", "*.example.com"));
+    assert!(shell_match("a.b", "?.*"));
+    assert!(!shell_match("example.org", "*.example.com"));
+
+    assert!(matches!(
+        PacResolver::parse("function other() { return 1; }"),
+        Err(ProxyError::PacError(_))
+    ));
+    assert!(matches!(
+        PacResolver::parse("function FindProxyForURL(url, host) { return weekdayRange(\"MON\"); }")
+            .unwrap()
+            .find_proxy("https://example.com/", "example.com"),
+        Err(ProxyError::PacError(_))
+    ));
+    assert!(matches!(
+        PacResolver::parse(
+            "function FindProxyForURL(url, host) { return FindProxyForURL(url, host); }"
+        )
+        .unwrap()
+        .find_proxy("https://example.com/", "example.com"),
+        Err(ProxyError::PacError(_))
+    ));
+}
+
+#[test]
+fn test_pac_grammar() {
+    let mut script = PacResolver::parse(
+        r#"
+        var rules = [
+            { pattern: /^(.+\.)?internal\.example\.com$/i, proxy: "DIRECT" },
+            { pattern: /\.example\.org$/, proxy: "HTTPS secure.example.com:443" },
+        ];
+        const fallback = ["PROXY a.example.com:3128", "PROXY b.example.com:3128"];
+
+        function FindProxyForURL(url, host) {
+            for (let i = 0; i < rules.length; i++) {
+                if (rules[i].pattern.test(host)) return rules[i].proxy;
+            }
+            switch (url.split(":")[0]) {
+                case "ftp":
+                    return "SOCKS " + myIpAddress() + ":1080";
+                default:
+                    break;
+            }
+            let levels = 0;
+            while (levels < dnsDomainLevels(host)) levels++;
+            const pick = (n) => fallback[n % fallback.length];
+            return `${pick(levels)}; DIRECT`;
+        }
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        script
+            .find_proxy(
+                "https://vpn.internal.example.com/",
+                "VPN.Internal.example.com"
+            )
+            .unwrap(),
+        "DIRECT"
+    );
+    assert_eq!(
+        script
+            .find_proxy("https://www.example.org/", "www.example.org")
+            .unwrap(),
+        "HTTPS secure.example.com:443"
+    );
+    assert!(script
+        .find_proxy("ftp://files.example.com/", "files.example.com")
+        .unwrap()
+        .starts_with("SOCKS "));
+    assert_eq!(
+        script
+            .find_proxy("https://example.com/", "example.com")
+            .unwrap(),
+        "PROXY b.example.com:3128; DIRECT"
+    );
+    assert_eq!(
+        script
+            .find_proxy("https://a.example.com/", "a.example.com")
+            .unwrap(),
+        "PROXY a.example.com:3128; DIRECT"
+    );
+
+    assert_eq!(
+        PacResolver::parse(
+            r#"function FindProxyForURL(url, host) {
+                return [localHostOrDomainIs(host, "www.example.com"), dnsResolve("10.0.0.1"),
+                    convert_addr("10.0.0.1"), isResolvable("10.0.0.1")].join(",");
+            }"#
+        )
+        .unwrap()
+        .find_proxy("https://www/", "www")
+        .unwrap(),
+        "true,10.0.0.1,167772161,true"
+    );
+}
+
+#[test]
+fn test_pac_compiled_once() {
+    let mut script = PacResolver::parse(
+        r#"
+        var calls = 0;
+        function FindProxyForURL(url, host) {
+            calls++;
+            return "PROXY proxy.example.com:" + calls;
+        }
+        "#,
+    )
+    .unwrap();
+
+    // the script runs once, later calls reuse its globals
+    for calls in 1..=3 {
+        assert_eq!(
+            script
+                .find_proxy("https://example.com/", "example.com")
+                .unwrap(),
+            format!("PROXY proxy.example.com:{}", calls)
+        );
+    }
+}
+
+#[test]
+fn test_pac_rejected() {
+    let find = |source: &str| {
+        PacResolver::parse(source)
+            .and_then(|mut script| script.find_proxy("https://example.com/", "example.com"))
+    };
+
+    // syntax errors
+    assert!(matches!(
+        PacResolver::parse("function FindProxyForURL(url, host) { return \"DIRECT\";"),
+        Err(ProxyError::PacError(_))
+    ));
+    assert!(matches!(
+        PacResolver::parse("function FindProxyForURL(url, host) { return = 1; }"),
+        Err(ProxyError::PacError(_))
+    ));
+
+    // FindProxyForURL is not a function
+    assert!(matches!(
+        PacResolver::parse("var FindProxyForURL = \"DIRECT\";"),
+        Err(ProxyError::PacError(_))
+    ));
+
+    // errors raised by the script
+    assert!(matches!(
+        find("function FindProxyForURL(url, host) { throw new Error(\"no proxy\"); }"),
+        Err(ProxyError::PacError(message)) if message.contains("no proxy")
+    ));
+    assert!(matches!(
+        find("function FindProxyForURL(url, host) { return undefinedFunction(host); }"),
+        Err(ProxyError::PacError(_))
+    ));
+    assert!(matches!(
+        find("function FindProxyForURL(url, host) { return 42; }"),
+        Err(ProxyError::PacError(_))
+    ));
+
+    // scripts which would never return
+    assert!(matches!(
+        find("function FindProxyForURL(url, host) { while (true) {} }"),
+        Err(ProxyError::PacError(_))
+    ));
+    assert!(matches!(
+        find("var x = 0; for (;;) { x++; } function FindProxyForURL(url, host) { return \"DIRECT\"; }"),
+        Err(ProxyError::PacError(_))
+    ));
+}
@@ -0,0 +1,639 @@
+//! Pre-flight checks of a server without logging in
+//!
+//! [probe] connects to the server, reports the certificate chain, the auth form and the single sign-on page it leads to,
+//! then cancels the authentication before any credential is asked for
+
+use crate::{
+    config::ConfigBuilder,
+    events::EventHandlers,
+    result::{OpenconnectError, OpenconnectResult},
+    Connectable, VpnClient,
+};
+use openconnect_sys::{
+    oc_auth_form, oc_form_opt, oc_form_opt_select, OC_FORM_OPT_HIDDEN, OC_FORM_OPT_IGNORE,
+    OC_FORM_OPT_PASSWORD, OC_FORM_OPT_SELECT, OC_FORM_OPT_SSO_TOKEN, OC_FORM_OPT_SSO_USER,
+    OC_FORM_OPT_TEXT, OC_FORM_OPT_TOKEN,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    ffi::CStr,
+    net::{TcpStream, ToSocketAddrs},
+    os::raw::c_char,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for the TCP connection and for the single sign-on page
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the server presented before the login
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeReport {
+    pub server: String,
+    pub hostname: Option<String>,
+    pub port: i32,
+    pub latency: ProbeLatency,
+    pub certificate: Option<CertificateInfo>,
+    /// `None` if the server went straight to single sign-on
+    pub auth_form: Option<AuthFormInfo>,
+    pub sso: Option<SsoInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeLatency {
+    /// Time to open the TCP connection
+    pub connect_ms: u64,
+    /// Time from the first connection until the auth form or the single sign-on page arrived, including the TLS handshake
+    pub auth_form_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateInfo {
+    /// The fingerprint to accept the certificate with, e.g. `pin-sha256:...`
+    pub fingerprint: String,
+    /// Whether the certificate was verified by the system trust store
+    pub trusted: bool,
+    pub details: Option<String>,
+    /// The peer certificate first
+    pub chain: Vec<ChainCertificate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainCertificate {
+    pub subject: Option<String>,
+    pub issuer: Option<String>,
+    pub not_before: Option<String>,
+    pub not_after: Option<String>,
+    /// SHA-256 of the DER encoding, in hex
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthFormInfo {
+    pub auth_id: String,
+    pub banner: Option<String>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+    pub fields: Vec<FormFieldInfo>,
+    /// The auth groups to choose from, empty if the server offers none
+    pub groups: Vec<String>,
+    pub selected_group: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormFieldInfo {
+    pub name: String,
+    pub label: Option<String>,
+    pub kind: FieldKind,
+    /// The choices of a select field
+    pub choices: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FieldKind {
+    Text,
+    Password,
+    Select,
+    Hidden,
+    Token,
+    SsoToken,
+    SsoUser,
+    Unknown,
+}
+
+impl FieldKind {
+    fn from_opt_type(type_: u32) -> Self {
+        match type_ {
+            OC_FORM_OPT_TEXT => FieldKind::Text,
+            OC_FORM_OPT_PASSWORD => FieldKind::Password,
+            OC_FORM_OPT_SELECT => FieldKind::Select,
+            OC_FORM_OPT_HIDDEN => FieldKind::Hidden,
+            OC_FORM_OPT_TOKEN => FieldKind::Token,
+            OC_FORM_OPT_SSO_TOKEN => FieldKind::SsoToken,
+            OC_FORM_OPT_SSO_USER => FieldKind::SsoUser,
+            _ => FieldKind::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SsoInfo {
+    /// The login page the browser would be sent to
+    pub login_url: String,
+    /// Where the login page redirects to, usually the identity provider
+    pub redirect: Option<String>,
+    pub kind: SsoKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SsoKind {
+    Saml,
+    Oidc,
+    Unknown,
+}
+
+impl SsoKind {
+    /// Tell the protocol apart by the parameters of the identity provider request
+    fn classify(url: &str) -> Option<Self> {
+        let url = url::Url::parse(url).ok()?;
+        let keys = url
+            .query_pairs()
+            .map(|(key, _)| key.to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        let has_key = |name: &str| keys.iter().any(|key| key == name);
+        let path = url.path().to_ascii_lowercase();
+
+        if has_key("samlrequest") || path.contains("saml") {
+            Some(SsoKind::Saml)
+        } else if (has_key("client_id") && has_key("response_type"))
+            || ["oauth", "openid", "oidc"]
+                .iter()
+                .any(|name| path.contains(name))
+        {
+            Some(SsoKind::Oidc)
+        } else {
+            None
+        }
+    }
+}
+
+/// What the callbacks record while a client is probing, see [VpnClient::is_probing]
+#[derive(Debug)]
+pub(crate) struct ProbeState {
+    started: Instant,
+    responded: Option<Duration>,
+    certificate: Option<CertificateInfo>,
+    auth_form: Option<AuthFormInfo>,
+    sso_login: Option<String>,
+}
+
+impl ProbeState {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            responded: None,
+            certificate: None,
+            auth_form: None,
+            sso_login: None,
+        }
+    }
+
+    fn respond(&mut self) {
+        self.responded.get_or_insert(self.started.elapsed());
+    }
+}
+
+impl ChainCertificate {
+    pub fn from_der(der: &[u8]) -> Self {
+        let names = parse_certificate(der);
+        Self {
+            subject: names.as_ref().and_then(|names| names.subject.clone()),
+            issuer: names.as_ref().and_then(|names| names.issuer.clone()),
+            not_before: names.as_ref().and_then(|names| names.not_before.clone()),
+            not_after: names.and_then(|names| names.not_after),
+            sha256: hex::encode(Sha256::digest(der)),
+        }
+    }
+}
+
+/// Connect to the server and report what it presents before the login, no credential is sent
+///
+/// Untrusted certificates are accepted for the probe only, [CertificateInfo::trusted] tells whether a connection
+/// would have to accept it
+pub fn probe(server: &str) -> OpenconnectResult<ProbeReport> {
+    let config = ConfigBuilder::new().build()?;
+    let client = VpnClient::new(config, EventHandlers::default())?;
+    client.probe(server)
+}
+
+impl VpnClient {
+    /// Whether the auth callbacks should record what they see instead of answering
+    pub(crate) fn is_probing(&self) -> bool {
+        self.probe.lock().is_ok_and(|probe| probe.is_some())
+    }
+
+    fn with_probe(&self, record: impl FnOnce(&mut ProbeState)) {
+        if let Ok(mut probe) = self.probe.lock() {
+            if let Some(probe) = probe.as_mut() {
+                record(probe);
+            }
+        }
+    }
+
+    /// Record the peer certificate, `trusted` is false when it is presented to the certificate callback
+    pub(crate) fn record_probe_cert(&self, trusted: bool) {
+        let certificate = CertificateInfo {
+            fingerprint: self.get_peer_cert_hash(),
+            trusted,
+            details: self.backend.get_peer_cert_details(),
+            chain: self
+                .backend
+                .get_peer_cert_chain()
+                .iter()
+                .map(|der| ChainCertificate::from_der(der))
+                .collect(),
+        };
+        self.with_probe(|probe| {
+            probe.certificate.get_or_insert(certificate);
+        });
+    }
+
+    /// A certificate which did not reach the certificate callback was verified by the system trust store
+    fn record_trusted_cert(&self) {
+        let missing = self.probe.lock().is_ok_and(|probe| {
+            probe
+                .as_ref()
+                .is_some_and(|probe| probe.certificate.is_none())
+        });
+        if missing {
+            self.record_probe_cert(true);
+        }
+    }
+
+    /// Record the first auth form
+    ///
+    /// SAFETY: `form` must point to a valid form of libopenconnect
+    pub(crate) unsafe fn record_probe_form(&self, form: *const oc_auth_form) {
+        self.record_trusted_cert();
+
+        let auth_form = describe_form(form);
+        self.with_probe(|probe| {
+            probe.respond();
+            probe.auth_form.get_or_insert(auth_form);
+        });
+    }
+
+    pub(crate) fn record_probe_sso(&self, uri: &str) {
+        self.record_trusted_cert();
+
+        self.with_probe(|probe| {
+            probe.respond();
+            probe.sso_login.get_or_insert(uri.to_string());
+        });
+    }
+
+    pub(crate) fn probe(&self, server: &str) -> OpenconnectResult<ProbeReport> {
+        self.set_protocol("anyconnect")?;
+        self.parse_url(server)?;
+        let hostname = self.get_hostname();
+        let port = self.get_port();
+
+        let connect = measure_connect(hostname.as_deref().unwrap_or_default(), port)?;
+
+        if let Ok(mut probe) = self.probe.lock() {
+            *probe = Some(ProbeState::new());
+        }
+        // offer single sign-on, so that the server reveals its login page
+        self.backend.set_external_browser_handler();
+
+        // the form callback cancels the login, so an error is expected
+        let result = self.obtain_cookie();
+        let state = self.probe.lock().ok().and_then(|mut probe| probe.take());
+
+        let Some(state) = state.filter(|state| state.certificate.is_some()) else {
+            return Err(result
+                .err()
+                .unwrap_or(OpenconnectError::ServerUnreachableError(
+                    "no certificate received".to_string(),
+                )));
+        };
+
+        let sso = state.sso_login.map(|login_url| {
+            let redirect = follow_login(&login_url);
+            let kind = redirect
+                .as_deref()
+                .and_then(SsoKind::classify)
+                .or_else(|| SsoKind::classify(&login_url))
+                .unwrap_or(SsoKind::Unknown);
+            SsoInfo {
+                login_url,
+                redirect,
+                kind,
+            }
+        });
+
+        Ok(ProbeReport {
+            server: server.to_string(),
+            hostname,
+            port,
+            latency: ProbeLatency {
+                connect_ms: connect.as_millis() as u64,
+                auth_form_ms: state.responded.map(|elapsed| elapsed.as_millis() as u64),
+            },
+            certificate: state.certificate,
+            auth_form: state.auth_form,
+            sso,
+        })
+    }
+}
+
+fn measure_connect(hostname: &str, port: i32) -> OpenconnectResult<Duration> {
+    let unreachable = |e: String| OpenconnectError::ServerUnreachableError(e);
+    let port = u16::try_from(port).map_err(|_| unreachable(format!("invalid port {}", port)))?;
+    let addrs = (hostname, port)
+        .to_socket_addrs()
+        .map_err(|e| unreachable(format!("{}: {}", hostname, e)))?;
+
+    let mut last_error = unreachable(format!("{} has no address", hostname));
+    for addr in addrs {
+        let started = Instant::now();
+        match TcpStream::connect_timeout(&addr, PROBE_TIMEOUT) {
+            Ok(_) => return Ok(started.elapsed()),
+            Err(e) => last_error = unreachable(format!("{}: {}", addr, e)),
+        }
+    }
+    Err(last_error)
+}
+
+/// Request the login page once and return where it redirects to
+fn follow_login(login_url: &str) -> Option<String> {
+    // the blocking client must not live on an async runtime, which the caller may be running on
+    let login_url = login_url.to_string();
+    std::thread::spawn(move || {
+        // the certificate of the gateway is reported separately, the request carries no credentials
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .redirect(reqwest::redirect::Policy::none())
+            .danger_accept_invalid_certs(true)
+            .timeout(PROBE_TIMEOUT)
+            .build()
+            .ok()?;
+        let response = client.get(&login_url).send().ok()?;
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)?
+            .to_str()
+            .ok()?;
+        response.url().join(location).ok().map(String::from)
+    })
+    .join()
+    .ok()
+    .flatten()
+}
+
+unsafe fn to_string(ptr: *const c_char) -> Option<String> {
+    (!ptr.is_null()).then(|| CStr::from_ptr(ptr).to_string_lossy().to_string())
+}
+
+unsafe fn choices(select: *const oc_form_opt_select) -> Vec<String> {
+    if (*select).choices.is_null() {
+        return vec![];
+    }
+    (0..(*select).nr_choices.max(0) as usize)
+        .map(|i| *(*select).choices.add(i))
+        .filter(|choice| !choice.is_null())
+        .filter_map(|choice| to_string((*choice).name))
+        .collect()
+}
+
+/// SAFETY: `form` must point to a valid form of libopenconnect
+unsafe fn describe_form(form: *const oc_auth_form) -> AuthFormInfo {
+    let authgroup = (*form).authgroup_opt;
+    let groups = if authgroup.is_null() {
+        vec![]
+    } else {
+        choices(authgroup)
+    };
+    let selected_group = usize::try_from((*form).authgroup_selection)
+        .ok()
+        .and_then(|i| groups.get(i).cloned());
+
+    let mut fields = vec![];
+    let mut opt = (*form).opts;
+    while !opt.is_null() {
+        let current: *mut oc_form_opt = opt;
+        opt = (*opt).next;
+
+        // the auth group is listed on its own
+        if current.cast::<oc_form_opt_select>() == authgroup
+            || (*current).flags & OC_FORM_OPT_IGNORE != 0
+        {
+            continue;
+        }
+
+        let kind = FieldKind::from_opt_type((*current).type_ as u32);
+        fields.push(FormFieldInfo {
+            name: to_string((*current).name).unwrap_or_default(),
+            label: to_string((*current).label),
+            kind,
+            choices: if kind == FieldKind::Select {
+                choices(current.cast())
+            } else {
+                vec![]
+            },
+        });
+    }
+
+    AuthFormInfo {
+        auth_id: to_string((*form).auth_id).unwrap_or_default(),
+        banner: to_string((*form).banner),
+        message: to_string((*form).message),
+        error: to_string((*form).error),
+        fields,
+        groups,
+        selected_group,
+    }
+}
+
+/// The names and the validity read from an X.509 certificate
+struct CertificateNames {
+    subject: Option<String>,
+    issuer: Option<String>,
+    not_before: Option<String>,
+    not_after: Option<String>,
+}
+
+/// A DER element, the tag and the contents
+struct Der<'a> {
+    tag: u8,
+    contents: &'a [u8],
+}
+
+/// Read one element, returning the rest of the input
+fn read_der(input: &[u8]) -> Option<(Der<'_>, &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, mut rest) = rest.split_first()?;
+    let len = if first < 0x80 {
+        first as usize
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count]
+            .iter()
+            .fold(0usize, |len, byte| len << 8 | *byte as usize);
+        rest = &rest[count..];
+        len
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some((
+        Der {
+            tag,
+            contents: &rest[..len],
+        },
+        &rest[len..],
+    ))
+}
+
+/// The fields of the TBSCertificate following the serial number
+fn tbs_fields(der: &[u8]) -> Option<&[u8]> {
+    let (certificate, _) = read_der(der)?;
+    let (tbs, _) = read_der(certificate.contents)?;
+
+    // the version is optional and tagged [0], the serial number follows
+    let (mut field, mut rest) = read_der(tbs.contents)?;
+    if field.tag == 0xa0 {
+        (field, rest) = read_der(rest)?;
+    }
+    if field.tag != 0x02 {
+        return None;
+    }
+
+    Some(rest)
+}
+
+fn parse_certificate(der: &[u8]) -> Option<CertificateNames> {
+    let rest = tbs_fields(der)?;
+    let (_signature, rest) = read_der(rest)?;
+    let (issuer, rest) = read_der(rest)?;
+    let (validity, rest) = read_der(rest)?;
+    let (subject, _) = read_der(rest)?;
+
+    let (not_before, rest) = read_der(validity.contents)?;
+    let (not_after, _) = read_der(rest)?;
+
+    Some(CertificateNames {
+        subject: parse_name(subject.contents),
+        issuer: parse_name(issuer.contents),
+        not_before: parse_time(&not_before),
+        not_after: parse_time(&not_after),
+    })
+}
+
+/// The DER encoded SubjectPublicKeyInfo of an X.509 certificate, including its tag and length
+///
+/// Its SHA-256 hash is the pin reported by `openconnect_get_peer_cert_hash`
+pub(crate) fn subject_public_key_info(der: &[u8]) -> Option<&[u8]> {
+    let mut rest = tbs_fields(der)?;
+
+    // signature, issuer, validity and subject
+    for _ in 0..4 {
+        (_, rest) = read_der(rest)?;
+    }
+
+    let (spki, after) = read_der(rest)?;
+    if spki.tag != 0x30 {
+        return None;
+    }
+    Some(&rest[..rest.len() - after.len()])
+}
+
+/// Format a distinguished name like `CN=vpn.example.com, O=Example`
+fn parse_name(mut input: &[u8]) -> Option<String> {
+    let mut parts = vec![];
+    while !input.is_empty() {
+        let (set, rest) = read_der(input)?;
+        input = rest;
+
+        let mut attributes = set.contents;
+        while !attributes.is_empty() {
+            let (attribute, rest) = read_der(attributes)?;
+            attributes = rest;
+
+            let (oid, rest) = read_der(attribute.contents)?;
+            let (value, _) = read_der(rest)?;
+            let oid = parse_oid(oid.contents);
+            let key = match oid.as_str() {
+                "2.5.4.3" => "CN",
+                "2.5.4.6" => "C",
+                "2.5.4.7" => "L",
+                "2.5.4.8" => "ST",
+                "2.5.4.10" => "O",
+                "2.5.4.11" => "OU",
+                _ => oid.as_str(),
+            };
+            parts.push(format!("{}={}", key, parse_string(&value)));
+        }
+    }
+    Some(parts.join(", "))
+}
+
+fn parse_oid(contents: &[u8]) -> String {
+    let Some((&first, rest)) = contents.split_first() else {
+        return String::new();
+    };
+    let mut arcs = vec![
+        (first / 40).min(2) as u64,
+        (first - (first / 40).min(2) * 40) as u64,
+    ];
+    let mut arc = 0u64;
+    for byte in rest {
+        arc = arc << 7 | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    arcs.iter()
+        .map(|arc| arc.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn parse_string(value: &Der) -> String {
+    match value.tag {
+        // BMPString is UTF-16
+        0x1e => char::decode_utf16(
+            value
+                .contents
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]])),
+        )
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect(),
+        _ => String::from_utf8_lossy(value.contents).to_string(),
+    }
+}
+
+/// Read a UTCTime or a GeneralizedTime as RFC 3339
+fn parse_time(value: &Der) -> Option<String> {
+    let text = std::str::from_utf8(value.contents).ok()?;
+    let text = match value.tag {
+        // two digit years before 50 are in the 21st century, see RFC 5280
+        0x17 => {
+            let year = text.get(..2)?.parse::<u32>().ok()?;
+            format!("{}{}", if year < 50 { "20" } else { "19" }, text)
+        }
+        0x18 => text.to_string(),
+        _ => return None,
+    };
+    chrono::NaiveDateTime::parse_from_str(&text, "%Y%m%d%H%M%SZ")
+        .ok()
+        .map(|time| time.and_utc().to_rfc3339())
+}
+
+#[test]
+fn test_sso_kind() {
+    let saml = "https://idp.example.com/sso?SAMLRequest=fZJN&RelayState=abc";
+    let oidc = "https://login.example.com/authorize?client_id=vpn&response_type=code";
+    assert_eq!(SsoKind::classify(saml), Some(SsoKind::Saml));
+    assert_eq!(SsoKind::classify(oidc), Some(SsoKind::Oidc));
+    assert_eq!(
+        SsoKind::classify("https://vpn.example.com/+CSCOE+/saml/sp/login?tgname=staff"),
+        Some(SsoKind::Saml)
+    );
+    assert_eq!(SsoKind::classify("https://vpn.example.com/login"), None);
+    assert_eq!(SsoKind::classify("not a url"), None);
+}
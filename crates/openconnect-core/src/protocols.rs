@@ -1,3 +1,4 @@
+use crate::result::{OpenconnectError, OpenconnectResult};
 use openconnect_sys::{oc_vpn_proto, openconnect_get_supported_protocols};
 
 #[derive(Debug, Clone)]
@@ -8,13 +9,13 @@ pub struct Protocol {
     pub flags: u32,
 }
 
-pub fn get_supported_protocols() -> Vec<Protocol> {
+pub fn get_supported_protocols() -> OpenconnectResult<Vec<Protocol>> {
     let mut raw_protocols = std::ptr::null_mut::<oc_vpn_proto>();
     let mut protocols: Vec<Protocol> = vec![];
     unsafe {
         let n = openconnect_get_supported_protocols(&mut raw_protocols);
         if n < 0 {
-            panic!("openconnect_get_supported_protocols failed");
+            return Err(OpenconnectError::GetSupportedProtocolsError(n));
         }
         while !raw_protocols.is_null() && !(*raw_protocols).name.is_null() {
             let name = std::ffi::CStr::from_ptr((*raw_protocols).name)
@@ -36,14 +37,15 @@ pub fn get_supported_protocols() -> Vec<Protocol> {
             raw_protocols = raw_protocols.offset(1);
         }
     }
-    protocols
+    Ok(protocols)
 }
 
 // TODO: temp solution
-pub fn get_anyconnect_protocol() -> Protocol {
-    get_supported_protocols()
-        .iter()
+pub fn get_anyconnect_protocol() -> OpenconnectResult<Protocol> {
+    get_supported_protocols()?
+        .into_iter()
         .find(|p| p.name == "anyconnect")
-        .expect("anyconnect protocol not found")
-        .clone()
+        .ok_or(OpenconnectError::ProtocolNotFoundError(
+            "anyconnect".to_string(),
+        ))
 }
@@ -0,0 +1,107 @@
+use crate::VpnClient;
+use lazy_static::lazy_static;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+/// Unique id of a [VpnClient] within the current process
+pub type ClientId = u64;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+lazy_static! {
+    static ref CLIENTS: Mutex<HashMap<ClientId, Weak<VpnClient>>> = Mutex::new(HashMap::new());
+}
+
+pub(crate) fn next_id() -> ClientId {
+    NEXT_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+/// Register a client, so that process wide events (e.g. signals) can be routed to it
+pub(crate) fn register(id: ClientId, client: Weak<VpnClient>) {
+    if let Ok(mut clients) = CLIENTS.lock() {
+        clients.insert(id, client);
+    }
+}
+
+pub(crate) fn unregister(id: ClientId) {
+    if let Ok(mut clients) = CLIENTS.lock() {
+        clients.remove(&id);
+    }
+}
+
+/// Get all registered clients which are still alive
+pub(crate) fn clients() -> Vec<Arc<VpnClient>> {
+    let Ok(mut clients) = CLIENTS.lock() else {
+        return vec![];
+    };
+
+    clients.retain(|_, client| client.strong_count() > 0);
+    clients.values().filter_map(Weak::upgrade).collect()
+}
+
+#[cfg(all(test, unix))]
+fn fake_client(signal_policy: crate::command::SignalPolicy) -> Arc<VpnClient> {
+    use crate::{backend::fake::FakeBackend, config::ConfigBuilder, events::EventHandlers};
+
+    let config = ConfigBuilder::default()
+        .signal_policy(signal_policy)
+        .build()
+        .unwrap();
+    VpnClient::with_backend(
+        config,
+        EventHandlers::default(),
+        Box::new(FakeBackend::new()),
+    )
+    .unwrap()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_register_across_threads() {
+    use crate::command::SignalPolicy;
+
+    let clients = (0..8)
+        .map(|_| std::thread::spawn(|| fake_client(SignalPolicy::Disabled)))
+        .map(|thread| thread.join().unwrap())
+        .collect::<Vec<_>>();
+
+    let mut ids = clients.iter().map(|client| client.id()).collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), clients.len());
+
+    let registered = |id: &ClientId| CLIENTS.lock().unwrap().contains_key(id);
+    assert!(ids.iter().all(registered));
+
+    // dropping on another thread unregisters as well
+    std::thread::spawn(move || drop(clients)).join().unwrap();
+    assert!(!ids.iter().any(registered));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_dispatch_to_live_clients() {
+    use crate::command::{dispatch, Command, SignalMap, SignalPolicy};
+
+    // a trigger no other test maps, since the registry is shared by the whole process
+    const TRIGGER: i32 = 4242;
+    let policy = SignalPolicy::Manual(SignalMap::new().map(TRIGGER, Command::Stats));
+
+    let live = fake_client(policy.clone());
+    let dropped = fake_client(policy);
+    let ignoring = fake_client(SignalPolicy::Disabled);
+    let dropped_id = dropped.id();
+    drop(dropped);
+
+    assert!(!clients().iter().any(|client| client.id() == dropped_id));
+    assert_eq!(dispatch(TRIGGER), 1);
+
+    drop(live);
+    assert_eq!(dispatch(TRIGGER), 0);
+    drop(ignoring);
+}
@@ -0,0 +1,111 @@
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
+
+type Lookup = Arc<dyn Fn(&str) -> Option<IpAddr> + Send + Sync>;
+
+/// Pin host names to addresses instead of asking the system resolver, e.g. to reach one node of a load-balanced gateway
+///
+/// Host names without a pinned address are resolved by the system as usual
+#[derive(Clone)]
+pub struct Resolver(Lookup);
+
+impl std::fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Resolver").finish_non_exhaustive()
+    }
+}
+
+impl Resolver {
+    pub fn new<F>(lookup: F) -> Self
+    where
+        F: Fn(&str) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        Self(Arc::new(lookup))
+    }
+
+    /// Pin each host of the map to its address, host names are matched case-insensitively
+    pub fn from_map<I>(pinned: I) -> Self
+    where
+        I: IntoIterator<Item = (String, IpAddr)>,
+    {
+        let pinned = pinned
+            .into_iter()
+            .map(|(host, ip)| (host.to_lowercase(), ip))
+            .collect::<HashMap<_, _>>();
+        Self::new(move |host| pinned.get(&host.to_lowercase()).copied())
+    }
+
+    /// Try this resolver first and fall back to the other one
+    pub fn or(self, other: Resolver) -> Self {
+        Self::new(move |host| self.lookup(host).or_else(|| other.lookup(host)))
+    }
+
+    /// The pinned address of the host, `None` to resolve it with the system resolver
+    pub fn lookup(&self, host: &str) -> Option<IpAddr> {
+        // a bracketed IPv6 literal may be passed as is
+        (self.0)(host.trim_start_matches('[').trim_end_matches(']'))
+    }
+}
+
+#[cfg(unix)]
+pub(crate) unsafe extern "C" fn getaddrinfo_cb(
+    privdata: *mut ::std::os::raw::c_void,
+    node: *const ::std::os::raw::c_char,
+    service: *const ::std::os::raw::c_char,
+    hints: *const openconnect_sys::addrinfo,
+    res: *mut *mut openconnect_sys::addrinfo,
+) -> ::std::os::raw::c_int {
+    use std::ffi::{CStr, CString};
+
+    let client = crate::VpnClient::ref_from_raw(privdata);
+    let hints = hints as *const libc::addrinfo;
+    let res = res as *mut *mut libc::addrinfo;
+
+    crate::result::guard_callback(Some(client), "getaddrinfo", libc::EAI_FAIL, || {
+        let pinned = (!node.is_null())
+            .then(|| CStr::from_ptr(node).to_string_lossy().to_string())
+            .and_then(|host| Some((client.resolve_host(&host)?, host)))
+            .and_then(|(ip, host)| {
+                tracing::debug!("Resolved {} to pinned address {}", host, ip);
+                CString::new(ip.to_string()).ok()
+            });
+
+        // the result is released by libopenconnect with freeaddrinfo, so it must come from getaddrinfo as well
+        match pinned {
+            Some(ip) => libc::getaddrinfo(ip.as_ptr(), service, hints, res),
+            None => libc::getaddrinfo(node, service, hints, res),
+        }
+    })
+}
+
+#[test]
+fn test_resolver() {
+    let pinned = Resolver::from_map([
+        ("vpn.example.com".to_string(), "192.0.2.10".parse().unwrap()),
+        (
+            "node2.example.com".to_string(),
+            "2001:db8::2".parse().unwrap(),
+        ),
+    ]);
+    assert_eq!(
+        pinned.lookup("VPN.example.com"),
+        Some("192.0.2.10".parse().unwrap())
+    );
+    assert_eq!(
+        pinned.lookup("[node2.example.com]"),
+        Some("2001:db8::2".parse().unwrap())
+    );
+    assert_eq!(pinned.lookup("other.example.com"), None);
+
+    let resolver = pinned.or(Resolver::new(|host| {
+        host.ends_with(".internal")
+            .then(|| "10.0.0.1".parse().unwrap())
+    }));
+    assert_eq!(
+        resolver.lookup("gateway.internal"),
+        Some("10.0.0.1".parse().unwrap())
+    );
+    assert_eq!(
+        resolver.lookup("vpn.example.com"),
+        Some("192.0.2.10".parse().unwrap())
+    );
+}
@@ -1,4 +1,4 @@
-use crate::{events::Events, VpnClient};
+use crate::{events::Events, proxy::ProxyError, VpnClient};
 use thiserror::Error;
 
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
@@ -9,54 +9,398 @@ pub enum OpenconnectError {
     #[error("Failed to setup shutdown error: {0}")]
     SetupShutdownError(String),
 
-    #[error("Failed to setup cookie manually. Error code: {0}")]
+    #[error("Failed to setup cookie manually: {}", ErrorCode::from(.0))]
     SetCookieError(i32),
 
-    #[error("Failed to obtain cookie from server. Error code: {0}")]
+    #[error("Failed to obtain cookie from server: {}", ErrorCode::from(.0))]
     ObtainCookieError(i32),
 
-    #[error("Failed to set protocol. Error code: {0}")]
+    #[error("Failed to set protocol: {}", ErrorCode::from(.0))]
     SetProtocolError(i32),
 
-    #[error("Failed to set reported OS. Error code: {0}")]
+    #[error("Failed to set reported OS: {}", ErrorCode::from(.0))]
     SetReportOSError(i32),
 
     #[error("Failed to setup command pipe. Error code: {0}")]
     CmdPipeError(i32),
 
-    #[error("Failed to set HTTP proxy. Error code: {0}")]
+    #[error("Failed to set HTTP proxy: {}", ErrorCode::from(.0))]
     SetProxyError(i32),
 
-    #[error("Failed to make CSTP connection. Error code: {0}")]
+    #[error("Failed to set proxy authentication methods: {}", ErrorCode::from(.0))]
+    SetProxyAuthError(i32),
+
+    #[error("Invalid proxy: {0}")]
+    InvalidProxyError(#[from] ProxyError),
+
+    #[error("Failed to make CSTP connection: {}", ErrorCode::from(.0))]
     MakeCstpError(i32),
 
-    #[error("Failed to disable DTLS. Error code: {0}")]
+    #[error("Failed to disable DTLS: {}", ErrorCode::from(.0))]
     DisableDTLSError(i32),
 
-    #[error("Failed to parse URL. Error code: {0}")]
+    #[error("Failed to disable IPv6: {}", ErrorCode::from(.0))]
+    DisableIPv6Error(i32),
+
+    #[error("Failed to set compression mode: {}", ErrorCode::from(.0))]
+    SetCompressionModeError(i32),
+
+    #[error("Failed to allow insecure crypto: {}", ErrorCode::from(.0))]
+    SetInsecureCryptoError(i32),
+
+    #[error("Failed to parse URL: {}", ErrorCode::from(.0))]
     ParseUrlError(i32),
 
-    #[error("Failed to setup tun device. Error code: {0}")]
+    #[error("Failed to set hostname: {}", ErrorCode::from(.0))]
+    SetHostnameError(i32),
+
+    #[error("Failed to set SNI: {}", ErrorCode::from(.0))]
+    SetSniError(i32),
+
+    #[error("Failed to setup tun device: {}", ErrorCode::from(.0))]
     SetupTunDeviceEror(i32),
 
-    #[error("Failed to set client certificate. Error code: {0}")]
+    #[error("Failed to set client certificate: {}", ErrorCode::from(.0))]
     SetClientCertError(i32),
 
-    #[error("Failed to set MCA certificate. Error code: {0}")]
+    #[error("Failed to set MCA certificate: {}", ErrorCode::from(.0))]
     SetMCACertError(i32),
 
-    #[error("Main loop error: {0}")]
+    #[error("Main loop error: {}", ErrorCode::from(.0))]
     MainLoopError(i32),
 
-    #[error("Failed to get IP info. Error code: {0}")]
+    #[error("Server is not reachable: {0}")]
+    ServerUnreachableError(String),
+
+    #[error("Dead peer detected, the VPN gateway is not reachable anymore")]
+    DeadPeerError,
+
+    #[error("Session closed by server due to idle timeout ({0} seconds)")]
+    IdleTimeoutError(i32),
+
+    #[error("Failed to get IP info: {}", ErrorCode::from(.0))]
     GetIpInfoError(i32),
 
+    #[error("Failed to setup DNS: {0}")]
+    SetupDnsError(String),
+
+    #[error("Failed to initialize SSL library: {}", ErrorCode::from(.0))]
+    InitSslError(i32),
+
+    #[error("Failed to create VPN info")]
+    CreateVpnInfoError,
+
+    #[error("Failed to get supported protocols: {}", ErrorCode::from(.0))]
+    GetSupportedProtocolsError(i32),
+
+    #[error("Protocol not supported: {0}")]
+    ProtocolNotFoundError(String),
+
+    #[error("Unsupported auth form field: {0}")]
+    UnsupportedFormFieldError(String),
+
+    #[error("Failed to process auth form: {0}")]
+    ProcessAuthFormError(String),
+
+    #[error("Panic in {0} callback")]
+    CallbackPanicError(String),
+
+    #[error("Peer certificate rejected: {0}")]
+    PeerCertRejectedError(String),
+
+    #[error("Failed to setup CSD wrapper: {}", ErrorCode::from(.0))]
+    SetupCsdError(i32),
+
+    #[error("Failed to set CSD environment: {}", ErrorCode::from(.0))]
+    SetCsdEnvironError(i32),
+
+    #[error("Failed to open single sign-on page: {0}")]
+    OpenBrowserError(String),
+
+    #[error("Single sign-on in login page failed: {0}")]
+    WebviewError(String),
+
+    #[error("Failed to install signal handlers: {0}")]
+    InstallSignalHandlerError(String),
+
+    #[error("Invalid client state: {0}")]
+    InvalidStateError(String),
+
     #[error("Other general error: {0}")]
     OtherError(String),
+
+    #[error("{error}. Last error from libopenconnect: {log}")]
+    WithLibraryLog {
+        error: Box<OpenconnectError>,
+        log: String,
+    },
+}
+
+/// The broad reason of an error, so that callers can decide how to react without matching every variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// Credentials, cookies or auth forms are rejected
+    Auth,
+    /// The server is not reachable or the connection dropped
+    Network,
+    /// The server certificate or the client certificate is not accepted
+    Certificate,
+    /// The configuration is invalid
+    Config,
+    /// Failures of the local system or of this library
+    Internal,
+}
+
+impl ErrorCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::Auth => "AUTH",
+            ErrorCategory::Network => "NETWORK",
+            ErrorCategory::Certificate => "CERTIFICATE",
+            ErrorCategory::Config => "CONFIG",
+            ErrorCategory::Internal => "INTERNAL",
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A return code of libopenconnect, negative codes are errno values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode(pub i32);
+
+impl From<&i32> for ErrorCode {
+    fn from(code: &i32) -> Self {
+        Self(*code)
+    }
+}
+
+const ERRNO_NAMES: &[(i32, &str)] = &[
+    (libc::EPERM, "EPERM"),
+    (libc::ENOENT, "ENOENT"),
+    (libc::EINTR, "EINTR"),
+    (libc::EIO, "EIO"),
+    (libc::ENOMEM, "ENOMEM"),
+    (libc::EACCES, "EACCES"),
+    (libc::EBUSY, "EBUSY"),
+    (libc::EINVAL, "EINVAL"),
+    (libc::EPIPE, "EPIPE"),
+    (libc::EAGAIN, "EAGAIN"),
+    (libc::EINPROGRESS, "EINPROGRESS"),
+    (libc::EOPNOTSUPP, "EOPNOTSUPP"),
+    (libc::EADDRNOTAVAIL, "EADDRNOTAVAIL"),
+    (libc::ENETDOWN, "ENETDOWN"),
+    (libc::ENETUNREACH, "ENETUNREACH"),
+    (libc::ECONNABORTED, "ECONNABORTED"),
+    (libc::ECONNRESET, "ECONNRESET"),
+    (libc::ENOTCONN, "ENOTCONN"),
+    (libc::ETIMEDOUT, "ETIMEDOUT"),
+    (libc::ECONNREFUSED, "ECONNREFUSED"),
+    (libc::EHOSTUNREACH, "EHOSTUNREACH"),
+];
+
+impl ErrorCode {
+    /// The errno of a negative code
+    pub fn errno(&self) -> Option<i32> {
+        (self.0 < 0).then_some(-self.0)
+    }
+
+    /// The errno name, e.g. `EPERM` for `-1`
+    pub fn name(&self) -> Option<&'static str> {
+        let errno = self.errno()?;
+        ERRNO_NAMES
+            .iter()
+            .find(|(value, _)| *value == errno)
+            .map(|(_, name)| *name)
+    }
+
+    pub fn description(&self) -> String {
+        match self.errno() {
+            Some(errno) => std::io::Error::from_raw_os_error(errno).to_string(),
+            None if self.0 > 0 => "cancelled".to_string(),
+            None => "success".to_string(),
+        }
+    }
+
+    fn is_network(&self) -> bool {
+        matches!(
+            self.errno(),
+            Some(
+                libc::EIO
+                    | libc::EPIPE
+                    | libc::EAGAIN
+                    | libc::EADDRNOTAVAIL
+                    | libc::ENETDOWN
+                    | libc::ENETUNREACH
+                    | libc::ECONNRESET
+                    | libc::ENOTCONN
+                    | libc::ETIMEDOUT
+                    | libc::ECONNREFUSED
+                    | libc::EHOSTUNREACH
+            )
+        )
+    }
+
+    fn is_auth(&self) -> bool {
+        matches!(self.errno(), Some(libc::EPERM | libc::EACCES))
+    }
+
+    /// Cancelled by a command, e.g. [crate::command::Command::Cancel] or [crate::command::Command::Detach]
+    fn is_cancelled(&self) -> bool {
+        self.0 > 0 || matches!(self.errno(), Some(libc::EINTR | libc::ECONNABORTED))
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{} ({}: {})", self.0, name, self.description()),
+            None => write!(f, "{} ({})", self.0, self.description()),
+        }
+    }
+}
+
+impl OpenconnectError {
+    /// Attach the last error logged by libopenconnect, which often tells more than the return code
+    pub fn with_library_log(self, log: Option<String>) -> Self {
+        match log {
+            Some(log) => OpenconnectError::WithLibraryLog {
+                error: Box::new(self),
+                log: crate::secret::redact(&log).into_owned(),
+            },
+            None => self,
+        }
+    }
+
+    /// The error without the attached library log
+    pub fn inner(&self) -> &OpenconnectError {
+        match self {
+            OpenconnectError::WithLibraryLog { error, .. } => error.inner(),
+            error => error,
+        }
+    }
+
+    /// The return code of libopenconnect, if the error carries one
+    pub fn code(&self) -> Option<ErrorCode> {
+        use OpenconnectError::*;
+        match self.inner() {
+            SetCookieError(code)
+            | ObtainCookieError(code)
+            | SetProtocolError(code)
+            | SetReportOSError(code)
+            | SetProxyError(code)
+            | SetProxyAuthError(code)
+            | MakeCstpError(code)
+            | DisableDTLSError(code)
+            | DisableIPv6Error(code)
+            | SetCompressionModeError(code)
+            | SetInsecureCryptoError(code)
+            | ParseUrlError(code)
+            | SetHostnameError(code)
+            | SetSniError(code)
+            | SetupTunDeviceEror(code)
+            | SetClientCertError(code)
+            | SetMCACertError(code)
+            | MainLoopError(code)
+            | GetIpInfoError(code)
+            | InitSslError(code)
+            | GetSupportedProtocolsError(code)
+            | SetupCsdError(code)
+            | SetCsdEnvironError(code) => Some(ErrorCode(*code)),
+            _ => None,
+        }
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        use OpenconnectError::*;
+        match self.inner() {
+            EntrypointConfigError(_)
+            | SetCookieError(_)
+            | SetProtocolError(_)
+            | SetReportOSError(_)
+            | SetProxyError(_)
+            | SetProxyAuthError(_)
+            | InvalidProxyError(_)
+            | DisableDTLSError(_)
+            | DisableIPv6Error(_)
+            | SetCompressionModeError(_)
+            | SetInsecureCryptoError(_)
+            | ParseUrlError(_)
+            | SetHostnameError(_)
+            | SetSniError(_)
+            | ProtocolNotFoundError(_)
+            | SetupCsdError(_)
+            | SetCsdEnvironError(_) => ErrorCategory::Config,
+
+            SetClientCertError(_) | SetMCACertError(_) | PeerCertRejectedError(_) => {
+                ErrorCategory::Certificate
+            }
+
+            UnsupportedFormFieldError(_)
+            | ProcessAuthFormError(_)
+            | OpenBrowserError(_)
+            | WebviewError(_) => ErrorCategory::Auth,
+
+            DeadPeerError | IdleTimeoutError(_) | ServerUnreachableError(_) => {
+                ErrorCategory::Network
+            }
+
+            ObtainCookieError(code) => match ErrorCode(*code) {
+                code if code.is_network() => ErrorCategory::Network,
+                _ => ErrorCategory::Auth,
+            },
+
+            MakeCstpError(code) | MainLoopError(code) => match ErrorCode(*code) {
+                code if code.is_auth() => ErrorCategory::Auth,
+                code if code.is_cancelled() => ErrorCategory::Internal,
+                _ => ErrorCategory::Network,
+            },
+
+            _ => ErrorCategory::Internal,
+        }
+    }
+
+    /// Whether trying again later, with the same configuration and credentials, may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self.inner() {
+            OpenconnectError::DeadPeerError | OpenconnectError::IdleTimeoutError(_) => true,
+            // the server closed the session on purpose
+            OpenconnectError::MainLoopError(code) if *code == -libc::EPIPE => false,
+            error => {
+                error.category() == ErrorCategory::Network
+                    && !error.code().is_some_and(|code| code.is_cancelled())
+            }
+        }
+    }
 }
 
 pub type OpenconnectResult<T> = std::result::Result<T, OpenconnectError>;
 
+/// Run the body of an `extern "C"` callback, a panic must not unwind into libopenconnect
+///
+/// On panic the fallback is returned to libopenconnect, and the error is kept by the client so that the failing call reports it
+pub(crate) fn guard_callback<T>(
+    client: Option<&VpnClient>,
+    name: &str,
+    fallback: T,
+    callback: impl FnOnce() -> T,
+) -> T {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(callback)) {
+        Ok(value) => value,
+        Err(_) => {
+            tracing::error!("Panic in {} callback", name);
+            if let Some(client) = client {
+                client.set_callback_error(OpenconnectError::CallbackPanicError(name.to_string()));
+            }
+            fallback
+        }
+    }
+}
+
 pub trait EmitError<T> {
     fn emit_error(self, client: &VpnClient) -> OpenconnectResult<T>;
 }
@@ -69,3 +413,27 @@ impl<T> EmitError<T> for OpenconnectResult<T> {
         self
     }
 }
+
+#[test]
+fn test_error_decoding() {
+    let err = OpenconnectError::MainLoopError(-libc::ECONNRESET);
+    assert_eq!(err.code().and_then(|code| code.name()), Some("ECONNRESET"));
+    assert_eq!(err.category(), ErrorCategory::Network);
+    assert!(err.is_retryable());
+
+    let err = OpenconnectError::ObtainCookieError(-libc::EPERM)
+        .with_library_log(Some("Login failed.".to_string()));
+    assert_eq!(err.category(), ErrorCategory::Auth);
+    assert!(!err.is_retryable());
+    assert!(err.to_string().contains("EPERM"));
+    assert!(err.to_string().contains("Login failed."));
+
+    let err = OpenconnectError::MainLoopError(-libc::EINTR);
+    assert_eq!(err.category(), ErrorCategory::Internal);
+    assert!(!err.is_retryable());
+
+    assert_eq!(
+        OpenconnectError::ParseUrlError(-libc::EINVAL).category(),
+        ErrorCategory::Config
+    );
+}
@@ -0,0 +1,287 @@
+use crate::{
+    secret::Secret,
+    storage::{write_atomic, PassEncryptor, StoredConfigError},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Sessions expiring within this margin are not reused, since the connection would drop right away
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// A session obtained from a server, the cookie can be reused by [crate::config::EntrypointBuilder::cookie] until it expires
+#[derive(Clone, PartialEq, Eq)]
+pub struct Session {
+    pub cookie: String,
+    pub expires_at: Option<SystemTime>,
+    pub refresh_token: Option<String>,
+}
+
+impl std::fmt::Debug for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session")
+            .field("cookie", &Secret::new(&self.cookie))
+            .field("expires_at", &self.expires_at)
+            .field(
+                "refresh_token",
+                &self.refresh_token.as_ref().map(Secret::new),
+            )
+            .finish()
+    }
+}
+
+impl Session {
+    pub fn new(cookie: &str) -> Self {
+        Self {
+            cookie: cookie.to_string(),
+            expires_at: None,
+            refresh_token: None,
+        }
+    }
+
+    pub fn with_expires_at(mut self, expires_at: Option<SystemTime>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    /// Take the expiration from the `exp` claim of the JWT the cookie was obtained with, e.g. an OIDC ID token
+    ///
+    /// The signature is not verified, the claim only tells when the cached session is stale. A cookie may outlive the
+    /// token, in which case the session is only authenticated again earlier than needed.
+    pub fn with_token_expiration(self, token: &str) -> Self {
+        self.with_expires_at(token_expiration(token))
+    }
+
+    pub fn with_refresh_token(mut self, refresh_token: Option<String>) -> Self {
+        self.refresh_token = refresh_token;
+        self
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= SystemTime::now() + EXPIRY_MARGIN)
+    }
+}
+
+/// The `exp` claim of a JWT, `None` if the token is not a JWT or has no expiration
+fn token_expiration(token: &str) -> Option<SystemTime> {
+    use base64::Engine;
+
+    #[derive(Deserialize)]
+    struct Claims {
+        exp: u64,
+    }
+
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()?;
+    let claims: Claims = serde_json::from_slice(&payload).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(claims.exp))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedSession {
+    server: String,
+    cookie: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+}
+
+/// Sessions cached per stored server, so that reconnecting does not need a full authentication
+///
+/// Cookies and refresh tokens are encrypted by [PassEncryptor], the cache lives next to the config file
+#[derive(Clone, Debug)]
+pub struct SessionCache {
+    sessions: HashMap<String, CachedSession>,
+    cipher: PassEncryptor,
+    cache_file: PathBuf,
+}
+
+impl SessionCache {
+    pub fn new(cipher: PassEncryptor, cache_file: PathBuf) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            cipher,
+            cache_file,
+        }
+    }
+
+    /// The cache file used for the given config file
+    pub fn cache_file_for(config_file: &Path) -> PathBuf {
+        config_file.with_file_name("sessions.json")
+    }
+
+    /// Read the cache from file, a missing file is an empty cache
+    pub async fn read_from_file(&mut self) -> Result<&mut Self, StoredConfigError> {
+        let content = match tokio::fs::read(&self.cache_file).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.sessions.clear();
+                return Ok(self);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        self.sessions = serde_json::from_slice(&content).map_err(|e| {
+            StoredConfigError::ParseError(format!("Failed to parse session cache: {}", e))
+        })?;
+
+        Ok(self)
+    }
+
+    pub async fn save_to_file(&self) -> Result<&Self, StoredConfigError> {
+        let json = serde_json::to_string(&self.sessions).map_err(|e| {
+            StoredConfigError::ParseError(format!("Failed to serialize session cache: {}", e))
+        })?;
+
+        // cookies grant access to the VPN, keep them private to the owner
+        write_atomic(&self.cache_file, json.as_bytes(), Some(0o600)).await?;
+
+        Ok(self)
+    }
+
+    /// Get the cached session of a stored server
+    ///
+    /// Returns `None` if the session expired, or if it was obtained from another server URL
+    pub fn get(&self, name: &str, server: &str) -> Option<Session> {
+        let cached = self.sessions.get(name)?;
+        if cached.server != server {
+            return None;
+        }
+
+        let session = Session {
+            cookie: self.cipher.decrypt(&cached.cookie).ok()?,
+            expires_at: cached
+                .expires_at
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            refresh_token: cached
+                .refresh_token
+                .as_ref()
+                .and_then(|token| self.cipher.decrypt(token).ok()),
+        };
+
+        (!session.is_expired()).then_some(session)
+    }
+
+    pub async fn insert(
+        &mut self,
+        name: &str,
+        server: &str,
+        session: &Session,
+    ) -> Result<&mut Self, StoredConfigError> {
+        let refresh_token = match session.refresh_token.as_ref() {
+            Some(token) => Some(self.cipher.encrypt(token)?),
+            None => None,
+        };
+
+        let cached = CachedSession {
+            server: server.to_string(),
+            cookie: self.cipher.encrypt(&session.cookie)?,
+            refresh_token,
+            expires_at: session
+                .expires_at
+                .and_then(|expires_at| expires_at.duration_since(UNIX_EPOCH).ok())
+                .map(|elapsed| elapsed.as_secs()),
+        };
+
+        self.sessions.insert(name.to_string(), cached);
+        self.save_to_file().await?;
+        Ok(self)
+    }
+
+    pub async fn remove(&mut self, name: &str) -> Result<&mut Self, StoredConfigError> {
+        if self.sessions.remove(name).is_some() {
+            self.save_to_file().await?;
+        }
+        Ok(self)
+    }
+
+    pub async fn clear(&mut self) -> Result<&mut Self, StoredConfigError> {
+        self.sessions.clear();
+        self.save_to_file().await?;
+        Ok(self)
+    }
+}
+
+#[tokio::test]
+async fn test_session_cache() {
+    let cache_file = std::env::temp_dir().join(format!(
+        "openconnect-rs-sessions-{}.json",
+        std::process::id()
+    ));
+    let mut cache = SessionCache::new(PassEncryptor::default(), cache_file.clone());
+    let session = Session::new("webvpn=123456")
+        .with_expires_at(Some(SystemTime::now() + Duration::from_secs(3600)))
+        .with_refresh_token(Some("refresh".to_string()));
+    cache
+        .insert("test", "https://vpn.example.com", &session)
+        .await
+        .unwrap();
+    cache
+        .insert(
+            "expired",
+            "https://vpn.example.com",
+            &Session::new("webvpn=0").with_expires_at(Some(SystemTime::now())),
+        )
+        .await
+        .unwrap();
+
+    let content = std::fs::read_to_string(&cache_file).unwrap();
+    assert!(!content.contains("webvpn=123456"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&cache_file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    let mut cache = SessionCache::new(PassEncryptor::default(), cache_file.clone());
+    cache.read_from_file().await.unwrap();
+    let cached = cache.get("test", "https://vpn.example.com").unwrap();
+    assert_eq!(cached.cookie, session.cookie);
+    assert_eq!(cached.refresh_token, session.refresh_token);
+    assert!(cache.get("test", "https://other.example.com").is_none());
+    assert!(cache.get("expired", "https://vpn.example.com").is_none());
+
+    cache.remove("test").await.unwrap();
+    assert!(cache.get("test", "https://vpn.example.com").is_none());
+
+    std::fs::remove_file(cache_file).unwrap();
+}
+
+#[test]
+fn test_token_expiration() {
+    use base64::Engine;
+
+    let encode = |json: &str| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json);
+    let token = format!(
+        "{}.{}.signature",
+        encode(r#"{"alg":"RS256"}"#),
+        encode(r#"{"iss":"https://idp.example.com","exp":1900000000}"#)
+    );
+
+    let session = Session::new("webvpn=123456").with_token_expiration(&token);
+    assert_eq!(
+        session.expires_at,
+        Some(UNIX_EPOCH + Duration::from_secs(1900000000))
+    );
+
+    // opaque tokens and tokens without expiration leave the session without one
+    assert_eq!(token_expiration("opaque-token"), None);
+    assert_eq!(
+        token_expiration(&format!("{}.{}.", encode("{}"), encode(r#"{"iss":"x"}"#))),
+        None
+    );
+
+    let expired = format!("{}.{}.", encode("{}"), encode(r#"{"exp":1}"#));
+    assert!(Session::new("webvpn=0")
+        .with_token_expiration(&expired)
+        .is_expired());
+}
@@ -1,4 +1,4 @@
-use crate::VpnClient;
+use crate::{result, VpnClient};
 use openconnect_sys::oc_stats;
 
 #[derive(Debug)]
@@ -12,6 +12,10 @@ pub struct Stats {
 pub(crate) extern "C" fn stats_fn(privdata: *mut ::std::os::raw::c_void, stats: *const oc_stats) {
     println!("stats_fn");
     let client = unsafe { VpnClient::ref_from_raw(privdata) };
+    result::guard_callback(Some(client), "stats", (), || handle_stats(client, stats));
+}
+
+fn handle_stats(client: &VpnClient, stats: *const oc_stats) {
     let dlts = client.get_dlts_cipher();
 
     let stats: Option<Stats> = if !stats.is_null() {
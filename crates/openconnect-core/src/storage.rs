@@ -1,3 +1,12 @@
+use crate::{
+    config::{EntrypointBuilder, TunnelOptions},
+    csd::CsdConfig,
+    dns::DnsMode,
+    log::LogConfig,
+    proxy::ProxyConfig,
+    secret::Secret,
+    session::SessionCache,
+};
 use chacha20poly1305::{
     aead::{Aead, AeadCore, KeyInit, OsRng},
     XChaCha20Poly1305, XNonce,
@@ -6,14 +15,208 @@ use rand::SeedableRng;
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    path::PathBuf,
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
+use tokio::io::AsyncWriteExt;
+
+/// The version of the config file written by this build, files without a version are version 0
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Upgrade one version of a parsed config file to the next
+type Migration = fn(&mut serde_json::Value) -> Result<(), StoredConfigError>;
+
+/// Upgrade steps of the config file, `MIGRATIONS[n]` upgrades version `n` to `n + 1`
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [migrate_v0];
+
+/// How long to wait for another process to release the config file lock
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often [StoredConfigs::watch] checks whether the config file was saved
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Replace a file so that readers see either the previous or the new contents, never a partial write
+///
+/// With a `mode`, the file is created with these permissions on unix before anything is written, otherwise it keeps the permissions of the replaced file
+pub(crate) async fn write_atomic(
+    path: &Path,
+    contents: &[u8],
+    mode: Option<u32>,
+) -> std::io::Result<()> {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(".tmp-{}", std::process::id()));
+    let temp_file = path.with_file_name(file_name);
+
+    let result = async {
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            options.mode(mode);
+        }
+        let mut file = options.open(&temp_file).await?;
+        match mode {
+            // a temp file left over by an earlier run keeps the permissions it was created with
+            #[cfg(unix)]
+            Some(mode) => {
+                use std::os::unix::fs::PermissionsExt;
+                file.set_permissions(std::fs::Permissions::from_mode(mode))
+                    .await?;
+            }
+            _ => {
+                if let Ok(metadata) = tokio::fs::metadata(path).await {
+                    file.set_permissions(metadata.permissions()).await?;
+                }
+            }
+        }
+        file.write_all(contents).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp_file, path).await
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp_file).await;
+    }
+    result
+}
+
+/// An advisory lock on the config file, held across a read-modify-write and released on drop
+#[derive(Debug)]
+pub struct ConfigLock {
+    _file: std::fs::File,
+}
+
+/// Reloads the config file whenever it is saved by any process or editor, stops watching on drop
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    changes: tokio::sync::mpsc::Receiver<StoredConfigs>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ConfigWatcher {
+    /// Wait for the next save of the config file, the configs are reloaded with the cipher of the watched configs
+    pub async fn changed(&mut self) -> Option<StoredConfigs> {
+        self.changes.recv().await
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// What tells a saved config file apart, the modified time may not change between two quick saves of the same length
+fn file_digest(content: &[u8]) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Whether the file belongs to the current user, a migration run by the root daemon must not take over the file of the user
+async fn is_owned_file(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        tokio::fs::metadata(path)
+            .await
+            .is_ok_and(|metadata| metadata.uid() == unsafe { libc::geteuid() })
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        true
+    }
+}
+
+/// Fill in the settings which older builds left out and drop a default pointing to a removed server
+fn migrate_v0(json: &mut serde_json::Value) -> Result<(), StoredConfigError> {
+    let config = json.as_object_mut().ok_or(StoredConfigError::ParseError(
+        "Config file is not an object".to_string(),
+    ))?;
+
+    let servers = config
+        .entry("servers")
+        .or_insert_with(|| serde_json::Value::Array(vec![]))
+        .as_array_mut()
+        .ok_or(StoredConfigError::ParseError(
+            "Servers are not a list".to_string(),
+        ))?;
+
+    let mut names = vec![];
+    for server in servers
+        .iter_mut()
+        .filter_map(|server| server.as_object_mut())
+    {
+        let allow_insecure = server
+            .entry("allowInsecure")
+            .or_insert(serde_json::Value::Bool(false));
+        if allow_insecure.is_null() {
+            *allow_insecure = serde_json::Value::Bool(false);
+        }
+        if let Some(name) = server.get("name").and_then(|name| name.as_str()) {
+            names.push(name.to_string());
+        }
+    }
+
+    let default = config.entry("default").or_insert(serde_json::Value::Null);
+    if default
+        .as_str()
+        .is_some_and(|default| !names.iter().any(|name| name == default))
+    {
+        *default = serde_json::Value::Null;
+    }
+
+    Ok(())
+}
+
+/// The version of a parsed config file, 0 for files written before the version was added
+fn config_version(json: &serde_json::Value) -> Result<u32, StoredConfigError> {
+    match json.get("version") {
+        None | Some(serde_json::Value::Null) => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or(StoredConfigError::ParseError(format!(
+                "Invalid config version: {}",
+                version
+            ))),
+    }
+}
+
+/// Upgrade a parsed config file to [CONFIG_VERSION] and return the version it had
+///
+/// Files of a later version are refused, reading them would drop what this build does not know on the next save
+pub fn migrate_config(json: &mut serde_json::Value) -> Result<u32, StoredConfigError> {
+    let version = config_version(json)?;
+    if version > CONFIG_VERSION {
+        return Err(StoredConfigError::UnsupportedVersion(version));
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(json)?;
+    }
+    if let Some(config) = json.as_object_mut() {
+        config.insert("version".to_string(), CONFIG_VERSION.into());
+    }
+
+    Ok(version)
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StoredConfigsJson {
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    revision: u64,
     default: Option<String>,
     servers: Vec<StoredServer>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    log: Option<LogConfig>,
 }
 
 impl StoredConfigsJson {
@@ -21,16 +224,14 @@ impl StoredConfigsJson {
         let servers = self
             .servers
             .iter()
-            .map(|server| match server {
-                StoredServer::Oidc(oidc_server) => StoredServer::Oidc(oidc_server.clone()),
-                StoredServer::Password(password_server) => {
-                    StoredServer::Password(password_server.decrypted_by(encryptor))
-                }
-            })
+            .map(|server| server.decrypted_by(encryptor))
             .collect();
         Self {
+            version: self.version,
+            revision: self.revision,
             default: self.default.clone(),
             servers,
+            log: self.log.clone(),
         }
     }
 }
@@ -46,6 +247,7 @@ impl TryFrom<(StoredConfigsJson, PathBuf)> for StoredConfigs {
             let name = match &server {
                 StoredServer::Oidc(OidcServer { name, .. }) => name,
                 StoredServer::Password(PasswordServer { name, .. }) => name,
+                StoredServer::Saml(SamlServer { name, .. }) => name,
             };
 
             if servers.contains_key(name) {
@@ -59,8 +261,10 @@ impl TryFrom<(StoredConfigsJson, PathBuf)> for StoredConfigs {
         }
 
         Ok(StoredConfigs {
+            revision: json.revision,
             default: json.default,
             servers,
+            log: json.log,
             cipher: PassEncryptor::default(),
             config_file,
         })
@@ -70,8 +274,40 @@ impl TryFrom<(StoredConfigsJson, PathBuf)> for StoredConfigs {
 impl From<StoredConfigs> for StoredConfigsJson {
     fn from(config: StoredConfigs) -> StoredConfigsJson {
         StoredConfigsJson {
+            version: CONFIG_VERSION,
+            revision: config.revision,
             default: config.default,
             servers: config.servers.into_values().collect(),
+            log: config.log,
+        }
+    }
+}
+
+/// Reach the server at another address than the one its host name resolves to, shared by all server types
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddressOverride {
+    /// Present this name in the TLS handshake, e.g. when the server URL is an address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sni: Option<String>,
+
+    /// Connect to these addresses instead of resolving the hosts, e.g. to pin one node of a load-balanced pool
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resolve: BTreeMap<String, IpAddr>,
+}
+
+impl AddressOverride {
+    pub fn is_empty(&self) -> bool {
+        self.sni.is_none() && self.resolve.is_empty()
+    }
+
+    pub fn apply(&self, entrypoint: &mut EntrypointBuilder) {
+        if let Some(sni) = &self.sni {
+            entrypoint.sni(sni);
+        }
+
+        for (host, ip) in &self.resolve {
+            entrypoint.resolve(host, *ip);
         }
     }
 }
@@ -83,35 +319,127 @@ pub struct OidcServer {
     pub server: String,
     pub issuer: String,
     pub client_id: String,
-    pub client_secret: Option<String>,
+    pub client_secret: Option<Secret<String>>,
     pub allow_insecure: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dpd: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<TunnelOptions>,
+    /// How DNS settings pushed by the server are applied, the vpnc-script handles them when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_reauth: Option<bool>,
+    /// Reach the server through this proxy, its password is encrypted like the server password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+    #[serde(flatten)]
+    pub address: AddressOverride,
     pub updated_at: Option<String>,
 }
 
+impl OidcServer {
+    pub fn decrypted_by(&self, encryptor: &PassEncryptor) -> Self {
+        Self {
+            proxy: self.proxy.as_ref().map(|p| p.decrypted_by(encryptor)),
+            ..self.clone()
+        }
+    }
+
+    pub fn encrypted_by(&self, encryptor: &PassEncryptor) -> Self {
+        Self {
+            proxy: self.proxy.as_ref().map(|p| p.encrypted_by(encryptor)),
+            ..self.clone()
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PasswordServer {
     pub name: String,
     pub server: String,
     pub username: String,
-    pub password: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub allow_insecure: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dpd: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<TunnelOptions>,
+    /// How DNS settings pushed by the server are applied, the vpnc-script handles them when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_reauth: Option<bool>,
+    /// Answer the CSD (hostscan) posture check during password authentication
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csd: Option<CsdConfig>,
+    /// Reach the server through this proxy, its password is encrypted like the server password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+    #[serde(flatten)]
+    pub address: AddressOverride,
+    pub updated_at: Option<String>,
+}
+
+/// A server authenticated by single sign-on (SAML), the login page is opened in a browser
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SamlServer {
+    pub name: String,
+    pub server: String,
     pub allow_insecure: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dpd: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<TunnelOptions>,
+    /// How DNS settings pushed by the server are applied, the vpnc-script handles them when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsMode>,
+    /// Log in inside an embedded webview of the app instead of the default browser, only supported by the GUI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webview: Option<bool>,
+    /// Reach the server through this proxy, its password is encrypted like the server password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+    #[serde(flatten)]
+    pub address: AddressOverride,
     pub updated_at: Option<String>,
 }
 
+impl SamlServer {
+    pub fn decrypted_by(&self, encryptor: &PassEncryptor) -> Self {
+        Self {
+            proxy: self.proxy.as_ref().map(|p| p.decrypted_by(encryptor)),
+            ..self.clone()
+        }
+    }
+
+    pub fn encrypted_by(&self, encryptor: &PassEncryptor) -> Self {
+        Self {
+            proxy: self.proxy.as_ref().map(|p| p.encrypted_by(encryptor)),
+            ..self.clone()
+        }
+    }
+}
+
 impl PasswordServer {
     pub fn decrypted_by(&self, encryptor: &PassEncryptor) -> Self {
         let password = self
             .password
             .as_ref()
-            .and_then(|p| encryptor.decrypt(p).ok());
+            .and_then(|p| encryptor.decrypt(p.expose()).ok())
+            .map(Secret::new);
         Self {
-            name: self.name.clone(),
-            server: self.server.clone(),
-            username: self.username.clone(),
             password,
-            allow_insecure: self.allow_insecure,
-            updated_at: self.updated_at.clone(),
+            proxy: self.proxy.as_ref().map(|p| p.decrypted_by(encryptor)),
+            ..self.clone()
         }
     }
 
@@ -119,14 +447,12 @@ impl PasswordServer {
         let password = self
             .password
             .as_ref()
-            .and_then(|p| encryptor.encrypt(p).ok());
+            .and_then(|p| encryptor.encrypt(p.expose()).ok())
+            .map(Secret::new);
         Self {
-            name: self.name.clone(),
-            server: self.server.clone(),
-            username: self.username.clone(),
             password,
-            allow_insecure: self.allow_insecure,
-            updated_at: self.updated_at.clone(),
+            proxy: self.proxy.as_ref().map(|p| p.encrypted_by(encryptor)),
+            ..self.clone()
         }
     }
 }
@@ -139,6 +465,25 @@ pub enum StoredServer {
 
     #[serde(rename_all = "camelCase")]
     Password(PasswordServer),
+
+    #[serde(rename_all = "camelCase")]
+    Saml(SamlServer),
+}
+
+impl StoredServer {
+    pub fn decrypted_by(&self, encryptor: &PassEncryptor) -> Self {
+        match self {
+            StoredServer::Oidc(oidc_server) => {
+                StoredServer::Oidc(oidc_server.decrypted_by(encryptor))
+            }
+            StoredServer::Password(password_server) => {
+                StoredServer::Password(password_server.decrypted_by(encryptor))
+            }
+            StoredServer::Saml(saml_server) => {
+                StoredServer::Saml(saml_server.decrypted_by(encryptor))
+            }
+        }
+    }
 }
 
 impl TryFrom<&StoredServer> for OidcServer {
@@ -147,7 +492,7 @@ impl TryFrom<&StoredServer> for OidcServer {
     fn try_from(server: &StoredServer) -> Result<OidcServer, StoredConfigError> {
         match server {
             StoredServer::Oidc(oidc_server) => Ok(oidc_server.clone()),
-            StoredServer::Password(_) => Err(StoredConfigError::ParseError(
+            _ => Err(StoredConfigError::ParseError(
                 "Server is not OIDC type".to_string(),
             )),
         }
@@ -160,17 +505,33 @@ impl TryFrom<&StoredServer> for PasswordServer {
     fn try_from(server: &StoredServer) -> Result<PasswordServer, StoredConfigError> {
         match server {
             StoredServer::Password(password_server) => Ok(password_server.clone()),
-            StoredServer::Oidc(_) => Err(StoredConfigError::ParseError(
+            _ => Err(StoredConfigError::ParseError(
                 "Server is not Password type".to_string(),
             )),
         }
     }
 }
 
+impl TryFrom<&StoredServer> for SamlServer {
+    type Error = StoredConfigError;
+
+    fn try_from(server: &StoredServer) -> Result<SamlServer, StoredConfigError> {
+        match server {
+            StoredServer::Saml(saml_server) => Ok(saml_server.clone()),
+            _ => Err(StoredConfigError::ParseError(
+                "Server is not SAML type".to_string(),
+            )),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct StoredConfigs {
+    /// Incremented on every save, a save is refused if the file was saved by someone else since it was read
+    pub revision: u64,
     pub default: Option<String>,
     pub servers: HashMap<String, StoredServer>,
+    pub log: Option<LogConfig>,
     pub cipher: PassEncryptor,
     pub config_file: PathBuf,
 }
@@ -188,18 +549,40 @@ pub enum StoredConfigError {
 
     #[error("Cipher error")]
     CipherError(#[from] PassEncryptorError),
+
+    #[error(
+        "Config file version {0} is newer than this build supports ({}), please upgrade",
+        CONFIG_VERSION
+    )]
+    UnsupportedVersion(u32),
+
+    #[error("Config file was changed by another process (revision {0}), reload it and try again")]
+    Conflict(u64),
+
+    #[error("Config file is locked by another process: {}", .0.display())]
+    Locked(PathBuf),
 }
 
 impl StoredConfigs {
     pub fn new(pass_key: Option<String>, config_file: PathBuf) -> Self {
         Self {
+            revision: 0,
             default: None,
             servers: HashMap::new(),
+            log: None,
             cipher: PassEncryptor::new(pass_key),
             config_file,
         }
     }
 
+    /// The session cache next to the config file, encrypted with the same cipher, call [SessionCache::read_from_file] to load it
+    pub fn session_cache(&self) -> SessionCache {
+        SessionCache::new(
+            self.cipher.clone(),
+            SessionCache::cache_file_for(&self.config_file),
+        )
+    }
+
     pub fn default_server(&self) -> Option<&StoredServer> {
         self.default
             .as_ref()
@@ -219,33 +602,218 @@ impl StoredConfigs {
 
         let config_file = config_folder.join("config.json");
         if !config_file.exists() {
-            std::fs::write(&config_file, br#"{"default":null,"servers":[]}"#)?;
+            std::fs::write(
+                &config_file,
+                format!(
+                    r#"{{"version":{},"default":null,"servers":[]}}"#,
+                    CONFIG_VERSION
+                ),
+            )?;
         }
 
         Ok(config_file)
     }
 
-    pub async fn save_to_file(&self) -> Result<&Self, StoredConfigError> {
-        let json = serde_json::to_string(&StoredConfigsJson::from(self.clone())).map_err(|e| {
-            StoredConfigError::ParseError(format!("Failed to serialize config: {}", e))
-        })?;
+    /// The log settings of the config file, read before the logger is initialized so that errors fall back to the defaults
+    pub fn read_log_config(config_file: &Path) -> LogConfig {
+        std::fs::read(config_file)
+            .ok()
+            .and_then(|content| serde_json::from_slice::<serde_json::Value>(&content).ok())
+            .and_then(|json| serde_json::from_value(json.get("log")?.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    /// The connection journal in the log directory configured by this config file
+    pub fn journal_file(&self) -> PathBuf {
+        Self::read_log_config(&self.config_file).journal_file()
+    }
+
+    /// The copy of the config file kept before it is migrated from this version
+    pub fn backup_file_for(config_file: &Path, version: u32) -> PathBuf {
+        let mut file_name = config_file.file_name().unwrap_or_default().to_os_string();
+        file_name.push(format!(".v{}.bak", version));
+        config_file.with_file_name(file_name)
+    }
+
+    /// The file locked by [StoredConfigs::lock], the config file itself is replaced on every save
+    pub fn lock_file_for(config_file: &Path) -> PathBuf {
+        let mut file_name = config_file.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".lock");
+        config_file.with_file_name(file_name)
+    }
+
+    /// Take the advisory lock shared by every process writing the config file
+    pub async fn lock(&self) -> Result<ConfigLock, StoredConfigError> {
+        let lock_file = Self::lock_file_for(&self.config_file);
+        // the lock file may have been created by the daemon running as root, a read-only handle can be locked as well
+        let file = match std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_file)
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                std::fs::File::open(&lock_file)?
+            }
+            result => result?,
+        };
+
+        let started = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(ConfigLock { _file: file }),
+                Err(std::fs::TryLockError::WouldBlock) if started.elapsed() < LOCK_TIMEOUT => {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                Err(std::fs::TryLockError::WouldBlock) => {
+                    return Err(StoredConfigError::Locked(lock_file))
+                }
+                Err(std::fs::TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Watch the config file for saves, this must be called within a tokio runtime
+    pub fn watch(&self) -> ConfigWatcher {
+        let (changes_tx, changes) = tokio::sync::mpsc::channel(1);
+        let mut configs = self.clone();
+        let mut last_digest = std::fs::read(&configs.config_file)
+            .ok()
+            .map(|content| file_digest(&content));
+
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Ok(content) = tokio::fs::read(&configs.config_file).await else {
+                    continue;
+                };
+                let digest = Some(file_digest(&content));
+                if digest == last_digest {
+                    continue;
+                }
+                last_digest = digest;
 
-        tokio::fs::write(&self.config_file, json).await?;
+                // an editor may be halfway through writing, the next save is picked up again
+                match configs.read_from_file().await {
+                    Ok(_) => {
+                        if changes_tx.send(configs.clone()).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => tracing::warn!("Failed to reload the changed config file: {}", e),
+                }
+            }
+        });
+
+        ConfigWatcher { changes, task }
+    }
 
+    pub async fn save_to_file(&mut self) -> Result<&mut Self, StoredConfigError> {
+        let _lock = self.lock().await?;
+        self.write_locked().await?;
         Ok(self)
     }
 
     pub async fn read_from_file(&mut self) -> Result<&mut Self, StoredConfigError> {
+        let _lock = self.lock().await?;
+        self.read_locked().await?;
+        Ok(self)
+    }
+
+    /// Apply a change to the latest config file while holding the lock, so that concurrent writers keep each other's changes
+    pub async fn update<F>(&mut self, change: F) -> Result<&mut Self, StoredConfigError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), StoredConfigError>,
+    {
+        let _lock = self.lock().await?;
+        if tokio::fs::try_exists(&self.config_file).await? {
+            self.read_locked().await?;
+        }
+        change(self)?;
+        self.write_locked().await?;
+        Ok(self)
+    }
+
+    async fn write_locked(&mut self) -> Result<(), StoredConfigError> {
+        if let Ok(content) = tokio::fs::read(&self.config_file).await {
+            if let Ok(json) = serde_json::from_slice::<serde_json::Value>(&content) {
+                // a later build may have written the file since it was read, keep what this build does not know
+                if let Some(version) = config_version(&json)
+                    .ok()
+                    .filter(|version| *version > CONFIG_VERSION)
+                {
+                    return Err(StoredConfigError::UnsupportedVersion(version));
+                }
+
+                let revision = json
+                    .get("revision")
+                    .and_then(|revision| revision.as_u64())
+                    .unwrap_or_default();
+                if revision != self.revision {
+                    return Err(StoredConfigError::Conflict(revision));
+                }
+            }
+        }
+
+        let mut config = self.clone();
+        config.revision += 1;
+        let json = serde_json::to_string(&StoredConfigsJson::from(config)).map_err(|e| {
+            StoredConfigError::ParseError(format!("Failed to serialize config: {}", e))
+        })?;
+
+        write_atomic(&self.config_file, json.as_bytes(), None).await?;
+        self.revision += 1;
+
+        Ok(())
+    }
+
+    async fn read_locked(&mut self) -> Result<(), StoredConfigError> {
         let content = tokio::fs::read(&self.config_file).await?;
-        let config_json: StoredConfigsJson = serde_json::from_slice(&content).map_err(|e| {
+        let mut json: serde_json::Value = serde_json::from_slice(&content).map_err(|e| {
+            StoredConfigError::ParseError(format!("Failed to parse config file: {}", e))
+        })?;
+        let version = migrate_config(&mut json)?;
+        let config_json: StoredConfigsJson = serde_json::from_value(json).map_err(|e| {
             StoredConfigError::ParseError(format!("Failed to parse config file: {}", e))
         })?;
         let config = StoredConfigs::try_from((config_json, self.config_file.clone()))?;
 
+        self.revision = config.revision;
         self.default = config.default;
         self.servers = config.servers;
+        self.log = config.log;
 
-        Ok(self)
+        if version < CONFIG_VERSION {
+            // a reader which cannot write the file, or does not own it, still gets the migrated config
+            let backup_file = Self::backup_file_for(&self.config_file, version);
+            let written = match is_owned_file(&self.config_file).await {
+                true => match write_atomic(&backup_file, &content, None).await {
+                    Ok(()) => self.write_locked().await,
+                    Err(e) => Err(e.into()),
+                },
+                false => Err(StoredConfigError::IoError(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Config file is owned by another user",
+                ))),
+            };
+            match written {
+                Ok(()) => tracing::info!(
+                    "Migrated config file from version {} to {}, the previous file is kept at {}",
+                    version,
+                    CONFIG_VERSION,
+                    backup_file.display()
+                ),
+                Err(e) => tracing::warn!(
+                    "Migrated config file from version {} to {} in memory only, failed to save it: {}",
+                    version,
+                    CONFIG_VERSION,
+                    e
+                ),
+            }
+        }
+
+        Ok(())
     }
 
     pub fn get_server_as_oidc_server(&self, name: &str) -> Result<&OidcServer, StoredConfigError> {
@@ -277,6 +845,19 @@ impl StoredConfigs {
             )))
     }
 
+    pub fn get_server_as_saml_server(&self, name: &str) -> Result<&SamlServer, StoredConfigError> {
+        self.servers
+            .get(name)
+            .and_then(|server| match server {
+                StoredServer::Saml(saml_server) => Some(saml_server),
+                _ => None,
+            })
+            .ok_or(StoredConfigError::ParseError(format!(
+                "Server '{}' not found",
+                name
+            )))
+    }
+
     pub async fn add_server(
         &mut self,
         server: StoredServer,
@@ -286,6 +867,7 @@ impl StoredConfigs {
         let name = match &mut server {
             StoredServer::Oidc(oidc_server) => {
                 oidc_server.updated_at = Some(update_at);
+                *oidc_server = oidc_server.encrypted_by(&self.cipher);
                 oidc_server.name.to_owned()
             }
             StoredServer::Password(password_server) => {
@@ -293,18 +875,25 @@ impl StoredConfigs {
                 *password_server = password_server.encrypted_by(&self.cipher);
                 password_server.name.to_owned()
             }
+            StoredServer::Saml(saml_server) => {
+                saml_server.updated_at = Some(update_at);
+                *saml_server = saml_server.encrypted_by(&self.cipher);
+                saml_server.name.to_owned()
+            }
         };
 
-        if let Entry::Vacant(e) = self.servers.entry(name.clone()) {
-            e.insert(server);
-            self.save_to_file().await?;
-            Ok(self)
-        } else {
-            Err(StoredConfigError::BadInput(format!(
-                "Server {} already exists",
-                name
-            )))
-        }
+        self.update(|configs| {
+            if let Entry::Vacant(e) = configs.servers.entry(name.clone()) {
+                e.insert(server);
+                Ok(())
+            } else {
+                Err(StoredConfigError::BadInput(format!(
+                    "Server {} already exists",
+                    name
+                )))
+            }
+        })
+        .await
     }
 
     pub async fn upsert_server(
@@ -316,6 +905,7 @@ impl StoredConfigs {
         let name = match &mut server {
             StoredServer::Oidc(oidc_server) => {
                 oidc_server.updated_at = Some(updated_at);
+                *oidc_server = oidc_server.encrypted_by(&self.cipher);
                 oidc_server.name.to_owned()
             }
             StoredServer::Password(password_server) => {
@@ -323,36 +913,47 @@ impl StoredConfigs {
                 *password_server = password_server.encrypted_by(&self.cipher);
                 password_server.name.to_owned()
             }
+            StoredServer::Saml(saml_server) => {
+                saml_server.updated_at = Some(updated_at);
+                *saml_server = saml_server.encrypted_by(&self.cipher);
+                saml_server.name.to_owned()
+            }
         };
 
-        *self.servers.entry(name).or_insert(server) = server.clone();
-        self.save_to_file().await?;
-        Ok(self)
+        self.update(|configs| {
+            configs.servers.insert(name, server);
+            Ok(())
+        })
+        .await
     }
 
     pub async fn remove_server(&mut self, name: &str) -> Result<&mut Self, StoredConfigError> {
-        if self.default.as_ref().is_some_and(|d| d == name) {
-            return Err(StoredConfigError::BadInput(format!(
-                "Cannot remove default server {}",
-                name
-            )));
-        }
-        self.servers.remove(name);
-        self.save_to_file().await?;
-        Ok(self)
+        self.update(|configs| {
+            if configs.default.as_ref().is_some_and(|d| d == name) {
+                return Err(StoredConfigError::BadInput(format!(
+                    "Cannot remove default server {}",
+                    name
+                )));
+            }
+            configs.servers.remove(name);
+            Ok(())
+        })
+        .await
     }
 
     pub async fn set_default_server(&mut self, name: &str) -> Result<&mut Self, StoredConfigError> {
-        if !self.servers.contains_key(name) {
-            return Err(StoredConfigError::ParseError(format!(
-                "Server {} not found",
-                name
-            )));
-        }
+        self.update(|configs| {
+            if !configs.servers.contains_key(name) {
+                return Err(StoredConfigError::ParseError(format!(
+                    "Server {} not found",
+                    name
+                )));
+            }
 
-        self.default = Some(name.to_string());
-        self.save_to_file().await?;
-        Ok(self)
+            configs.default = Some(name.to_string());
+            Ok(())
+        })
+        .await
     }
 }
 
@@ -403,6 +1004,11 @@ impl PassEncryptor {
         let ciphertext = hex::decode(ciphertext).map_err(|e| {
             PassEncryptorError::CipherError(format!("Failed to decrypt password: {}", e))
         })?;
+        if ciphertext.len() <= 24 {
+            return Err(PassEncryptorError::CipherError(
+                "Failed to decrypt password: ciphertext is too short".to_string(),
+            ));
+        }
         let nonce = XNonce::from_slice(&ciphertext[..24]);
         let plaintext = cipher.decrypt(nonce, &ciphertext[24..]).map_err(|e| {
             PassEncryptorError::CipherError(format!("Failed to decrypt password: {}", e))
@@ -420,18 +1026,41 @@ fn test_pass_enc() {
     let encrypted = encryptor.encrypt(password).unwrap();
     let decrypted = encryptor.decrypt(&encrypted).unwrap();
     assert_eq!(password, decrypted);
+
+    // a truncated value is an error rather than a panic
+    assert!(encryptor.decrypt(&encrypted[..40]).is_err());
+    assert!(encryptor.decrypt("").is_err());
+}
+
+/// A config file as created by [StoredConfigs::getorinit_config_file], in a directory of its own
+#[cfg(test)]
+fn temp_config_file(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("openconnect-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config_file = dir.join("config.json");
+    std::fs::write(
+        &config_file,
+        format!(
+            r#"{{"version":{},"default":null,"servers":[]}}"#,
+            CONFIG_VERSION
+        ),
+    )
+    .unwrap();
+    config_file
 }
 
 #[tokio::test]
 async fn test_read_config() {
-    let config_file = StoredConfigs::getorinit_config_file().unwrap();
-    let mut stored_configs = StoredConfigs::new(None, config_file);
+    let config_file = temp_config_file("read");
+    let mut stored_configs = StoredConfigs::new(None, config_file.clone());
     stored_configs.read_from_file().await.unwrap();
     println!("parsed struct: {:#?}", stored_configs);
 
     let stored_configs_json = StoredConfigsJson::from(stored_configs.clone());
     let json = serde_json::to_string(&stored_configs_json).unwrap();
     println!("json: {}", json);
+
+    std::fs::remove_dir_all(config_file.parent().unwrap()).unwrap();
 }
 
 #[tokio::test]
@@ -441,12 +1070,19 @@ async fn test_save_config() {
         server: "https://example.com".to_string(),
         issuer: "https://example.com".to_string(),
         client_id: "client_id".to_string(),
-        client_secret: Some("client_secret".to_string()),
+        client_secret: Some("client_secret".into()),
         allow_insecure: Some(true),
+        dpd: None,
+        keepalive: None,
+        tunnel: None,
+        dns: None,
+        auto_reauth: None,
+        proxy: None,
+        address: Default::default(),
         updated_at: None,
     });
 
-    let config_file = StoredConfigs::getorinit_config_file().unwrap();
+    let config_file = temp_config_file("save");
     let mut stored_config = StoredConfigs::new(None, config_file.clone());
     let config = stored_config
         .read_from_file()
@@ -460,13 +1096,12 @@ async fn test_save_config() {
         .unwrap();
 
     println!("saved: {:?}", config);
-    println!(
-        "read: {:?}",
-        StoredConfigs::new(None, config_file)
-            .read_from_file()
-            .await
-            .unwrap()
-    );
+    let mut read = StoredConfigs::new(None, config_file.clone());
+    read.read_from_file().await.unwrap();
+    println!("read: {:?}", read);
+    assert!(read.servers.contains_key("test"));
+
+    std::fs::remove_dir_all(config_file.parent().unwrap()).unwrap();
 }
 
 #[tokio::test]
@@ -478,6 +1113,13 @@ async fn test_config_type() {
         client_id: "client_id".to_string(),
         client_secret: None,
         allow_insecure: Some(true),
+        dpd: None,
+        keepalive: None,
+        tunnel: None,
+        dns: None,
+        auto_reauth: None,
+        proxy: None,
+        address: Default::default(),
         updated_at: None,
     });
 
@@ -491,8 +1133,16 @@ async fn test_config_type() {
         name: "password_server".to_string(),
         server: "https://example.com".to_string(),
         username: "username".to_string(),
-        password: Some("password".to_string()),
+        password: Some("password".into()),
         allow_insecure: Some(true),
+        dpd: None,
+        keepalive: None,
+        tunnel: None,
+        dns: None,
+        auto_reauth: None,
+        csd: None,
+        proxy: None,
+        address: Default::default(),
         updated_at: None,
     });
 
@@ -502,3 +1152,188 @@ async fn test_config_type() {
         r#"{"authType":"password","server":"https://example.com","username":"username","password":"password","updatedAt":null}"#
     );
 }
+
+#[test]
+fn test_address_override() {
+    let json = r#"{
+        "name": "pinned",
+        "authType": "saml",
+        "server": "https://vpn.example.com",
+        "sni": "vpn.example.com",
+        "resolve": { "vpn.example.com": "192.0.2.10" }
+    }"#;
+
+    let StoredServer::Saml(server) = serde_json::from_str::<StoredServer>(json).unwrap() else {
+        panic!("expected a SAML server");
+    };
+    assert_eq!(server.address.sni.as_deref(), Some("vpn.example.com"));
+    assert_eq!(
+        server.address.resolve.get("vpn.example.com"),
+        Some(&"192.0.2.10".parse().unwrap())
+    );
+
+    let value = serde_json::to_value(StoredServer::Saml(server)).unwrap();
+    assert_eq!(value["resolve"]["vpn.example.com"], "192.0.2.10");
+    assert!(value.get("address").is_none());
+}
+
+#[test]
+fn test_migrate_config() {
+    let mut json = serde_json::json!({
+        "default": "removed",
+        "servers": [
+            { "name": "legacy", "authType": "password", "server": "https://vpn.example.com", "username": "alice", "password": null, "updatedAt": null },
+            { "name": "explicit", "authType": "saml", "server": "https://vpn.example.com", "allowInsecure": true, "updatedAt": null }
+        ]
+    });
+
+    assert!(matches!(migrate_config(&mut json), Ok(0)));
+    assert_eq!(json["version"], CONFIG_VERSION);
+    assert_eq!(json["default"], serde_json::Value::Null);
+    assert_eq!(json["servers"][0]["allowInsecure"], false);
+    assert_eq!(json["servers"][1]["allowInsecure"], true);
+    serde_json::from_value::<StoredConfigsJson>(json.clone()).unwrap();
+
+    // migrating again changes nothing
+    let migrated = json.clone();
+    assert!(matches!(migrate_config(&mut json), Ok(CONFIG_VERSION)));
+    assert_eq!(json, migrated);
+
+    let mut json = serde_json::json!({ "version": CONFIG_VERSION + 1, "servers": [] });
+    assert!(matches!(
+        migrate_config(&mut json),
+        Err(StoredConfigError::UnsupportedVersion(version)) if version == CONFIG_VERSION + 1
+    ));
+}
+
+#[tokio::test]
+async fn test_migrate_config_file() {
+    let dir = std::env::temp_dir().join(format!("openconnect-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config_file = dir.join("config.json");
+
+    let legacy = r#"{"default":"legacy","servers":[{"name":"legacy","authType":"saml","server":"https://vpn.example.com","updatedAt":null}]}"#;
+    std::fs::write(&config_file, legacy).unwrap();
+    let mut stored_configs = StoredConfigs::new(None, config_file.clone());
+    stored_configs.read_from_file().await.unwrap();
+
+    assert_eq!(stored_configs.default.as_deref(), Some("legacy"));
+    assert_eq!(
+        std::fs::read_to_string(StoredConfigs::backup_file_for(&config_file, 0)).unwrap(),
+        legacy
+    );
+    let saved: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&config_file).unwrap()).unwrap();
+    assert_eq!(saved["version"], CONFIG_VERSION);
+
+    // a file of a later build is neither read nor overwritten
+    let future = format!(
+        r#"{{"version":{},"servers":[],"added":true}}"#,
+        CONFIG_VERSION + 1
+    );
+    std::fs::write(&config_file, &future).unwrap();
+    assert!(matches!(
+        StoredConfigs::new(None, config_file.clone())
+            .read_from_file()
+            .await,
+        Err(StoredConfigError::UnsupportedVersion(_))
+    ));
+    assert!(matches!(
+        stored_configs.save_to_file().await,
+        Err(StoredConfigError::UnsupportedVersion(_))
+    ));
+    assert_eq!(std::fs::read_to_string(&config_file).unwrap(), future);
+
+    // a file which cannot be saved is migrated in memory and left as it is
+    std::fs::write(&config_file, legacy).unwrap();
+    let backup_file = StoredConfigs::backup_file_for(&config_file, 0);
+    std::fs::remove_file(&backup_file).unwrap();
+    std::fs::create_dir(&backup_file).unwrap();
+    let mut stored_configs = StoredConfigs::new(None, config_file.clone());
+    stored_configs.read_from_file().await.unwrap();
+    assert_eq!(stored_configs.default.as_deref(), Some("legacy"));
+    assert_eq!(std::fs::read_to_string(&config_file).unwrap(), legacy);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_concurrent_update() {
+    let dir = std::env::temp_dir().join(format!("openconnect-update-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config_file = dir.join("config.json");
+    std::fs::write(&config_file, r#"{"version":1,"default":null,"servers":[]}"#).unwrap();
+
+    let server = |name: &str| {
+        serde_json::from_value::<StoredServer>(serde_json::json!({
+            "name": name, "authType": "saml", "server": "https://vpn.example.com", "updatedAt": null
+        }))
+        .unwrap()
+    };
+
+    // both copies are read before either writes, like the GUI and a CLI command
+    let mut gui = StoredConfigs::new(None, config_file.clone());
+    gui.read_from_file().await.unwrap();
+    let mut cli = StoredConfigs::new(None, config_file.clone());
+    cli.read_from_file().await.unwrap();
+
+    cli.add_server(server("cli")).await.unwrap();
+    gui.add_server(server("gui")).await.unwrap();
+    assert!(gui.servers.contains_key("cli"));
+
+    let mut stored_configs = StoredConfigs::new(None, config_file.clone());
+    stored_configs.read_from_file().await.unwrap();
+    assert_eq!(stored_configs.revision, 2);
+    assert_eq!(stored_configs.servers.len(), 2);
+
+    // saving a stale copy is refused instead of dropping the other change
+    let content = std::fs::read_to_string(&config_file).unwrap();
+    assert!(matches!(
+        cli.save_to_file().await,
+        Err(StoredConfigError::Conflict(2))
+    ));
+    assert_eq!(std::fs::read_to_string(&config_file).unwrap(), content);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_watch_config() {
+    let dir = std::env::temp_dir().join(format!("openconnect-watch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config_file = dir.join("config.json");
+    std::fs::write(&config_file, r#"{"version":1,"default":null,"servers":[]}"#).unwrap();
+
+    let mut stored_configs = StoredConfigs::new(None, config_file.clone());
+    stored_configs.read_from_file().await.unwrap();
+    let mut watcher = stored_configs.watch();
+
+    let server = serde_json::from_value::<StoredServer>(serde_json::json!({
+        "name": "added", "authType": "saml", "server": "https://vpn.example.com", "updatedAt": null
+    }))
+    .unwrap();
+    StoredConfigs::new(None, config_file.clone())
+        .add_server(server)
+        .await
+        .unwrap();
+
+    let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(changed.revision, 1);
+    assert!(changed.servers.contains_key("added"));
+
+    // a save of the same length right after is seen as well
+    let mut saved = changed.clone();
+    saved.save_to_file().await.unwrap();
+    let changed = tokio::time::timeout(Duration::from_secs(5), watcher.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(changed.revision, 2);
+
+    drop(watcher);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,264 @@
+use crate::{
+    result::{self, ErrorCode},
+    VpnClient,
+};
+use openconnect_sys::{oc_webview_result, openconnect_info, openconnect_webview_load_changed};
+use std::{
+    ffi::{CStr, CString},
+    sync::{Arc, Condvar, Mutex},
+};
+
+/// A page load of the embedded login page, with the cookies and response headers seen by the webview
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WebviewLoad {
+    pub uri: String,
+    pub cookies: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl WebviewLoad {
+    pub fn new(uri: &str) -> Self {
+        Self {
+            uri: uri.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_cookie(mut self, name: &str, value: &str) -> Self {
+        self.cookies.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebviewState {
+    /// The login page is still in progress
+    Pending,
+
+    /// The gateway issued the single sign-on token, the webview can be closed
+    Done,
+
+    /// The login failed or the webview was closed by the user
+    Failed(String),
+}
+
+type LoadReporter = Box<dyn Fn(&WebviewLoad) -> i32 + Send + Sync>;
+
+/// The single sign-on in progress, the webview reports each page load until the login is done
+pub struct WebviewSession {
+    report: LoadReporter,
+    state: Mutex<WebviewState>,
+    cond: Condvar,
+}
+
+impl std::fmt::Debug for WebviewSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebviewSession")
+            .field("state", &self.state())
+            .finish_non_exhaustive()
+    }
+}
+
+impl WebviewSession {
+    /// Create a session, `report` returns 0 once the login is done and `-EAGAIN` while it is still in progress, like [openconnect_webview_load_changed]
+    pub(crate) fn new<F>(report: F) -> Arc<Self>
+    where
+        F: Fn(&WebviewLoad) -> i32 + Send + Sync + 'static,
+    {
+        Arc::new(Self {
+            report: Box::new(report),
+            state: Mutex::new(WebviewState::Pending),
+            cond: Condvar::new(),
+        })
+    }
+
+    fn for_vpninfo(vpninfo: *mut openconnect_info) -> Arc<Self> {
+        // libopenconnect is blocked in the webview callback while the session is pending
+        let vpninfo = vpninfo as usize;
+        Self::new(move |load| unsafe { report_load(vpninfo as *mut openconnect_info, load) })
+    }
+
+    pub fn state(&self) -> WebviewState {
+        self.state
+            .lock()
+            .map(|state| state.clone())
+            .unwrap_or(WebviewState::Failed("Webview state poisoned".to_string()))
+    }
+
+    /// Report a page load, returns true once the login is done and the webview can be closed
+    pub fn load_changed(&self, load: &WebviewLoad) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return true;
+        };
+
+        if *state == WebviewState::Pending {
+            match (self.report)(load) {
+                0 => *state = WebviewState::Done,
+                ret if ret == -libc::EAGAIN => {}
+                ret => *state = WebviewState::Failed(ErrorCode(ret).to_string()),
+            }
+            self.cond.notify_all();
+        }
+
+        *state != WebviewState::Pending
+    }
+
+    /// Give up the login, e.g. when the user closed the webview
+    pub fn cancel(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            if *state == WebviewState::Pending {
+                *state = WebviewState::Failed("Login page closed".to_string());
+                self.cond.notify_all();
+            }
+        }
+    }
+
+    /// Block until the login is done or failed
+    pub(crate) fn wait(&self) -> Result<(), String> {
+        let state = self
+            .state
+            .lock()
+            .map_err(|_| "Webview state poisoned".to_string())?;
+        let state = self
+            .cond
+            .wait_while(state, |state| *state == WebviewState::Pending)
+            .map_err(|_| "Webview state poisoned".to_string())?;
+
+        match &*state {
+            WebviewState::Failed(reason) => Err(reason.clone()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Show the login page of the gateway in an embedded webview and report its page loads
+pub trait WebviewHandler: Send + Sync {
+    /// Open the webview and return, each page load is reported to [WebviewSession::load_changed] until it returns true
+    ///
+    /// The client blocks until the session is done, so this must not wait for the login itself
+    fn open(&self, uri: &str, session: Arc<WebviewSession>) -> Result<(), String>;
+}
+
+impl<F> WebviewHandler for F
+where
+    F: Fn(&str, Arc<WebviewSession>) -> Result<(), String>,
+    F: Send + Sync,
+{
+    fn open(&self, uri: &str, session: Arc<WebviewSession>) -> Result<(), String> {
+        self(uri, session)
+    }
+}
+
+/// Flatten pairs into the NULL terminated `name, value, name, value, ...` layout of libopenconnect
+fn to_c_pairs(pairs: &[(String, String)]) -> Vec<CString> {
+    pairs
+        .iter()
+        .flat_map(|(name, value)| [name, value])
+        .filter_map(|s| CString::new(s.as_str()).ok())
+        .collect()
+}
+
+unsafe fn report_load(vpninfo: *mut openconnect_info, load: &WebviewLoad) -> i32 {
+    let Ok(uri) = CString::new(load.uri.as_str()) else {
+        return -libc::EINVAL;
+    };
+
+    let cookies = to_c_pairs(&load.cookies);
+    let headers = to_c_pairs(&load.headers);
+
+    let mut cookie_ptrs = cookies
+        .iter()
+        .map(|s| s.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect::<Vec<_>>();
+    let mut header_ptrs = headers
+        .iter()
+        .map(|s| s.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect::<Vec<_>>();
+
+    let result = oc_webview_result {
+        uri: uri.as_ptr(),
+        cookies: cookie_ptrs.as_mut_ptr(),
+        headers: header_ptrs.as_mut_ptr(),
+    };
+
+    openconnect_webview_load_changed(vpninfo, &result)
+}
+
+pub(crate) extern "C" fn open_webview_cb(
+    vpninfo: *mut openconnect_info,
+    uri: *const ::std::os::raw::c_char,
+    privdata: *mut ::std::os::raw::c_void,
+) -> ::std::os::raw::c_int {
+    let client = unsafe { VpnClient::ref_from_raw(privdata) };
+
+    result::guard_callback(Some(client), "open_webview", -1, || {
+        if uri.is_null() {
+            return -1;
+        }
+
+        let uri = unsafe { CStr::from_ptr(uri) }.to_string_lossy();
+        let session = WebviewSession::for_vpninfo(vpninfo);
+        match client.handle_open_webview(&uri, session) {
+            Ok(()) => 0,
+            Err(e) => {
+                tracing::error!("Login page failed: {}", e);
+                client.set_callback_error(e);
+                -1
+            }
+        }
+    })
+}
+
+#[test]
+fn test_webview_session() {
+    // a fake gateway, done once the token cookie shows up on the final page
+    let session = WebviewSession::new(|load| {
+        let has_token = load
+            .cookies
+            .iter()
+            .any(|(name, value)| name == "acSamlv2Token" && !value.is_empty());
+        if load.uri.ends_with("/+CSCOE+/saml_ac_login.html") && has_token {
+            0
+        } else {
+            -libc::EAGAIN
+        }
+    });
+
+    // a fake webview navigating through the identity provider on another thread
+    let webview = std::thread::spawn({
+        let session = session.clone();
+        move || {
+            let loads = [
+                WebviewLoad::new("https://idp.example.com/login"),
+                WebviewLoad::new("https://idp.example.com/mfa").with_header("Server", "idp"),
+                WebviewLoad::new("https://vpn.example.com/+CSCOE+/saml_ac_login.html")
+                    .with_cookie("acSamlv2Token", "token"),
+                WebviewLoad::new("https://vpn.example.com/after"),
+            ];
+            loads
+                .iter()
+                .map(|load| session.load_changed(load))
+                .collect::<Vec<_>>()
+        }
+    });
+
+    assert_eq!(session.wait(), Ok(()));
+    assert_eq!(webview.join().unwrap(), vec![false, false, true, true]);
+    assert_eq!(session.state(), WebviewState::Done);
+
+    let session = WebviewSession::new(|_| -libc::EAGAIN);
+    assert!(!session.load_changed(&WebviewLoad::new("https://idp.example.com/login")));
+    session.cancel();
+    assert_eq!(session.wait(), Err("Login page closed".to_string()));
+
+    let session = WebviewSession::new(|_| -libc::EINVAL);
+    assert!(session.load_changed(&WebviewLoad::new("https://idp.example.com/login")));
+    assert!(matches!(session.state(), WebviewState::Failed(_)));
+}
@@ -0,0 +1,315 @@
+//! End-to-end tests against a local AnyConnect gateway, see [openconnect_mock]
+//!
+//! The tunnel is handed to a script instead of a tun device, so no privileges are required
+#![cfg(unix)]
+
+use openconnect_core::{
+    config::{ConfigBuilder, Entrypoint, EntrypointBuilder, LogLevel},
+    events::EventHandlers,
+    probe::{self, FieldKind, SsoKind},
+    result::OpenconnectError,
+    Connectable, Status, VpnClient,
+};
+use openconnect_mock::{GatewayEvent, MockGateway, MockGatewayBuilder};
+use std::{sync::Arc, time::Duration};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Drain the tunnel until libopenconnect closes the socket
+const TUN_SCRIPT: &str = r#"exec cat <&"$VPNFD" >/dev/null"#;
+
+fn start_gateway() -> MockGateway {
+    MockGatewayBuilder::new()
+        .user("alice", "hunter2")
+        .group("staff")
+        .group("contractors")
+        .start()
+        .unwrap()
+}
+
+/// A client which trusts the certificate of the gateway only
+fn new_client(gateway: &MockGateway) -> Arc<VpnClient> {
+    let pin = gateway.pin_sha256().to_string();
+    let config = ConfigBuilder::default()
+        .loglevel(LogLevel::Debug)
+        .tun_script(TUN_SCRIPT)
+        .build()
+        .unwrap();
+    let event_handlers = EventHandlers::default()
+        .with_handle_peer_cert_invalid(move |fingerprint| fingerprint == pin);
+    VpnClient::new(config, event_handlers).unwrap()
+}
+
+fn entrypoint(gateway: &MockGateway, password: &str) -> Entrypoint {
+    EntrypointBuilder::new()
+        .name("mock")
+        .server(&gateway.url())
+        .username("alice")
+        .password(password)
+        .enable_udp(false)
+        .build()
+        .unwrap()
+}
+
+fn cookie_entrypoint(gateway: &MockGateway, cookie: &str) -> Entrypoint {
+    EntrypointBuilder::new()
+        .name("mock")
+        .server(&gateway.url())
+        .cookie(cookie)
+        .enable_udp(false)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn test_password_login() {
+    let gateway = start_gateway();
+    let client = new_client(&gateway);
+
+    let cookie = client
+        .connect_for_cookie(entrypoint(&gateway, "hunter2"))
+        .unwrap()
+        .unwrap();
+
+    assert!(gateway.events().contains(&GatewayEvent::CookieIssued {
+        username: "alice".to_string(),
+        group: "staff".to_string(),
+        cookie,
+    }));
+}
+
+#[test]
+fn test_group_login() {
+    // the group is chosen by its label, in full or by a unique prefix, and the gateway is sent its name
+    for group in ["Contractors", "contr"] {
+        let gateway = MockGatewayBuilder::new()
+            .user("alice", "hunter2")
+            .labelled_group("staff-vpn", "Staff")
+            .labelled_group("contractors-vpn", "Contractors")
+            .start()
+            .unwrap();
+        let client = new_client(&gateway);
+        let entrypoint = EntrypointBuilder::new()
+            .name("mock")
+            .server(&gateway.url())
+            .username("alice")
+            .password("hunter2")
+            .group(group)
+            .enable_udp(false)
+            .build()
+            .unwrap();
+
+        let cookie = client.connect_for_cookie(entrypoint).unwrap().unwrap();
+
+        assert!(gateway.events().contains(&GatewayEvent::GroupSelected {
+            group: "contractors-vpn".to_string(),
+        }));
+        assert!(gateway.events().contains(&GatewayEvent::CookieIssued {
+            username: "alice".to_string(),
+            group: "contractors-vpn".to_string(),
+            cookie,
+        }));
+    }
+}
+
+#[test]
+fn test_challenge_login() {
+    let gateway = MockGatewayBuilder::new()
+        .user("alice", "hunter2")
+        .challenge("123456")
+        .start()
+        .unwrap();
+    let pin = gateway.pin_sha256().to_string();
+    let config = ConfigBuilder::default()
+        .loglevel(LogLevel::Debug)
+        .build()
+        .unwrap();
+    let client_with_code = |code: &'static str| {
+        let pin = pin.clone();
+        let event_handlers = EventHandlers::default()
+            .with_handle_peer_cert_invalid(move |fingerprint| fingerprint == pin)
+            .with_handle_challenge(move |_| Some(code.to_string()));
+        VpnClient::new(config.clone(), event_handlers).unwrap()
+    };
+
+    let result = client_with_code("000000").connect_for_cookie(entrypoint(&gateway, "hunter2"));
+    assert!(result.is_err());
+    assert!(gateway.events().contains(&GatewayEvent::ChallengeFailed {
+        username: "alice".to_string(),
+    }));
+
+    let cookie = client_with_code("123456")
+        .connect_for_cookie(entrypoint(&gateway, "hunter2"))
+        .unwrap()
+        .unwrap();
+    assert!(gateway.events().contains(&GatewayEvent::ChallengeIssued {
+        username: "alice".to_string(),
+    }));
+    assert!(gateway.events().contains(&GatewayEvent::CookieIssued {
+        username: "alice".to_string(),
+        group: "default".to_string(),
+        cookie,
+    }));
+}
+
+#[test]
+fn test_wrong_password() {
+    let gateway = start_gateway();
+    let client = new_client(&gateway);
+
+    let result = client.connect_for_cookie(entrypoint(&gateway, "wrong"));
+
+    assert!(result.is_err());
+    assert!(matches!(client.get_status(), Status::Error(_)));
+    let failures = gateway
+        .events()
+        .iter()
+        .filter(|event| matches!(event, GatewayEvent::LoginFailed { .. }))
+        .count();
+    // the rejected password is not sent again
+    assert_eq!(failures, 1);
+    assert!(!gateway
+        .events()
+        .iter()
+        .any(|event| matches!(event, GatewayEvent::CookieIssued { .. })));
+}
+
+#[test]
+fn test_untrusted_certificate() {
+    let gateway = start_gateway();
+    let config = ConfigBuilder::default().build().unwrap();
+    let client = VpnClient::new(config, EventHandlers::default()).unwrap();
+
+    let result = client.connect_for_cookie(entrypoint(&gateway, "hunter2"));
+
+    match result {
+        Err(OpenconnectError::PeerCertRejectedError(fingerprint)) => {
+            assert_eq!(fingerprint, gateway.pin_sha256())
+        }
+        result => panic!("expected the certificate to be rejected, got {:?}", result),
+    }
+    assert!(!gateway
+        .events()
+        .iter()
+        .any(|event| matches!(event, GatewayEvent::AuthInit { .. })));
+}
+
+#[test]
+fn test_rejected_cookie() {
+    let gateway = start_gateway();
+    let client = new_client(&gateway);
+
+    let result = client.init_connection(cookie_entrypoint(&gateway, "forged"));
+
+    assert!(result.is_err());
+    assert!(gateway.events().contains(&GatewayEvent::TunnelRejected {
+        cookie: "forged".to_string()
+    }));
+}
+
+/// Obtain a cookie with one client and connect with another, as the CLI daemon does
+#[test]
+fn test_tunnel_with_cookie() {
+    let gateway = start_gateway();
+    let cookie = new_client(&gateway)
+        .connect_for_cookie(entrypoint(&gateway, "hunter2"))
+        .unwrap()
+        .unwrap();
+
+    let client = new_client(&gateway);
+    client
+        .init_connection(cookie_entrypoint(&gateway, &cookie))
+        .unwrap();
+    assert_eq!(client.get_status(), Status::Connected);
+    assert!(gateway
+        .wait_for_event(TIMEOUT, |event| *event
+            == GatewayEvent::TunnelConnected {
+                cookie: cookie.clone()
+            })
+        .is_some());
+
+    let main_loop = {
+        let client = client.clone();
+        std::thread::spawn(move || client.run_loop())
+    };
+
+    // give the main loop time to set up the tunnel before it is cancelled
+    std::thread::sleep(Duration::from_millis(500));
+    client.disconnect();
+
+    assert!(main_loop.join().unwrap().is_ok());
+    assert_eq!(client.get_status(), Status::Disconnected);
+    assert!(gateway
+        .wait_for_event(TIMEOUT, |event| *event == GatewayEvent::ClientDisconnected)
+        .is_some());
+}
+
+#[test]
+fn test_server_idle_disconnect() {
+    let gateway = start_gateway();
+    let client = new_client(&gateway);
+    client
+        .init_connection(entrypoint(&gateway, "hunter2"))
+        .unwrap();
+
+    let main_loop = {
+        let client = client.clone();
+        std::thread::spawn(move || client.run_loop())
+    };
+
+    std::thread::sleep(Duration::from_millis(500));
+    gateway.disconnect_tunnels("Idle timeout");
+
+    let result = main_loop.join().unwrap();
+    assert!(
+        matches!(result, Err(OpenconnectError::IdleTimeoutError(_))),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn test_probe() {
+    let gateway = start_gateway();
+
+    let report = probe::probe(&gateway.url()).unwrap();
+
+    let certificate = report.certificate.unwrap();
+    assert_eq!(certificate.fingerprint, gateway.pin_sha256());
+    assert!(!certificate.trusted);
+    assert!(!certificate.chain.is_empty());
+
+    let form = report.auth_form.unwrap();
+    assert_eq!(form.groups, vec!["staff", "contractors"]);
+    assert!(form
+        .fields
+        .iter()
+        .any(|field| field.name == "password" && field.kind == FieldKind::Password));
+
+    // the probe stops before the login
+    assert!(!gateway
+        .events()
+        .iter()
+        .any(|event| matches!(event, GatewayEvent::LoginFailed { .. })));
+}
+
+#[test]
+fn test_probe_sso() {
+    let gateway = MockGatewayBuilder::new()
+        .group("staff")
+        .sso("https://idp.example.com/saml2?SAMLRequest=fZJN")
+        .start()
+        .unwrap();
+
+    let report = probe::probe(&gateway.url()).unwrap();
+
+    let sso = report.sso.unwrap();
+    assert_eq!(sso.kind, SsoKind::Saml);
+    assert_eq!(
+        sso.redirect.as_deref(),
+        Some("https://idp.example.com/saml2?SAMLRequest=fZJN")
+    );
+    assert!(gateway.events().contains(&GatewayEvent::SsoLogin {
+        group: "staff".to_string()
+    }));
+}
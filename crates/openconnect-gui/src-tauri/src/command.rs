@@ -17,15 +17,16 @@ impl Display for ErrorResponse {
 impl From<StateError> for ErrorResponse {
     fn from(e: StateError) -> Self {
         let code = match e {
-            StateError::ConfigError(_) => "CONFIG_ERROR",
-            StateError::OpenconnectError(_) => "OPENCONNECT_ERROR",
-            StateError::ChannelError(_) => "CHANNEL_ERROR",
-            StateError::TauriError(_) => "TAURI_ERROR",
-            StateError::OpenIdError(_) => "OPENID_ERROR",
-            StateError::IoError(_) => "IO_ERROR",
+            StateError::ConfigError(_) => "CONFIG_ERROR".to_string(),
+            // e.g. OPENCONNECT_AUTH_ERROR, so that the frontend can tell wrong credentials from network failures
+            StateError::OpenconnectError(ref e) => format!("OPENCONNECT_{}_ERROR", e.category()),
+            StateError::ChannelError(_) => "CHANNEL_ERROR".to_string(),
+            StateError::TauriError(_) => "TAURI_ERROR".to_string(),
+            StateError::OpenIdError(_) => "OPENID_ERROR".to_string(),
+            StateError::IoError(_) => "IO_ERROR".to_string(),
         };
         Self {
-            code: code.to_string(),
+            code,
             message: e.to_string(),
         }
     }
@@ -38,6 +39,9 @@ impl From<StoredConfigError> for ErrorResponse {
             StoredConfigError::ParseError(_) => "PARSE_ERROR",
             StoredConfigError::IoError(_) => "IO_ERROR",
             StoredConfigError::CipherError(_) => "CIPHER_ERROR",
+            StoredConfigError::UnsupportedVersion(_) => "UNSUPPORTED_VERSION",
+            StoredConfigError::Conflict(_) => "CONFLICT",
+            StoredConfigError::Locked(_) => "LOCKED",
         };
         Self {
             code: code.to_string(),
@@ -62,6 +66,14 @@ pub async fn connect_with_oidc(
     Ok(app_state.connect_with_oidc(&server_name).await?)
 }
 
+#[tauri::command]
+pub async fn connect_with_saml(
+    app_state: tauri::State<'_, AppState>,
+    server_name: String,
+) -> Result<(), ErrorResponse> {
+    Ok(app_state.connect_with_saml(&server_name).await?)
+}
+
 #[tauri::command]
 pub async fn disconnect(app_state: tauri::State<'_, AppState>) -> Result<(), ErrorResponse> {
     Ok(app_state.disconnect().await?)
@@ -111,5 +123,11 @@ pub async fn remove_server(
 ) -> Result<(), ErrorResponse> {
     let mut stored_configs = app_state.stored_configs.write().await;
     stored_configs.remove_server(&server_name).await?;
+    stored_configs
+        .session_cache()
+        .read_from_file()
+        .await?
+        .remove(&server_name)
+        .await?;
     Ok(())
 }
@@ -4,14 +4,18 @@
 mod command;
 mod state;
 mod system_tray;
+mod webview;
 
 use command::*;
 use openconnect_core::storage::StoredConfigs;
-use state::AppState;
+use state::{AppState, LOGIN_WINDOW};
 use system_tray::AppSystemTray;
 use tauri::Manager;
 
 fn main() {
+    // this process may be started by libopenconnect to answer a CSD hostscan
+    openconnect_core::csd::run_responder_if_requested();
+
     let config_file = StoredConfigs::getorinit_config_file().expect("failed to get config file");
 
     #[cfg(target_os = "linux")]
@@ -65,6 +69,7 @@ fn main() {
 
     let app_system_tray = AppSystemTray::new();
 
+    let log_config = StoredConfigs::read_log_config(&config_file);
     let app_system_tray_clone = app_system_tray.clone();
     let app = tauri::Builder::default()
         .system_tray(app_system_tray.create_empty())
@@ -85,7 +90,8 @@ fn main() {
                 .body(b"Authenticated, close this window and return to the application.".to_vec())
         })
         .setup(move |app| {
-            openconnect_core::log::Logger::init().expect("failed to init logger");
+            openconnect_core::log::Logger::init_with_config(&log_config)
+                .expect("failed to init logger");
             // This is to fully remove dock icon, temp disable
             // #[cfg(target_os = "macos")]
             // app.set_activation_policy(tauri::ActivationPolicy::Accessory);
@@ -156,6 +162,7 @@ fn main() {
             remove_server,
             connect_with_password,
             connect_with_oidc,
+            connect_with_saml,
         ])
         .build(tauri::generate_context!())
         .unwrap();
@@ -167,6 +174,11 @@ fn main() {
             ..
         } = event
         {
+            // the login window is opened for each single sign-on, only keep the main window around
+            if label == LOGIN_WINDOW {
+                return;
+            }
+
             let win = app.get_window(label.as_str()).unwrap();
             win.hide().unwrap();
             api.prevent_close();
@@ -2,16 +2,29 @@ use crate::system_tray::AppSystemTray;
 use openconnect_core::{
     config::{ConfigBuilder, EntrypointBuilder, LogLevel},
     events::EventHandlers,
-    storage::{StoredConfigError, StoredConfigs, StoredServer},
+    secret::Secret,
+    session::{Session, SessionCache},
+    storage::{
+        OidcServer, PasswordServer, SamlServer, StoredConfigError, StoredConfigs, StoredServer,
+    },
+    webview::WebviewSession,
     Connectable, Status, VpnClient,
 };
 use openconnect_oidc::{
-    obtain_cookie_by_oidc_token,
+    obtain_cookie_by_oidc_token_with_options,
     oidc_token::{OpenIDTokenAuth, OpenIDTokenAuthConfig, OpenIDTokenAuthError, OIDC_REDIRECT_URI},
+    CookieRequestOptions,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::UNIX_EPOCH,
 };
-use std::{path::PathBuf, sync::Arc};
 use tauri::{
-    async_runtime::{channel, RwLock, Sender},
+    async_runtime::{channel, Mutex, RwLock, Sender},
     Manager, State,
 };
 use tokio::sync::mpsc::error::SendError;
@@ -40,7 +53,71 @@ pub enum StateError {
 
 #[derive(Debug, Clone)]
 pub enum VpnEvent {
-    Status { status: StatusPayload },
+    Status {
+        status: StatusPayload,
+    },
+    AuthExpiring {
+        expires_at: u64,
+    },
+    OpenLoginPage {
+        uri: String,
+        session: Option<Arc<WebviewSession>>,
+    },
+    StoredConfigsChanged {
+        configs: StoredConfigs,
+    },
+}
+
+/// Label of the window showing the single sign-on page
+pub(crate) const LOGIN_WINDOW: &str = "login";
+
+/// Show the single sign-on page in a window of the app
+///
+/// Without a session, the redirect at the end is caught by libopenconnect. With a session, each page load is reported to it until the login is done
+fn open_login_window(
+    handle: &tauri::AppHandle,
+    uri: &str,
+    session: Option<Arc<WebviewSession>>,
+) -> Result<(), StateError> {
+    if let Some(window) = handle.get_window(LOGIN_WINDOW) {
+        window.close()?;
+    }
+
+    let url = uri.parse::<url::Url>().map_err(|e| {
+        StateError::IoError(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid login page: {}", e),
+        ))
+    })?;
+
+    let window = tauri::WindowBuilder::new(handle, LOGIN_WINDOW, tauri::WindowUrl::External(url))
+        .title("Single Sign-On")
+        .inner_size(800.0, 640.0)
+        .build()?;
+
+    if let Some(session) = session {
+        let report_session = session.clone();
+        let report_handle = handle.clone();
+        crate::webview::watch_page_loads(
+            &window,
+            Arc::new(move |load| {
+                if report_session.load_changed(&load) {
+                    if let Some(window) = report_handle.get_window(LOGIN_WINDOW) {
+                        let _ = window.close();
+                    }
+                }
+            }),
+        )?;
+
+        window.on_window_event(move |event| {
+            // closing the window before the login is done fails the authentication
+            if let tauri::WindowEvent::Destroyed = event {
+                session.cancel();
+            }
+        });
+    }
+
+    Ok(())
 }
 
 #[derive(serde::Serialize, Debug, Clone)]
@@ -57,6 +134,7 @@ impl From<Status> for StatusPayload {
             Status::Connected => ("CONNECTED".to_string(), None),
             Status::Disconnecting => ("DISCONNECTING".to_string(), None),
             Status::Disconnected => ("DISCONNECTED".to_string(), None),
+            Status::Paused => ("PAUSED".to_string(), None),
             Status::Error(err) => ("ERROR".to_string(), Some(err.to_string())),
         };
 
@@ -64,6 +142,66 @@ impl From<Status> for StatusPayload {
     }
 }
 
+/// The proxy and pinned addresses of the decrypted server for the OIDC cookie request
+///
+/// Resolving the proxy may download and run a PAC file, so it runs on the blocking pool
+async fn cookie_request_options(
+    oidc_server: &OidcServer,
+) -> Result<CookieRequestOptions, StateError> {
+    let proxy_config = oidc_server.proxy.clone();
+    let server = oidc_server.server.clone();
+    let proxy = tauri::async_runtime::spawn_blocking(move || {
+        proxy_config
+            .map(|proxy| proxy.resolve(&server))
+            .transpose()
+            .map(Option::flatten)
+    })
+    .await?
+    .map_err(openconnect_core::result::OpenconnectError::from)?
+    .map(|proxy| proxy.to_reqwest())
+    .transpose()
+    .map_err(openconnect_core::result::OpenconnectError::from)?;
+
+    Ok(CookieRequestOptions {
+        proxy,
+        resolve: oidc_server.address.resolve.clone().into_iter().collect(),
+    })
+}
+
+async fn read_cached_session(
+    session_cache: &mut SessionCache,
+    name: &str,
+    server: &str,
+) -> Option<Session> {
+    match session_cache.read_from_file().await {
+        Ok(session_cache) => session_cache.get(name, server),
+        Err(e) => {
+            eprintln!("Failed to read session cache: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Cache the session for the next connection, failing to cache does not fail the connection
+async fn cache_session(
+    session_cache: &mut SessionCache,
+    name: &str,
+    server: &str,
+    session: &Session,
+) {
+    let result = match session_cache.read_from_file().await {
+        Ok(session_cache) => session_cache
+            .insert(name, server, session)
+            .await
+            .map(|_| ()),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Failed to cache session: {:?}", e);
+    }
+}
+
 pub struct AppState {
     pub(crate) event_tx: Sender<VpnEvent>,
     pub(crate) client: RwLock<Option<Arc<VpnClient>>>,
@@ -80,8 +218,24 @@ impl AppState {
     ) -> Result<(), StateError> {
         let (event_tx, mut event_rx) = channel::<VpnEvent>(100);
         let app_state = AppState::new(event_tx, vpnc_scipt, config_file).await?;
+        let watched_configs = app_state.stored_configs.read().await.clone();
+        let config_tx = app_state.event_tx.clone();
         app.manage(app_state);
 
+        // servers may be changed by the CLI or an editor while the app is running
+        tauri::async_runtime::spawn(async move {
+            let mut watcher = watched_configs.watch();
+            while let Some(configs) = watcher.changed().await {
+                if config_tx
+                    .send(VpnEvent::StoredConfigsChanged { configs })
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
         let handle = app.app_handle();
 
         tauri::async_runtime::spawn(async move {
@@ -90,12 +244,46 @@ impl AppState {
                 let app_system_tray: State<'_, Arc<AppSystemTray>> = handle.state();
                 match event {
                     VpnEvent::Status { status } => {
+                        // the login page is done once the connection is settled
+                        if status.status != "CONNECTING" {
+                            if let Some(window) = handle.get_window(LOGIN_WINDOW) {
+                                let _ = window.close();
+                            }
+                        }
+
                         let result = handle.emit_all("vpnStatus", Some(status));
                         app_system_tray.recreate(&handle).await.unwrap();
                         if let Err(e) = result {
                             eprintln!("Error while emitting event: {:?}", e);
                         }
                     }
+                    VpnEvent::AuthExpiring { expires_at } => {
+                        let result = handle.emit_all("vpnAuthExpiring", Some(expires_at));
+                        if let Err(e) = result {
+                            eprintln!("Error while emitting event: {:?}", e);
+                        }
+                    }
+                    VpnEvent::OpenLoginPage { uri, session } => {
+                        if let Err(e) = open_login_window(&handle, &uri, session) {
+                            eprintln!("Error while opening login page: {:?}", e);
+                        }
+                    }
+                    VpnEvent::StoredConfigsChanged { configs } => {
+                        {
+                            let app_state: State<'_, AppState> = handle.state();
+                            let mut stored_configs = app_state.stored_configs.write().await;
+                            // a save of this app may have landed after the reload
+                            if configs.revision >= stored_configs.revision {
+                                *stored_configs = configs;
+                            }
+                        }
+
+                        let result = handle.emit_all("storedConfigsChanged", ());
+                        app_system_tray.recreate(&handle).await.unwrap();
+                        if let Err(e) = result {
+                            eprintln!("Error while emitting event: {:?}", e);
+                        }
+                    }
                 }
             }
         });
@@ -127,6 +315,7 @@ impl AppState {
         match server {
             Some(StoredServer::Password(_)) => self.connect_with_user_pass(server_name).await,
             Some(StoredServer::Oidc(_)) => self.connect_with_oidc(server_name).await,
+            Some(StoredServer::Saml(_)) => self.connect_with_saml(server_name).await,
             None => Err(StoredConfigError::BadInput("Server not found".to_string()).into()),
         }
     }
@@ -141,25 +330,195 @@ impl AppState {
         #[cfg(not(target_os = "windows"))]
         let config = config.vpncscript(&self.vpnc_sciprt);
 
-        let config = config.loglevel(LogLevel::Info).build()?;
+        if let Some(dpd) = password_server.dpd {
+            config.dpd(dpd);
+        }
+
+        if let Some(keepalive) = password_server.keepalive {
+            config.keepalive(keepalive);
+        }
 
-        let entrypoint = EntrypointBuilder::new()
+        if let Some(dns) = password_server.dns {
+            config.dns_mode(dns);
+        }
+
+        if let Some(proxy) = password_server.proxy.clone() {
+            config.proxy(proxy);
+        }
+
+        config.auto_reauth(password_server.auto_reauth.unwrap_or(false));
+
+        let config = config
+            .loglevel(LogLevel::Info)
+            .journal(&stored_server.journal_file())
+            .build()?;
+
+        let mut session_cache = stored_server.session_cache();
+        let cached_session = read_cached_session(
+            &mut session_cache,
+            &password_server.name,
+            &password_server.server,
+        )
+        .await;
+
+        let mut entrypoint = EntrypointBuilder::new();
+        entrypoint
             .name(&password_server.name)
             .server(&password_server.server)
             .username(&password_server.username)
-            .password(&password_server.password.clone().unwrap_or("".to_string()))
+            .password(
+                password_server
+                    .password
+                    .as_ref()
+                    .map(|p| p.expose().as_str())
+                    .unwrap_or_default(),
+            )
             .accept_insecure_cert(password_server.allow_insecure.unwrap_or(false))
             .enable_udp(true)
+            .tunnel_options(password_server.tunnel.clone().unwrap_or_default());
+
+        if let Some(csd) = password_server.csd.clone() {
+            entrypoint.csd(csd);
+        }
+
+        password_server.address.apply(&mut entrypoint);
+
+        // the client authenticates again with the password if the cached cookie is rejected
+        if let Some(session) = cached_session {
+            entrypoint.cookie(&session.cookie);
+        }
+
+        let entrypoint = entrypoint.build()?;
+
+        let event_handlers = self
+            .create_event_handler(Arc::new(AtomicBool::new(true)))
+            .with_handle_reauth(
+                self.password_reauth_handler(password_server.clone(), session_cache.clone()),
+            );
+
+        let client = VpnClient::new(config, event_handlers)?;
+        {
+            self.client.write().await.replace(client.clone());
+        }
+        client.init_connection(entrypoint)?;
+
+        if let Some(session) = client.get_session() {
+            cache_session(
+                &mut session_cache,
+                &password_server.name,
+                &password_server.server,
+                &session,
+            )
+            .await;
+        }
+
+        tauri::async_runtime::spawn_blocking(move || {
+            let _ = client.run_loop(); // ignore the result
+        });
+
+        Ok(())
+    }
+
+    pub async fn connect_with_saml(&self, server_name: &str) -> Result<(), StateError> {
+        let stored_server = self.stored_configs.read().await;
+        let saml_server = stored_server.get_server_as_saml_server(server_name)?;
+        let saml_server = &saml_server.decrypted_by(&stored_server.cipher);
+
+        let mut config = ConfigBuilder::default();
+
+        #[cfg(not(target_os = "windows"))]
+        let config = config.vpncscript(&self.vpnc_sciprt);
+
+        if let Some(dpd) = saml_server.dpd {
+            config.dpd(dpd);
+        }
+
+        if let Some(keepalive) = saml_server.keepalive {
+            config.keepalive(keepalive);
+        }
+
+        if let Some(dns) = saml_server.dns {
+            config.dns_mode(dns);
+        }
+
+        if let Some(proxy) = saml_server.proxy.clone() {
+            config.proxy(proxy);
+        }
+
+        let config = config
+            .loglevel(LogLevel::Info)
+            .journal(&stored_server.journal_file())
             .build()?;
 
-        let event_handlers = self.create_event_handler();
+        let mut session_cache = stored_server.session_cache();
+        let cached_session =
+            read_cached_session(&mut session_cache, &saml_server.name, &saml_server.server).await;
+
+        let mut entrypoint = EntrypointBuilder::new();
+        entrypoint
+            .name(&saml_server.name)
+            .server(&saml_server.server)
+            .accept_insecure_cert(saml_server.allow_insecure.unwrap_or(false))
+            .enable_udp(true)
+            .tunnel_options(saml_server.tunnel.clone().unwrap_or_default());
+
+        saml_server.address.apply(&mut entrypoint);
+
+        if let Some(session) = cached_session {
+            entrypoint.cookie(&session.cookie);
+        }
+
+        let entrypoint = entrypoint.build()?;
+
+        let event_tx = self.event_tx.clone();
+        let event_handlers = self.create_event_handler(Arc::new(AtomicBool::new(true)));
+        let event_handlers = if saml_server.webview.unwrap_or(false) {
+            event_handlers.with_webview_handler(move |uri: &str, session: Arc<WebviewSession>| {
+                let event_tx = event_tx.clone();
+                let uri = uri.to_string();
+                tauri::async_runtime::spawn(async move {
+                    let _ = event_tx
+                        .send(VpnEvent::OpenLoginPage {
+                            uri,
+                            session: Some(session),
+                        })
+                        .await;
+                    // ignore the result
+                });
+                Ok(())
+            })
+        } else {
+            event_handlers.with_browser_handler(move |uri: &str| {
+                let event_tx = event_tx.clone();
+                let uri = uri.to_string();
+                tauri::async_runtime::spawn(async move {
+                    let _ = event_tx
+                        .send(VpnEvent::OpenLoginPage { uri, session: None })
+                        .await;
+                    // ignore the result
+                });
+                Ok(())
+            })
+        };
 
         let client = VpnClient::new(config, event_handlers)?;
         {
             self.client.write().await.replace(client.clone());
         }
+
+        // the client opens the login page again if the cached cookie is rejected
         client.init_connection(entrypoint)?;
 
+        if let Some(session) = client.get_session() {
+            cache_session(
+                &mut session_cache,
+                &saml_server.name,
+                &saml_server.server,
+                &session,
+            )
+            .await;
+        }
+
         tauri::async_runtime::spawn_blocking(move || {
             let _ = client.run_loop(); // ignore the result
         });
@@ -170,50 +529,167 @@ impl AppState {
     pub async fn connect_with_oidc(&self, server_name: &str) -> Result<(), StateError> {
         let stored_server = self.stored_configs.read().await;
         let oidc_server = stored_server.get_server_as_oidc_server(server_name)?;
+        let oidc_server = &oidc_server.decrypted_by(&stored_server.cipher);
 
         let openid_config = OpenIDTokenAuthConfig {
             issuer_url: oidc_server.issuer.clone(),
             redirect_uri: OIDC_REDIRECT_URI.to_string(),
             client_id: oidc_server.client_id.clone(),
-            client_secret: oidc_server.client_secret.clone(),
+            client_secret: oidc_server.client_secret.clone().map(Secret::into_inner),
             use_pkce_challenge: true,
         };
 
-        let mut openid = OpenIDTokenAuth::new(openid_config).await?;
-        let (authorize_url, req_state, _) = openid.auth_request();
+        let openid = Arc::new(Mutex::new(OpenIDTokenAuth::new(openid_config).await?));
 
-        open::that(authorize_url.to_string())?;
-        let (code, callback_state) = openid.wait_for_callback().await?;
+        let journal = stored_server.journal_file();
+        let mut session_cache = stored_server.session_cache();
+        let cached_session =
+            read_cached_session(&mut session_cache, &oidc_server.name, &oidc_server.server).await;
+
+        if let Some(session) = cached_session {
+            if let Some(refresh_token) = session.refresh_token.as_ref() {
+                openid.lock().await.set_refresh_token(refresh_token);
+            }
 
-        if req_state.secret() != callback_state.secret() {
-            return Err(OpenIDTokenAuthError::StateValidationError(
-                "State validation failed".to_string(),
-            ))?;
+            // errors of the cached session are not shown, since a full authentication follows
+            let report_errors = Arc::new(AtomicBool::new(false));
+            let result = self
+                .start_oidc_client(
+                    oidc_server,
+                    &session.cookie,
+                    openid.clone(),
+                    session_cache.clone(),
+                    report_errors.clone(),
+                    &journal,
+                )
+                .await;
+            report_errors.store(true, Ordering::SeqCst);
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    eprintln!("Cached session rejected, authenticating again: {:?}", e);
+                    if let Err(e) = session_cache.remove(&oidc_server.name).await {
+                        eprintln!("Failed to remove cached session: {:?}", e);
+                    }
+                }
+            }
         }
 
-        let token = openid.exchange_token(code).await?;
-        let cookie = obtain_cookie_by_oidc_token(&oidc_server.server, &token)
+        let cookie = {
+            let mut openid = openid.lock().await;
+            let (authorize_url, req_state, _) = openid.auth_request();
+
+            open::that(authorize_url.to_string())?;
+            let (code, callback_state) = openid.wait_for_callback().await?;
+
+            if req_state.secret() != callback_state.secret() {
+                return Err(OpenIDTokenAuthError::StateValidationError(
+                    "State validation failed".to_string(),
+                ))?;
+            }
+
+            let token = openid.exchange_token(code).await?;
+            obtain_cookie_by_oidc_token_with_options(
+                &oidc_server.server,
+                &token,
+                cookie_request_options(oidc_server).await?,
+            )
             .await
             .ok_or(StateError::IoError(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 "Failed to obtain cookie",
-            )))?;
+            )))?
+        };
 
+        self.start_oidc_client(
+            oidc_server,
+            &cookie,
+            openid,
+            session_cache,
+            Arc::new(AtomicBool::new(true)),
+            &journal,
+        )
+        .await
+    }
+
+    /// Connect to an OIDC server with the cookie and cache the session once connected
+    async fn start_oidc_client(
+        &self,
+        oidc_server: &OidcServer,
+        cookie: &str,
+        openid: Arc<Mutex<OpenIDTokenAuth>>,
+        mut session_cache: SessionCache,
+        report_errors: Arc<AtomicBool>,
+        journal: &Path,
+    ) -> Result<(), StateError> {
         let mut config = ConfigBuilder::default();
 
         #[cfg(not(target_os = "windows"))]
         let config = config.vpncscript(&self.vpnc_sciprt);
 
-        let config = config.loglevel(LogLevel::Info).build()?;
+        if let Some(dpd) = oidc_server.dpd {
+            config.dpd(dpd);
+        }
+
+        if let Some(keepalive) = oidc_server.keepalive {
+            config.keepalive(keepalive);
+        }
+
+        if let Some(dns) = oidc_server.dns {
+            config.dns_mode(dns);
+        }
 
-        let entrypoint = EntrypointBuilder::new()
+        if let Some(proxy) = oidc_server.proxy.clone() {
+            config.proxy(proxy);
+        }
+
+        config.auto_reauth(oidc_server.auto_reauth.unwrap_or(false));
+
+        let config = config.loglevel(LogLevel::Info).journal(journal).build()?;
+
+        let mut entrypoint = EntrypointBuilder::new();
+        entrypoint
             .name(&oidc_server.name)
             .server(&oidc_server.server)
-            .cookie(&cookie)
+            .cookie(cookie)
             .accept_insecure_cert(oidc_server.allow_insecure.unwrap_or(false))
-            .build()?;
-
-        let event_handlers = self.create_event_handler();
+            .tunnel_options(oidc_server.tunnel.clone().unwrap_or_default());
+
+        oidc_server.address.apply(&mut entrypoint);
+
+        let entrypoint = entrypoint.build()?;
+
+        // refresh the id token and exchange it for a fresh cookie, the refresh token is kept by openid
+        let reauth_openid = openid.clone();
+        let reauth_cache = session_cache.clone();
+        let name = oidc_server.name.clone();
+        let server = oidc_server.server.clone();
+        let options = cookie_request_options(oidc_server).await?;
+        let event_handlers =
+            self.create_event_handler(report_errors)
+                .with_handle_reauth(move || {
+                    let mut reauth_cache = reauth_cache.clone();
+                    tauri::async_runtime::block_on(async {
+                        let mut openid = reauth_openid.lock().await;
+                        let token = openid
+                            .refresh()
+                            .await
+                            .map_err(|e| eprintln!("Failed to refresh OpenID token: {:?}", e))
+                            .ok()?;
+                        let cookie = obtain_cookie_by_oidc_token_with_options(
+                            &server,
+                            &token,
+                            options.clone(),
+                        )
+                        .await?;
+                        let session = Session::new(&cookie)
+                            .with_token_expiration(&token)
+                            .with_refresh_token(openid.refresh_token());
+                        cache_session(&mut reauth_cache, &name, &server, &session).await;
+                        Some(cookie)
+                    })
+                });
 
         let client = VpnClient::new(config, event_handlers)?;
         {
@@ -222,6 +698,17 @@ impl AppState {
 
         client.init_connection(entrypoint)?;
 
+        if let Some(session) = client.get_session() {
+            let session = session.with_refresh_token(openid.lock().await.refresh_token());
+            cache_session(
+                &mut session_cache,
+                &oidc_server.name,
+                &oidc_server.server,
+                &session,
+            )
+            .await;
+        }
+
         tauri::async_runtime::spawn_blocking(move || {
             let _ = client.run_loop(); // ignore the result
         });
@@ -241,12 +728,85 @@ impl AppState {
         Ok(())
     }
 
-    pub fn create_event_handler(&self) -> openconnect_core::events::EventHandlers {
+    /// Log in again with the stored credentials on a separate client, the running client keeps its connection
+    fn password_reauth_handler(
+        &self,
+        password_server: PasswordServer,
+        session_cache: SessionCache,
+    ) -> impl Fn() -> Option<String> + Send + 'static {
+        #[cfg(not(target_os = "windows"))]
+        let vpnc_script = self.vpnc_sciprt.clone();
+
+        move || {
+            let mut config = ConfigBuilder::default();
+
+            #[cfg(not(target_os = "windows"))]
+            config.vpncscript(&vpnc_script);
+
+            if let Some(proxy) = password_server.proxy.clone() {
+                config.proxy(proxy);
+            }
+
+            let config = config.loglevel(LogLevel::Info).build().ok()?;
+
+            let mut entrypoint = EntrypointBuilder::new();
+            entrypoint
+                .name(&password_server.name)
+                .server(&password_server.server)
+                .username(&password_server.username)
+                .password(
+                    password_server
+                        .password
+                        .as_ref()
+                        .map(|p| p.expose().as_str())
+                        .unwrap_or_default(),
+                )
+                .accept_insecure_cert(password_server.allow_insecure.unwrap_or(false))
+                .enable_udp(true)
+                .tunnel_options(password_server.tunnel.clone().unwrap_or_default());
+
+            if let Some(csd) = password_server.csd.clone() {
+                entrypoint.csd(csd);
+            }
+
+            password_server.address.apply(&mut entrypoint);
+
+            let entrypoint = entrypoint.build().ok()?;
+
+            let client = VpnClient::new(config, EventHandlers::default()).ok()?;
+            client
+                .connect_for_cookie(entrypoint)
+                .map_err(|e| eprintln!("Failed to re-authenticate: {:?}", e))
+                .ok()?;
+
+            let session = client.get_session()?;
+            let mut session_cache = session_cache.clone();
+            tauri::async_runtime::block_on(cache_session(
+                &mut session_cache,
+                &password_server.name,
+                &password_server.server,
+                &session,
+            ));
+
+            Some(session.cookie)
+        }
+    }
+
+    /// Create event handlers forwarding to the frontend, error states are dropped while `report_errors` is false
+    pub fn create_event_handler(
+        &self,
+        report_errors: Arc<AtomicBool>,
+    ) -> openconnect_core::events::EventHandlers {
         let event_tx_for_state = self.event_tx.clone();
         let event_tx_for_cert = self.event_tx.clone();
+        let event_tx_for_expiring = self.event_tx.clone();
 
         EventHandlers::default()
             .with_handle_connection_state_change(move |state| {
+                if matches!(state, Status::Error(_)) && !report_errors.load(Ordering::SeqCst) {
+                    return;
+                }
+
                 let event_tx = event_tx_for_state.clone();
                 tauri::async_runtime::spawn(async move {
                     let _ = event_tx
@@ -273,6 +833,16 @@ impl AppState {
                 });
                 false
             })
+            .with_handle_auth_expiring(move |expiration| {
+                let event_tx = event_tx_for_expiring.clone();
+                let expires_at = expiration
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_secs());
+                tauri::async_runtime::spawn(async move {
+                    let _ = event_tx.send(VpnEvent::AuthExpiring { expires_at }).await;
+                    // ignore the result
+                });
+            })
     }
 
     pub async fn new(
@@ -1,5 +1,5 @@
 use crate::state::AppState;
-use openconnect_core::storage::{OidcServer, PasswordServer, StoredServer};
+use openconnect_core::storage::{OidcServer, PasswordServer, SamlServer, StoredServer};
 use std::sync::Arc;
 use tauri::{
     AppHandle, CustomMenuItem, Manager, State, SystemTray, SystemTrayEvent, SystemTrayMenu,
@@ -43,6 +43,7 @@ impl AppSystemTray {
                 let server_name = match server {
                     StoredServer::Oidc(OidcServer { name, .. }) => name.clone(),
                     StoredServer::Password(PasswordServer { name, .. }) => name.clone(),
+                    StoredServer::Saml(SamlServer { name, .. }) => name.clone(),
                 };
                 let is_server_connected = current_server_name.as_ref() == Some(&server_name)
                     && status.status == "CONNECTED";
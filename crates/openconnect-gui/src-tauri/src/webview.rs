@@ -0,0 +1,244 @@
+use openconnect_core::webview::WebviewLoad;
+use std::sync::Arc;
+
+/// Called with the location and all cookies of the login window, including those marked as HttpOnly, each time a page finished loading
+pub(crate) type LoadReport = Arc<dyn Fn(WebviewLoad) + Send + Sync>;
+
+/// Watch the page loads of a window through the load events and the cookie store of the platform webview
+pub(crate) fn watch_page_loads(window: &tauri::Window, report: LoadReport) -> tauri::Result<()> {
+    #[cfg(target_os = "linux")]
+    return window.with_webview(move |webview| webkit::watch(&webview.inner(), report));
+
+    #[cfg(target_os = "windows")]
+    return window.with_webview(move |webview| {
+        if let Err(e) = webview2::watch(&webview.controller(), report) {
+            eprintln!("Failed to watch the login page: {:?}", e);
+        }
+    });
+
+    #[cfg(target_os = "macos")]
+    return wkwebview::watch(window, report);
+}
+
+#[cfg(target_os = "linux")]
+mod webkit {
+    use super::LoadReport;
+    use openconnect_core::webview::WebviewLoad;
+    use webkit2gtk::{
+        CookieManagerExt, LoadEvent, WebContextExt, WebView, WebViewExt, WebsiteDataManagerExt,
+    };
+
+    pub(super) fn watch(webview: &WebView, report: LoadReport) {
+        webview.connect_load_changed(move |webview, event| {
+            if event != LoadEvent::Finished {
+                return;
+            }
+
+            let Some(uri) = webview.uri().map(|uri| uri.to_string()) else {
+                return;
+            };
+            let Some(cookie_manager) = webview
+                .context()
+                .and_then(|context| context.website_data_manager())
+                .and_then(|manager| manager.cookie_manager())
+            else {
+                return;
+            };
+
+            let report = report.clone();
+            cookie_manager.cookies(&uri, None::<&gio::Cancellable>, move |cookies| {
+                let load = cookies
+                    .unwrap_or_default()
+                    .iter_mut()
+                    .filter_map(|cookie| Some((cookie.name()?, cookie.value()?)))
+                    .fold(WebviewLoad::new(&uri), |load, (name, value)| {
+                        load.with_cookie(&name, &value)
+                    });
+                report(load);
+            });
+        });
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod webview2 {
+    use super::LoadReport;
+    use openconnect_core::webview::WebviewLoad;
+    use webview2_com::{
+        pwstr_from_str, take_pwstr, GetCookiesCompletedHandler,
+        Microsoft::Web::WebView2::Win32::{
+            ICoreWebView2, ICoreWebView2Controller, ICoreWebView2CookieList, ICoreWebView2_2,
+        },
+        NavigationCompletedEventHandler,
+    };
+    use windows::{
+        core::{Interface, PCWSTR, PWSTR},
+        Win32::System::WinRT::EventRegistrationToken,
+    };
+
+    fn cookies(list: &ICoreWebView2CookieList) -> windows::core::Result<Vec<(String, String)>> {
+        let mut count = 0;
+        unsafe { list.Count(&mut count)? };
+
+        (0..count)
+            .map(|index| unsafe {
+                let cookie = list.GetValueAtIndex(index)?;
+                let (mut name, mut value) = (PWSTR::null(), PWSTR::null());
+                cookie.Name(&mut name)?;
+                cookie.Value(&mut value)?;
+                Ok((take_pwstr(name), take_pwstr(value)))
+            })
+            .collect()
+    }
+
+    pub(super) fn watch(
+        controller: &ICoreWebView2Controller,
+        report: LoadReport,
+    ) -> windows::core::Result<()> {
+        let webview = unsafe { controller.CoreWebView2()? };
+
+        let handler = NavigationCompletedEventHandler::create(Box::new(
+            move |sender: Option<ICoreWebView2>, _| {
+                let Some(webview) = sender else {
+                    return Ok(());
+                };
+
+                let mut uri = PWSTR::null();
+                unsafe { webview.Source(&mut uri)? };
+                let uri = take_pwstr(uri);
+
+                let report = report.clone();
+                let cookie_manager = unsafe { webview.cast::<ICoreWebView2_2>()?.CookieManager()? };
+                let completed = GetCookiesCompletedHandler::create(Box::new(
+                    move |_, list: Option<ICoreWebView2CookieList>| {
+                        let cookies = list.map(|list| cookies(&list)).transpose()?;
+                        let load = cookies
+                            .unwrap_or_default()
+                            .iter()
+                            .fold(WebviewLoad::new(&uri), |load, (name, value)| {
+                                load.with_cookie(name, value)
+                            });
+                        report(load);
+                        Ok(())
+                    },
+                ));
+                unsafe {
+                    cookie_manager.GetCookies(PCWSTR(pwstr_from_str(&uri).0), &completed)?;
+                }
+                Ok(())
+            },
+        ));
+
+        let mut token = EventRegistrationToken::default();
+        unsafe { webview.add_NavigationCompleted(&handler, &mut token) }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod wkwebview {
+    use super::LoadReport;
+    use block::ConcreteBlock;
+    use cocoa::{
+        base::{id, nil, BOOL, NO},
+        foundation::NSString,
+    };
+    use objc::{msg_send, sel, sel_impl};
+    use openconnect_core::webview::WebviewLoad;
+    use std::{
+        ffi::CStr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    /// The navigation delegate of the WKWebView is owned by wry, so the page loads are polled instead
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    unsafe fn to_string(string: id) -> Option<String> {
+        if string == nil {
+            return None;
+        }
+        let bytes = string.UTF8String();
+        (!bytes.is_null()).then(|| CStr::from_ptr(bytes).to_string_lossy().into_owned())
+    }
+
+    /// The location of the webview once it finished loading
+    unsafe fn loaded_uri(webview: id) -> Option<String> {
+        let loading: BOOL = msg_send![webview, isLoading];
+        if loading != NO {
+            return None;
+        }
+        let url: id = msg_send![webview, URL];
+        if url == nil {
+            return None;
+        }
+        to_string(msg_send![url, absoluteString])
+    }
+
+    unsafe fn report_cookies(webview: id, uri: String, report: LoadReport) {
+        let host = url::Url::parse(&uri)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default();
+
+        let block = ConcreteBlock::new(move |cookies: id| {
+            let count: usize = msg_send![cookies, count];
+            let load = (0..count)
+                .filter_map(|index| {
+                    let cookie: id = msg_send![cookies, objectAtIndex: index];
+                    let domain = to_string(msg_send![cookie, domain])?;
+                    let domain = domain.trim_start_matches('.');
+                    if host != domain && !host.ends_with(&format!(".{}", domain)) {
+                        return None;
+                    }
+                    Some((
+                        to_string(msg_send![cookie, name])?,
+                        to_string(msg_send![cookie, value])?,
+                    ))
+                })
+                .fold(WebviewLoad::new(&uri), |load, (name, value)| {
+                    load.with_cookie(&name, &value)
+                });
+            report(load);
+        })
+        .copy();
+
+        let configuration: id = msg_send![webview, configuration];
+        let data_store: id = msg_send![configuration, websiteDataStore];
+        let cookie_store: id = msg_send![data_store, httpCookieStore];
+        let _: () = msg_send![cookie_store, getAllCookies: &*block];
+    }
+
+    pub(super) fn watch(window: &tauri::Window, report: LoadReport) -> tauri::Result<()> {
+        let window = window.clone();
+        let reported = Arc::new(Mutex::new(None::<String>));
+
+        tauri::async_runtime::spawn(async move {
+            loop {
+                tokio::time::sleep(POLL_INTERVAL).await;
+
+                let report = report.clone();
+                let reported = reported.clone();
+                let watched = window.with_webview(move |webview| unsafe {
+                    let webview = webview.inner();
+                    let Some(uri) = loaded_uri(webview) else {
+                        return;
+                    };
+                    let Ok(mut reported) = reported.lock() else {
+                        return;
+                    };
+                    if reported.as_deref() != Some(uri.as_str()) {
+                        *reported = Some(uri.clone());
+                        report_cookies(webview, uri, report);
+                    }
+                });
+
+                // the window is closed
+                if watched.is_err() || window.is_visible().is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -15,7 +15,7 @@ import { atom, useAtom } from "jotai";
 import { ServerSelector } from "./ServerSelector";
 import bg from "./assets/bg.jpeg";
 import { useStoredConfigs } from "./state";
-import { toastError } from "./lib/toast";
+import { toastError, toastWarning } from "./lib/toast";
 import { ToastContainer } from "react-toastify";
 import connected from "./assets/connected-animate.json";
 import Lottie from "lottie-react";
@@ -28,6 +28,7 @@ enum EStatus {
   Disconnected = "DISCONNECTED",
   Connecting = "CONNECTING",
   Connected = "CONNECTED",
+  Paused = "PAUSED",
   Error = "ERROR",
 }
 interface VpnStatus {
@@ -42,6 +43,12 @@ vpnStatusAtom.onMount = (set) => {
   });
 };
 
+// unix timestamp in seconds of the session expiration
+listen<number>("vpnAuthExpiring", (event) => {
+  const expiresAt = new Date(event.payload * 1000);
+  toastWarning(`VPN session expires at ${expiresAt.toLocaleTimeString()}`);
+});
+
 function App() {
   const [vpnStatus] = useAtom(vpnStatusAtom);
   const { selectedServer } = useStoredConfigs();
@@ -51,7 +58,10 @@ function App() {
   useKey(
     "Enter",
     () => {
-      if (vpnStatus.status === EStatus.Connected) {
+      if (
+        vpnStatus.status === EStatus.Connected ||
+        vpnStatus.status === EStatus.Paused
+      ) {
         handleDisconnect();
       } else if (selectedServer) {
         handleConnect();
@@ -75,6 +85,11 @@ function App() {
               serverName: selectedServer.name,
             });
             break;
+          case "saml":
+            await invoke("connect_with_saml", {
+              serverName: selectedServer.name,
+            });
+            break;
         }
       } catch (e) {
         toastError(e);
@@ -168,11 +183,19 @@ function App() {
                       <div>Connected</div>
                     </div>
                   );
+
+                case EStatus.Paused:
+                  return (
+                    <div className="flex w-full h-full items-center justify-center">
+                      <span>Paused</span>
+                    </div>
+                  );
               }
             })()}
           </CardBody>
           <CardFooter className="justify-center">
-            {vpnStatus.status === EStatus.Connected && (
+            {(vpnStatus.status === EStatus.Connected ||
+              vpnStatus.status === EStatus.Paused) && (
               <Button
                 color="primary"
                 className="m-3 w-[50%]"
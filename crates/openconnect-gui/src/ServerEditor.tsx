@@ -9,16 +9,145 @@ import {
 import { useForm, SubmitHandler, useWatch, Controller } from "react-hook-form";
 import { useCallback, useEffect, useMemo } from "react";
 import { invoke } from "@tauri-apps/api";
-import { OidcServer, PasswordServer, useStoredConfigs } from "./state";
+import {
+  CsdConfig,
+  ProxyConfig,
+  OidcServer,
+  PasswordServer,
+  SamlServer,
+  TunnelOptions,
+  useStoredConfigs,
+} from "./state";
 import { toastError, toastSuccess } from "./lib/toast";
 import { enc } from "crypto-js";
 
 export interface FormParams {
   mode: "add" | "edit";
   name?: string;
-  addFromImport?: Partial<OidcServer | PasswordServer>;
+  addFromImport?: Partial<OidcServer | PasswordServer | SamlServer>;
 }
 
+// number inputs give back strings, empty input means not set
+const toSeconds = (value?: number | string) => {
+  if (value === undefined || value === "") {
+    return undefined;
+  }
+  const seconds = Number(value);
+  return Number.isInteger(seconds) && seconds >= 0 ? seconds : undefined;
+};
+
+// drop unset tunnel options so the stored config only keeps what the user changed
+const toTunnel = (tunnel?: TunnelOptions): TunnelOptions | undefined => {
+  if (!tunnel) {
+    return undefined;
+  }
+  const options: TunnelOptions = {
+    mtu: toSeconds(tunnel.mtu),
+    disableIpv6: tunnel.disableIpv6 || undefined,
+    compression: tunnel.compression || undefined,
+    pfs: tunnel.pfs || undefined,
+    allowInsecureCrypto: tunnel.allowInsecureCrypto || undefined,
+    passTos: tunnel.passTos || undefined,
+  };
+  return Object.values(options).some((value) => value !== undefined)
+    ? options
+    : undefined;
+};
+
+// the wrapper mode needs a script path, otherwise CSD is not configured
+const toCsd = (csd?: CsdConfig): CsdConfig | undefined => {
+  switch (csd?.mode) {
+    case "wrapper":
+      return csd.path
+        ? {
+            mode: "wrapper",
+            path: csd.path,
+            trojanInterval: toSeconds(csd.trojanInterval),
+          }
+        : undefined;
+    case "responder":
+      return {
+        mode: "responder",
+        hostscan: csd.hostscan,
+        trojanInterval: toSeconds(csd.trojanInterval),
+      };
+    default:
+      return undefined;
+  }
+};
+
+// pinned addresses are edited as "host=address" pairs separated by commas
+const fromResolve = (resolve?: Record<string, string> | string) =>
+  typeof resolve === "string"
+    ? resolve
+    : Object.entries(resolve ?? {})
+        .map(([host, address]) => `${host}=${address}`)
+        .join(", ");
+
+const toResolve = (
+  resolve?: Record<string, string> | string
+): Record<string, string> | undefined => {
+  const entries = fromResolve(resolve)
+    .split(",")
+    .map((pair) => pair.split("=").map((part) => part.trim()))
+    .filter(([host, address]) => host && address);
+  return entries.length ? Object.fromEntries(entries) : undefined;
+};
+
+// the proxy is not configured without its url or PAC file, the credentials are kept only with a username
+const toProxy = (proxy?: ProxyConfig): ProxyConfig | undefined => {
+  const auto =
+    proxy?.auto?.mode === "env"
+      ? { mode: "env" as const }
+      : proxy?.auto?.mode === "pac" && proxy.auto.url
+        ? { mode: "pac" as const, url: proxy.auto.url }
+        : undefined;
+  if (!auto && !proxy?.url) {
+    return undefined;
+  }
+  return {
+    url: auto ? undefined : proxy?.url,
+    auto,
+    authMethods: proxy?.authMethods?.length ? proxy.authMethods : undefined,
+    username: proxy?.username || undefined,
+    password: proxy?.username ? proxy.password || undefined : undefined,
+  };
+};
+
+const switchClassNames = {
+  base: cn(
+    "inline-flex flex-row-reverse w-full bg-content2 hover:bg-content3 items-center",
+    "justify-between cursor-pointer rounded-lg gap-2 p-2 border-2 border-transparent"
+  ),
+  wrapper: "p-0 h-4 overflow-visible",
+  thumb: cn(
+    "w-6 h-6 border-2 shadow-lg",
+    "group-data-[selected=true]:ml-6",
+    "group-data-[pressed=true]:w-7",
+    "group-data-[selected]:group-data-[pressed]:ml-4"
+  ),
+};
+
+const OptionSwitch = (props: {
+  title: string;
+  description: string;
+  value?: boolean;
+  onChange: (value: boolean) => void;
+}) => (
+  <Switch
+    classNames={switchClassNames}
+    size="sm"
+    aria-label={props.title}
+    isSelected={props.value ?? false}
+    onValueChange={props.onChange}
+  >
+    <div className="flex flex-col gap-1">
+      <p className="text-medium">{props.title}</p>
+      <p className="text-tiny text-default-400">{props.description}</p>
+    </div>
+  </Switch>
+);
+
 export const ServerEditor = (props: FormParams) => {
   const { getStoredConfigs, serverList, defaultName } = useStoredConfigs();
 
@@ -38,11 +167,11 @@ export const ServerEditor = (props: FormParams) => {
   }, [props.name, props.mode, props.addFromImport, serverList]);
 
   const { handleSubmit, reset, unregister, control } = useForm<
-    OidcServer | PasswordServer
+    OidcServer | PasswordServer | SamlServer
   >();
 
-  const save: SubmitHandler<OidcServer | PasswordServer> = async (data) => {
-    let toSave: OidcServer | PasswordServer;
+  const save: SubmitHandler<OidcServer | PasswordServer | SamlServer> = async (data) => {
+    let toSave: OidcServer | PasswordServer | SamlServer;
     switch (data.authType) {
       case "oidc":
         toSave = {
@@ -53,6 +182,14 @@ export const ServerEditor = (props: FormParams) => {
           clientId: data.clientId,
           clientSecret: data.clientSecret,
           allowInsecure: data.allowInsecure,
+          dpd: toSeconds(data.dpd),
+          keepalive: toSeconds(data.keepalive),
+          tunnel: toTunnel(data.tunnel),
+          dns: data.dns || undefined,
+          autoReauth: data.autoReauth || undefined,
+          proxy: toProxy(data.proxy),
+          sni: data.sni || undefined,
+          resolve: toResolve(data.resolve),
         };
         break;
       case "password":
@@ -63,6 +200,31 @@ export const ServerEditor = (props: FormParams) => {
           username: data.username,
           password: data.password,
           allowInsecure: data.allowInsecure,
+          dpd: toSeconds(data.dpd),
+          keepalive: toSeconds(data.keepalive),
+          tunnel: toTunnel(data.tunnel),
+          dns: data.dns || undefined,
+          autoReauth: data.autoReauth || undefined,
+          csd: toCsd(data.csd),
+          proxy: toProxy(data.proxy),
+          sni: data.sni || undefined,
+          resolve: toResolve(data.resolve),
+        };
+        break;
+      case "saml":
+        toSave = {
+          name: data.name,
+          authType: "saml",
+          server: data.server,
+          allowInsecure: data.allowInsecure,
+          dpd: toSeconds(data.dpd),
+          keepalive: toSeconds(data.keepalive),
+          tunnel: toTunnel(data.tunnel),
+          dns: data.dns || undefined,
+          webview: data.webview || undefined,
+          proxy: toProxy(data.proxy),
+          sni: data.sni || undefined,
+          resolve: toResolve(data.resolve),
         };
         break;
     }
@@ -97,9 +259,12 @@ export const ServerEditor = (props: FormParams) => {
   }, [initialData.name, getStoredConfigs]);
 
   const watchedAuthType = useWatch({ control, name: "authType" });
+  const watchedCsdMode = useWatch({ control, name: "csd.mode" });
+  const watchedProxyUrl = useWatch({ control, name: "proxy.url" });
+  const watchedProxyMode = useWatch({ control, name: "proxy.auto.mode" });
 
   const handleShare = useCallback(() => {
-    let toShare: Partial<OidcServer | PasswordServer> = {};
+    let toShare: Partial<OidcServer | PasswordServer | SamlServer> = {};
     switch (initialData.authType) {
       case "oidc": {
         const { updatedAt, name, ...rest } = initialData;
@@ -111,6 +276,11 @@ export const ServerEditor = (props: FormParams) => {
         toShare = rest;
         break;
       }
+      case "saml": {
+        const { updatedAt, name, ...rest } = initialData;
+        toShare = rest;
+        break;
+      }
     }
     const jsonString = JSON.stringify(toShare);
     const words = enc.Utf8.parse(jsonString);
@@ -130,6 +300,14 @@ export const ServerEditor = (props: FormParams) => {
         unregister("clientId");
         unregister("clientSecret");
         break;
+      case "saml":
+        unregister("username");
+        unregister("password");
+        unregister("issuer");
+        unregister("clientId");
+        unregister("clientSecret");
+        unregister("autoReauth");
+        break;
     }
     reset(initialData, {
       keepValues: false,
@@ -187,6 +365,9 @@ export const ServerEditor = (props: FormParams) => {
               <SelectItem key="password" value="password">
                 Password Server
               </SelectItem>
+              <SelectItem key="saml" value="saml">
+                SAML Server
+              </SelectItem>
             </Select>
           )}
         />
@@ -240,6 +421,45 @@ export const ServerEditor = (props: FormParams) => {
                 />
               )}
             />
+            <Controller
+              name="csd.mode"
+              control={control}
+              render={({ field }) => (
+                <Select
+                  label="CSD Hostscan:"
+                  labelPlacement="inside"
+                  placeholder="Not configured"
+                  selectionMode="single"
+                  size="sm"
+                  selectedKeys={field.value ? [field.value] : []}
+                  {...field}
+                >
+                  <SelectItem key="wrapper" value="wrapper">
+                    Wrapper Script
+                  </SelectItem>
+                  <SelectItem key="responder" value="responder">
+                    Built-in Responder
+                  </SelectItem>
+                </Select>
+              )}
+            />
+            {watchedCsdMode === "wrapper" && (
+              <Controller
+                name="csd.path"
+                control={control}
+                rules={{ required: "This field is required" }}
+                render={({ field, fieldState }) => (
+                  <Input
+                    label="CSD Wrapper:"
+                    labelPlacement="inside"
+                    placeholder="/usr/libexec/openconnect/csd-post.sh"
+                    size="sm"
+                    errorMessage={fieldState.error?.message}
+                    {...field}
+                  />
+                )}
+              />
+            )}
           </>
         )}
         {watchedAuthType === "oidc" && (
@@ -289,6 +509,310 @@ export const ServerEditor = (props: FormParams) => {
             />
           </>
         )}
+        <Controller
+          name="dpd"
+          control={control}
+          render={({ field }) => (
+            <Input
+              label="Dead Peer Detection Interval (seconds):"
+              labelPlacement="inside"
+              placeholder="Use server default"
+              size="sm"
+              type="number"
+              min={0}
+              {...field}
+              value={field.value?.toString() ?? ""}
+            />
+          )}
+        />
+        <Controller
+          name="keepalive"
+          control={control}
+          render={({ field }) => (
+            <Input
+              label="Keepalive Interval (seconds):"
+              labelPlacement="inside"
+              placeholder="Disabled"
+              size="sm"
+              type="number"
+              min={0}
+              {...field}
+              value={field.value?.toString() ?? ""}
+            />
+          )}
+        />
+        <Controller
+          name="dns"
+          control={control}
+          render={({ field }) => (
+            <Select
+              label="DNS Configuration:"
+              labelPlacement="inside"
+              placeholder="vpnc-script (default)"
+              selectionMode="single"
+              size="sm"
+              selectedKeys={field.value ? [field.value] : []}
+              {...field}
+            >
+              <SelectItem key="script" value="script">
+                vpnc-script
+              </SelectItem>
+              <SelectItem key="systemdResolved" value="systemdResolved">
+                systemd-resolved
+              </SelectItem>
+              <SelectItem key="resolvConf" value="resolvConf">
+                resolv.conf
+              </SelectItem>
+            </Select>
+          )}
+        />
+        <Controller
+          name="proxy.auto.mode"
+          control={control}
+          render={({ field }) => (
+            <Select
+              label="Proxy Detection:"
+              labelPlacement="inside"
+              placeholder="Manual"
+              selectionMode="single"
+              size="sm"
+              selectedKeys={field.value ? [field.value] : []}
+              {...field}
+            >
+              <SelectItem key="env" value="env">
+                Environment Variables
+              </SelectItem>
+              <SelectItem key="pac" value="pac">
+                PAC File
+              </SelectItem>
+            </Select>
+          )}
+        />
+        {!watchedProxyMode && (
+          <Controller
+            name="proxy.url"
+            control={control}
+            rules={{
+              pattern: {
+                value: /^(https?|socks5?):\/\/[^\s@]+$/,
+                message: "Expected http, https, socks or socks5 URL without credentials",
+              },
+            }}
+            render={({ field, fieldState }) => (
+              <Input
+                label="Proxy:"
+                labelPlacement="inside"
+                placeholder="Direct connection"
+                size="sm"
+                errorMessage={fieldState.error?.message}
+                {...field}
+                value={field.value ?? ""}
+              />
+            )}
+          />
+        )}
+        {watchedProxyMode === "pac" && (
+          <Controller
+            name="proxy.auto.url"
+            control={control}
+            rules={{ required: "This field is required" }}
+            render={({ field, fieldState }) => (
+              <Input
+                label="PAC File:"
+                labelPlacement="inside"
+                placeholder="http://wpad.example.com/wpad.dat or file:///path/to/proxy.pac"
+                size="sm"
+                errorMessage={fieldState.error?.message}
+                {...field}
+                value={field.value ?? ""}
+              />
+            )}
+          />
+        )}
+        {(watchedProxyUrl || watchedProxyMode) && (
+          <>
+            <Controller
+              name="proxy.username"
+              control={control}
+              render={({ field }) => (
+                <Input
+                  label="Proxy Username:"
+                  labelPlacement="inside"
+                  placeholder="No authentication"
+                  size="sm"
+                  {...field}
+                  value={field.value ?? ""}
+                />
+              )}
+            />
+            <Controller
+              name="proxy.password"
+              control={control}
+              render={({ field }) => (
+                <Input
+                  label="Proxy Password:"
+                  labelPlacement="inside"
+                  placeholder="password"
+                  size="sm"
+                  type="password"
+                  {...field}
+                  value={field.value ?? ""}
+                />
+              )}
+            />
+          </>
+        )}
+        <Controller
+          name="sni"
+          control={control}
+          render={({ field }) => (
+            <Input
+              label="SNI:"
+              labelPlacement="inside"
+              placeholder="Host name of the server"
+              size="sm"
+              {...field}
+              value={field.value ?? ""}
+            />
+          )}
+        />
+        <Controller
+          name="resolve"
+          control={control}
+          rules={{
+            validate: (value) =>
+              /^\s*([^\s=,]+\s*=\s*[0-9a-fA-F.:]+\s*(,\s*|$))*$/.test(fromResolve(value)) ||
+              "Expected host=address pairs separated by commas",
+          }}
+          render={({ field, fieldState }) => (
+            <Input
+              label="Pinned Addresses:"
+              labelPlacement="inside"
+              placeholder="vpn.example.com=192.0.2.10"
+              size="sm"
+              errorMessage={fieldState.error?.message}
+              {...field}
+              value={fromResolve(field.value)}
+            />
+          )}
+        />
+        {watchedAuthType === "saml" && (
+          <Controller
+            name="webview"
+            control={control}
+            render={({ field }) => (
+              <OptionSwitch
+                title="Embedded Login Page"
+                description="Sign in inside the app instead of the default browser, for gateways which require it."
+                value={field.value}
+                onChange={field.onChange}
+              />
+            )}
+          />
+        )}
+        {watchedAuthType !== "saml" && (
+          <Controller
+            name="autoReauth"
+            control={control}
+            render={({ field }) => (
+              <OptionSwitch
+                title="Auto Re-authenticate"
+                description="Obtain a fresh session with the stored credentials before the current one expires."
+                value={field.value}
+                onChange={field.onChange}
+              />
+            )}
+          />
+        )}
+        <Controller
+          name="tunnel.mtu"
+          control={control}
+          render={({ field }) => (
+            <Input
+              label="MTU:"
+              labelPlacement="inside"
+              placeholder="Use server default"
+              size="sm"
+              type="number"
+              min={0}
+              {...field}
+              value={field.value?.toString() ?? ""}
+            />
+          )}
+        />
+        <Controller
+          name="tunnel.compression"
+          control={control}
+          render={({ field }) => (
+            <Select
+              label="Compression:"
+              labelPlacement="inside"
+              placeholder="Stateless (default)"
+              selectionMode="single"
+              size="sm"
+              selectedKeys={field.value ? [field.value] : []}
+              {...field}
+            >
+              <SelectItem key="none" value="none">
+                None
+              </SelectItem>
+              <SelectItem key="stateless" value="stateless">
+                Stateless
+              </SelectItem>
+              <SelectItem key="all" value="all">
+                All
+              </SelectItem>
+            </Select>
+          )}
+        />
+        <Controller
+          name="tunnel.disableIpv6"
+          control={control}
+          render={({ field }) => (
+            <OptionSwitch
+              title="Disable IPv6"
+              description="Do not request IPv6 connectivity from the server."
+              value={field.value}
+              onChange={field.onChange}
+            />
+          )}
+        />
+        <Controller
+          name="tunnel.pfs"
+          control={control}
+          render={({ field }) => (
+            <OptionSwitch
+              title="Require Perfect Forward Secrecy"
+              description="Refuse to connect if the server cannot negotiate a PFS cipher suite."
+              value={field.value}
+              onChange={field.onChange}
+            />
+          )}
+        />
+        <Controller
+          name="tunnel.allowInsecureCrypto"
+          control={control}
+          render={({ field }) => (
+            <OptionSwitch
+              title="Allow Insecure Crypto"
+              description="Allow insecure ciphers and protocol versions, needed by some legacy gateways."
+              value={field.value}
+              onChange={field.onChange}
+            />
+          )}
+        />
+        <Controller
+          name="tunnel.passTos"
+          control={control}
+          render={({ field }) => (
+            <OptionSwitch
+              title="Pass TOS"
+              description="Copy the TOS field of tunneled packets to the outer packets."
+              value={field.value}
+              onChange={field.onChange}
+            />
+          )}
+        />
         <Controller
           name="allowInsecure"
           control={control}
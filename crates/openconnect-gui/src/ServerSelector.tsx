@@ -9,6 +9,7 @@ import { FC, PropsWithChildren, useEffect } from "react";
 import { ServerEditorModal } from "./ServerEditorModal";
 import { useStoredConfigs } from "./state";
 import { useKey } from "react-use";
+import { listen } from "@tauri-apps/api/event";
 
 export const ServerSelector = () => {
   const {
@@ -52,6 +53,13 @@ export const ServerSelector = () => {
 
   useEffect(() => {
     getStoredConfigs();
+    // servers may be changed by the CLI or an editor while the window is open
+    const unlisten = listen("storedConfigsChanged", () => {
+      getStoredConfigs();
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
   }, [getStoredConfigs]);
 
   return (
@@ -18,6 +18,10 @@ export const toastError = (e: unknown) => {
   );
 };
 
+export const toastWarning = (message: string) => {
+  toast.warning(message, { position: "bottom-center", autoClose: 10000 });
+};
+
 export const toastSuccess = (message: string) => {
   toast.success(message, { position: "bottom-center", autoClose: 2000 });
 };
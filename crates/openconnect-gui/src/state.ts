@@ -2,6 +2,32 @@ import { invoke } from "@tauri-apps/api";
 import { atom, useAtom } from "jotai";
 import { useCallback, useMemo } from "react";
 
+export interface TunnelOptions {
+  mtu?: number;
+  disableIpv6?: boolean;
+  compression?: "none" | "stateless" | "all";
+  pfs?: boolean;
+  allowInsecureCrypto?: boolean;
+  passTos?: boolean;
+}
+
+export type DnsMode = "script" | "systemdResolved" | "resolvConf";
+
+export interface CsdConfig {
+  mode: "wrapper" | "responder";
+  path?: string;
+  hostscan?: string;
+  trojanInterval?: number;
+}
+
+export interface ProxyConfig {
+  url?: string;
+  auto?: { mode: "env" | "pac"; url?: string };
+  authMethods?: ("basic" | "digest" | "ntlm" | "gssapi")[];
+  username?: string;
+  password?: string;
+}
+
 export interface OidcServer {
   name: string;
   authType: "oidc";
@@ -10,6 +36,14 @@ export interface OidcServer {
   clientId: string;
   clientSecret?: string;
   allowInsecure?: boolean,
+  dpd?: number;
+  keepalive?: number;
+  tunnel?: TunnelOptions;
+  dns?: DnsMode;
+  autoReauth?: boolean;
+  proxy?: ProxyConfig;
+  sni?: string;
+  resolve?: Record<string, string>;
   updatedAt?: string;
 }
 
@@ -20,12 +54,39 @@ export interface PasswordServer {
   username: string;
   password: string;
   allowInsecure?: boolean,
+  dpd?: number;
+  keepalive?: number;
+  tunnel?: TunnelOptions;
+  dns?: DnsMode;
+  autoReauth?: boolean;
+  csd?: CsdConfig;
+  proxy?: ProxyConfig;
+  sni?: string;
+  resolve?: Record<string, string>;
+  updatedAt?: string;
+}
+
+export interface SamlServer {
+  name: string;
+  authType: "saml";
+  server: string;
+  allowInsecure?: boolean,
+  dpd?: number;
+  keepalive?: number;
+  tunnel?: TunnelOptions;
+  dns?: DnsMode;
+  webview?: boolean;
+  proxy?: ProxyConfig;
+  sni?: string;
+  resolve?: Record<string, string>;
   updatedAt?: string;
 }
 
 export interface StoredConfigs {
+  version?: number;
+  revision?: number;
   default?: string | null;
-  servers: (OidcServer | PasswordServer)[];
+  servers: (OidcServer | PasswordServer | SamlServer)[];
 }
 
 export const storedConfigsAtom = atom<StoredConfigs["servers"]>([]);
@@ -0,0 +1,316 @@
+use crate::{http::Response, server::Shared, GatewayEvent};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref REQUEST_TYPE: Regex = Regex::new(r#"<config-auth\b[^>]*\btype="([^"]*)""#).unwrap();
+}
+
+/// The single sign-on login page, announced in the login form and redirecting to the identity provider
+pub(crate) const SSO_LOGIN_PATH: &str = "+CSCOE+/saml/sp/login";
+
+/// Pending logins, the handle is sent in the opaque element which is echoed in every reply
+#[derive(Debug, Default)]
+pub(crate) struct AuthSessions {
+    next_handle: u64,
+    sessions: HashMap<u64, AuthSession>,
+}
+
+#[derive(Debug)]
+struct AuthSession {
+    group: String,
+    /// Set once the password is accepted and the challenge is pending
+    username: Option<String>,
+    failures: u32,
+}
+
+impl AuthSessions {
+    fn create(&mut self, group: &str) -> u64 {
+        self.next_handle += 1;
+        self.sessions.insert(
+            self.next_handle,
+            AuthSession {
+                group: group.to_string(),
+                username: None,
+                failures: 0,
+            },
+        );
+        self.next_handle
+    }
+}
+
+/// Answer a request of the XML auth exchange, `init` and `auth-reply` requests are told apart by the type attribute
+pub(crate) fn handle(shared: &Shared, body: &str) -> Response {
+    let settings = &shared.settings;
+    let mut sessions = shared.auth.lock().unwrap_or_else(|e| e.into_inner());
+
+    let handle = element(body, "auth-handle")
+        .and_then(|handle| handle.parse().ok())
+        .filter(|handle| sessions.sessions.contains_key(handle))
+        .unwrap_or_else(|| sessions.create(settings.default_group()));
+    let Some(session) = sessions.sessions.get_mut(&handle) else {
+        return Response::empty(500, "Internal Server Error");
+    };
+
+    if let Some(group) = element(body, "group-select") {
+        if group != session.group {
+            if !settings.groups.iter().any(|known| known.name == group) {
+                return login_form(shared, handle, session, Some("Unknown group"));
+            }
+            session.group = group.clone();
+            session.username = None;
+            shared.record(GatewayEvent::GroupSelected { group });
+            return login_form(shared, handle, session, None);
+        }
+    }
+
+    let request_type = REQUEST_TYPE.captures(body).map_or("init", |captures| {
+        captures.get(1).map_or("init", |m| m.as_str())
+    });
+
+    match request_type {
+        "init" => {
+            shared.record(GatewayEvent::AuthInit {
+                group: session.group.clone(),
+            });
+            login_form(shared, handle, session, None)
+        }
+        "auth-reply" => match session.username.clone() {
+            None => {
+                let username = element(body, "username").unwrap_or_default();
+                let password = element(body, "password").unwrap_or_default();
+                let valid = settings
+                    .users
+                    .iter()
+                    .any(|user| user.username == username && user.password == password);
+
+                if !valid {
+                    shared.record(GatewayEvent::LoginFailed { username });
+                    return failed(shared, &mut sessions, handle, "Login failed.");
+                }
+
+                if settings.challenge.is_some() {
+                    session.username = Some(username.clone());
+                    shared.record(GatewayEvent::ChallengeIssued { username });
+                    return challenge_form(handle, None);
+                }
+
+                let group = session.group.clone();
+                sessions.sessions.remove(&handle);
+                complete(shared, handle, username, group)
+            }
+            Some(username) => {
+                let code = element(body, "secondary_password").unwrap_or_default();
+                if settings.challenge.as_deref() != Some(code.as_str()) {
+                    shared.record(GatewayEvent::ChallengeFailed { username });
+                    return failed(shared, &mut sessions, handle, "Invalid code.");
+                }
+
+                let group = session.group.clone();
+                sessions.sessions.remove(&handle);
+                complete(shared, handle, username, group)
+            }
+        },
+        _ => Response::empty(400, "Bad Request"),
+    }
+}
+
+/// Ask again until the attempts are used up, then reject the request like a locked account
+fn failed(shared: &Shared, sessions: &mut AuthSessions, handle: u64, error: &str) -> Response {
+    let Some(session) = sessions.sessions.get_mut(&handle) else {
+        return Response::empty(500, "Internal Server Error");
+    };
+
+    session.failures += 1;
+    if session.failures >= shared.settings.max_attempts {
+        sessions.sessions.remove(&handle);
+        return Response::empty(401, "Unauthorized").header("Connection", "close");
+    }
+
+    match session.username {
+        Some(_) => challenge_form(handle, Some(error)),
+        None => login_form(shared, handle, session, Some(error)),
+    }
+}
+
+fn login_form(
+    shared: &Shared,
+    handle: u64,
+    session: &AuthSession,
+    error: Option<&str>,
+) -> Response {
+    let groups = &shared.settings.groups;
+    let group_select = if groups.is_empty() {
+        String::new()
+    } else {
+        let options = groups
+            .iter()
+            .map(|group| {
+                let selected = if group.name == session.group {
+                    r#" selected="true""#
+                } else {
+                    ""
+                };
+                format!(
+                    r#"<option value="{}"{}>{}</option>"#,
+                    escape(&group.name),
+                    selected,
+                    escape(&group.label)
+                )
+            })
+            .collect::<String>();
+        format!(
+            r#"<select name="group_list" label="GROUP:">{}</select>"#,
+            options
+        )
+    };
+
+    if shared.settings.sso.is_some() {
+        let url = &shared.url;
+        return auth_request(
+            handle,
+            &session.group,
+            "main",
+            "Please complete the authentication process in the AnyConnect Login window.",
+            error,
+            &format!(
+                r#"<sso-v2-login>{url}{SSO_LOGIN_PATH}?tgname={group}</sso-v2-login><sso-v2-login-final>{url}+CSCOE+/saml_ac_login.html</sso-v2-login-final><sso-v2-token-cookie-name>acSamlv2Token</sso-v2-token-cookie-name>"#,
+                group = escape(&session.group),
+            ),
+            &format!(
+                r#"<input type="sso" name="sso-token"></input>{}"#,
+                group_select
+            ),
+        );
+    }
+
+    auth_request(
+        handle,
+        &session.group,
+        "main",
+        "Please enter your username and password.",
+        error,
+        "",
+        &format!(
+            r#"<input type="text" name="username" label="Username:"></input><input type="password" name="password" label="Password:"></input>{}"#,
+            group_select
+        ),
+    )
+}
+
+/// Send the browser on to the identity provider
+pub(crate) fn sso_login(shared: &Shared, path: &str) -> Response {
+    let Some(ref identity_provider) = shared.settings.sso else {
+        return Response::empty(404, "Not Found");
+    };
+
+    let group = path
+        .split_once("tgname=")
+        .map_or(shared.settings.default_group(), |(_, group)| group);
+    shared.record(GatewayEvent::SsoLogin {
+        group: group.to_string(),
+    });
+    Response::empty(302, "Found").header("Location", identity_provider)
+}
+
+fn challenge_form(handle: u64, error: Option<&str>) -> Response {
+    auth_request(
+        handle,
+        "",
+        "challenge",
+        "Enter the code of your authenticator.",
+        error,
+        "",
+        r#"<input type="password" name="secondary_password" label="Code:"></input>"#,
+    )
+}
+
+fn auth_request(
+    handle: u64,
+    group: &str,
+    auth_id: &str,
+    message: &str,
+    error: Option<&str>,
+    elements: &str,
+    inputs: &str,
+) -> Response {
+    let error = error.map_or(String::new(), |error| {
+        format!(
+            r#"<error id="98" param1="" param2="">{}</error>"#,
+            escape(error)
+        )
+    });
+
+    Response::xml(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<config-auth client="vpn" type="auth-request" aggregate-auth-version="2">
+<opaque is-for="sg"><tunnel-group>{group}</tunnel-group><auth-handle>{handle}</auth-handle></opaque>
+<auth id="{auth_id}">
+<title>Login</title>
+<message>{message}</message>
+{error}
+{elements}
+<form>{inputs}</form>
+</auth>
+</config-auth>
+"#,
+        group = escape(group),
+        message = escape(message),
+    ))
+}
+
+fn complete(shared: &Shared, handle: u64, username: String, group: String) -> Response {
+    let cookie = shared.issue_cookie(handle, &username);
+    shared.record(GatewayEvent::CookieIssued {
+        username,
+        group,
+        cookie: cookie.clone(),
+    });
+
+    Response::xml(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<config-auth client="vpn" type="complete" aggregate-auth-version="2">
+<session-id>{handle}</session-id>
+<session-token>{cookie}</session-token>
+<auth id="success"><message id="0" param1="" param2=""></message></auth>
+<capabilities><crypto-supported>ssl-dhe</crypto-supported></capabilities>
+</config-auth>
+"#
+    ))
+    .header("Set-Cookie", &format!("webvpn={}; path=/; secure", cookie))
+}
+
+/// The text of the first element with this name, values of auth replies are sent as elements named after the inputs
+pub(crate) fn element(xml: &str, name: &str) -> Option<String> {
+    let start = format!("<{}>", name);
+    let begin = xml.find(&start)? + start.len();
+    let end = xml[begin..].find(&format!("</{}>", name))? + begin;
+    Some(unescape(&xml[begin..end]))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[test]
+fn test_element() {
+    let reply = r#"<config-auth client="vpn" type="auth-reply"><auth><username>alice</username><password>a&amp;b&lt;c</password></auth><group-select>staff</group-select></config-auth>"#;
+    assert_eq!(element(reply, "username").as_deref(), Some("alice"));
+    assert_eq!(element(reply, "password").as_deref(), Some("a&b<c"));
+    assert_eq!(element(reply, "group-select").as_deref(), Some("staff"));
+    assert_eq!(element(reply, "secondary_password"), None);
+    assert_eq!(unescape(&escape(r#"<"x" & 'y'>"#)), r#"<"x" & 'y'>"#);
+}
@@ -0,0 +1,149 @@
+use crate::{
+    http::{Request, Response},
+    server::{self, Shared, TlsStream},
+    GatewayEvent,
+};
+use std::io::{BufReader, Read, Write};
+
+const MAGIC: &[u8; 4] = b"STF\x01";
+
+pub(crate) const DATA: u8 = 0x00;
+pub(crate) const DPD_REQ: u8 = 0x03;
+pub(crate) const DPD_RESP: u8 = 0x04;
+pub(crate) const DISCONNECT: u8 = 0x05;
+pub(crate) const KEEPALIVE: u8 = 0x07;
+pub(crate) const TERMINATE: u8 = 0x09;
+
+/// A CSTP packet, an 8 byte header followed by the payload
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Frame {
+    pub(crate) kind: u8,
+    pub(crate) payload: Vec<u8>,
+}
+
+impl Frame {
+    pub(crate) fn new(kind: u8, payload: Vec<u8>) -> Self {
+        Self { kind, payload }
+    }
+
+    /// A disconnect sent by the server, the payload is a reason code and the message
+    pub(crate) fn disconnect(reason: &str) -> Self {
+        let mut payload = vec![0xb0];
+        payload.extend_from_slice(reason.as_bytes());
+        Self::new(DISCONNECT, payload)
+    }
+
+    pub(crate) fn read(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid CSTP header: {:02x?}", header),
+            ));
+        }
+
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload)?;
+        Ok(Self::new(header[6], payload))
+    }
+
+    pub(crate) fn write_to(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let length = u16::try_from(self.payload.len()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "CSTP payload too large")
+        })?;
+
+        let mut frame = MAGIC.to_vec();
+        frame.extend_from_slice(&length.to_be_bytes());
+        frame.extend_from_slice(&[self.kind, 0]);
+        frame.extend_from_slice(&self.payload);
+        writer.write_all(&frame)?;
+        writer.flush()
+    }
+}
+
+/// Accept the CONNECT request of a client holding an issued cookie, then run a tunnel which only answers control packets
+pub(crate) fn serve(
+    shared: &Shared,
+    reader: &mut BufReader<TlsStream>,
+    request: &Request,
+) -> std::io::Result<()> {
+    let cookie = request.cookie("webvpn").unwrap_or_default().to_string();
+    if !shared.has_cookie(&cookie) {
+        shared.record(GatewayEvent::TunnelRejected { cookie });
+        return Response::empty(401, "Unauthorized")
+            .header("Connection", "close")
+            .write_to(reader.get_mut());
+    }
+
+    let tunnel = &shared.settings.tunnel;
+    let mut response = Response::new(200, "OK")
+        .header("X-CSTP-Version", "1")
+        .header("X-CSTP-Protocol", "Copyright (c) 2004 Cisco Systems, Inc.")
+        .header("X-CSTP-Address", &tunnel.address.to_string())
+        .header("X-CSTP-Netmask", &tunnel.netmask.to_string())
+        .header("X-CSTP-Lease-Duration", "1209600")
+        .header("X-CSTP-Keep", "true")
+        .header("X-CSTP-MTU", &tunnel.mtu.to_string())
+        .header("X-CSTP-Base-MTU", "1500")
+        .header("X-CSTP-Rekey-Method", "none")
+        .header("X-CSTP-DPD", &tunnel.dpd.to_string())
+        .header("X-CSTP-Keepalive", &tunnel.keepalive.to_string());
+    for dns in &tunnel.dns {
+        response = response.header("X-CSTP-DNS", &dns.to_string());
+    }
+    if let Some(idle_timeout) = tunnel.idle_timeout {
+        response = response.header("X-CSTP-Idle-Timeout", &idle_timeout.to_string());
+    }
+
+    let disconnect = shared.subscribe_disconnect();
+    shared.record(GatewayEvent::TunnelConnected { cookie });
+    response.write_to(reader.get_mut())?;
+
+    loop {
+        let readable = server::wait_readable(shared, reader, |stream| {
+            let Ok(reason) = disconnect.try_recv() else {
+                return Ok(true);
+            };
+            let frame = Frame::disconnect(&reason);
+            shared.record(GatewayEvent::ServerDisconnected { reason });
+            frame.write_to(stream)?;
+            Ok(false)
+        })?;
+        if !readable {
+            return Ok(());
+        }
+
+        let frame = server::read_message(reader, Frame::read)?;
+        match frame.kind {
+            DPD_REQ => {
+                shared.record(GatewayEvent::DeadPeerDetection);
+                Frame::new(DPD_RESP, frame.payload).write_to(reader.get_mut())?;
+            }
+            KEEPALIVE => shared.record(GatewayEvent::Keepalive),
+            DATA => shared.record(GatewayEvent::Packet {
+                len: frame.payload.len(),
+            }),
+            DISCONNECT | TERMINATE => {
+                shared.record(GatewayEvent::ClientDisconnected);
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_frame() {
+    let mut buffer = vec![];
+    Frame::disconnect("Idle timeout")
+        .write_to(&mut buffer)
+        .unwrap();
+    assert_eq!(&buffer[..8], b"STF\x01\x00\x0d\x05\x00");
+
+    let frame = Frame::read(&mut buffer.as_slice()).unwrap();
+    assert_eq!(frame.kind, DISCONNECT);
+    assert_eq!(&frame.payload[1..], b"Idle timeout");
+    assert!(Frame::read(&mut &b"HTTP/1.1"[..]).is_err());
+}
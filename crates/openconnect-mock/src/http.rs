@@ -0,0 +1,147 @@
+use std::io::{BufRead, Write};
+
+/// The largest request body accepted, auth replies are a few kilobytes
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    /// Read the next request of a connection, `None` once the client closed it
+    pub(crate) fn read(reader: &mut impl BufRead) -> std::io::Result<Option<Self>> {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+            return Err(invalid_data(format!(
+                "invalid request line: {}",
+                line.trim()
+            )));
+        };
+        let (method, path) = (method.to_string(), path.to_string());
+
+        let mut headers = vec![];
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        let mut request = Self {
+            method,
+            path,
+            headers,
+            body: vec![],
+        };
+
+        let length = match request.header("Content-Length") {
+            Some(length) => length
+                .parse::<usize>()
+                .map_err(|_| invalid_data(format!("invalid content length: {}", length)))?,
+            None => 0,
+        };
+        if length > MAX_BODY_SIZE {
+            return Err(invalid_data(format!("request body too large: {}", length)));
+        }
+        request.body.resize(length, 0);
+        reader.read_exact(&mut request.body)?;
+
+        Ok(Some(request))
+    }
+
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of a cookie sent in the `Cookie` headers
+    pub(crate) fn cookie(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("Cookie"))
+            .flat_map(|(_, value)| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Response {
+    status: u16,
+    reason: &'static str,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// A response without a body, the headers are followed by the tunnel for a CONNECT request
+    pub(crate) fn new(status: u16, reason: &'static str) -> Self {
+        Self {
+            status,
+            reason,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    pub(crate) fn empty(status: u16, reason: &'static str) -> Self {
+        Self::new(status, reason).header("Content-Length", "0")
+    }
+
+    pub(crate) fn xml(body: String) -> Self {
+        Self::new(200, "OK")
+            .header("Content-Type", "text/xml; charset=utf-8")
+            .header("X-Transcend-Version", "1")
+            .header("Content-Length", &body.len().to_string())
+            .body(body.into_bytes())
+    }
+
+    pub(crate) fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub(crate) fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub(crate) fn write_to(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, self.reason);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+
+        writer.write_all(head.as_bytes())?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
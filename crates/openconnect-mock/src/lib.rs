@@ -0,0 +1,531 @@
+//! A local AnyConnect gateway for end-to-end tests
+//!
+//! The gateway speaks enough of the XML auth exchange and of CSTP for a client to log in with a password,
+//! pick a group, answer a second factor, obtain a cookie and open a tunnel. Everything runs on loopback
+//! with a self-signed certificate, so tests do not need network access or privileges.
+//!
+//! ```no_run
+//! use openconnect_mock::{GatewayEvent, MockGatewayBuilder};
+//! use std::time::Duration;
+//!
+//! let gateway = MockGatewayBuilder::new()
+//!     .user("alice", "hunter2")
+//!     .start()
+//!     .unwrap();
+//!
+//! // point the client to gateway.url() and accept gateway.pin_sha256()
+//!
+//! let issued = gateway.wait_for_event(Duration::from_secs(5), |event| {
+//!     matches!(event, GatewayEvent::CookieIssued { .. })
+//! });
+//! ```
+
+mod auth;
+mod cstp;
+mod http;
+mod server;
+mod tls;
+
+use server::Shared;
+use std::{
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum MockGatewayError {
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("TLS error: {0}")]
+    TlsError(#[from] rustls::Error),
+
+    #[error("Certificate error: {0}")]
+    CertificateError(#[from] rcgen::Error),
+}
+
+/// What the gateway saw, in order, see [MockGateway::events]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GatewayEvent {
+    AuthInit {
+        group: String,
+    },
+    GroupSelected {
+        group: String,
+    },
+    LoginFailed {
+        username: String,
+    },
+    ChallengeIssued {
+        username: String,
+    },
+    ChallengeFailed {
+        username: String,
+    },
+    SsoLogin {
+        group: String,
+    },
+    CookieIssued {
+        username: String,
+        group: String,
+        cookie: String,
+    },
+    TunnelConnected {
+        cookie: String,
+    },
+    TunnelRejected {
+        cookie: String,
+    },
+    DeadPeerDetection,
+    Keepalive,
+    Packet {
+        len: usize,
+    },
+    ClientDisconnected,
+    ServerDisconnected {
+        reason: String,
+    },
+    Logout,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockUser {
+    pub username: String,
+    pub password: String,
+}
+
+/// An auth group, the name is sent back by the client and the label is shown to the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockGroup {
+    pub name: String,
+    pub label: String,
+}
+
+/// Settings of the tunnel announced in the CONNECT response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockTunnel {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub dns: Vec<Ipv4Addr>,
+    pub mtu: u16,
+    pub dpd: u32,
+    pub keepalive: u32,
+    pub idle_timeout: Option<u32>,
+}
+
+impl Default for MockTunnel {
+    fn default() -> Self {
+        Self {
+            address: Ipv4Addr::new(10, 10, 0, 2),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            dns: vec![Ipv4Addr::new(10, 10, 0, 1)],
+            mtu: 1399,
+            dpd: 30,
+            keepalive: 20,
+            idle_timeout: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockGatewayBuilder {
+    users: Vec<MockUser>,
+    groups: Vec<MockGroup>,
+    challenge: Option<String>,
+    max_attempts: u32,
+    cookie: Option<String>,
+    hostnames: Vec<String>,
+    tunnel: MockTunnel,
+    sso: Option<String>,
+}
+
+impl MockGatewayBuilder {
+    pub fn new() -> Self {
+        Self {
+            users: vec![],
+            groups: vec![],
+            challenge: None,
+            max_attempts: 3,
+            cookie: None,
+            hostnames: vec!["localhost".to_string(), "127.0.0.1".to_string()],
+            tunnel: MockTunnel::default(),
+            sso: None,
+        }
+    }
+
+    pub fn user(&mut self, username: &str, password: &str) -> &mut Self {
+        self.users.push(MockUser {
+            username: username.to_string(),
+            password: password.to_string(),
+        });
+        self
+    }
+
+    /// Offer a group in the login form, the first group is selected by default
+    pub fn group(&mut self, group: &str) -> &mut Self {
+        self.labelled_group(group, group)
+    }
+
+    /// Offer a group whose option shows a label other than its name, as real gateways do
+    pub fn labelled_group(&mut self, name: &str, label: &str) -> &mut Self {
+        self.groups.push(MockGroup {
+            name: name.to_string(),
+            label: label.to_string(),
+        });
+        self
+    }
+
+    /// Ask for this code in a second form once the password is accepted
+    pub fn challenge(&mut self, code: &str) -> &mut Self {
+        self.challenge = Some(code.to_string());
+        self
+    }
+
+    /// Failed logins of one session before the gateway rejects it with 401, 3 by default
+    pub fn max_attempts(&mut self, max_attempts: u32) -> &mut Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Issue this cookie instead of a random one
+    pub fn cookie(&mut self, cookie: &str) -> &mut Self {
+        self.cookie = Some(cookie.to_string());
+        self
+    }
+
+    /// Names in the certificate, `localhost` and `127.0.0.1` by default
+    pub fn hostnames(&mut self, hostnames: &[&str]) -> &mut Self {
+        self.hostnames = hostnames.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn tunnel(&mut self, tunnel: MockTunnel) -> &mut Self {
+        self.tunnel = tunnel;
+        self
+    }
+
+    /// Offer single sign-on instead of the login form, the login page redirects to this identity provider URL
+    ///
+    /// Only the redirect is served, the gateway does not complete a single sign-on login
+    pub fn sso(&mut self, identity_provider: &str) -> &mut Self {
+        self.sso = Some(identity_provider.to_string());
+        self
+    }
+
+    pub(crate) fn default_group(&self) -> &str {
+        self.groups
+            .first()
+            .map_or("default", |group| group.name.as_str())
+    }
+
+    /// Listen on a free loopback port and serve in the background until the gateway is dropped
+    pub fn start(&self) -> Result<MockGateway, MockGatewayError> {
+        let identity = tls::Identity::generate(&self.hostnames)?;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let addr = listener.local_addr()?;
+
+        let shared = Arc::new(Shared::new(self.clone(), format!("https://{}/", addr)));
+        let server = {
+            let shared = shared.clone();
+            let tls = identity.server_config.clone();
+            std::thread::spawn(move || server::serve(shared, listener, tls))
+        };
+
+        Ok(MockGateway {
+            addr,
+            certificate: identity.certificate,
+            pin_sha256: identity.pin_sha256,
+            shared,
+            server: Some(server),
+        })
+    }
+}
+
+impl Default for MockGatewayBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A running gateway, see [MockGatewayBuilder::start]
+pub struct MockGateway {
+    addr: SocketAddr,
+    certificate: Vec<u8>,
+    pin_sha256: String,
+    shared: Arc<Shared>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl MockGateway {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn url(&self) -> String {
+        format!("https://{}/", self.addr)
+    }
+
+    /// The DER encoded certificate, for clients which verify it against a root store
+    pub fn certificate_der(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    /// The fingerprint reported to the peer certificate handler of a client
+    pub fn pin_sha256(&self) -> &str {
+        &self.pin_sha256
+    }
+
+    pub fn events(&self) -> Vec<GatewayEvent> {
+        self.shared.events().clone()
+    }
+
+    /// Block until an event matches, `None` if none arrived before the timeout
+    pub fn wait_for_event(
+        &self,
+        timeout: Duration,
+        predicate: impl Fn(&GatewayEvent) -> bool,
+    ) -> Option<GatewayEvent> {
+        self.shared.wait_for_event(timeout, predicate)
+    }
+
+    /// Close every open tunnel from the server side, a reason containing `idle` reads as an idle timeout
+    pub fn disconnect_tunnels(&self, reason: &str) {
+        self.shared.disconnect_tunnels(reason);
+    }
+}
+
+impl Drop for MockGateway {
+    fn drop(&mut self) {
+        self.shared.shutdown();
+
+        // wake up the accept loop
+        let _ = TcpStream::connect(self.addr);
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
+
+#[cfg(test)]
+type TestClient =
+    std::io::BufReader<rustls::StreamOwned<rustls::ClientConnection, std::net::TcpStream>>;
+
+#[cfg(test)]
+fn connect_client(gateway: &MockGateway) -> TestClient {
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(rustls::pki_types::CertificateDer::from(
+            gateway.certificate_der().to_vec(),
+        ))
+        .unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connection = rustls::ClientConnection::new(
+        Arc::new(config),
+        rustls::pki_types::ServerName::try_from("localhost").unwrap(),
+    )
+    .unwrap();
+    let stream = TcpStream::connect(gateway.addr()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    std::io::BufReader::new(rustls::StreamOwned::new(connection, stream))
+}
+
+/// Send a request and read the status and the body of the response
+#[cfg(test)]
+fn request(client: &mut TestClient, head: &str, body: &str) -> (u16, String) {
+    use std::io::{BufRead, Read, Write};
+
+    write!(client.get_mut(), "{}\r\nHost: localhost\r\n", head).unwrap();
+    if !body.is_empty() {
+        write!(client.get_mut(), "Content-Length: {}\r\n", body.len()).unwrap();
+    }
+    write!(client.get_mut(), "\r\n{}", body).unwrap();
+
+    let mut line = String::new();
+    client.read_line(&mut line).unwrap();
+    let status = line.split_whitespace().nth(1).unwrap().parse().unwrap();
+
+    let mut length = 0;
+    loop {
+        line.clear();
+        client.read_line(&mut line).unwrap();
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    client.read_exact(&mut body).unwrap();
+    (status, String::from_utf8(body).unwrap())
+}
+
+#[test]
+fn test_login_and_tunnel() {
+    let gateway = MockGatewayBuilder::new()
+        .user("alice", "hunter2")
+        .group("staff")
+        .group("contractors")
+        .challenge("123456")
+        .start()
+        .unwrap();
+    let mut client = connect_client(&gateway);
+
+    let (status, form) = request(
+        &mut client,
+        "POST / HTTP/1.1",
+        r#"<config-auth client="vpn" type="init"></config-auth>"#,
+    );
+    assert_eq!(status, 200);
+    assert!(form.contains(r#"<option value="staff" selected="true">staff</option>"#));
+    let handle = auth::element(&form, "auth-handle").unwrap();
+
+    let mut reply = |fields: &str| {
+        let body = format!(
+            r#"<config-auth client="vpn" type="auth-reply"><opaque is-for="sg"><auth-handle>{}</auth-handle></opaque><auth>{}</auth><group-select>contractors</group-select></config-auth>"#,
+            handle, fields
+        );
+        request(&mut client, "POST / HTTP/1.1", &body).1
+    };
+
+    // switching the group shows the login form again
+    let form = reply("");
+    assert!(form.contains(r#"<option value="contractors" selected="true">contractors</option>"#));
+
+    let form = reply("<username>alice</username><password>wrong</password>");
+    assert!(form.contains("Login failed."));
+
+    let form = reply("<username>alice</username><password>hunter2</password>");
+    assert!(form.contains(r#"<auth id="challenge">"#));
+
+    let complete = reply("<secondary_password>123456</secondary_password>");
+    let cookie = auth::element(&complete, "session-token").unwrap();
+
+    let mut tunnel = connect_client(&gateway);
+    let (status, _) = request(
+        &mut tunnel,
+        &format!(
+            "CONNECT /CSCOSSLC/tunnel HTTP/1.1\r\nCookie: webvpn={}",
+            cookie
+        ),
+        "",
+    );
+    assert_eq!(status, 200);
+
+    cstp::Frame::new(cstp::DPD_REQ, vec![])
+        .write_to(tunnel.get_mut())
+        .unwrap();
+    assert_eq!(cstp::Frame::read(&mut tunnel).unwrap().kind, cstp::DPD_RESP);
+
+    gateway.disconnect_tunnels("Idle timeout");
+    let frame = cstp::Frame::read(&mut tunnel).unwrap();
+    assert_eq!(frame, cstp::Frame::disconnect("Idle timeout"));
+
+    assert_eq!(
+        gateway.events(),
+        vec![
+            GatewayEvent::AuthInit {
+                group: "staff".to_string()
+            },
+            GatewayEvent::GroupSelected {
+                group: "contractors".to_string()
+            },
+            GatewayEvent::LoginFailed {
+                username: "alice".to_string()
+            },
+            GatewayEvent::ChallengeIssued {
+                username: "alice".to_string()
+            },
+            GatewayEvent::CookieIssued {
+                username: "alice".to_string(),
+                group: "contractors".to_string(),
+                cookie: cookie.clone()
+            },
+            GatewayEvent::TunnelConnected { cookie },
+            GatewayEvent::DeadPeerDetection,
+            GatewayEvent::ServerDisconnected {
+                reason: "Idle timeout".to_string()
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_rejected_login() {
+    let gateway = MockGatewayBuilder::new()
+        .user("alice", "hunter2")
+        .max_attempts(2)
+        .start()
+        .unwrap();
+    let mut client = connect_client(&gateway);
+
+    let reply = r#"<config-auth client="vpn" type="auth-reply"><auth><username>alice</username><password>wrong</password></auth></config-auth>"#;
+    let (status, form) = request(&mut client, "POST / HTTP/1.1", reply);
+    assert_eq!(status, 200);
+    let handle = auth::element(&form, "auth-handle").unwrap();
+
+    let reply = reply.replace(
+        "<auth>",
+        &format!(
+            "<opaque><auth-handle>{}</auth-handle></opaque><auth>",
+            handle
+        ),
+    );
+    let (status, _) = request(&mut client, "POST / HTTP/1.1", &reply);
+    assert_eq!(status, 401);
+
+    let mut tunnel = connect_client(&gateway);
+    let (status, _) = request(
+        &mut tunnel,
+        "CONNECT /CSCOSSLC/tunnel HTTP/1.1\r\nCookie: webvpn=forged",
+        "",
+    );
+    assert_eq!(status, 401);
+    assert_eq!(
+        gateway.events().last(),
+        Some(&GatewayEvent::TunnelRejected {
+            cookie: "forged".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_sso_redirect() {
+    let gateway = MockGatewayBuilder::new()
+        .group("staff")
+        .sso("https://idp.example.com/saml2?SAMLRequest=abc")
+        .start()
+        .unwrap();
+    let mut client = connect_client(&gateway);
+
+    let (_, form) = request(
+        &mut client,
+        "POST / HTTP/1.1",
+        r#"<config-auth client="vpn" type="init"></config-auth>"#,
+    );
+    assert!(form.contains(r#"<input type="sso" name="sso-token"></input>"#));
+    let login = auth::element(&form, "sso-v2-login").unwrap();
+    assert_eq!(
+        login,
+        format!("{}+CSCOE+/saml/sp/login?tgname=staff", gateway.url())
+    );
+
+    let path = login.trim_start_matches(gateway.url().trim_end_matches('/'));
+    let (status, _) = request(&mut client, &format!("GET {} HTTP/1.1", path), "");
+    assert_eq!(status, 302);
+    assert_eq!(
+        gateway.events().last(),
+        Some(&GatewayEvent::SsoLogin {
+            group: "staff".to_string()
+        })
+    );
+}
@@ -0,0 +1,228 @@
+use crate::{
+    auth::{self, AuthSessions},
+    cstp,
+    http::{Request, Response},
+    GatewayEvent, MockGatewayBuilder,
+};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub(crate) type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// How often idle connections check for a shutdown or a disconnect request
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The time to read a message once its first byte arrived
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// State of the gateway shared by the connection threads
+pub(crate) struct Shared {
+    pub(crate) settings: MockGatewayBuilder,
+    /// The base URL of the gateway, for links in the auth forms
+    pub(crate) url: String,
+    pub(crate) auth: Mutex<AuthSessions>,
+    events: Mutex<Vec<GatewayEvent>>,
+    events_changed: Condvar,
+    /// Issued cookies and their users
+    cookies: Mutex<HashMap<String, String>>,
+    tunnels: Mutex<Vec<Sender<String>>>,
+    shutdown: AtomicBool,
+}
+
+impl Shared {
+    pub(crate) fn new(settings: MockGatewayBuilder, url: String) -> Self {
+        Self {
+            settings,
+            url,
+            auth: Mutex::new(AuthSessions::default()),
+            events: Mutex::new(vec![]),
+            events_changed: Condvar::new(),
+            cookies: Mutex::new(HashMap::new()),
+            tunnels: Mutex::new(vec![]),
+            shutdown: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn record(&self, event: GatewayEvent) {
+        tracing::debug!("Mock gateway: {:?}", event);
+        self.events().push(event);
+        self.events_changed.notify_all();
+    }
+
+    pub(crate) fn events(&self) -> MutexGuard<'_, Vec<GatewayEvent>> {
+        self.events.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Block until an event matches or the timeout elapsed
+    pub(crate) fn wait_for_event(
+        &self,
+        timeout: Duration,
+        predicate: impl Fn(&GatewayEvent) -> bool,
+    ) -> Option<GatewayEvent> {
+        let events = self.events();
+        let (events, _) = self
+            .events_changed
+            .wait_timeout_while(events, timeout, |events| !events.iter().any(&predicate))
+            .unwrap_or_else(|e| e.into_inner());
+        events.iter().find(|event| predicate(event)).cloned()
+    }
+
+    pub(crate) fn issue_cookie(&self, handle: u64, username: &str) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos());
+        let cookie = self
+            .settings
+            .cookie
+            .clone()
+            .unwrap_or_else(|| format!("{:024X}{:08X}", nanos, handle));
+
+        self.cookies
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(cookie.clone(), username.to_string());
+        cookie
+    }
+
+    pub(crate) fn has_cookie(&self, cookie: &str) -> bool {
+        self.cookies
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(cookie)
+    }
+
+    pub(crate) fn subscribe_disconnect(&self) -> Receiver<String> {
+        let (sender, receiver) = channel();
+        self.tunnels
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(sender);
+        receiver
+    }
+
+    /// Ask every open tunnel to send a disconnect packet with the reason
+    pub(crate) fn disconnect_tunnels(&self, reason: &str) {
+        self.tunnels
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|tunnel| tunnel.send(reason.to_string()).is_ok());
+    }
+
+    pub(crate) fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn is_shut_down(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+/// Accept connections until the gateway is shut down, each connection is served on its own thread
+pub(crate) fn serve(shared: Arc<Shared>, listener: TcpListener, tls: Arc<ServerConfig>) {
+    for stream in listener.incoming() {
+        if shared.is_shut_down() {
+            break;
+        }
+        let Ok(stream) = stream else {
+            continue;
+        };
+
+        let shared = shared.clone();
+        let tls = tls.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_connection(&shared, stream, tls) {
+                tracing::debug!("Mock gateway connection closed: {}", e);
+            }
+        });
+    }
+}
+
+fn handle_connection(
+    shared: &Shared,
+    stream: TcpStream,
+    tls: Arc<ServerConfig>,
+) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let connection = ServerConnection::new(tls).map_err(std::io::Error::other)?;
+    let mut reader = BufReader::new(StreamOwned::new(connection, stream));
+
+    while wait_readable(shared, &mut reader, |_| Ok(true))? {
+        let Some(request) = read_message(&mut reader, Request::read)? else {
+            break;
+        };
+        tracing::debug!("Mock gateway request: {} {}", request.method, request.path);
+
+        let response = match request.method.as_str() {
+            "CONNECT" => return cstp::serve(shared, &mut reader, &request),
+            "GET" if request.path.contains("logout") => {
+                shared.record(GatewayEvent::Logout);
+                Response::empty(200, "OK")
+            }
+            "GET" if request.path.contains(auth::SSO_LOGIN_PATH) => {
+                auth::sso_login(shared, &request.path)
+            }
+            "GET" => auth::handle(shared, ""),
+            "POST" => auth::handle(shared, &String::from_utf8_lossy(&request.body)),
+            _ => Response::empty(405, "Method Not Allowed"),
+        };
+
+        response.write_to(reader.get_mut())?;
+        if response.status() == 401 {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Wait for data from the client, `idle` runs while waiting and stops the wait by returning false
+///
+/// Returns false once the client closed the connection or the gateway is shut down
+pub(crate) fn wait_readable(
+    shared: &Shared,
+    reader: &mut BufReader<TlsStream>,
+    mut idle: impl FnMut(&mut TlsStream) -> std::io::Result<bool>,
+) -> std::io::Result<bool> {
+    loop {
+        match reader.fill_buf() {
+            Ok(buffer) => return Ok(!buffer.is_empty()),
+            Err(e) if is_timeout(&e) => {
+                if shared.is_shut_down() || !idle(reader.get_mut())? {
+                    return Ok(false);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Read a whole message with a longer timeout, so that a message split across packets is not cut by the polling
+pub(crate) fn read_message<T>(
+    reader: &mut BufReader<TlsStream>,
+    read: impl FnOnce(&mut BufReader<TlsStream>) -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    reader.get_ref().sock.set_read_timeout(Some(READ_TIMEOUT))?;
+    let result = read(reader);
+    reader
+        .get_ref()
+        .sock
+        .set_read_timeout(Some(POLL_INTERVAL))?;
+    result
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
@@ -0,0 +1,45 @@
+use crate::MockGatewayError;
+use base64::Engine;
+use rcgen::{CertificateParams, KeyPair};
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    ServerConfig,
+};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// A self-signed certificate of the gateway, generated for each instance
+pub(crate) struct Identity {
+    pub(crate) server_config: Arc<ServerConfig>,
+    pub(crate) certificate: Vec<u8>,
+    pub(crate) pin_sha256: String,
+}
+
+impl Identity {
+    pub(crate) fn generate(hostnames: &[String]) -> Result<Self, MockGatewayError> {
+        let key = KeyPair::generate()?;
+        let certificate = CertificateParams::new(hostnames.to_vec())?.self_signed(&key)?;
+        let certificate = certificate.der().to_vec();
+
+        // the same format as openconnect_get_peer_cert_hash, a hash of the subject public key info
+        let pin_sha256 = format!(
+            "pin-sha256:{}",
+            base64::engine::general_purpose::STANDARD.encode(Sha256::digest(key.public_key_der()))
+        );
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let server_config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(certificate.clone())],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+            )?;
+
+        Ok(Self {
+            server_config: Arc::new(server_config),
+            certificate,
+            pin_sha256,
+        })
+    }
+}
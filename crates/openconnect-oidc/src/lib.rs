@@ -4,7 +4,27 @@ pub mod oidc_device;
 pub mod oidc_token;
 
 pub async fn obtain_cookie_by_oidc_token(server_url: &str, token: &str) -> Option<String> {
-    let client = reqwest::ClientBuilder::new()
+    obtain_cookie_by_oidc_token_with_options(server_url, token, CookieRequestOptions::default())
+        .await
+}
+
+/// How the cookie request reaches the server
+#[derive(Debug, Clone, Default)]
+pub struct CookieRequestOptions {
+    /// Reach the server through this proxy instead of connecting directly
+    pub proxy: Option<reqwest::Proxy>,
+
+    /// Connect to these addresses instead of resolving the hosts, the port of the URL is kept
+    pub resolve: Vec<(String, std::net::IpAddr)>,
+}
+
+/// Same as [obtain_cookie_by_oidc_token], with a proxy and pinned addresses
+pub async fn obtain_cookie_by_oidc_token_with_options(
+    server_url: &str,
+    token: &str,
+    options: CookieRequestOptions,
+) -> Option<String> {
+    let client_builder = reqwest::ClientBuilder::new()
         .danger_accept_invalid_certs(true)
         .default_headers(reqwest::header::HeaderMap::new())
         .http1_allow_obsolete_multiline_headers_in_responses(true)
@@ -13,10 +33,22 @@ pub async fn obtain_cookie_by_oidc_token(server_url: &str, token: &str) -> Optio
         .http1_title_case_headers()
         .no_brotli()
         .no_deflate()
-        .no_gzip()
-        .no_proxy()
-        .build()
-        .ok()?;
+        .no_gzip();
+
+    let client_builder = match options.proxy {
+        Some(proxy) => client_builder.proxy(proxy),
+        None => client_builder.no_proxy(),
+    };
+
+    // reqwest ignores the port of an overridden address and keeps the one of the URL
+    let client_builder = options
+        .resolve
+        .iter()
+        .fold(client_builder, |builder, (host, ip)| {
+            builder.resolve(host, std::net::SocketAddr::new(*ip, 0))
+        });
+
+    let client = client_builder.build().ok()?;
 
     let mut url = reqwest::Url::from_str(server_url).ok()?;
     if url.path().is_empty() {
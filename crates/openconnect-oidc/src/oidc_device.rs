@@ -7,12 +7,13 @@ use openidconnect::{
     },
     reqwest::async_http_client,
     AdditionalProviderMetadata, AuthType, ClientId, ClientSecret, DeviceAuthorizationUrl,
-    IssuerUrl, ProviderMetadata, TokenResponse,
+    IssuerUrl, OAuth2TokenResponse, ProviderMetadata, RefreshToken, TokenResponse,
 };
 use std::{future::Future, time::Duration};
 
 pub struct OpenIDDeviceAuth {
     client: CoreClient,
+    refresh_token: Option<RefreshToken>,
 }
 
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
@@ -84,7 +85,10 @@ impl OpenIDDeviceAuth {
                 .set_device_authorization_uri(device_authorization_endpoint)
                 .set_auth_type(AuthType::RequestBody);
 
-        Ok(OpenIDDeviceAuth { client })
+        Ok(OpenIDDeviceAuth {
+            client,
+            refresh_token: None,
+        })
     }
 
     /// Exchange device token
@@ -133,6 +137,51 @@ impl OpenIDDeviceAuth {
             .await
             .map_err(|e| OpenIDDeviceAuthError::TokenExchangeError(e.to_string()))?;
 
+        self.refresh_token = token_response.refresh_token().cloned();
+
+        let token = token_response
+            .id_token()
+            .ok_or(OpenIDDeviceAuthError::TokenExchangeError(
+                "No ID token".to_string(),
+            ))?
+            .to_string();
+
+        Ok(token)
+    }
+
+    /// The refresh token from the last token exchange, if the provider issued one
+    pub fn refresh_token(&self) -> Option<String> {
+        self.refresh_token
+            .as_ref()
+            .map(|token| token.secret().to_string())
+    }
+
+    /// Use a refresh token obtained by another instance, e.g. in another process
+    pub fn set_refresh_token(&mut self, refresh_token: &str) {
+        self.refresh_token = Some(RefreshToken::new(refresh_token.to_string()));
+    }
+
+    /// Obtain a fresh ID token with the refresh token, without user interaction
+    pub async fn refresh(&mut self) -> Result<String, OpenIDDeviceAuthError> {
+        let refresh_token =
+            self.refresh_token
+                .clone()
+                .ok_or(OpenIDDeviceAuthError::TokenExchangeError(
+                    "No refresh token".to_string(),
+                ))?;
+
+        let token_response = self
+            .client
+            .exchange_refresh_token(&refresh_token)
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| OpenIDDeviceAuthError::TokenExchangeError(e.to_string()))?;
+
+        // providers may rotate the refresh token
+        if let Some(refresh_token) = token_response.refresh_token() {
+            self.refresh_token = Some(refresh_token.clone());
+        }
+
         let token = token_response
             .id_token()
             .ok_or(OpenIDDeviceAuthError::TokenExchangeError(
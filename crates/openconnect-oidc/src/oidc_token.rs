@@ -2,7 +2,8 @@ use openidconnect::{
     core::{CoreClient, CoreProviderMetadata, CoreResponseType},
     reqwest::async_http_client,
     AuthenticationFlow, AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce,
-    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, TokenResponse,
+    OAuth2TokenResponse, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RefreshToken,
+    TokenResponse,
 };
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
 use url::Url;
@@ -14,6 +15,7 @@ pub struct OpenIDTokenAuth {
     client: CoreClient,
     pkce_challenge: Option<PkceCodeChallenge>,
     pkce_verifier: Option<PkceCodeVerifier>,
+    refresh_token: Option<RefreshToken>,
 }
 
 pub struct OpenIDTokenAuthConfig {
@@ -70,12 +72,14 @@ impl OpenIDTokenAuth {
                 client,
                 pkce_challenge: Some(pkce_challenge),
                 pkce_verifier: Some(pkce_verifier),
+                refresh_token: None,
             })
         } else {
             Ok(OpenIDTokenAuth {
                 client,
                 pkce_challenge: None,
                 pkce_verifier: None,
+                refresh_token: None,
             })
         }
     }
@@ -126,6 +130,51 @@ impl OpenIDTokenAuth {
             .await
             .map_err(|e| OpenIDTokenAuthError::TokenExchangeError(e.to_string()))?;
 
+        self.refresh_token = token_response.refresh_token().cloned();
+
+        let token = token_response
+            .id_token()
+            .ok_or(OpenIDTokenAuthError::TokenExchangeError(
+                "No ID token".to_string(),
+            ))?
+            .to_string();
+
+        Ok(token)
+    }
+
+    /// The refresh token from the last token exchange, if the provider issued one
+    pub fn refresh_token(&self) -> Option<String> {
+        self.refresh_token
+            .as_ref()
+            .map(|token| token.secret().to_string())
+    }
+
+    /// Use a refresh token obtained earlier, e.g. from a cached session
+    pub fn set_refresh_token(&mut self, refresh_token: &str) {
+        self.refresh_token = Some(RefreshToken::new(refresh_token.to_string()));
+    }
+
+    /// Obtain a fresh ID token with the refresh token, without user interaction
+    pub async fn refresh(&mut self) -> Result<String, OpenIDTokenAuthError> {
+        let refresh_token =
+            self.refresh_token
+                .clone()
+                .ok_or(OpenIDTokenAuthError::TokenExchangeError(
+                    "No refresh token".to_string(),
+                ))?;
+
+        let token_response = self
+            .client
+            .exchange_refresh_token(&refresh_token)
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| OpenIDTokenAuthError::TokenExchangeError(e.to_string()))?;
+
+        // providers may rotate the refresh token
+        if let Some(refresh_token) = token_response.refresh_token() {
+            self.refresh_token = Some(refresh_token.clone());
+        }
+
         let token = token_response
             .id_token()
             .ok_or(OpenIDTokenAuthError::TokenExchangeError(